@@ -0,0 +1,486 @@
+//! Job batches and chains.
+//!
+//! A [`Chain`] runs workers one after another, handing each step the result
+//! the previous one reported through [`super::report_result`]. A [`Batch`]
+//! runs a set of jobs (or chains) side by side and enqueues a callback once
+//! all of them completed, or once the first one failed.
+//!
+//! Queue providers see them as plain data ([`JobSpec`], [`BatchSpec`]): a
+//! job row carries the rest of its chain and the id of its batch, and the
+//! provider enqueues the follow-ups when the job completes or fails.
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use serde_variant::to_variant_name;
+
+use super::{erase_worker, upcast, BackgroundWorker, JobHandler, JobId, JobReporter};
+use crate::{app::AppContext, config::WorkerMode, Error, Result};
+
+/// A step of a chain that has not run yet. Its args are the result of the
+/// step before it.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ChainStep {
+    pub class: String,
+    pub queue: Option<String>,
+    pub tags: Option<Vec<String>>,
+    #[serde(default = "upcast::initial_args_version")]
+    pub args_version: u32,
+}
+
+impl ChainStep {
+    /// The job this step becomes once the previous step produced `args`,
+    /// followed by the `rest` of the chain.
+    #[must_use]
+    pub fn into_job(self, args: JsonValue, rest: Vec<Self>) -> JobSpec {
+        JobSpec {
+            class: self.class,
+            queue: self.queue,
+            args,
+            tags: self.tags,
+            priority: None,
+            chain: rest,
+            args_version: self.args_version,
+        }
+    }
+}
+
+/// A job to enqueue, along with the chain steps that run after it.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct JobSpec {
+    pub class: String,
+    pub queue: Option<String>,
+    pub args: JsonValue,
+    pub tags: Option<Vec<String>>,
+    pub priority: Option<i32>,
+    #[serde(default)]
+    pub chain: Vec<ChainStep>,
+    #[serde(default = "upcast::initial_args_version")]
+    pub args_version: u32,
+}
+
+impl JobSpec {
+    /// The job to enqueue after this one completed with `output`, if the
+    /// chain goes on.
+    #[must_use]
+    pub fn continue_chain(chain: &[ChainStep], output: Option<JsonValue>) -> Option<Self> {
+        let (next, rest) = chain.split_first()?;
+        Some(
+            next.clone()
+                .into_job(output.unwrap_or(JsonValue::Null), rest.to_vec()),
+        )
+    }
+}
+
+/// A batch to enqueue: its member jobs and the callbacks to enqueue when
+/// they are done.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BatchSpec {
+    pub jobs: Vec<JobSpec>,
+    pub on_complete: Option<JobSpec>,
+    pub on_failure: Option<JobSpec>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum BatchStatus {
+    /// Some members have not finished yet.
+    #[serde(rename = "pending")]
+    Pending,
+    /// Every member completed; `on_complete` was enqueued.
+    #[serde(rename = "completed")]
+    Completed,
+    /// A member failed; `on_failure` was enqueued when the first one did.
+    #[serde(rename = "failed")]
+    Failed,
+}
+
+impl std::str::FromStr for BatchStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "completed" => Ok(Self::Completed),
+            "failed" => Ok(Self::Failed),
+            _ => Err(format!("Invalid batch status: {s}")),
+        }
+    }
+}
+
+impl std::fmt::Display for BatchStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        to_variant_name(self).expect("only enum supported").fmt(f)
+    }
+}
+
+/// A batch's bookkeeping, as stored by the queue provider. A member counts
+/// as completed once the last step of its chain completed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct JobBatch {
+    pub id: String,
+    pub status: BatchStatus,
+    pub total: i32,
+    pub completed: i32,
+    pub failed: i32,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+type BuildHandler = fn(&AppContext) -> JobHandler;
+
+fn build_handler<W, A>(ctx: &AppContext) -> JobHandler
+where
+    A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+    W: BackgroundWorker<A> + 'static,
+{
+    erase_worker(W::build(ctx))
+}
+
+pub(super) fn step<W, A>() -> (ChainStep, BuildHandler)
+where
+    A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+    W: BackgroundWorker<A> + 'static,
+{
+    let tags = W::tags();
+    let step = ChainStep {
+        class: W::class_name(),
+        queue: W::queue(),
+        tags: if tags.is_empty() { None } else { Some(tags) },
+        args_version: W::args_version(),
+    };
+    (step, build_handler::<W, A>)
+}
+
+/// Workers that run one after another, each receiving the result the
+/// previous one reported as its args:
+///
+/// ```rust,ignore
+/// Chain::new::<ExtractWorker, _>(ExtractArgs { source })
+///     .then::<TransformWorker, _>()
+///     .then::<LoadWorker, _>()
+///     .enqueue(&ctx)
+///     .await?;
+/// ```
+///
+/// A step that reports no result hands `null` to the next one. The chain
+/// stops at a step that fails (after its retries).
+pub struct Chain {
+    args: serde_json::Result<JsonValue>,
+    steps: Vec<(ChainStep, BuildHandler)>,
+}
+
+impl Chain {
+    /// Starts a chain with worker `W` performing `args`.
+    #[must_use]
+    pub fn new<W, A>(args: A) -> Self
+    where
+        A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+        W: BackgroundWorker<A> + 'static,
+    {
+        Self {
+            args: serde_json::to_value(args),
+            steps: vec![step::<W, A>()],
+        }
+    }
+
+    /// Adds worker `W` as the next step, taking the previous step's result as
+    /// its args.
+    #[must_use]
+    pub fn then<W, A>(mut self) -> Self
+    where
+        A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+        W: BackgroundWorker<A> + 'static,
+    {
+        self.steps.push(step::<W, A>());
+        self
+    }
+
+    fn into_spec(self) -> Result<JobSpec> {
+        let mut steps = self.steps.into_iter().map(|(step, _)| step);
+        let first = steps.next().ok_or_else(|| Error::string("empty chain"))?;
+        Ok(first.into_job(self.args?, steps.collect()))
+    }
+
+    /// Runs the chain in this process, returning the last step's result.
+    async fn perform(self, ctx: &AppContext) -> Result<Option<JsonValue>> {
+        let mut args = self.args?;
+        let mut output = None;
+        for (_, build) in self.steps {
+            output = perform_inline(ctx, build, args).await?;
+            args = output.clone().unwrap_or(JsonValue::Null);
+        }
+        Ok(output)
+    }
+
+    /// Enqueues (or runs) the chain according to the configured
+    /// [`WorkerMode`] and returns the id of its first job.
+    ///
+    /// # Errors
+    /// Fails if the args cannot be serialized or the queue provider fails to
+    /// enqueue the first job. In `ForegroundBlocking` mode, fails with the
+    /// error of the first step that fails.
+    pub async fn enqueue(self, ctx: &AppContext) -> Result<String> {
+        match &ctx.config.workers.mode {
+            WorkerMode::BackgroundQueue => {
+                if let Some(p) = &ctx.queue_provider {
+                    let id = p.enqueue_chain(self.into_spec()?).await?;
+                    return Ok(id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()));
+                }
+                tracing::error!(
+                    "chain: background queue is selected, but queue was not populated in context"
+                );
+            }
+            WorkerMode::ForegroundBlocking => {
+                self.perform(ctx).await?;
+            }
+            WorkerMode::BackgroundAsync => {
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = self.perform(&ctx).await {
+                        tracing::error!(err = err.to_string(), "chain failed to perform");
+                    }
+                });
+            }
+        }
+        Ok(uuid::Uuid::new_v4().to_string())
+    }
+}
+
+/// Jobs that run side by side, with callbacks enqueued once all of them
+/// completed or the first of them failed:
+///
+/// ```rust,ignore
+/// let batch_id = Batch::new()
+///     .job::<ResizeWorker, _>(ResizeArgs { image_id: 1 })
+///     .job::<ResizeWorker, _>(ResizeArgs { image_id: 2 })
+///     .on_complete::<NotifyWorker, _>(NotifyArgs { album_id: 7 })
+///     .on_failure::<AlertWorker, _>(AlertArgs { album_id: 7 })
+///     .enqueue(&ctx)
+///     .await?;
+/// ```
+///
+/// A member can also be a whole [`Chain`], which counts as completed once its
+/// last step is.
+#[derive(Default)]
+pub struct Batch {
+    members: Vec<Chain>,
+    on_complete: Option<Chain>,
+    on_failure: Option<Chain>,
+}
+
+impl Batch {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a job of worker `W` performing `args`.
+    #[must_use]
+    pub fn job<W, A>(self, args: A) -> Self
+    where
+        A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+        W: BackgroundWorker<A> + 'static,
+    {
+        self.chain(Chain::new::<W, A>(args))
+    }
+
+    /// Adds a chain as a single member of the batch.
+    #[must_use]
+    pub fn chain(mut self, chain: Chain) -> Self {
+        self.members.push(chain);
+        self
+    }
+
+    /// Sets the job enqueued once every member completed.
+    #[must_use]
+    pub fn on_complete<W, A>(mut self, args: A) -> Self
+    where
+        A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+        W: BackgroundWorker<A> + 'static,
+    {
+        self.on_complete = Some(Chain::new::<W, A>(args));
+        self
+    }
+
+    /// Sets the job enqueued when the first member fails (after its
+    /// retries). Members that are still running are not cancelled.
+    #[must_use]
+    pub fn on_failure<W, A>(mut self, args: A) -> Self
+    where
+        A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+        W: BackgroundWorker<A> + 'static,
+    {
+        self.on_failure = Some(Chain::new::<W, A>(args));
+        self
+    }
+
+    fn into_spec(self) -> Result<BatchSpec> {
+        Ok(BatchSpec {
+            jobs: self
+                .members
+                .into_iter()
+                .map(Chain::into_spec)
+                .collect::<Result<_>>()?,
+            on_complete: self.on_complete.map(Chain::into_spec).transpose()?,
+            on_failure: self.on_failure.map(Chain::into_spec).transpose()?,
+        })
+    }
+
+    /// Runs the batch in this process, one member after another.
+    async fn perform(self, ctx: &AppContext) -> Result<()> {
+        let mut failed = false;
+        for member in self.members {
+            if let Err(err) = member.perform(ctx).await {
+                tracing::error!(err = err.to_string(), "batch member failed to perform");
+                failed = true;
+            }
+        }
+        let callback = if failed {
+            self.on_failure
+        } else {
+            self.on_complete
+        };
+        if let Some(callback) = callback {
+            callback.perform(ctx).await?;
+        }
+        Ok(())
+    }
+
+    /// Enqueues (or runs) the batch according to the configured
+    /// [`WorkerMode`] and returns its id.
+    ///
+    /// # Errors
+    /// Fails if the batch has no members, args cannot be serialized, or the
+    /// queue provider fails to enqueue the batch. In `ForegroundBlocking`
+    /// mode, fails with the error of a failing callback; a failing member
+    /// only decides which callback runs.
+    pub async fn enqueue(self, ctx: &AppContext) -> Result<String> {
+        if self.members.is_empty() {
+            return Err(Error::string("batch has no jobs"));
+        }
+        match &ctx.config.workers.mode {
+            WorkerMode::BackgroundQueue => {
+                if let Some(p) = &ctx.queue_provider {
+                    let id = p.enqueue_batch(self.into_spec()?).await?;
+                    return Ok(id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()));
+                }
+                tracing::error!(
+                    "batch: background queue is selected, but queue was not populated in context"
+                );
+            }
+            WorkerMode::ForegroundBlocking => {
+                self.perform(ctx).await?;
+            }
+            WorkerMode::BackgroundAsync => {
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = self.perform(&ctx).await {
+                        tracing::error!(err = err.to_string(), "batch failed to perform");
+                    }
+                });
+            }
+        }
+        Ok(uuid::Uuid::new_v4().to_string())
+    }
+}
+
+/// Keeps the result a job performed in this process reports.
+#[derive(Default)]
+struct InlineReporter {
+    result: Mutex<Option<JsonValue>>,
+}
+
+#[async_trait]
+impl JobReporter for InlineReporter {
+    async fn set_progress(&self, _id: &JobId, _percent: u8) -> Result<()> {
+        Ok(())
+    }
+
+    async fn set_result(&self, _id: &JobId, result: JsonValue) -> Result<()> {
+        *self
+            .result
+            .lock()
+            .map_err(|_| Error::string("job result lock poisoned"))? = Some(result);
+        Ok(())
+    }
+}
+
+/// Performs one job in this process and returns the result it reported.
+async fn perform_inline(
+    ctx: &AppContext,
+    build: BuildHandler,
+    args: JsonValue,
+) -> Result<Option<JsonValue>> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let reporter = Arc::new(InlineReporter::default());
+    super::scope_job(id.clone(), reporter.clone(), build(ctx)(id, args)).await?;
+    let result = reporter
+        .result
+        .lock()
+        .map_err(|_| Error::string("job result lock poisoned"))?
+        .take();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    use super::*;
+    use crate::tests_cfg;
+
+    static LAST_TOTAL: AtomicI64 = AtomicI64::new(0);
+
+    struct AddWorker;
+    #[async_trait]
+    impl BackgroundWorker<i64> for AddWorker {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+        async fn perform(&self, args: i64) -> Result<()> {
+            super::super::report_result(&(args + 1)).await
+        }
+    }
+
+    struct RecordWorker;
+    #[async_trait]
+    impl BackgroundWorker<i64> for RecordWorker {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+        async fn perform(&self, args: i64) -> Result<()> {
+            LAST_TOTAL.store(args, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn continue_chain_hands_output_to_next_step() {
+        let (step, _) = step::<AddWorker, i64>();
+        let chain = vec![step.clone(), step];
+
+        let next =
+            JobSpec::continue_chain(&chain, Some(serde_json::json!(3))).expect("chain goes on");
+        assert_eq!(next.class, "AddWorker");
+        assert_eq!(next.args, serde_json::json!(3));
+        assert_eq!(next.chain.len(), 1);
+
+        assert!(JobSpec::continue_chain(&[], None).is_none());
+    }
+
+    #[tokio::test]
+    async fn chain_runs_inline_in_foreground_mode() {
+        let ctx = tests_cfg::app::get_app_context().await;
+
+        Chain::new::<AddWorker, _>(1)
+            .then::<AddWorker, _>()
+            .then::<RecordWorker, _>()
+            .enqueue(&ctx)
+            .await
+            .expect("perform chain");
+
+        assert_eq!(LAST_TOTAL.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,589 @@
+//! A jobs dashboard: routes that show the configured queue as HTML or JSON,
+//! and retry, cancel or purge its jobs, so operators don't need shell access
+//! to run `cargo loco jobs`.
+//!
+//! Every route goes through a [`Guard`], which decides who may use it:
+//!
+//! ```rust
+//! use loco_rs::{bgworker::dashboard, controller::AppRoutes, prelude::*};
+//!
+//! fn routes() -> AppRoutes {
+//!     AppRoutes::with_default_routes()
+//!         .add_route(dashboard::routes(dashboard::BasicAuth::new("admin", "s3cret")))
+//! }
+//! ```
+//!
+//! The routes live under `/_jobs` (change it with [`Routes::prefix`]):
+//!
+//! | Route | |
+//! |---|---|
+//! | `GET /_jobs?status=failed&tag=email` | Job counts per status, and the newest jobs matching the filters |
+//! | `GET /_jobs/{id}` | One job, with its payload, error and attempts |
+//! | `POST /_jobs/{id}/retry` | Retries a failed job |
+//! | `POST /_jobs/retry` | Retries every failed job |
+//! | `POST /_jobs/cancel?name=SomeWorker` | Cancels the jobs of a worker |
+//! | `POST /_jobs/purge?status=completed` | Deletes the jobs with a status |
+//!
+//! They answer in JSON when the request asks for it (see
+//! [`crate::controller::middleware::format::RespondTo`]), and in HTML
+//! otherwise, where the actions redirect back to the list.
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, MatchedPath, Path, Query, State},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::{Job, JobFilter, JobStatus, Queue};
+use crate::{
+    app::AppContext,
+    controller::{format, middleware::format::RespondTo, Routes},
+    Error, Result,
+};
+
+/// Where [`routes`] are mounted unless told otherwise.
+pub const DEFAULT_PREFIX: &str = "_jobs";
+
+/// How many jobs the list shows unless the `limit` parameter says otherwise.
+const DEFAULT_LIMIT: usize = 100;
+
+const TEMPLATE_NAME: &str = "dashboard.html";
+
+/// Decides whether a request may use the dashboard.
+///
+/// Implemented for [`BasicAuth`], and for closures taking the
+/// [`AppContext`] and the request [`Parts`] and returning whether to let the
+/// request through. Implement it to reuse the app's own authentication, for
+/// example with [`crate::controller::extractor::auth::JWT`]:
+///
+/// ```rust
+/// use axum::{extract::FromRequestParts, http::request::Parts};
+/// use loco_rs::{bgworker::dashboard::Guard, controller::extractor::auth, prelude::*};
+///
+/// struct Admins;
+///
+/// #[async_trait]
+/// impl Guard for Admins {
+///     async fn authorize(&self, ctx: &AppContext, parts: &mut Parts) -> Result<(), Response> {
+///         let jwt = auth::JWT::from_request_parts(parts, ctx)
+///             .await
+///             .map_err(IntoResponse::into_response)?;
+///         if jwt.claims.pid == "admin" {
+///             Ok(())
+///         } else {
+///             Err(Error::Unauthorized("not an admin".to_string()).into_response())
+///         }
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Guard: Send + Sync + 'static {
+    /// Lets the request through with `Ok`, or answers it with the `Err`
+    /// response instead.
+    async fn authorize(
+        &self,
+        ctx: &AppContext,
+        parts: &mut Parts,
+    ) -> std::result::Result<(), Response>;
+}
+
+#[async_trait]
+impl<F> Guard for F
+where
+    F: Fn(&AppContext, &Parts) -> bool + Send + Sync + 'static,
+{
+    async fn authorize(
+        &self,
+        ctx: &AppContext,
+        parts: &mut Parts,
+    ) -> std::result::Result<(), Response> {
+        if self(ctx, parts) {
+            Ok(())
+        } else {
+            Err(
+                Error::Unauthorized("jobs dashboard: request denied by guard".to_string())
+                    .into_response(),
+            )
+        }
+    }
+}
+
+/// A [`Guard`] asking for a username and password with HTTP basic
+/// authentication, which browsers prompt for.
+#[derive(Clone, Debug)]
+pub struct BasicAuth {
+    username: String,
+    password: String,
+}
+
+impl BasicAuth {
+    #[must_use]
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    fn matches(&self, parts: &Parts) -> bool {
+        let Some(credentials) = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(encoded.trim())
+                    .ok()
+            })
+        else {
+            return false;
+        };
+        let expected = format!("{}:{}", self.username, self.password);
+        constant_time_eq(&credentials, expected.as_bytes())
+    }
+}
+
+#[async_trait]
+impl Guard for BasicAuth {
+    async fn authorize(
+        &self,
+        _ctx: &AppContext,
+        parts: &mut Parts,
+    ) -> std::result::Result<(), Response> {
+        if self.matches(parts) {
+            return Ok(());
+        }
+        tracing::warn!("jobs dashboard: missing or wrong basic auth credentials");
+        Err((
+            StatusCode::UNAUTHORIZED,
+            [(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"jobs\""),
+            )],
+        )
+            .into_response())
+    }
+}
+
+/// Compares `a` and `b` in a time that only depends on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Extractor running the [`Guard`] that [`routes`] installed.
+struct Authorized;
+
+impl FromRequestParts<AppContext> for Authorized {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        ctx: &AppContext,
+    ) -> std::result::Result<Self, Response> {
+        let Some(guard) = parts.extensions.get::<Arc<dyn Guard>>().cloned() else {
+            return Err(Error::InternalServerError.into_response());
+        };
+        guard.authorize(ctx, parts).await?;
+        Ok(Self)
+    }
+}
+
+/// Extractor refusing the dashboard's actions to cross-site requests, which
+/// a browser holding basic auth credentials would otherwise send along.
+struct SameSite;
+
+impl<S: Send + Sync> FromRequestParts<S> for SameSite {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> std::result::Result<Self, Response> {
+        let cross_site = parts
+            .headers
+            .get("sec-fetch-site")
+            .is_some_and(|site| site == "cross-site");
+        if cross_site {
+            return Err(StatusCode::FORBIDDEN.into_response());
+        }
+        Ok(Self)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ListParams {
+    /// Only jobs with this status.
+    pub status: Option<String>,
+    /// Only jobs carrying this tag.
+    pub tag: Option<String>,
+    /// How many jobs to show at most, newest first.
+    pub limit: Option<usize>,
+}
+
+impl ListParams {
+    /// The status filter, if any; the HTML form sends an empty one for "any".
+    fn status(&self) -> Result<Option<JobStatus>> {
+        match self.status.as_deref() {
+            None | Some("") => Ok(None),
+            Some(status) => status.parse().map(Some).map_err(Error::BadRequest),
+        }
+    }
+
+    fn tag(&self) -> Option<&str> {
+        self.tag.as_deref().filter(|tag| !tag.is_empty())
+    }
+}
+
+/// What the list route answers with.
+#[derive(Debug, Serialize)]
+pub struct Overview {
+    /// How many jobs the queue holds per status.
+    pub counts: BTreeMap<String, u64>,
+    /// The newest jobs matching the filters.
+    pub jobs: Vec<Job>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelParams {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PurgeParams {
+    pub status: JobStatus,
+}
+
+/// What the action routes answer with in JSON.
+#[derive(Debug, Serialize)]
+pub struct ActionResult {
+    /// How many jobs were retried, when the action says.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+}
+
+fn queue(ctx: &AppContext) -> Result<&Queue> {
+    ctx.queue_provider
+        .as_deref()
+        .ok_or(Error::QueueProviderMissing)
+}
+
+/// The path the dashboard is mounted at, from the route that matched and
+/// the part of it below the mount point.
+fn base(path: &MatchedPath, suffix: &str) -> String {
+    let base = path.as_str().strip_suffix(suffix).unwrap_or(path.as_str());
+    base.trim_end_matches('/').to_string()
+}
+
+fn render(data: &serde_json::Value) -> Result<Response> {
+    let mut tera = tera::Tera::default();
+    tera.add_raw_template(TEMPLATE_NAME, include_str!("dashboard.html"))?;
+    let html = tera.render(TEMPLATE_NAME, &tera::Context::from_serialize(data)?)?;
+    format::html(&html)
+}
+
+/// Answers an action with JSON, or by going back to the list.
+fn done(respond_to: &RespondTo, base: &str, count: Option<u64>) -> Result<Response> {
+    match respond_to {
+        RespondTo::Json => format::json(ActionResult { count }),
+        _ => format::render()
+            .status(StatusCode::SEE_OTHER)
+            .redirect(if base.is_empty() { "/" } else { base }),
+    }
+}
+
+async fn list(
+    _: Authorized,
+    State(ctx): State<AppContext>,
+    path: MatchedPath,
+    respond_to: RespondTo,
+    Query(params): Query<ListParams>,
+) -> Result<Response> {
+    let queue = queue(&ctx)?;
+    let status = params.status()?;
+    let jobs = queue
+        .list_jobs(&JobFilter {
+            statuses: status.clone().into_iter().collect(),
+            tag: params.tag().map(ToString::to_string),
+            limit: Some(params.limit.unwrap_or(DEFAULT_LIMIT)),
+            ..JobFilter::default()
+        })
+        .await?;
+
+    let by_status = queue.count_by_status().await?;
+    let counts = [
+        JobStatus::Queued,
+        JobStatus::Processing,
+        JobStatus::Completed,
+        JobStatus::Failed,
+        JobStatus::Cancelled,
+    ]
+    .into_iter()
+    .map(|status| {
+        let count = by_status.get(&status).copied().unwrap_or_default();
+        (status.to_string(), count)
+    })
+    .collect();
+
+    let overview = Overview { counts, jobs };
+    match respond_to {
+        RespondTo::Json => format::json(overview),
+        _ => render(&serde_json::json!({
+            "queue": queue.describe(),
+            "base": base(&path, "/"),
+            "filter": { "status": status.map(|s| s.to_string()), "tag": params.tag() },
+            "counts": overview.counts,
+            "jobs": overview.jobs,
+        })),
+    }
+}
+
+async fn show(
+    _: Authorized,
+    State(ctx): State<AppContext>,
+    path: MatchedPath,
+    respond_to: RespondTo,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let queue = queue(&ctx)?;
+    let job = queue.get_job(&id).await?.ok_or(Error::NotFound)?;
+    match respond_to {
+        RespondTo::Json => format::json(job),
+        _ => render(&serde_json::json!({
+            "queue": queue.describe(),
+            "base": base(&path, "/{id}"),
+            "payload": serde_json::to_string_pretty(&job.data)?,
+            "result": job.result.as_ref().map(serde_json::to_string_pretty).transpose()?,
+            "job": job,
+        })),
+    }
+}
+
+async fn retry(
+    _: Authorized,
+    _: SameSite,
+    State(ctx): State<AppContext>,
+    path: MatchedPath,
+    respond_to: RespondTo,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let count = queue(&ctx)?.retry_failed(Some(&id)).await?;
+    done(&respond_to, &base(&path, "/{id}/retry"), Some(count))
+}
+
+async fn retry_all(
+    _: Authorized,
+    _: SameSite,
+    State(ctx): State<AppContext>,
+    path: MatchedPath,
+    respond_to: RespondTo,
+) -> Result<Response> {
+    let count = queue(&ctx)?.retry_failed(None).await?;
+    done(&respond_to, &base(&path, "/retry"), Some(count))
+}
+
+async fn cancel(
+    _: Authorized,
+    _: SameSite,
+    State(ctx): State<AppContext>,
+    path: MatchedPath,
+    respond_to: RespondTo,
+    Query(params): Query<CancelParams>,
+) -> Result<Response> {
+    queue(&ctx)?.cancel_jobs(&params.name).await?;
+    done(&respond_to, &base(&path, "/cancel"), None)
+}
+
+async fn purge(
+    _: Authorized,
+    _: SameSite,
+    State(ctx): State<AppContext>,
+    path: MatchedPath,
+    respond_to: RespondTo,
+    Query(params): Query<PurgeParams>,
+) -> Result<Response> {
+    queue(&ctx)?.clear_by_status(vec![params.status]).await?;
+    done(&respond_to, &base(&path, "/purge"), None)
+}
+
+/// The dashboard routes, under [`DEFAULT_PREFIX`] and behind `guard`. See
+/// the [module docs](self).
+#[must_use]
+pub fn routes<G: Guard>(guard: G) -> Routes {
+    let guard: Arc<dyn Guard> = Arc::new(guard);
+    Routes::at(DEFAULT_PREFIX)
+        .get("/", list)
+        .post("/retry", retry_all)
+        .post("/cancel", cancel)
+        .post("/purge", purge)
+        .get("/{id}", show)
+        .post("/{id}/retry", retry)
+        .layer(Extension(guard))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        bgworker::{inmem, BackgroundWorker, EnqueueOptions},
+        controller::AppRoutes,
+        tests_cfg,
+    };
+
+    struct Noop;
+
+    #[async_trait]
+    impl BackgroundWorker<serde_json::Value> for Noop {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+
+        async fn perform(&self, _args: serde_json::Value) -> Result<()> {
+            Err(Error::string("<boom>"))
+        }
+    }
+
+    async fn dashboard(guard: impl Guard) -> (axum::Router, Arc<Queue>) {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        let queue = Arc::new(inmem::create_provider());
+        queue.register(Noop).await.unwrap();
+        ctx.queue_provider = Some(queue.clone());
+        let mut router = axum::Router::new();
+        for route in AppRoutes::empty().add_route(routes(guard)).collect() {
+            router = router.route(&route.uri, route.method);
+        }
+        (router.with_state(ctx), queue)
+    }
+
+    async fn enqueue(queue: &Queue, tags: Option<Vec<String>>) -> String {
+        queue
+            .enqueue(
+                "Noop".to_string(),
+                serde_json::json!({ "user": "<script>" }),
+                EnqueueOptions {
+                    tags,
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn call(router: &axum::Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn guard_decides_who_gets_in() {
+        let (router, _) = dashboard(BasicAuth::new("admin", "secret")).await;
+
+        let (status, _) = call(&router, get("/_jobs")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let wrong = Request::get("/_jobs")
+            .header(header::AUTHORIZATION, "Basic YWRtaW46bm9wZQ==")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(&router, wrong).await.0, StatusCode::UNAUTHORIZED);
+
+        let right = Request::get("/_jobs")
+            .header(header::AUTHORIZATION, "Basic YWRtaW46c2VjcmV0")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(&router, right).await.0, StatusCode::OK);
+
+        let (router, _) = dashboard(|_: &AppContext, _: &Parts| false).await;
+        assert_eq!(
+            call(&router, get("/_jobs")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_and_shows_jobs_as_json_and_html() {
+        let (router, queue) = dashboard(|_: &AppContext, _: &Parts| true).await;
+        let tagged = enqueue(&queue, Some(vec!["email".to_string()])).await;
+        enqueue(&queue, None).await;
+
+        let request = Request::get("/_jobs?tag=email")
+            .header(header::ACCEPT, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = call(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let overview: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(overview["counts"]["queued"], 2);
+        assert_eq!(overview["counts"]["failed"], 0);
+        assert_eq!(overview["jobs"].as_array().unwrap().len(), 1);
+        assert_eq!(overview["jobs"][0]["id"], tagged.as_str());
+
+        let (status, html) = call(&router, get("/_jobs")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains(&format!("href=\"/_jobs/{tagged}\"")));
+
+        let (status, html) = call(&router, get(&format!("/_jobs/{tagged}"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("<script>"));
+
+        assert_eq!(
+            call(&router, get("/_jobs/nope")).await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn actions_retry_cancel_and_purge_jobs() {
+        let (router, queue) = dashboard(|_: &AppContext, _: &Parts| true).await;
+        let failed = enqueue(&queue, None).await;
+        queue.drain().await.unwrap();
+        let queued = enqueue(&queue, None).await;
+
+        let request = Request::post(format!("/_jobs/{failed}/retry"))
+            .header(header::ACCEPT, "application/json")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = call(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"count":1}"#);
+
+        let request = Request::post("/_jobs/cancel?name=Noop")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/_jobs");
+        let job = queue.get_job(&queued).await.unwrap().unwrap();
+        assert_eq!(job.status, JobStatus::Cancelled);
+
+        let request = Request::post("/_jobs/purge?status=cancelled")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(&router, request).await.0, StatusCode::SEE_OTHER);
+        assert!(queue.get_jobs(None, None).await.unwrap().is_empty());
+
+        let request = Request::post("/_jobs/retry")
+            .header("sec-fetch-site", "cross-site")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(&router, request).await.0, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,661 @@
+//! An in-memory queue provider for tests, selected with `kind: InMem` in the
+//! queue config when the `testing` feature is on.
+//!
+//! Jobs enqueued in `BackgroundQueue` mode are kept in memory, where a test
+//! can inspect them (see [`crate::testing::bgworker`]) and run them on
+//! demand with [`Queue::drain`], instead of choosing between
+//! `ForegroundBlocking` mode, which runs every job on the spot, and a real
+//! Redis or Postgres instance.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value as JsonValue;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use super::{
+    EnqueueOptions, Job, JobAttempt, JobFilter, JobHandler, JobId, JobReporter, JobStatus, Queue,
+    QueueProvider, UniqueWindow, WorkerOpts,
+};
+use crate::{Error, Result};
+
+/// How often a running job checks whether it was cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// On shutdown, how long a running job gets to finish before it is
+/// interrupted and requeued.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The jobs the queue holds, in the order they were enqueued.
+#[derive(Default)]
+struct Store {
+    jobs: Vec<Job>,
+    cancel_requested: HashSet<JobId>,
+}
+
+impl Store {
+    fn job_mut(&mut self, id: &str) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    /// Claims the next due job, for a worker with `tags` (or for any job
+    /// without them), highest priority first.
+    fn dequeue(&mut self, tags: Option<&[String]>) -> Option<Job> {
+        let now = Utc::now();
+        let job = self
+            .jobs
+            .iter_mut()
+            .filter(|job| job.status == JobStatus::Queued && job.run_at <= now)
+            .filter(|job| {
+                tags.is_none_or(|tags| match &job.tags {
+                    None => tags.is_empty(),
+                    Some(job_tags) => job_tags.iter().any(|tag| tags.contains(tag)),
+                })
+            })
+            .min_by(|a, b| b.priority.cmp(&a.priority).then(a.run_at.cmp(&b.run_at)))?;
+        if job.attempts == 0 {
+            job.history.clear();
+        }
+        job.status = JobStatus::Processing;
+        job.attempts += 1;
+        job.progress = None;
+        job.result = None;
+        job.updated_at = Some(now);
+        let job = job.clone();
+        self.cancel_requested.remove(&job.id);
+        Some(job)
+    }
+}
+
+/// Whether `job` still blocks duplicates carrying its unique key.
+fn in_unique_window(job: &Job, window: &UniqueWindow, now: DateTime<Utc>) -> bool {
+    match window {
+        UniqueWindow::Queued => job.status == JobStatus::Queued,
+        UniqueWindow::QueuedOrProcessing => {
+            matches!(job.status, JobStatus::Queued | JobStatus::Processing)
+        }
+        UniqueWindow::For(window) => job.created_at.is_some_and(|created_at| {
+            chrono::Duration::from_std(*window).is_ok_and(|window| created_at + window > now)
+        }),
+    }
+}
+
+/// [`JobReporter`] writing into the queue's [`Store`].
+struct StoreReporter {
+    store: Arc<Mutex<Store>>,
+}
+
+impl StoreReporter {
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl JobReporter for StoreReporter {
+    async fn set_progress(&self, id: &JobId, percent: u8) -> Result<()> {
+        if let Some(job) = self.store().job_mut(id) {
+            job.progress = Some(percent);
+            job.updated_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn set_result(&self, id: &JobId, result: JsonValue) -> Result<()> {
+        if let Some(job) = self.store().job_mut(id) {
+            job.result = Some(result);
+            job.updated_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn cancel_requested(&self, id: &JobId) -> Result<bool> {
+        Ok(self.store().cancel_requested.contains(id))
+    }
+}
+
+/// A queue provider keeping its jobs in memory. See the [module
+/// docs](self).
+///
+/// It honors priorities, `run_at`, retries, unique jobs and cancellation
+/// like the other providers, but not concurrency caps or rate limits, nor
+/// chains and batches. Jobs are lost when it is dropped.
+pub struct InMemQueue {
+    store: Arc<Mutex<Store>>,
+    handlers: RwLock<HashMap<String, (Arc<JobHandler>, WorkerOpts)>>,
+    enqueued: Notify,
+    token: CancellationToken,
+}
+
+impl InMemQueue {
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn handler(&self, name: &str) -> Option<(Arc<JobHandler>, WorkerOpts)> {
+        self.handlers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+    }
+
+    /// Performs `job`, which was just dequeued, and records how it went.
+    async fn perform(&self, job: Job) {
+        let Some((handler, opts)) = self.handler(&job.name) else {
+            error!(job_name = %job.name, "No handler registered for job");
+            let err = Error::string(&format!("no handler registered for job {}", job.name));
+            self.finish(&job.id, JobStatus::Failed, Some(&err));
+            return;
+        };
+        debug!(job_id = %job.id, job_name = %job.name, "Processing job");
+        let reporter: Arc<dyn JobReporter> = Arc::new(StoreReporter {
+            store: self.store.clone(),
+        });
+        let started_at = Utc::now();
+        let drain = super::Drain {
+            token: self.token.clone(),
+            timeout: SHUTDOWN_TIMEOUT,
+        };
+        let super::JobRun {
+            result,
+            cancelled,
+            interrupted,
+        } = super::run_job(
+            job.id.clone(),
+            reporter,
+            Some(&opts),
+            CANCEL_POLL_INTERVAL,
+            &drain,
+            super::call_handler(&handler, Some(&opts), &job),
+        )
+        .await;
+        if interrupted {
+            if let Some(stored) = self.store().job_mut(&job.id) {
+                debug!(job_id = %job.id, "Requeueing job interrupted by shutdown");
+                stored.status = JobStatus::Queued;
+                stored.attempts = (stored.attempts - 1).max(0);
+                stored.updated_at = Some(Utc::now());
+            }
+            return;
+        }
+        let attempt = JobAttempt::new(job.attempts, started_at, &result);
+        if let Some(stored) = self.store().job_mut(&job.id) {
+            stored.history.push(attempt);
+        }
+        match result {
+            Ok(()) => match job.interval {
+                Some(interval_ms) => self.reschedule(
+                    &job.id,
+                    Utc::now() + chrono::Duration::milliseconds(interval_ms),
+                    None,
+                    true,
+                ),
+                None => self.finish(&job.id, JobStatus::Completed, None),
+            },
+            Err(err) if cancelled => self.finish(&job.id, JobStatus::Cancelled, Some(&err)),
+            Err(err) => match opts.next_run_at(job.attempts) {
+                Some(run_at) => self.reschedule(&job.id, run_at, Some(&err), false),
+                None => self.finish(&job.id, JobStatus::Failed, Some(&err)),
+            },
+        }
+    }
+
+    fn finish(&self, id: &JobId, status: JobStatus, error: Option<&Error>) {
+        debug!(job_id = %id, status = %status, error = ?error.map(ToString::to_string), "Finishing job");
+        if let Some(job) = self.store().job_mut(id) {
+            job.status = status;
+            job.updated_at = Some(Utc::now());
+            if let Some(error) = error {
+                job.last_error = Some(super::error_message(error));
+            }
+        }
+    }
+
+    fn reschedule(&self, id: &JobId, run_at: DateTime<Utc>, error: Option<&Error>, reset: bool) {
+        debug!(job_id = %id, run_at = %run_at, "Rescheduling job");
+        if let Some(job) = self.store().job_mut(id) {
+            job.status = JobStatus::Queued;
+            job.run_at = run_at;
+            job.updated_at = Some(Utc::now());
+            if let Some(error) = error {
+                job.last_error = Some(super::error_message(error));
+            }
+            if reset {
+                job.attempts = 0;
+            }
+        }
+    }
+
+    fn retain(&self, keep: impl Fn(&Job) -> bool) {
+        self.store().jobs.retain(keep);
+    }
+}
+
+impl Default for InMemQueue {
+    fn default() -> Self {
+        Self {
+            store: Arc::default(),
+            handlers: RwLock::default(),
+            enqueued: Notify::new(),
+            token: CancellationToken::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl QueueProvider for InMemQueue {
+    async fn enqueue(
+        &self,
+        class: String,
+        args: JsonValue,
+        options: EnqueueOptions,
+    ) -> Result<Option<String>> {
+        let EnqueueOptions {
+            queue,
+            tags,
+            priority,
+            run_at,
+            unique,
+            args_version,
+        } = options;
+        let now = Utc::now();
+        let mut store = self.store();
+        if let Some(unique) = &unique
+            && let Some(job) = store.jobs.iter().find(|job| {
+                job.unique_key.as_ref() == Some(&unique.key)
+                    && in_unique_window(job, &unique.window, now)
+            })
+        {
+            debug!(job_id = %job.id, unique_key = %unique.key, "Unique job already enqueued");
+            return Ok(Some(job.id.clone()));
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        debug!(job_id = %id, job_name = %class, run_at = ?run_at, tags = ?tags, priority = ?priority, "Enqueueing job");
+        store.jobs.push(Job {
+            id: id.clone(),
+            name: class,
+            data: args,
+            status: JobStatus::Queued,
+            run_at: run_at.unwrap_or(now),
+            interval: None,
+            created_at: Some(now),
+            updated_at: Some(now),
+            tags: tags.filter(|tags| !tags.is_empty()),
+            priority: priority.unwrap_or(0),
+            attempts: 0,
+            last_error: None,
+            history: Vec::new(),
+            unique_key: unique.map(|unique| unique.key),
+            progress: None,
+            result: None,
+            batch_id: None,
+            chain: Vec::new(),
+            queue,
+            args_version,
+        });
+        drop(store);
+        self.enqueued.notify_waiters();
+        Ok(Some(id))
+    }
+
+    async fn register_handler(
+        &self,
+        name: String,
+        handler: JobHandler,
+        opts: WorkerOpts,
+    ) -> Result<()> {
+        self.handlers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name, (Arc::new(handler), opts));
+        Ok(())
+    }
+
+    async fn run(&self, tags: Vec<String>) -> Result<()> {
+        loop {
+            if self.token.is_cancelled() {
+                return Ok(());
+            }
+            let enqueued = self.enqueued.notified();
+            let job = self.store().dequeue(Some(&tags));
+            if let Some(job) = job {
+                self.perform(job).await;
+                continue;
+            }
+            tokio::select! {
+                biased;
+                () = self.token.cancelled() => return Ok(()),
+                () = enqueued => {}
+                () = tokio::time::sleep(Duration::from_secs(1)) => {}
+            }
+        }
+    }
+
+    async fn drain(&self) -> Result<usize> {
+        let mut performed = 0;
+        loop {
+            let job = self.store().dequeue(None);
+            let Some(job) = job else {
+                return Ok(performed);
+            };
+            self.perform(job).await;
+            performed += 1;
+        }
+    }
+
+    async fn setup(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.retain(|_| false);
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_jobs(
+        &self,
+        status: Option<&Vec<JobStatus>>,
+        age_days: Option<i64>,
+    ) -> Result<Vec<Job>> {
+        let cutoff = age_days.map(|age_days| Utc::now() - chrono::Duration::days(age_days));
+        Ok(self
+            .store()
+            .jobs
+            .iter()
+            .filter(|job| status.is_none_or(|status| status.contains(&job.status)))
+            .filter(|job| cutoff.is_none_or(|cutoff| job.created_at.is_some_and(|at| at <= cutoff)))
+            .cloned()
+            .collect())
+    }
+
+    async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        Ok(self.store().jobs.iter().find(|job| job.id == id).cloned())
+    }
+
+    async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<Job>> {
+        let mut jobs: Vec<Job> = self
+            .store()
+            .jobs
+            .iter()
+            .filter(|job| filter.matches(job))
+            .cloned()
+            .collect();
+        filter.apply_limit(&mut jobs);
+        Ok(jobs)
+    }
+
+    async fn cancel_jobs_by_name(&self, name: &str) -> Result<()> {
+        let now = Utc::now();
+        let mut store = self.store();
+        let mut running = Vec::new();
+        for job in store.jobs.iter_mut().filter(|job| job.name == name) {
+            match job.status {
+                JobStatus::Queued => {
+                    job.status = JobStatus::Cancelled;
+                    job.updated_at = Some(now);
+                }
+                JobStatus::Processing => running.push(job.id.clone()),
+                _ => {}
+            }
+        }
+        store.cancel_requested.extend(running);
+        Ok(())
+    }
+
+    async fn clear_by_status(&self, status: Vec<JobStatus>) -> Result<()> {
+        self.retain(|job| !status.contains(&job.status));
+        Ok(())
+    }
+
+    async fn clear_jobs_older_than(
+        &self,
+        age_days: i64,
+        status: Option<&Vec<JobStatus>>,
+    ) -> Result<()> {
+        let cutoff = Utc::now() - chrono::Duration::days(age_days);
+        self.retain(|job| {
+            let old = job.created_at.is_some_and(|at| at <= cutoff);
+            let matches =
+                status.is_none_or(|status| status.is_empty() || status.contains(&job.status));
+            !(old && matches)
+        });
+        Ok(())
+    }
+
+    async fn requeue(&self, age_minutes: &i64) -> Result<()> {
+        let now = Utc::now();
+        let cutoff = now - chrono::Duration::minutes(*age_minutes);
+        for job in &mut self.store().jobs {
+            if job.status == JobStatus::Processing && job.updated_at.is_some_and(|at| at <= cutoff)
+            {
+                job.status = JobStatus::Queued;
+                job.updated_at = Some(now);
+            }
+        }
+        Ok(())
+    }
+
+    async fn retry_failed(&self, id: Option<&str>) -> Result<u64> {
+        let now = Utc::now();
+        let mut retried = 0;
+        for job in &mut self.store().jobs {
+            if job.status == JobStatus::Failed && id.is_none_or(|id| job.id == id) {
+                job.status = JobStatus::Queued;
+                job.run_at = now;
+                job.updated_at = Some(now);
+                job.attempts = 0;
+                retried += 1;
+            }
+        }
+        Ok(retried)
+    }
+
+    fn describe(&self) -> String {
+        "in-memory queue".to_string()
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.token.cancel();
+        Ok(())
+    }
+}
+
+/// Create this provider
+#[must_use]
+pub fn create_provider() -> Queue {
+    Queue::from_provider(Arc::new(InMemQueue::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        app::AppContext,
+        bgworker::{report_result, BackgroundWorker, UniqueJob},
+    };
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct EchoArgs {
+        fail: bool,
+    }
+
+    struct EchoWorker;
+
+    #[async_trait]
+    impl BackgroundWorker<EchoArgs> for EchoWorker {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+
+        fn max_retries() -> u32 {
+            0
+        }
+
+        fn unique_key(args: &EchoArgs) -> Option<String> {
+            Some(args.fail.to_string())
+        }
+
+        async fn perform(&self, args: EchoArgs) -> Result<()> {
+            if args.fail {
+                return Err(Error::string("asked to fail"));
+            }
+            report_result(&"done").await
+        }
+    }
+
+    fn unique(fail: bool) -> Option<UniqueJob> {
+        Some(UniqueJob {
+            key: format!("EchoWorker:{fail}"),
+            window: UniqueWindow::Queued,
+        })
+    }
+
+    #[tokio::test]
+    async fn drain_performs_due_jobs_and_records_their_outcome() {
+        let queue = create_provider();
+        queue.register(EchoWorker).await.unwrap();
+
+        let ok = queue
+            .enqueue(
+                "EchoWorker".to_string(),
+                EchoArgs { fail: false },
+                EnqueueOptions {
+                    unique: unique(false),
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let duplicate = queue
+            .enqueue(
+                "EchoWorker".to_string(),
+                EchoArgs { fail: false },
+                EnqueueOptions {
+                    unique: unique(false),
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ok, duplicate);
+        let failing = queue
+            .enqueue(
+                "EchoWorker".to_string(),
+                EchoArgs { fail: true },
+                EnqueueOptions::default(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let later = queue
+            .enqueue(
+                "EchoWorker".to_string(),
+                EchoArgs { fail: false },
+                EnqueueOptions {
+                    run_at: Some(Utc::now() + chrono::Duration::hours(1)),
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(queue.drain().await.unwrap(), 2);
+
+        let ok = queue.get_job(&ok).await.unwrap().unwrap();
+        assert_eq!(ok.status, JobStatus::Completed);
+        assert_eq!(ok.result, Some(serde_json::json!("done")));
+        let failing = queue.get_job(&failing).await.unwrap().unwrap();
+        assert_eq!(failing.status, JobStatus::Failed);
+        assert_eq!(failing.last_error.as_deref(), Some("asked to fail"));
+        assert_eq!(failing.history.len(), 1);
+        let later = queue.get_job(&later).await.unwrap().unwrap();
+        assert_eq!(later.status, JobStatus::Queued);
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct GreetArgs {
+        names: Vec<String>,
+    }
+
+    struct GreetWorker;
+
+    #[async_trait]
+    impl BackgroundWorker<GreetArgs> for GreetWorker {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+
+        fn args_version() -> u32 {
+            2
+        }
+
+        fn upcasters() -> crate::bgworker::Upcasters {
+            crate::bgworker::Upcasters::new().add(1, |mut data| {
+                let name = data["name"].take();
+                Ok(serde_json::json!({ "names": [name] }))
+            })
+        }
+
+        async fn perform(&self, args: GreetArgs) -> Result<()> {
+            report_result(&args.names.join(",")).await
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_upcasts_args_enqueued_at_an_older_version() {
+        let queue = create_provider();
+        queue.register(GreetWorker).await.unwrap();
+
+        let mut ids = Vec::new();
+        for (args, version) in [
+            (serde_json::json!({"name": "ada"}), 1),
+            (serde_json::json!({"names": ["bob", "cy"]}), 2),
+            (serde_json::json!({"names": ["dee"]}), 3),
+        ] {
+            let id = queue
+                .enqueue(
+                    "GreetWorker".to_string(),
+                    args,
+                    EnqueueOptions {
+                        args_version: version,
+                        ..EnqueueOptions::default()
+                    },
+                )
+                .await
+                .unwrap()
+                .unwrap();
+            ids.push(id);
+        }
+
+        assert_eq!(queue.drain().await.unwrap(), 3);
+
+        let old = queue.get_job(&ids[0]).await.unwrap().unwrap();
+        assert_eq!(old.status, JobStatus::Completed);
+        assert_eq!(old.result, Some(serde_json::json!("ada")));
+        assert_eq!(old.data, serde_json::json!({"name": "ada"}));
+        assert_eq!(old.args_version, 1);
+        let current = queue.get_job(&ids[1]).await.unwrap().unwrap();
+        assert_eq!(current.result, Some(serde_json::json!("bob,cy")));
+        let newer = queue.get_job(&ids[2]).await.unwrap().unwrap();
+        assert_eq!(newer.status, JobStatus::Failed);
+        assert!(newer
+            .last_error
+            .is_some_and(|err| err.contains("newer than the worker's version 2")));
+    }
+}
@@ -0,0 +1,346 @@
+//! Job middleware: code that wraps every job a queue worker performs, for
+//! what would otherwise be repeated in each worker's `perform` — a tracing
+//! span with the job's id and class, tenant context, timing, error
+//! reporting.
+//!
+//! A [`JobMiddleware`] is added once with [`super::Queue::add_middleware`],
+//! typically in `connect_workers`, and applies to every worker registered on
+//! that queue, whatever the provider:
+//!
+//! ```rust
+//! use loco_rs::{
+//!     bgworker::middleware::{JobContext, JobMiddleware, Next},
+//!     prelude::*,
+//! };
+//! use tracing::Instrument;
+//!
+//! struct Span;
+//!
+//! #[async_trait]
+//! impl JobMiddleware for Span {
+//!     async fn around(&self, job: &JobContext, next: Next) -> Result<()> {
+//!         let span = tracing::info_span!("job", id = %job.id, class = %job.class);
+//!         next.run().instrument(span).await
+//!     }
+//!
+//!     async fn on_error(&self, job: &JobContext, error: &Error, elapsed: std::time::Duration) {
+//!         tracing::error!(id = %job.id, ?elapsed, %error, "job failed");
+//!     }
+//! }
+//!
+//! async fn connect_workers(ctx: &AppContext, queue: &Queue) -> Result<()> {
+//!     queue.add_middleware(Span);
+//!     // queue.register(..).await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Middleware only wraps jobs performed by a queue worker, in
+//! `BackgroundQueue` mode; `ForegroundBlocking` and `BackgroundAsync` call
+//! `perform` directly.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use super::{JobData, JobHandler, JobId};
+use crate::{Error, Result};
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// The job a middleware is wrapping.
+#[derive(Clone, Debug)]
+pub struct JobContext {
+    pub id: JobId,
+    /// The worker's [`super::BackgroundWorker::class_name`].
+    pub class: String,
+    /// The job's args, as stored in the queue.
+    pub data: JobData,
+}
+
+/// The rest of the chain: the middleware registered after this one, then
+/// the worker itself.
+pub struct Next(JobFuture);
+
+impl Next {
+    /// Runs the rest of the chain.
+    ///
+    /// # Errors
+    /// Returns the error of the worker, or of a middleware further down.
+    pub async fn run(self) -> Result<()> {
+        self.0.await
+    }
+}
+
+/// Hooks around every job a queue worker performs. All of them default to
+/// doing nothing.
+///
+/// For one job, the middleware added first runs outermost: its `before`
+/// runs first and its `after` or `on_error` last. A job whose `before`
+/// fails is not performed, and fails with that error (retries included).
+#[async_trait]
+pub trait JobMiddleware: Send + Sync + 'static {
+    /// Called before the job runs.
+    ///
+    /// # Errors
+    /// An error fails the job without running it.
+    async fn before(&self, _job: &JobContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// Wraps the rest of the chain, which it must [`Next::run`] for the job
+    /// to be performed. Use it to run the job inside a span, a task-local or
+    /// a timeout.
+    ///
+    /// # Errors
+    /// The job fails with the returned error.
+    async fn around(&self, _job: &JobContext, next: Next) -> Result<()> {
+        next.run().await
+    }
+
+    /// Called once the job succeeded, with how long `around` took.
+    async fn after(&self, _job: &JobContext, _elapsed: Duration) {}
+
+    /// Called once the job failed, with its error (a panic in the worker
+    /// comes as `worker panicked: ..`) and how long `around` took.
+    async fn on_error(&self, _job: &JobContext, _error: &Error, _elapsed: Duration) {}
+}
+
+/// The middleware added to a [`super::Queue`]. Shared with the handlers it
+/// wraps, which read it on every job, so middleware added after a worker
+/// was registered still applies to it.
+#[derive(Clone, Default)]
+pub(crate) struct Stack(Arc<RwLock<Vec<Arc<dyn JobMiddleware>>>>);
+
+impl Stack {
+    pub(crate) fn push(&self, middleware: Arc<dyn JobMiddleware>) {
+        self.0
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(middleware);
+    }
+
+    fn snapshot(&self) -> Vec<Arc<dyn JobMiddleware>> {
+        self.0
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Wraps the handler of worker `class` in the middleware.
+    pub(crate) fn wrap(&self, class: String, handler: JobHandler) -> JobHandler {
+        let stack = self.clone();
+        let handler = Arc::new(handler);
+        Box::new(move |id: JobId, data: JobData| {
+            let middleware = stack.snapshot();
+            if middleware.is_empty() {
+                return handler(id, data);
+            }
+            let job = Arc::new(JobContext {
+                id: id.clone(),
+                class: class.clone(),
+                data: data.clone(),
+            });
+            let mut fut = handler(id, data);
+            for middleware in middleware.into_iter().rev() {
+                fut = Box::pin(wrap_one(middleware, job.clone(), fut));
+            }
+            fut
+        })
+    }
+}
+
+async fn wrap_one(
+    middleware: Arc<dyn JobMiddleware>,
+    job: Arc<JobContext>,
+    next: JobFuture,
+) -> Result<()> {
+    let started = Instant::now();
+    let result = match middleware.before(&job).await {
+        Ok(()) => middleware.around(&job, Next(next)).await,
+        Err(err) => Err(err),
+    };
+    match &result {
+        Ok(()) => middleware.after(&job, started.elapsed()).await,
+        Err(err) => middleware.on_error(&job, err, started.elapsed()).await,
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        app::AppContext,
+        bgworker::{inmem, BackgroundWorker, EnqueueOptions},
+    };
+
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<String>>>);
+
+    impl Log {
+        fn push(&self, line: String) {
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn lines(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    struct Recorder {
+        name: &'static str,
+        log: Log,
+        fail_before: bool,
+    }
+
+    #[async_trait]
+    impl JobMiddleware for Recorder {
+        async fn before(&self, job: &JobContext) -> Result<()> {
+            self.log.push(format!("{} before {}", self.name, job.class));
+            if self.fail_before {
+                return Err(Error::string("refused"));
+            }
+            Ok(())
+        }
+
+        async fn around(&self, _job: &JobContext, next: Next) -> Result<()> {
+            self.log.push(format!("{} around", self.name));
+            next.run().await
+        }
+
+        async fn after(&self, _job: &JobContext, _elapsed: Duration) {
+            self.log.push(format!("{} after", self.name));
+        }
+
+        async fn on_error(&self, _job: &JobContext, error: &Error, _elapsed: Duration) {
+            self.log.push(format!("{} on_error {error}", self.name));
+        }
+    }
+
+    fn handler(log: &Log, fail: bool) -> JobHandler {
+        let log = log.clone();
+        Box::new(move |id, _data| {
+            let log = log.clone();
+            Box::pin(async move {
+                log.push(format!("perform {id}"));
+                if fail {
+                    Err(Error::string("boom"))
+                } else {
+                    Ok(())
+                }
+            })
+        })
+    }
+
+    fn recorder(name: &'static str, log: &Log) -> Arc<dyn JobMiddleware> {
+        Arc::new(Recorder {
+            name,
+            log: log.clone(),
+            fail_before: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn first_middleware_wraps_the_others() {
+        let log = Log::default();
+        let stack = Stack::default();
+        let wrapped = stack.wrap("Mailer".to_string(), handler(&log, false));
+        // added after the handler was wrapped, and still applied
+        stack.push(recorder("a", &log));
+        stack.push(recorder("b", &log));
+
+        wrapped("1".to_string(), serde_json::Value::Null)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            log.lines(),
+            [
+                "a before Mailer",
+                "a around",
+                "b before Mailer",
+                "b around",
+                "perform 1",
+                "b after",
+                "a after",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_reach_on_error_and_before_can_refuse_a_job() {
+        let log = Log::default();
+        let stack = Stack::default();
+        stack.push(recorder("a", &log));
+        let failing = stack.wrap("Mailer".to_string(), handler(&log, true));
+        let err = failing("1".to_string(), serde_json::Value::Null)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+        assert_eq!(
+            log.lines(),
+            [
+                "a before Mailer",
+                "a around",
+                "perform 1",
+                "a on_error boom"
+            ]
+        );
+
+        let log = Log::default();
+        let stack = Stack::default();
+        stack.push(Arc::new(Recorder {
+            name: "guard",
+            log: log.clone(),
+            fail_before: true,
+        }));
+        let refused = stack.wrap("Mailer".to_string(), handler(&log, false));
+        assert!(refused("2".to_string(), serde_json::Value::Null)
+            .await
+            .is_err());
+        assert_eq!(
+            log.lines(),
+            ["guard before Mailer", "guard on_error refused"]
+        );
+    }
+
+    struct Mailer;
+
+    #[async_trait]
+    impl BackgroundWorker<serde_json::Value> for Mailer {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+
+        async fn perform(&self, _args: serde_json::Value) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn wraps_the_workers_registered_on_a_queue() {
+        let log = Log::default();
+        let queue = inmem::create_provider();
+        queue.register(Mailer).await.unwrap();
+        queue.add_middleware(Recorder {
+            name: "a",
+            log: log.clone(),
+            fail_before: false,
+        });
+
+        queue
+            .enqueue("Mailer".to_string(), (), EnqueueOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(queue.drain().await.unwrap(), 1);
+
+        assert_eq!(log.lines(), ["a before Mailer", "a around", "a after"]);
+    }
+}
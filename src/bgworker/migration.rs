@@ -0,0 +1,183 @@
+//! The queue tables as a migration, for a Postgres or `SQLite` queue with
+//! `use_app_db: true`. Such a queue lives in the application's database, and
+//! creates or upgrades its tables on startup like any other queue; add this
+//! migration to the app's migrator, usually first, when the app's own
+//! migrations need the tables to exist, such as for a foreign key to them:
+//!
+//! ```rust,ignore
+//! fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+//!     vec![
+//!         Box::new(loco_rs::bgworker::migration::Migration),
+//!         Box::new(m20220101_000001_users::Migration),
+//!     ]
+//! }
+//! ```
+//!
+//! Its `up` runs the same setup as startup: it creates the tables, or adds
+//! the columns newer Loco versions need to existing ones, so it is safe on a
+//! database that already had a queue.
+use sea_orm::{ConnectionTrait, DbBackend, DbErr};
+use sea_orm_migration::{MigrationName, MigrationTrait, SchemaManager, SchemaManagerConnection};
+
+use super::{pg, sqlt};
+
+/// Creates and drops the queue tables. See the [module docs](self).
+#[derive(Debug)]
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m00000000_000000_loco_queue"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The queue's setup runs on the sqlx pool, outside a transaction.
+        let SchemaManagerConnection::Connection(db) = manager.get_connection() else {
+            return Err(DbErr::Migration(
+                "the loco queue migration cannot run in a transaction".to_string(),
+            ));
+        };
+        match manager.get_database_backend() {
+            DbBackend::Postgres => pg::initialize_database(db.get_postgres_connection_pool()).await,
+            DbBackend::Sqlite => sqlt::initialize_database(db.get_sqlite_connection_pool()).await,
+            backend => {
+                return Err(DbErr::Migration(format!(
+                    "the loco queue does not support {backend:?}"
+                )));
+            }
+        }
+        .map_err(|err| DbErr::Migration(err.to_string()))
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let tables = match manager.get_database_backend() {
+            DbBackend::Postgres => ["pg_loco_queue", "pg_loco_batches"],
+            DbBackend::Sqlite => ["sqlt_loco_queue", "sqlt_loco_batches"],
+            backend => {
+                return Err(DbErr::Migration(format!(
+                    "the loco queue does not support {backend:?}"
+                )));
+            }
+        };
+        for table in tables {
+            manager
+                .get_connection()
+                .execute_unprepared(&format!("DROP TABLE IF EXISTS {table}"))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn use_transaction(&self) -> Option<bool> {
+        Some(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm_migration::MigratorTrait;
+
+    use super::*;
+    use crate::{
+        bgworker::{
+            create_queue_provider, create_queue_provider_with_db, EnqueueOptions, JobStatus,
+        },
+        config::{self, QueueConfig, SqliteQueueConfig},
+        tests_cfg,
+    };
+
+    struct Migrator;
+
+    #[async_trait::async_trait]
+    impl MigratorTrait for Migrator {
+        fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+            vec![Box::new(Migration)]
+        }
+    }
+
+    fn app_db_config(root: &std::path::Path) -> config::Config {
+        let mut config = tests_cfg::config::test_config();
+        config.database.uri = format!("sqlite://{}?mode=rwc", root.join("app.sqlite").display());
+        config.workers.mode = config::WorkerMode::BackgroundQueue;
+        config.queue = Some(QueueConfig::Sqlite(
+            serde_yaml::from_str::<SqliteQueueConfig>("use_app_db: true").expect("queue config"),
+        ));
+        config
+    }
+
+    #[tokio::test]
+    async fn app_db_queue_shares_the_pool_and_migrates() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let config = app_db_config(tree_fs.root.as_path());
+        let db = crate::db::connect(&config.database).await.expect("connect");
+
+        Migrator::up(&db, None).await.expect("migrate up");
+
+        let queue = create_queue_provider_with_db(&config, &db)
+            .await
+            .expect("create provider")
+            .expect("a queue provider");
+        crate::bgworker::converge(&queue, config.queue.as_ref().unwrap())
+            .await
+            .expect("converge");
+        queue
+            .enqueue(
+                "MailerWorker".to_string(),
+                serde_json::json!({"user_id": 1}),
+                EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue");
+
+        let queued: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM sqlt_loco_queue WHERE status = ?")
+                .bind(JobStatus::Queued.to_string())
+                .fetch_one(db.get_sqlite_connection_pool())
+                .await
+                .expect("count jobs");
+        assert_eq!(queued, 1);
+
+        Migrator::down(&db, None).await.expect("migrate down");
+        assert!(sqlx::query("SELECT * FROM sqlt_loco_queue")
+            .fetch_all(db.get_sqlite_connection_pool())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn app_db_queue_sets_up_its_tables_on_startup() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let config = app_db_config(tree_fs.root.as_path());
+        let db = crate::db::connect(&config.database).await.expect("connect");
+
+        // Without the application's connection, the queue is refused rather
+        // than opening a second pool.
+        assert!(create_queue_provider(&config).await.is_err());
+
+        let queue = create_queue_provider_with_db(&config, &db)
+            .await
+            .expect("create provider")
+            .expect("a queue provider");
+        crate::bgworker::converge(&queue, config.queue.as_ref().unwrap())
+            .await
+            .expect("converge");
+        queue
+            .enqueue(
+                "MailerWorker".to_string(),
+                serde_json::json!({"user_id": 1}),
+                EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue");
+        assert_eq!(queue.get_jobs(None, None).await.expect("get jobs").len(), 1);
+    }
+}
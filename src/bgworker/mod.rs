@@ -1,8 +1,10 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io::Write,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -10,28 +12,43 @@ use chrono::{DateTime, Utc};
 #[cfg(feature = "cli")]
 use clap::ValueEnum;
 use futures_util::FutureExt;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use serde_variant::to_variant_name;
+use tokio_util::sync::CancellationToken;
+pub mod batch;
+pub mod dashboard;
+#[cfg(feature = "testing")]
+pub mod inmem;
+pub mod middleware;
+#[cfg(all(feature = "worker", feature = "with-db"))]
+pub mod migration;
 #[cfg(feature = "worker")]
 pub mod pg;
+pub mod recurring;
 #[cfg(feature = "worker_redis")]
 pub mod redis;
 #[cfg(feature = "worker")]
 pub(crate) mod sql;
 #[cfg(feature = "worker")]
 pub mod sqlt;
+pub mod stats;
+pub mod upcast;
 
+pub use self::batch::{Batch, Chain};
+use self::batch::{BatchSpec, ChainStep, JobBatch, JobSpec};
+pub use self::upcast::Upcasters;
 use crate::{
     app::AppContext,
     config::{
         self, Config, PostgresQueueConfig, QueueConfig, RedisQueueConfig, SqliteQueueConfig,
-        WorkerMode,
+        WorkerLimitsConfig, WorkerMode,
     },
     Error, Result,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "cli", derive(ValueEnum))]
 #[non_exhaustive]
 pub enum JobStatus {
@@ -87,6 +104,600 @@ pub struct Job {
     pub tags: Option<Vec<String>>,
     #[serde(default)]
     pub priority: i32,
+    /// How many times a worker has picked this job up, counting the run in
+    /// progress. Compared against [`BackgroundWorker::max_retries`] when a
+    /// run fails.
+    #[serde(default)]
+    pub attempts: i32,
+    /// The error the most recent failed attempt returned (or the message it
+    /// panicked with).
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// The attempts of the job's current run: all of them for a one-off job,
+    /// the latest run's for a recurring one.
+    #[serde(default)]
+    pub history: Vec<JobAttempt>,
+    /// The uniqueness key the job holds, if its worker defines
+    /// [`BackgroundWorker::unique_key`]. Released once the job leaves its
+    /// [`UniqueWindow`].
+    #[serde(default)]
+    pub unique_key: Option<String>,
+    /// How far the current attempt has got, in percent, as reported by the
+    /// worker through [`report_progress`]. Cleared when an attempt starts.
+    #[serde(default)]
+    pub progress: Option<u8>,
+    /// The payload the worker reported through [`report_result`]. Cleared
+    /// when an attempt starts.
+    #[serde(default)]
+    pub result: Option<JsonValue>,
+    /// The [`batch::Batch`] this job (or the chain it is a step of) belongs to.
+    #[serde(default)]
+    pub batch_id: Option<String>,
+    /// The [`batch::Chain`] steps left to run after this job, each taking the
+    /// previous one's result as its args.
+    #[serde(default)]
+    pub chain: Vec<ChainStep>,
+    /// The named queue the job waits in, on providers that record it.
+    #[serde(default)]
+    pub queue: Option<String>,
+    /// The [`BackgroundWorker::args_version`] the job was enqueued with.
+    #[serde(default = "upcast::initial_args_version")]
+    pub args_version: u32,
+}
+
+/// The state of a [`Job`] as a caller polling it cares about, ready to be
+/// returned from a controller with `format::json`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub name: String,
+    pub status: JobStatus,
+    pub progress: Option<u8>,
+    pub result: Option<JsonValue>,
+    /// The error of the most recent failed attempt, which a job that is
+    /// being retried keeps until it completes.
+    pub error: Option<String>,
+    pub attempts: i32,
+    pub run_at: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<Job> for JobInfo {
+    fn from(job: Job) -> Self {
+        Self {
+            id: job.id,
+            name: job.name,
+            status: job.status,
+            progress: job.progress,
+            result: job.result,
+            error: job.last_error,
+            attempts: job.attempts,
+            run_at: job.run_at,
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+        }
+    }
+}
+
+/// Stores the progress and result a running job reports. Each queue
+/// backend's run loop makes one available to the handler it calls.
+#[async_trait]
+pub trait JobReporter: Send + Sync {
+    /// Records that job `id` is `percent` done.
+    ///
+    /// # Errors
+    /// This function will return an error if the update fails.
+    async fn set_progress(&self, id: &JobId, percent: u8) -> Result<()>;
+
+    /// Records the result payload of job `id`.
+    ///
+    /// # Errors
+    /// This function will return an error if the update fails.
+    async fn set_result(&self, id: &JobId, result: JsonValue) -> Result<()>;
+
+    /// Whether [`Queue::cancel_jobs`] asked for job `id` to stop while it
+    /// runs. Defaults to `false` for reporters that cannot be reached from
+    /// outside the process.
+    ///
+    /// # Errors
+    /// This function will return an error if the lookup fails.
+    async fn cancel_requested(&self, _id: &JobId) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// The job a worker task is performing, as seen by [`report_progress`],
+/// [`report_result`] and [`cancellation_token`].
+struct JobScope {
+    id: JobId,
+    reporter: Arc<dyn JobReporter>,
+    cancel: CancellationToken,
+}
+
+tokio::task_local! {
+    static CURRENT_JOB: JobScope;
+}
+
+/// Runs `fut` (a job handler call) with job `id` and its `reporter` as the
+/// target of [`report_progress`] and [`report_result`].
+pub(crate) async fn scope_job<F: std::future::Future>(
+    id: JobId,
+    reporter: Arc<dyn JobReporter>,
+    fut: F,
+) -> F::Output {
+    let scope = JobScope {
+        id,
+        reporter,
+        cancel: CancellationToken::new(),
+    };
+    CURRENT_JOB.scope(scope, fut).await
+}
+
+/// The outcome of a job handler call made by [`run_job`].
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "testing"))]
+pub(crate) struct JobRun {
+    pub result: Result<()>,
+    /// Whether the job was asked to stop while it ran.
+    pub cancelled: bool,
+    /// Whether the job was dropped unfinished because its worker shut down.
+    /// It should go back to the queue, as if it never ran.
+    pub interrupted: bool,
+}
+
+/// How a queue worker winds down: once `token` is cancelled, it takes no
+/// new job, and gives the one it is running `timeout` to finish.
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "testing"))]
+#[derive(Clone, Debug)]
+pub(crate) struct Drain {
+    pub token: CancellationToken,
+    pub timeout: Duration,
+}
+
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "testing"))]
+impl Drain {
+    /// Resolves `timeout` after `token` is cancelled.
+    async fn deadline(&self) {
+        self.token.cancelled().await;
+        tokio::time::sleep(self.timeout).await;
+    }
+}
+
+/// Runs `fut` (a job handler call) like [`scope_job`], for a queue worker:
+/// drops it once the worker's [`BackgroundWorker::timeout`] has passed, and
+/// checks every `poll_interval` whether the job should be cancelled, which
+/// trips the token [`cancellation_token`] hands out. Drops it as well once
+/// `drain`'s deadline has passed, reporting it interrupted.
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "testing"))]
+pub(crate) async fn run_job<F>(
+    id: JobId,
+    reporter: Arc<dyn JobReporter>,
+    opts: Option<&WorkerOpts>,
+    poll_interval: Duration,
+    drain: &Drain,
+    fut: F,
+) -> JobRun
+where
+    F: std::future::Future<Output = Result<()>>,
+{
+    let cancel = CancellationToken::new();
+    let timeout = opts.and_then(|opts| opts.timeout);
+    let scope = JobScope {
+        id: id.clone(),
+        reporter: reporter.clone(),
+        cancel: cancel.clone(),
+    };
+    let perform = CURRENT_JOB.scope(scope, async move {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fut)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(Error::string(&format!("job timed out after {timeout:?}")))
+                }),
+            None => fut.await,
+        }
+    });
+    let watch = async {
+        while !cancel.is_cancelled() {
+            tokio::time::sleep(poll_interval).await;
+            match reporter.cancel_requested(&id).await {
+                Ok(true) => {
+                    tracing::debug!(job_id = %id, "Cancellation requested for running job");
+                    cancel.cancel();
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    tracing::warn!(error = %err, job_id = %id, "Failed to check job for cancellation");
+                }
+            }
+        }
+        std::future::pending::<()>().await;
+    };
+    tokio::pin!(perform);
+    tokio::select! {
+        result = &mut perform => JobRun {
+            result,
+            cancelled: cancel.is_cancelled(),
+            interrupted: false,
+        },
+        () = drain.deadline() => {
+            tracing::warn!(job_id = %id, timeout = ?drain.timeout, "Job still running at the shutdown deadline, interrupting it");
+            JobRun {
+                result: Err(Error::string("interrupted by worker shutdown")),
+                cancelled: false,
+                interrupted: true,
+            }
+        }
+        () = watch => unreachable!("the cancellation watch never finishes"),
+    }
+}
+
+/// The cancellation token of the job being performed. It is cancelled once
+/// [`Queue::cancel_jobs`] is called for the job's class while it runs, so
+/// long-running work in [`BackgroundWorker::perform`] can stop early, e.g.
+/// by `select!`ing on [`CancellationToken::cancelled`]. A job that then
+/// returns an error is recorded as [`JobStatus::Cancelled`] and not retried.
+///
+/// Outside a queue worker, i.e. in `ForegroundBlocking` and
+/// `BackgroundAsync` modes, this is a token that is never cancelled.
+#[must_use]
+pub fn cancellation_token() -> CancellationToken {
+    CURRENT_JOB
+        .try_with(|scope| scope.cancel.clone())
+        .unwrap_or_default()
+}
+
+/// Whether the job being performed was asked to stop. See
+/// [`cancellation_token`].
+#[must_use]
+pub fn is_cancelled() -> bool {
+    CURRENT_JOB
+        .try_with(|scope| scope.cancel.is_cancelled())
+        .unwrap_or(false)
+}
+
+/// Reports how far the job being performed has got, in percent (values over
+/// 100 are capped). Call it from [`BackgroundWorker::perform`]; it can then
+/// be read back through [`Queue::get_job`].
+///
+/// Does nothing outside a queue worker, i.e. in `ForegroundBlocking` and
+/// `BackgroundAsync` modes, or from a task `perform` spawned.
+///
+/// # Errors
+/// This function will return an error if the queue provider fails to store it.
+pub async fn report_progress(percent: u8) -> Result<()> {
+    let Ok((id, reporter)) =
+        CURRENT_JOB.try_with(|scope| (scope.id.clone(), scope.reporter.clone()))
+    else {
+        return Ok(());
+    };
+    reporter.set_progress(&id, percent.min(100)).await
+}
+
+/// Reports the result of the job being performed, such as the path of the
+/// file it produced. See [`report_progress`].
+///
+/// # Errors
+/// This function will return an error if `result` cannot be serialized or
+/// the queue provider fails to store it.
+pub async fn report_result<T: Serialize + Sync>(result: &T) -> Result<()> {
+    let Ok((id, reporter)) =
+        CURRENT_JOB.try_with(|scope| (scope.id.clone(), scope.reporter.clone()))
+    else {
+        return Ok(());
+    };
+    reporter
+        .set_result(&id, serde_json::to_value(result)?)
+        .await
+}
+
+/// One run of a [`Job`]'s handler, as recorded in [`Job::history`].
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct JobAttempt {
+    /// 1 for the first attempt, counting retries after it.
+    pub attempt: i32,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// `None` if the attempt succeeded.
+    pub error: Option<String>,
+    /// Where the error came from: the backtrace the error, or the panic of
+    /// the handler, captured with `RUST_BACKTRACE` set. `None` without one.
+    #[serde(default)]
+    pub backtrace: Option<String>,
+}
+
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "testing"))]
+impl JobAttempt {
+    /// Attempt `attempt` of a job, started at `started_at` and finishing now
+    /// with `result`.
+    pub(crate) fn new(attempt: i32, started_at: DateTime<Utc>, result: &Result<()>) -> Self {
+        let backtrace = match result {
+            Err(Error::WithBacktrace { backtrace, .. })
+                if backtrace.status() == std::backtrace::BacktraceStatus::Captured =>
+            {
+                Some(backtrace.to_string())
+            }
+            _ => None,
+        };
+        Self {
+            attempt,
+            started_at,
+            finished_at: Utc::now(),
+            error: result.as_ref().err().map(error_message),
+            backtrace,
+        }
+    }
+}
+
+/// What a job records of `error` as its [`Job::last_error`] and its
+/// attempt's [`JobAttempt::error`]: the error, without the backtrace of an
+/// [`Error::WithBacktrace`], which goes to [`JobAttempt::backtrace`].
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "testing"))]
+pub(crate) fn error_message(error: &Error) -> String {
+    match error {
+        Error::WithBacktrace { inner, .. } => inner.to_string(),
+        error => error.to_string(),
+    }
+}
+
+/// The delay before a failed job is retried. See [`BackgroundWorker::backoff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Backoff {
+    /// Wait the same delay before every retry.
+    Fixed(Duration),
+    /// Double the delay on every retry, starting at `base` and capped at
+    /// `max`. With `jitter`, each delay is randomized between half and all of
+    /// its value, so jobs that failed together do not all retry together.
+    Exponential {
+        base: Duration,
+        max: Duration,
+        jitter: bool,
+    },
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::Exponential {
+            base: Duration::from_secs(5),
+            max: Duration::from_secs(60 * 60),
+            jitter: true,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay before retry number `retry` (1 for the first retry).
+    #[must_use]
+    pub fn delay(&self, retry: u32) -> Duration {
+        match self {
+            Self::Fixed(delay) => *delay,
+            Self::Exponential { base, max, jitter } => {
+                let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+                let delay = base.saturating_mul(factor).min(*max);
+                if *jitter {
+                    delay.mul_f64(rand::rng().random_range(0.5..=1.0))
+                } else {
+                    delay
+                }
+            }
+        }
+    }
+}
+
+/// How many jobs of a worker class may start per `period`, across every
+/// worker process. See [`BackgroundWorker::rate_limit`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    #[must_use]
+    pub const fn per_second(limit: u32) -> Self {
+        Self {
+            limit,
+            period: Duration::from_secs(1),
+        }
+    }
+
+    #[must_use]
+    pub const fn per_minute(limit: u32) -> Self {
+        Self {
+            limit,
+            period: Duration::from_secs(60),
+        }
+    }
+}
+
+/// How long a unique job blocks duplicates of itself. See
+/// [`BackgroundWorker::unique_window`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum UniqueWindow {
+    /// Until a worker picks the job up.
+    #[default]
+    Queued,
+    /// Until the job completes, fails or is cancelled.
+    QueuedOrProcessing,
+    /// For a fixed time after the job was enqueued, whatever its status.
+    For(Duration),
+}
+
+/// The uniqueness constraint an enqueue carries: a job with the same `key`
+/// still inside its `window` makes the enqueue return that job's id instead
+/// of adding a new one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UniqueJob {
+    pub key: String,
+    pub window: UniqueWindow,
+}
+
+/// How [`Queue::enqueue`] adds a job, besides its worker and args. The
+/// default adds an unversioned job to the default queue, runnable now:
+///
+/// ```rust
+/// use loco_rs::bgworker::EnqueueOptions;
+///
+/// let options = EnqueueOptions {
+///     priority: Some(10),
+///     ..EnqueueOptions::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnqueueOptions {
+    /// The named queue to add the job to; `None` is the default queue.
+    pub queue: Option<String>,
+    pub tags: Option<Vec<String>>,
+    /// Higher values are dequeued first; `None` is 0.
+    pub priority: Option<i32>,
+    /// Holds the job back until then; `None` makes it runnable now.
+    pub run_at: Option<DateTime<Utc>>,
+    /// With a key, a job already holding it inside its window wins.
+    pub unique: Option<UniqueJob>,
+    /// Tells the worker which upcasters to run on the args. See
+    /// [`BackgroundWorker::args_version`].
+    pub args_version: u32,
+}
+
+impl Default for EnqueueOptions {
+    fn default() -> Self {
+        Self {
+            queue: None,
+            tags: None,
+            priority: None,
+            run_at: None,
+            unique: None,
+            args_version: 1,
+        }
+    }
+}
+
+/// Which jobs [`Queue::list_jobs`] returns, newest first. The default
+/// returns every job.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JobFilter {
+    /// Only jobs with one of these statuses; any status when empty.
+    pub statuses: Vec<JobStatus>,
+    /// Only jobs of this worker class.
+    pub name: Option<String>,
+    /// Only jobs carrying this tag.
+    pub tag: Option<String>,
+    /// Only jobs created or updated at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// At most this many jobs.
+    pub limit: Option<usize>,
+}
+
+impl JobFilter {
+    /// Whether `job` passes the filters other than the limit.
+    #[must_use]
+    pub fn matches(&self, job: &Job) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&job.status))
+            && self.name.as_ref().is_none_or(|name| job.name == *name)
+            && self.tag.as_ref().is_none_or(|tag| {
+                job.tags
+                    .as_ref()
+                    .is_some_and(|tags| tags.iter().any(|t| t == tag))
+            })
+            && self.since.is_none_or(|since| {
+                job.updated_at
+                    .or(job.created_at)
+                    .is_some_and(|at| at >= since)
+            })
+    }
+
+    /// Orders `jobs` newest first and cuts them to the limit.
+    pub(crate) fn apply_limit(&self, jobs: &mut Vec<Job>) {
+        jobs.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        if let Some(limit) = self.limit {
+            jobs.truncate(limit);
+        }
+    }
+}
+
+/// Per-worker settings a backend's run loop needs next to the erased
+/// [`JobHandler`], captured from the [`BackgroundWorker`] impl at registration.
+#[derive(Clone, Debug)]
+pub struct WorkerOpts {
+    pub max_retries: u32,
+    pub backoff: Backoff,
+    pub concurrency: Option<u32>,
+    pub rate_limit: Option<RateLimit>,
+    pub timeout: Option<Duration>,
+    pub args_version: u32,
+    pub upcasters: Upcasters,
+}
+
+impl Default for WorkerOpts {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff: Backoff::default(),
+            concurrency: None,
+            rate_limit: None,
+            timeout: None,
+            args_version: upcast::INITIAL_ARGS_VERSION,
+            upcasters: Upcasters::default(),
+        }
+    }
+}
+
+impl WorkerOpts {
+    /// Captures the settings declared by worker `W`.
+    #[must_use]
+    pub fn of<A, W>() -> Self
+    where
+        A: Send + Sync + Serialize + 'static,
+        W: BackgroundWorker<A>,
+    {
+        Self {
+            max_retries: W::max_retries(),
+            backoff: W::backoff(),
+            concurrency: W::concurrency(),
+            rate_limit: W::rate_limit(),
+            timeout: W::timeout(),
+            args_version: W::args_version(),
+            upcasters: W::upcasters(),
+        }
+    }
+
+    /// Overrides the worker's limits with the ones set in the queue config,
+    /// if any.
+    #[must_use]
+    pub fn with_limits(mut self, limits: Option<&WorkerLimitsConfig>) -> Self {
+        if let Some(limits) = limits {
+            if let Some(concurrency) = limits.concurrency {
+                self.concurrency = Some(concurrency);
+            }
+            if let Some(rate_limit) = &limits.rate_limit {
+                self.rate_limit = Some(RateLimit {
+                    limit: rate_limit.limit,
+                    period: Duration::from_millis(rate_limit.period_ms),
+                });
+            }
+        }
+        self
+    }
+
+    /// Whether jobs of this class are capped or rate limited.
+    #[must_use]
+    pub const fn is_limited(&self) -> bool {
+        self.concurrency.is_some() || self.rate_limit.is_some()
+    }
+
+    /// When a job that just failed its `attempts`-th run should run again, or
+    /// `None` once its retries are exhausted and it should be marked
+    /// [`JobStatus::Failed`].
+    #[must_use]
+    pub fn next_run_at(&self, attempts: i32) -> Option<DateTime<Utc>> {
+        let attempts = u32::try_from(attempts).unwrap_or(0);
+        if attempts == 0 || attempts > self.max_retries {
+            return None;
+        }
+        let delay = chrono::Duration::from_std(self.backoff.delay(attempts))
+            .unwrap_or_else(|_| chrono::Duration::zero());
+        Some(Utc::now() + delay)
+    }
 }
 
 /// A boxed, type-erased job handler: takes a job's id and data and runs the
@@ -101,6 +712,31 @@ pub type JobHandler = Box<
         + Sync,
 >;
 
+thread_local! {
+    /// The location and backtrace of the latest panic on this thread, kept by
+    /// the hook [`capture_panics`] installs for a worker that catches it.
+    static LAST_PANIC: std::cell::RefCell<Option<(String, std::backtrace::Backtrace)>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Installs, once, a panic hook that keeps where each panic happened in
+/// [`LAST_PANIC`], with its backtrace when `RUST_BACKTRACE` is set, before
+/// calling the hook it replaces. The stack is gone by the time
+/// [`erase_worker`] catches the panic.
+fn capture_panics() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let location = info.location().map(ToString::to_string).unwrap_or_default();
+            let _ = LAST_PANIC.try_with(|last| {
+                last.replace(Some((location, std::backtrace::Backtrace::capture())))
+            });
+            previous(info);
+        }));
+    });
+}
+
 /// Erases a [`BackgroundWorker`]'s argument type into a [`JobHandler`] that
 /// can be stored in a backend's job registry alongside handlers for other,
 /// differently-typed workers.
@@ -113,6 +749,7 @@ where
     Args: Send + Serialize + Sync + 'static + for<'de> Deserialize<'de>,
     W: BackgroundWorker<Args> + 'static,
 {
+    capture_panics();
     let worker = Arc::new(worker);
     Box::new(move |_job_id: JobId, job_data: JobData| {
         let w = worker.clone();
@@ -132,8 +769,25 @@ where
                                 .map(String::as_str)
                                 .or_else(|| panic.downcast_ref::<&str>().copied())
                                 .unwrap_or("Unknown panic occurred");
-                            tracing::error!(err = panic_msg, "worker panicked");
-                            Err(Error::string(panic_msg))
+                            let (location, backtrace) = LAST_PANIC
+                                .try_with(std::cell::RefCell::take)
+                                .ok()
+                                .flatten()
+                                .unzip();
+                            tracing::error!(err = panic_msg, location, "worker panicked");
+                            let err = Error::string(&format!("worker panicked: {panic_msg}"));
+                            Err(match backtrace {
+                                Some(backtrace)
+                                    if backtrace.status()
+                                        == std::backtrace::BacktraceStatus::Captured =>
+                                {
+                                    Error::WithBacktrace {
+                                        inner: Box::new(err),
+                                        backtrace: Box::new(backtrace),
+                                    }
+                                }
+                                _ => err,
+                            })
                         }
                     }
                 }
@@ -144,11 +798,34 @@ where
     })
 }
 
+/// Calls `handler` with the args of `job`, brought up to its worker's
+/// current [`BackgroundWorker::args_version`] by the worker's upcasters.
+/// Shared between every queue backend's run loop.
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "testing"))]
+pub(crate) fn call_handler(
+    handler: &JobHandler,
+    opts: Option<&WorkerOpts>,
+    job: &Job,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), crate::Error>> + Send>> {
+    let data = match opts {
+        Some(opts) => opts
+            .upcasters
+            .upcast(job.data.clone(), job.args_version, opts.args_version),
+        None => Ok(job.data.clone()),
+    };
+    match data {
+        Ok(data) => handler(job.id.clone(), data),
+        Err(err) => Box::pin(std::future::ready(Err(err))),
+    }
+}
+
 /// Object-safe interface implemented by every queue backend (Postgres,
 /// `SQLite`, Redis, and the built-in no-op).
 ///
-/// [`Queue`] is a thin newtype wrapper around `Arc<dyn QueueProvider>` that
-/// forwards every call here.
+/// [`Queue`] wraps an `Arc<dyn QueueProvider>` and forwards its queue
+/// operations here. It keeps the [`middleware::JobMiddleware`] wrapping its
+/// workers, its [`recurring`] jobs and its job chains itself, so a provider
+/// only stores and hands out jobs.
 #[async_trait]
 pub trait QueueProvider: Send + Sync {
     /// Add a job to the queue. See [`Queue::enqueue`] for the full contract.
@@ -158,17 +835,21 @@ pub trait QueueProvider: Send + Sync {
     async fn enqueue(
         &self,
         class: String,
-        queue: Option<String>,
         args: JsonValue,
-        tags: Option<Vec<String>>,
-        priority: Option<i32>,
+        options: EnqueueOptions,
     ) -> Result<Option<String>>;
 
-    /// Registers a pre-erased job handler under `name`.
+    /// Registers a pre-erased job handler under `name`, along with the
+    /// worker's [`WorkerOpts`].
     ///
     /// # Errors
     /// This function will return an error if it fails to register.
-    async fn register_handler(&self, name: String, handler: JobHandler) -> Result<()>;
+    async fn register_handler(
+        &self,
+        name: String,
+        handler: JobHandler,
+        opts: WorkerOpts,
+    ) -> Result<()>;
 
     /// Runs the worker loop for this provider.
     ///
@@ -176,6 +857,19 @@ pub trait QueueProvider: Send + Sync {
     /// This function will return an error if it fails.
     async fn run(&self, tags: Vec<String>) -> Result<()>;
 
+    /// Performs every job that is due, one at a time, until none is left,
+    /// returning how many were performed. See [`Queue::drain`].
+    ///
+    /// # Errors
+    /// This function will return an error if it fails, or if the provider
+    /// does not support draining (the default).
+    #[cfg(feature = "testing")]
+    async fn drain(&self) -> Result<usize> {
+        Err(Error::string(
+            "this queue provider does not support draining",
+        ))
+    }
+
     /// Runs the setup of this provider.
     ///
     /// # Errors
@@ -204,7 +898,123 @@ pub trait QueueProvider: Send + Sync {
         age_days: Option<i64>,
     ) -> Result<Vec<Job>>;
 
-    /// Cancels queued jobs by name.
+    /// Retrieves a single job by id, or `None` if there is no such job.
+    ///
+    /// The default implementation scans [`QueueProvider::get_jobs`]; the
+    /// built-in providers look the job up directly.
+    ///
+    /// # Errors
+    /// This function will return an error if it fails.
+    async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        Ok(self
+            .get_jobs(None, None)
+            .await?
+            .into_iter()
+            .find(|job| job.id == id))
+    }
+
+    /// Retrieves the newest jobs passing `filter`. See [`Queue::list_jobs`].
+    ///
+    /// The default implementation filters [`QueueProvider::get_jobs`]; the
+    /// SQL providers filter and limit in the query.
+    ///
+    /// # Errors
+    /// This function will return an error if it fails.
+    async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<Job>> {
+        let statuses = (!filter.statuses.is_empty()).then(|| filter.statuses.clone());
+        let mut jobs = self.get_jobs(statuses.as_ref(), None).await?;
+        jobs.retain(|job| filter.matches(job));
+        filter.apply_limit(&mut jobs);
+        Ok(jobs)
+    }
+
+    /// Counts the jobs per status. Statuses without jobs may be missing.
+    ///
+    /// The default implementation counts [`QueueProvider::get_jobs`]; the
+    /// SQL providers count in the query.
+    ///
+    /// # Errors
+    /// This function will return an error if it fails.
+    async fn count_by_status(&self) -> Result<HashMap<JobStatus, u64>> {
+        let mut counts = HashMap::new();
+        for job in self.get_jobs(None, None).await? {
+            *counts.entry(job.status).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Computes the queue's metrics. See [`stats::QueueStats`].
+    ///
+    /// The default implementation reports a job under its [`Job::queue`],
+    /// or `default`; providers that keep named queues elsewhere say which
+    /// one a job waits in.
+    ///
+    /// # Errors
+    /// This function will return an error if it fails.
+    async fn stats(&self) -> Result<stats::QueueStats> {
+        let jobs = self.get_jobs(None, None).await?;
+        Ok(stats::QueueStats::from_jobs(
+            jobs.iter()
+                .map(|job| (job.queue.as_deref().unwrap_or("default"), job)),
+            Utc::now(),
+        ))
+    }
+
+    /// Adds a job like [`QueueProvider::enqueue`], but as part of `txn`, so
+    /// that it only exists once `txn` commits. See
+    /// [`Queue::enqueue_in_transaction`].
+    ///
+    /// # Errors
+    /// This function will return an error if the enqueue operation fails, or
+    /// if the provider does not support it (the default).
+    #[cfg(feature = "with-db")]
+    async fn enqueue_in_transaction(
+        &self,
+        _txn: &sea_orm::DatabaseTransaction,
+        _class: String,
+        _args: JsonValue,
+        _options: EnqueueOptions,
+    ) -> Result<Option<String>> {
+        Err(Error::string(
+            "this queue provider does not support enqueueing in a transaction",
+        ))
+    }
+
+    /// Adds the first job of a chain, which carries the remaining steps. See
+    /// [`batch::Chain`].
+    ///
+    /// # Errors
+    /// This function will return an error if the enqueue operation fails, or
+    /// if the provider does not support chains (the default).
+    async fn enqueue_chain(&self, _job: JobSpec) -> Result<Option<String>> {
+        Err(Error::string("this queue provider does not support chains"))
+    }
+
+    /// Records a batch and adds its jobs, returning the batch id. See
+    /// [`batch::Batch`].
+    ///
+    /// # Errors
+    /// This function will return an error if the enqueue operation fails, or
+    /// if the provider does not support batches (the default).
+    async fn enqueue_batch(&self, _batch: BatchSpec) -> Result<Option<String>> {
+        Err(Error::string(
+            "this queue provider does not support batches",
+        ))
+    }
+
+    /// Retrieves the bookkeeping of every batch.
+    ///
+    /// # Errors
+    /// This function will return an error if it fails, or if the provider
+    /// does not support batches (the default).
+    async fn get_batches(&self) -> Result<Vec<JobBatch>> {
+        Err(Error::string(
+            "this queue provider does not support batches",
+        ))
+    }
+
+    /// Cancels queued jobs by name, and asks running ones to stop (see
+    /// [`JobReporter::cancel_requested`]).
     ///
     /// # Errors
     /// This function will return an error if it fails.
@@ -237,8 +1047,9 @@ pub trait QueueProvider: Send + Sync {
     ///
     /// Distinct from [`Queue::requeue`], which rescues jobs stranded in
     /// [`JobStatus::Processing`] by a crashed worker and cannot touch a failed
-    /// one. Without this, a failed job is terminal: there is no automatic retry
-    /// in any driver, so an operator's only recourse was to dump and re-import.
+    /// one. Automatic retries ([`BackgroundWorker::max_retries`]) happen before
+    /// a job is marked failed; this is the operator's recourse once they are
+    /// exhausted, and it resets the job's attempt count.
     ///
     /// # Errors
     /// This function will return an error if it fails.
@@ -301,15 +1112,37 @@ impl QueueProvider for NoopQueue {
     async fn enqueue(
         &self,
         _class: String,
-        _queue: Option<String>,
         _args: JsonValue,
-        _tags: Option<Vec<String>>,
-        _priority: Option<i32>,
+        _options: EnqueueOptions,
     ) -> Result<Option<String>> {
         Ok(None)
     }
 
-    async fn register_handler(&self, _name: String, _handler: JobHandler) -> Result<()> {
+    #[cfg(feature = "with-db")]
+    async fn enqueue_in_transaction(
+        &self,
+        _txn: &sea_orm::DatabaseTransaction,
+        _class: String,
+        _args: JsonValue,
+        _options: EnqueueOptions,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn enqueue_chain(&self, _job: JobSpec) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn enqueue_batch(&self, _batch: BatchSpec) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn register_handler(
+        &self,
+        _name: String,
+        _handler: JobHandler,
+        _opts: WorkerOpts,
+    ) -> Result<()> {
         Ok(())
     }
 
@@ -393,23 +1226,41 @@ impl QueueProvider for NoopQueue {
 
 /// A handle to a background job queue.
 ///
-/// Newtype facade over `Arc<dyn QueueProvider>`: every operation is a thin
+/// Facade over `Arc<dyn QueueProvider>`: every operation is a thin
 /// delegate to the configured provider (Postgres, `SQLite`, Redis, or
-/// [`Queue::empty`]'s no-op).
-pub struct Queue(Arc<dyn QueueProvider>);
+/// [`Queue::empty`]'s no-op), plus the [`middleware::JobMiddleware`] that
+/// wraps the workers registered through it, the [`recurring`] jobs it ticks,
+/// and how to enqueue a job of each registered worker by its class name.
+pub struct Queue {
+    provider: Arc<dyn QueueProvider>,
+    middleware: middleware::Stack,
+    recurring: recurring::Registry,
+    /// How to enqueue a job of each registered worker, by class name.
+    chains: Arc<RwLock<HashMap<String, ChainStep>>>,
+}
 
 impl Queue {
     /// Wraps a third-party (or built-in) [`QueueProvider`] as a [`Queue`].
     #[must_use]
     pub fn from_provider(provider: Arc<dyn QueueProvider>) -> Self {
-        Self(provider)
+        Self {
+            provider,
+            middleware: middleware::Stack::default(),
+            recurring: recurring::Registry::default(),
+            chains: Arc::default(),
+        }
     }
 
     /// A [`Queue`] with no configured provider. Replaces the old
     /// `Queue::None` enum variant.
     #[must_use]
     pub fn empty() -> Self {
-        Self(Arc::new(NoopQueue))
+        Self {
+            provider: Arc::new(NoopQueue),
+            middleware: middleware::Stack::default(),
+            recurring: recurring::Registry::default(),
+            chains: Arc::default(),
+        }
     }
 
     /// Add a job to the queue.
@@ -426,17 +1277,45 @@ impl Queue {
     /// - Higher value means higher urgency.
     /// - Valid range is full `i32` (`-2_147_483_648..=2_147_483_647`).
     /// - Ties are resolved by earlier `run_at`, then by stable job id ordering.
+    ///
+    /// See [`EnqueueOptions`] for the queue, tags, priority, run time,
+    /// uniqueness and args version the job is enqueued with.
     pub async fn enqueue<A: Serialize + Send + Sync>(
         &self,
         class: String,
-        queue: Option<String>,
         args: A,
-        tags: Option<Vec<String>>,
-        priority: Option<i32>,
+        options: EnqueueOptions,
     ) -> Result<Option<String>> {
-        tracing::debug!(worker = class, queue = ?queue, tags = ?tags, "Enqueuing background job");
-        self.0
-            .enqueue(class, queue, serde_json::to_value(args)?, tags, priority)
+        tracing::debug!(worker = class, queue = ?options.queue, tags = ?options.tags, run_at = ?options.run_at, "Enqueuing background job");
+        self.provider
+            .enqueue(class, serde_json::to_value(args)?, options)
+            .await
+    }
+
+    /// Add a job to the queue as part of the database transaction `txn`, so
+    /// that workers only see it once `txn` commits, and never if it is
+    /// rolled back. Otherwise the same as [`Queue::enqueue`].
+    ///
+    /// Only the Postgres and `SQLite` providers support it, and only when
+    /// the queue lives in the database `txn` is on: the job row is written
+    /// through `txn` itself.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the enqueue operation fails, if
+    /// the provider does not support transactions, or if `txn` is on another
+    /// kind of database than the queue.
+    #[cfg(feature = "with-db")]
+    pub async fn enqueue_in_transaction<A: Serialize + Send + Sync>(
+        &self,
+        txn: &sea_orm::DatabaseTransaction,
+        class: String,
+        args: A,
+        options: EnqueueOptions,
+    ) -> Result<Option<String>> {
+        tracing::debug!(worker = class, queue = ?options.queue, tags = ?options.tags, run_at = ?options.run_at, "Enqueuing background job in transaction");
+        self.provider
+            .enqueue_in_transaction(txn, class, serde_json::to_value(args)?, options)
             .await
     }
 
@@ -454,18 +1333,123 @@ impl Queue {
     ) -> Result<()> {
         tracing::info!(worker = W::class_name(), "Registering background worker");
         let name = W::class_name();
-        let handler = erase_worker(worker);
-        self.0.register_handler(name, handler).await
+        let opts = WorkerOpts::of::<A, W>();
+        let handler = self.middleware.wrap(name.clone(), erase_worker(worker));
+        self.chains
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.clone(), batch::step::<W, A>().0);
+        self.provider.register_handler(name, handler, opts).await
+    }
+
+    /// Whether a worker with class name `class` was registered on this
+    /// queue.
+    #[must_use]
+    pub fn is_registered(&self, class: &str) -> bool {
+        self.chains
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(class)
+    }
+
+    /// Enqueues a job of the worker registered as `class`, performing
+    /// `args`, on the queue and with the tags and args version that worker
+    /// declares. For callers that know the worker by name only, such as the
+    /// scheduler.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if no worker named `class` was
+    /// registered on this queue, or if the enqueue operation fails.
+    pub async fn enqueue_registered(&self, class: &str, args: JsonValue) -> Result<Option<String>> {
+        let step = self
+            .chains
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(class)
+            .cloned()
+            .ok_or_else(|| Error::string(&format!("worker `{class}` is not registered")))?;
+        self.enqueue(
+            step.class,
+            args,
+            EnqueueOptions {
+                queue: step.queue,
+                tags: step.tags,
+                args_version: step.args_version,
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+    }
+
+    /// Adds a [`middleware::JobMiddleware`] around every job performed by the
+    /// workers registered on this queue, including those registered before.
+    /// Middleware added first runs outermost.
+    pub fn add_middleware<M: middleware::JobMiddleware>(&self, middleware: M) {
+        tracing::info!(
+            middleware = std::any::type_name::<M>(),
+            "Adding background job middleware"
+        );
+        self.middleware.push(Arc::new(middleware));
     }
 
-    /// Runs the worker loop for this [`Queue`].
+    /// Declares a [`recurring`] job: worker `W` performing `args` on every
+    /// tick of `schedule`. Each tick is enqueued once, however many processes
+    /// run this queue's workers; `name` identifies the job across them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if `args` cannot be serialized,
+    /// if a recurring job named `name` was already added, or if `schedule`
+    /// never ticks.
+    pub fn recurring<W, A>(&self, name: &str, schedule: recurring::Schedule, args: A) -> Result<()>
+    where
+        A: Send + Sync + Serialize + 'static + for<'de> Deserialize<'de>,
+        W: BackgroundWorker<A> + 'static,
+    {
+        tracing::info!(recurring = name, worker = W::class_name(), schedule = ?schedule, "Adding recurring job");
+        let (step, _) = batch::step::<W, A>();
+        self.recurring.add(recurring::Recurring {
+            name: name.to_string(),
+            schedule,
+            job: step.into_job(serde_json::to_value(args)?, Vec::new()),
+        })
+    }
+
+    /// Runs the worker loop for this [`Queue`], and ticks its
+    /// [`recurring`] jobs while it runs.
     ///
     /// # Errors
     ///
     /// This function will return an error if fails
     pub async fn run(&self, tags: Vec<String>) -> Result<()> {
         tracing::info!("Starting background job processing");
-        self.0.run(tags).await
+        let recurring = self.recurring.snapshot();
+        if recurring.is_empty() {
+            return self.provider.run(tags).await;
+        }
+        tokio::select! {
+            res = self.provider.run(tags) => res,
+            () = recurring::run(self, recurring) => Ok(()),
+        }
+    }
+
+    /// Performs every due job through its registered worker, in this task
+    /// and one at a time, until none is left, including jobs that the
+    /// performed ones enqueue. Returns how many jobs were performed. Jobs
+    /// scheduled for later, such as retries waiting out their backoff, stay
+    /// queued.
+    ///
+    /// Meant for tests, with the in-memory provider (see
+    /// [`inmem::InMemQueue`]); the other providers do not support it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the provider does not support
+    /// draining.
+    #[cfg(feature = "testing")]
+    pub async fn drain(&self) -> Result<usize> {
+        self.provider.drain().await
     }
 
     /// Runs the setup of this [`Queue`].
@@ -474,7 +1458,7 @@ impl Queue {
     ///
     /// This function will return an error if fails
     pub async fn setup(&self) -> Result<()> {
-        self.0.setup().await
+        self.provider.setup().await
     }
 
     /// Performs clear on this [`Queue`].
@@ -484,7 +1468,7 @@ impl Queue {
     /// This function will return an error if fails
     pub async fn clear(&self) -> Result<()> {
         tracing::info!("Clearing all jobs from queue");
-        self.0.clear().await
+        self.provider.clear().await
     }
 
     /// Returns a ping of this [`Queue`].
@@ -494,12 +1478,12 @@ impl Queue {
     /// This function will return an error if fails
     pub async fn ping(&self) -> Result<()> {
         tracing::trace!("Pinging job queue");
-        self.0.ping().await
+        self.provider.ping().await
     }
 
     #[must_use]
     pub fn describe(&self) -> String {
-        self.0.describe()
+        self.provider.describe()
     }
 
     /// # Errors
@@ -508,20 +1492,110 @@ impl Queue {
     /// queue implementations might, so using Result here as return type.
     pub fn shutdown(&self) -> Result<()> {
         tracing::info!("Shutting down background job processing");
-        self.0.shutdown()
+        self.provider.shutdown()
     }
 
-    async fn get_jobs(
+    /// Retrieves jobs, optionally filtered by status and by a maximum age in
+    /// days.
+    ///
+    /// # Errors
+    /// - If no queue provider is configured, it will return an error indicating the lack of configuration.
+    /// - Any error in the underlying provider's job retrieval logic will propagate from the respective function.
+    pub async fn get_jobs(
         &self,
         status: Option<&Vec<JobStatus>>,
         age_days: Option<i64>,
     ) -> Result<Vec<Job>> {
         tracing::info!(status = ?status, age_days = ?age_days, "Retrieving jobs");
-        self.0.get_jobs(status, age_days).await
+        self.provider.get_jobs(status, age_days).await
+    }
+
+    /// Looks up a job by the id [`BackgroundWorker::perform_later`] returned,
+    /// including the progress and result it reported. Convert it into a
+    /// [`JobInfo`] to hand it to an HTTP client.
+    ///
+    /// # Errors
+    /// This function will return an error if the provider fails to fetch it.
+    pub async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        tracing::debug!(job_id = id, "Retrieving job");
+        self.provider.get_job(id).await
+    }
+
+    /// Retrieves the newest jobs with the status and tag in `filter`, at most
+    /// its `limit`, without loading the rest from a SQL queue. Behind the
+    /// jobs [`dashboard`].
+    ///
+    /// # Errors
+    /// This function will return an error if the provider fails to fetch
+    /// them.
+    pub async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<Job>> {
+        tracing::debug!(filter = ?filter, "Listing jobs");
+        self.provider.list_jobs(filter).await
+    }
+
+    /// Counts the jobs per status, without loading them from a SQL queue.
+    /// Statuses without jobs may be missing.
+    ///
+    /// # Errors
+    /// This function will return an error if the provider fails to count
+    /// them.
+    pub async fn count_by_status(&self) -> Result<HashMap<JobStatus, u64>> {
+        tracing::debug!("Counting jobs by status");
+        self.provider.count_by_status().await
+    }
+
+    /// Computes the depth of each named queue, how long the oldest due jobs
+    /// have been waiting, how long attempts ran and how often they failed,
+    /// per worker class. Behind `cargo loco jobs stats`.
+    ///
+    /// # Errors
+    /// This function will return an error if the provider fails to fetch the
+    /// jobs.
+    pub async fn stats(&self) -> Result<stats::QueueStats> {
+        tracing::debug!("Computing queue stats");
+        self.provider.stats().await
+    }
+
+    /// Adds the first job of a chain. Use [`batch::Chain::enqueue`] rather
+    /// than calling this directly.
+    ///
+    /// # Errors
+    /// This function will return an error if the enqueue operation fails.
+    pub async fn enqueue_chain(&self, job: JobSpec) -> Result<Option<String>> {
+        tracing::debug!(
+            worker = job.class,
+            steps = job.chain.len() + 1,
+            "Enqueuing job chain"
+        );
+        self.provider.enqueue_chain(job).await
+    }
+
+    /// Records a batch and adds its jobs. Use [`batch::Batch::enqueue`]
+    /// rather than calling this directly.
+    ///
+    /// # Errors
+    /// This function will return an error if the enqueue operation fails.
+    pub async fn enqueue_batch(&self, batch: BatchSpec) -> Result<Option<String>> {
+        tracing::debug!(jobs = batch.jobs.len(), "Enqueuing job batch");
+        self.provider.enqueue_batch(batch).await
+    }
+
+    /// Retrieves the bookkeeping of every batch.
+    ///
+    /// # Errors
+    /// This function will return an error if the provider fails to fetch
+    /// them or does not support batches.
+    pub async fn get_batches(&self) -> Result<Vec<JobBatch>> {
+        self.provider.get_batches().await
     }
 
     /// Cancels jobs based on the given job name for the configured queue provider.
     ///
+    /// Queued jobs are marked [`JobStatus::Cancelled`] right away. Jobs that
+    /// are already running get their [`cancellation_token`] cancelled within
+    /// a poll interval; `perform` decides whether to stop, and if it then
+    /// returns an error the job is marked cancelled instead of retried.
+    ///
     /// # Errors
     /// - If no queue provider is configured, it will return an error indicating the lack of configuration.
     /// - If the Redis provider is selected, it will return an error stating that cancellation is not supported.
@@ -529,7 +1603,7 @@ impl Queue {
     ///
     pub async fn cancel_jobs(&self, job_name: &str) -> Result<()> {
         tracing::info!(job_name = job_name, "Cancelling jobs by name");
-        self.0.cancel_jobs_by_name(job_name).await
+        self.provider.cancel_jobs_by_name(job_name).await
     }
 
     /// Clears jobs older than a specified number of days for the configured queue provider.
@@ -545,7 +1619,9 @@ impl Queue {
         status: &Vec<JobStatus>,
     ) -> Result<()> {
         tracing::info!(age_days = age_days, status = ?status, "Clearing older jobs");
-        self.0.clear_jobs_older_than(age_days, Some(status)).await
+        self.provider
+            .clear_jobs_older_than(age_days, Some(status))
+            .await
     }
 
     /// Clears jobs based on their status for the configured queue provider.
@@ -556,7 +1632,7 @@ impl Queue {
     /// - Any error in the underlying provider's job clearing logic will propagate from the respective function.
     pub async fn clear_by_status(&self, status: Vec<JobStatus>) -> Result<()> {
         tracing::info!(status = ?status, "Clearing jobs by status");
-        self.0.clear_by_status(status).await
+        self.provider.clear_by_status(status).await
     }
 
     /// Requeued job with the given minutes ages.
@@ -567,7 +1643,7 @@ impl Queue {
     /// - Any error in the underlying provider's job clearing logic will propagate from the respective function.
     pub async fn requeue(&self, age_minutes: &i64) -> Result<()> {
         tracing::info!(age_minutes = age_minutes, "Requeuing stale jobs");
-        self.0.requeue(age_minutes).await
+        self.provider.requeue(age_minutes).await
     }
 
     /// Moves failed jobs back to [`JobStatus::Queued`], returning how many
@@ -578,7 +1654,7 @@ impl Queue {
     /// - Any error in the underlying provider's retry logic will propagate from the respective function.
     pub async fn retry_failed(&self, id: Option<&str>) -> Result<u64> {
         tracing::info!(job_id = ?id, "Retrying failed jobs");
-        self.0.retry_failed(id).await
+        self.provider.retry_failed(id).await
     }
 
     /// Dumps the list of jobs to a YAML file at the specified path.
@@ -636,10 +1712,15 @@ impl Queue {
         for job in jobs {
             self.enqueue(
                 job.name.clone(),
-                None,
                 job.data,
-                job.tags.clone(),
-                Some(job.priority),
+                EnqueueOptions {
+                    queue: job.queue,
+                    tags: job.tags,
+                    priority: Some(job.priority),
+                    run_at: Some(job.run_at),
+                    args_version: job.args_version,
+                    ..EnqueueOptions::default()
+                },
             )
             .await?;
         }
@@ -665,6 +1746,90 @@ pub trait BackgroundWorker<A: Send + Sync + serde::Serialize + 'static>: Send +
         Vec::new()
     }
 
+    /// How many times a failed job is retried before it is marked
+    /// [`JobStatus::Failed`]. The default of `0` makes the first failure
+    /// terminal.
+    ///
+    /// Retries are rescheduled by the queue provider (its `run_at` is pushed
+    /// out by [`BackgroundWorker::backoff`]), so they apply in
+    /// `BackgroundQueue` mode only.
+    #[must_use]
+    fn max_retries() -> u32 {
+        0
+    }
+
+    /// The delay between retries. Defaults to exponential backoff from 5
+    /// seconds, capped at an hour, with jitter.
+    #[must_use]
+    fn backoff() -> Backoff {
+        Backoff::default()
+    }
+
+    /// Identifies jobs that are duplicates of each other. While a job with
+    /// the same key is inside its [`BackgroundWorker::unique_window`],
+    /// enqueueing another one adds nothing and returns the existing job's ID.
+    /// Keys are scoped to the worker's class name. The default of `None`
+    /// makes every job distinct.
+    ///
+    /// Only enforced in `BackgroundQueue` mode.
+    #[must_use]
+    fn unique_key(_args: &A) -> Option<String> {
+        None
+    }
+
+    /// How long a job blocks duplicates of itself. Defaults to
+    /// [`UniqueWindow::Queued`].
+    #[must_use]
+    fn unique_window() -> UniqueWindow {
+        UniqueWindow::default()
+    }
+
+    /// How many jobs of this worker may be processing at once, across every
+    /// worker process sharing the queue. Jobs over the cap stay queued until
+    /// a running one finishes. Defaults to no cap; `limits` in the queue
+    /// config overrides it.
+    #[must_use]
+    fn concurrency() -> Option<u32> {
+        None
+    }
+
+    /// How many jobs of this worker may start per period, across every
+    /// worker process sharing the queue, e.g. `Some(RateLimit::per_second(10))`.
+    /// Defaults to no limit; `limits` in the queue config overrides it.
+    #[must_use]
+    fn rate_limit() -> Option<RateLimit> {
+        None
+    }
+
+    /// How long a single run of [`BackgroundWorker::perform`] may take. Once
+    /// it is over, the run is dropped at its next `.await` and counts as a
+    /// failed attempt, so it is retried or marked [`JobStatus::Failed`] like
+    /// any other error. Defaults to no timeout.
+    ///
+    /// Only enforced in `BackgroundQueue` mode.
+    #[must_use]
+    fn timeout() -> Option<Duration> {
+        None
+    }
+
+    /// The version of this worker's args, stored with every job it enqueues.
+    /// Bump it when the args change shape, and add an upcaster from the
+    /// previous version to [`BackgroundWorker::upcasters`], so that jobs
+    /// already in the queue can still be performed. Defaults to
+    /// [`upcast::INITIAL_ARGS_VERSION`].
+    #[must_use]
+    fn args_version() -> u32 {
+        upcast::INITIAL_ARGS_VERSION
+    }
+
+    /// Transforms the args of jobs enqueued at an older
+    /// [`BackgroundWorker::args_version`] into the current shape, before
+    /// they are deserialized. See [`upcast`].
+    #[must_use]
+    fn upcasters() -> Upcasters {
+        Upcasters::default()
+    }
+
     fn build(ctx: &AppContext) -> Self;
     #[must_use]
     fn class_name() -> String
@@ -701,48 +1866,151 @@ pub trait BackgroundWorker<A: Send + Sync + serde::Serialize + 'static>: Send +
     where
         Self: Sized,
     {
-        let job_id = match &ctx.config.workers.mode {
-            WorkerMode::BackgroundQueue => {
-                if let Some(p) = &ctx.queue_provider {
-                    let tags = Self::tags();
-                    let tags_option = if tags.is_empty() { None } else { Some(tags) };
-                    p.enqueue(
-                        Self::class_name(),
-                        Self::queue(),
-                        args,
-                        tags_option,
-                        priority,
-                    )
-                    .await?
-                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
-                } else {
-                    tracing::error!(
-                        "perform_later: background queue is selected, but queue was not populated \
-                         in context"
-                    );
-                    uuid::Uuid::new_v4().to_string()
-                }
-            }
-            WorkerMode::ForegroundBlocking => {
-                Self::build(ctx).perform(args).await?;
-                uuid::Uuid::new_v4().to_string()
-            }
-            WorkerMode::BackgroundAsync => {
-                let dx = ctx.clone();
-                tokio::spawn(async move {
-                    if let Err(err) = Self::build(&dx).perform(args).await {
-                        tracing::error!(err = err.to_string(), "worker failed to perform job");
-                    }
-                });
-                uuid::Uuid::new_v4().to_string()
-            }
+        dispatch::<A, Self>(ctx, args, priority, None).await
+    }
+
+    /// Enqueue the job to run no earlier than `run_at` and return its ID.
+    ///
+    /// In `BackgroundQueue` mode the job is stored right away and picked up by
+    /// a worker once `run_at` has passed. `BackgroundAsync` mode sleeps in a
+    /// spawned task until then (the job is lost if the process exits first),
+    /// and `ForegroundBlocking` mode ignores the schedule and runs the job
+    /// immediately, so tests never wait on it.
+    async fn perform_at(ctx: &AppContext, run_at: DateTime<Utc>, args: A) -> crate::Result<String>
+    where
+        Self: Sized,
+    {
+        dispatch::<A, Self>(ctx, args, None, Some(run_at)).await
+    }
+
+    /// Enqueue the job to run after `delay` and return its ID. See
+    /// [`BackgroundWorker::perform_at`].
+    ///
+    /// # Errors
+    /// Fails if `delay` is too large to be added to the current time.
+    async fn perform_in(ctx: &AppContext, delay: Duration, args: A) -> crate::Result<String>
+    where
+        Self: Sized,
+    {
+        let delay = chrono::Duration::from_std(delay)
+            .map_err(|_| Error::string("perform_in: delay is out of range"))?;
+        Self::perform_at(ctx, Utc::now() + delay, args).await
+    }
+
+    /// Enqueue the job as part of the database transaction `txn` and return
+    /// its ID, so that it is only performed if `txn` commits — a job
+    /// referring to rows written in `txn` never runs without them.
+    ///
+    /// In `BackgroundQueue` mode this needs the Postgres or `SQLite` queue,
+    /// living in the database `txn` is on (see
+    /// [`Queue::enqueue_in_transaction`]). The other modes do not store jobs
+    /// and behave like [`BackgroundWorker::perform_later`], running the job
+    /// whether or not `txn` commits.
+    ///
+    /// # Errors
+    /// Fails if the queue provider cannot enqueue in `txn`.
+    #[cfg(feature = "with-db")]
+    async fn perform_later_in_transaction(
+        ctx: &AppContext,
+        txn: &sea_orm::DatabaseTransaction,
+        args: A,
+    ) -> crate::Result<String>
+    where
+        Self: Sized,
+    {
+        let WorkerMode::BackgroundQueue = &ctx.config.workers.mode else {
+            return dispatch::<A, Self>(ctx, args, None, None).await;
         };
-        Ok(job_id)
+        let Some(p) = &ctx.queue_provider else {
+            tracing::error!(
+                "perform_later: background queue is selected, but queue was not populated in \
+                 context"
+            );
+            return Ok(uuid::Uuid::new_v4().to_string());
+        };
+        let options = enqueue_options::<A, Self>(&args);
+        Ok(
+            p.enqueue_in_transaction(txn, Self::class_name(), args, options)
+                .await?
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        )
     }
 
     async fn perform(&self, args: A) -> crate::Result<()>;
 }
 
+/// The queue, tags, uniqueness and args version worker `W` enqueues a job
+/// with `args` under.
+fn enqueue_options<A, W>(args: &A) -> EnqueueOptions
+where
+    A: Send + Sync + Serialize + 'static,
+    W: BackgroundWorker<A>,
+{
+    let tags = W::tags();
+    EnqueueOptions {
+        queue: W::queue(),
+        tags: if tags.is_empty() { None } else { Some(tags) },
+        unique: W::unique_key(args).map(|key| UniqueJob {
+            key: format!("{}:{key}", W::class_name()),
+            window: W::unique_window(),
+        }),
+        args_version: W::args_version(),
+        ..EnqueueOptions::default()
+    }
+}
+
+/// Shared body of [`BackgroundWorker::perform_later_with_priority`] and
+/// [`BackgroundWorker::perform_at`]: enqueues or runs the job according to
+/// the configured [`WorkerMode`].
+async fn dispatch<A, W>(
+    ctx: &AppContext,
+    args: A,
+    priority: Option<i32>,
+    run_at: Option<DateTime<Utc>>,
+) -> Result<String>
+where
+    A: Send + Sync + Serialize + 'static,
+    W: BackgroundWorker<A>,
+{
+    let job_id = match &ctx.config.workers.mode {
+        WorkerMode::BackgroundQueue => {
+            if let Some(p) = &ctx.queue_provider {
+                let options = EnqueueOptions {
+                    priority,
+                    run_at,
+                    ..enqueue_options::<A, W>(&args)
+                };
+                p.enqueue(W::class_name(), args, options)
+                    .await?
+                    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+            } else {
+                tracing::error!(
+                    "perform_later: background queue is selected, but queue was not populated in \
+                     context"
+                );
+                uuid::Uuid::new_v4().to_string()
+            }
+        }
+        WorkerMode::ForegroundBlocking => {
+            W::build(ctx).perform(args).await?;
+            uuid::Uuid::new_v4().to_string()
+        }
+        WorkerMode::BackgroundAsync => {
+            let dx = ctx.clone();
+            tokio::spawn(async move {
+                if let Some(delay) = run_at.and_then(|run_at| (run_at - Utc::now()).to_std().ok()) {
+                    tokio::time::sleep(delay).await;
+                }
+                if let Err(err) = W::build(&dx).perform(args).await {
+                    tracing::error!(err = err.to_string(), "worker failed to perform job");
+                }
+            });
+            uuid::Uuid::new_v4().to_string()
+        }
+    };
+    Ok(job_id)
+}
+
 /// Initialize the system according to configuration
 ///
 /// # Errors
@@ -754,18 +2022,24 @@ pub async fn converge(queue: &Queue, config: &QueueConfig) -> Result<()> {
         QueueConfig::Postgres(PostgresQueueConfig {
             dangerously_flush,
             uri: _,
+            use_app_db: _,
             max_connections: _,
             enable_logging: _,
             connect_timeout: _,
             idle_timeout: _,
             poll_interval_sec: _,
+            listen: _,
             num_workers: _,
             min_connections: _,
+            queues: _,
             reaper: _,
+            limits: _,
+            shutdown_timeout_sec: _,
         })
         | QueueConfig::Sqlite(SqliteQueueConfig {
             dangerously_flush,
             uri: _,
+            use_app_db: _,
             max_connections: _,
             enable_logging: _,
             connect_timeout: _,
@@ -773,26 +2047,37 @@ pub async fn converge(queue: &Queue, config: &QueueConfig) -> Result<()> {
             poll_interval_sec: _,
             num_workers: _,
             min_connections: _,
+            queues: _,
             reaper: _,
+            limits: _,
+            shutdown_timeout_sec: _,
         })
         | QueueConfig::Redis(RedisQueueConfig {
             dangerously_flush,
             uri: _,
             queues: _,
+            worker_queues: _,
             num_workers: _,
             reaper: _,
+            limits: _,
+            shutdown_timeout_sec: _,
         }) => {
             if *dangerously_flush {
                 tracing::warn!("Flush mode enabled - clearing all jobs from queue");
                 queue.clear().await?;
             }
         }
+        #[cfg(feature = "testing")]
+        QueueConfig::InMem => {}
     }
     Ok(())
 }
 
 /// Create a provider
 ///
+/// A queue with `use_app_db` needs the application's database connection:
+/// create it with [`create_queue_provider_with_db`] instead.
+///
 /// # Errors
 ///
 /// This function will return an error if fails to build
@@ -816,6 +2101,11 @@ pub async fn create_queue_provider(config: &Config) -> Result<Option<Arc<Queue>>
                     tracing::debug!("Creating SQLite queue provider");
                     Ok(Some(Arc::new(sqlt::create_provider(qcfg).await?)))
                 }
+                #[cfg(feature = "testing")]
+                config::QueueConfig::InMem => {
+                    tracing::debug!("Creating in-memory queue provider");
+                    Ok(Some(Arc::new(inmem::create_provider())))
+                }
 
                 #[allow(unreachable_patterns)]
                 _ => Err(Error::string(
@@ -833,6 +2123,48 @@ pub async fn create_queue_provider(config: &Config) -> Result<Option<Arc<Queue>>
     }
 }
 
+/// Create a provider like [`create_queue_provider`], but on `db` for a queue
+/// with `use_app_db`, so that it shares the application's connection pool.
+///
+/// # Errors
+///
+/// This function will return an error if fails to build
+#[cfg(feature = "with-db")]
+pub async fn create_queue_provider_with_db(
+    config: &Config,
+    db: &sea_orm::DatabaseConnection,
+) -> Result<Option<Arc<Queue>>> {
+    #[cfg(feature = "worker")]
+    if uses_app_db(config) {
+        return app_db_queue_provider(config, db);
+    }
+    create_queue_provider(config).await
+}
+
+#[cfg(all(feature = "with-db", feature = "worker"))]
+fn app_db_queue_provider(
+    config: &Config,
+    db: &sea_orm::DatabaseConnection,
+) -> Result<Option<Arc<Queue>>> {
+    match &config.queue {
+        Some(config::QueueConfig::Postgres(qcfg)) => {
+            tracing::debug!("Creating Postgres queue provider on the application database");
+            Ok(Some(Arc::new(pg::create_provider_with_db(qcfg, db)?)))
+        }
+        Some(config::QueueConfig::Sqlite(qcfg)) => {
+            tracing::debug!("Creating SQLite queue provider on the application database");
+            Ok(Some(Arc::new(sqlt::create_provider_with_db(qcfg, db)?)))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[cfg(all(feature = "with-db", feature = "worker"))]
+fn uses_app_db(config: &Config) -> bool {
+    config.workers.mode == config::WorkerMode::BackgroundQueue
+        && config.queue.as_ref().is_some_and(QueueConfig::use_app_db)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -849,6 +2181,7 @@ mod tests {
                 "sqlite://{}?mode=rwc",
                 db_path.join("sample.sqlite").display()
             ),
+            use_app_db: false,
             dangerously_flush: false,
             enable_logging: false,
             max_connections: 1,
@@ -858,6 +2191,9 @@ mod tests {
             poll_interval_sec: 1,
             num_workers: 1,
             reaper: None,
+            queues: Default::default(),
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         }
     }
 
@@ -933,4 +2269,148 @@ mod tests {
 
         assert_eq!(count, 14);
     }
+
+    #[tokio::test]
+    async fn imported_jobs_keep_their_queue_and_run_at() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let source = sqlt::create_provider(&sqlite_config(tree_fs.root.as_path()))
+            .await
+            .expect("create sqlite queue");
+        source.setup().await.expect("setup sqlite db");
+        let run_at = "2099-01-01T10:00:00Z".parse().unwrap();
+        source
+            .enqueue(
+                "MailerWorker".to_string(),
+                serde_json::json!({"user_id": 1}),
+                EnqueueOptions {
+                    queue: Some("mailers".to_string()),
+                    tags: Some(vec!["email".to_string()]),
+                    priority: Some(5),
+                    run_at: Some(run_at),
+                    args_version: 2,
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .expect("enqueue");
+        let dump_file = source
+            .dump(tree_fs.root.as_path(), None, None)
+            .await
+            .expect("dump jobs");
+
+        let target_dir = tree_fs.root.join("target");
+        std::fs::create_dir_all(&target_dir).unwrap();
+        let target = sqlt::create_provider(&sqlite_config(&target_dir))
+            .await
+            .expect("create sqlite queue");
+        target.setup().await.expect("setup sqlite db");
+        target.import(&dump_file).await.expect("import jobs");
+
+        let jobs = target.get_jobs(None, None).await.expect("get jobs");
+        assert_eq!(jobs.len(), 1);
+        let job = &jobs[0];
+        assert_eq!(job.name, "MailerWorker");
+        assert_eq!(job.data, serde_json::json!({"user_id": 1}));
+        assert_eq!(job.queue.as_deref(), Some("mailers"));
+        assert_eq!(job.tags, Some(vec!["email".to_string()]));
+        assert_eq!(job.priority, 5);
+        assert_eq!(job.run_at, run_at);
+        assert_eq!(job.args_version, 2);
+    }
+
+    #[test]
+    fn exponential_backoff_doubles_up_to_max() {
+        let backoff = Backoff::Exponential {
+            base: Duration::from_secs(5),
+            max: Duration::from_secs(30),
+            jitter: false,
+        };
+        assert_eq!(backoff.delay(1), Duration::from_secs(5));
+        assert_eq!(backoff.delay(2), Duration::from_secs(10));
+        assert_eq!(backoff.delay(3), Duration::from_secs(20));
+        assert_eq!(backoff.delay(4), Duration::from_secs(30));
+        assert_eq!(backoff.delay(100), Duration::from_secs(30));
+
+        let jittered = Backoff::Exponential {
+            base: Duration::from_secs(8),
+            max: Duration::from_secs(60),
+            jitter: true,
+        };
+        for _ in 0..20 {
+            let delay = jittered.delay(2);
+            assert!(delay >= Duration::from_secs(8) && delay <= Duration::from_secs(16));
+        }
+    }
+
+    #[test]
+    fn attempts_keep_where_their_error_came_from() {
+        let started_at = Utc::now();
+        let ok = JobAttempt::new(1, started_at, &Ok(()));
+        assert_eq!((ok.error, ok.backtrace), (None, None));
+
+        let failed = JobAttempt::new(2, started_at, &Err(Error::string("boom")));
+        assert_eq!(failed.error.as_deref(), Some("boom"));
+        assert_eq!(failed.backtrace, None);
+
+        let traced = Error::WithBacktrace {
+            inner: Box::new(Error::string("boom")),
+            backtrace: Box::new(std::backtrace::Backtrace::force_capture()),
+        };
+        let failed = JobAttempt::new(3, started_at, &Err(traced));
+        assert_eq!(failed.error.as_deref(), Some("boom"));
+        assert!(failed.backtrace.is_some_and(
+            |backtrace| backtrace.contains("attempts_keep_where_their_error_came_from")
+        ));
+    }
+
+    #[test]
+    fn next_run_at_stops_after_max_retries() {
+        let opts = WorkerOpts {
+            max_retries: 2,
+            backoff: Backoff::Fixed(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let before = Utc::now();
+        let run_at = opts.next_run_at(1).expect("first retry");
+        assert!(run_at >= before + chrono::Duration::seconds(60));
+        assert!(opts.next_run_at(2).is_some());
+        assert!(opts.next_run_at(3).is_none());
+        assert!(WorkerOpts::default().next_run_at(1).is_none());
+    }
+
+    #[test]
+    fn queue_config_limits_override_the_worker_limits() {
+        let limits: WorkerLimitsConfig = serde_yaml::from_str(
+            "concurrency: 2
+rate_limit:
+  limit: 10",
+        )
+        .expect("parse limits");
+        let opts = WorkerOpts {
+            concurrency: Some(5),
+            rate_limit: Some(RateLimit::per_minute(1)),
+            ..Default::default()
+        }
+        .with_limits(Some(&limits));
+        assert_eq!(opts.concurrency, Some(2));
+        assert_eq!(opts.rate_limit, Some(RateLimit::per_second(10)));
+
+        let unchanged = WorkerOpts {
+            concurrency: Some(5),
+            ..Default::default()
+        }
+        .with_limits(None);
+        assert_eq!(unchanged.concurrency, Some(5));
+        assert!(unchanged.is_limited());
+        assert!(!WorkerOpts::default().is_limited());
+    }
+
+    #[tokio::test]
+    async fn reporting_outside_a_queued_job_is_a_no_op() {
+        assert!(report_progress(50).await.is_ok());
+        assert!(report_result(&"done").await.is_ok());
+    }
 }
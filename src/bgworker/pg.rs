@@ -1,27 +1,33 @@
 /// Postgres based background job queue provider
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-pub use super::sql::{Job, JobData, JobId, JobRegistry, RunOpts};
+pub use super::sql::{Job, JobAttempt, JobData, JobId, JobRegistry, RunOpts};
 use super::{
-    sql::{to_job, Driver},
-    JobHandler, JobStatus, Queue, QueueProvider,
+    batch::{BatchSpec, BatchStatus, ChainStep, JobBatch, JobSpec},
+    sql::{to_batch, to_job, Driver, DEFAULT_QUEUE},
+    EnqueueOptions, JobFilter, JobHandler, JobStatus, Queue, QueueProvider, UniqueJob,
+    UniqueWindow, WorkerOpts,
+};
+use crate::{
+    config::{PostgresQueueConfig, WorkerLimitsConfig},
+    Error, Result,
 };
-use crate::{config::PostgresQueueConfig, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 pub use sqlx::PgPool;
 use sqlx::{
-    postgres::{PgConnectOptions, PgPoolOptions, PgRow},
-    AssertSqlSafe, ConnectOptions,
+    postgres::{PgConnectOptions, PgListener, PgPoolOptions, PgRow},
+    AssertSqlSafe, ConnectOptions, PgConnection, Row,
 };
 use std::fmt::Write;
 use std::sync::Arc;
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, trace};
+use tracing::{debug, error, trace, warn};
 use ulid::Ulid;
 
 /// [`Driver`] implementation delegating to the Postgres-specific
-/// `dequeue`/`complete_job`/`fail_job` free functions below.
+/// `dequeue`/`complete_job`/`fail_job`/`retry_job` free functions below.
 pub struct PgDriver;
 
 impl Driver for PgDriver {
@@ -31,8 +37,13 @@ impl Driver for PgDriver {
         pool.num_idle()
     }
 
-    async fn dequeue(pool: &Self::Pool, tags: &[String]) -> crate::Result<Option<Job>> {
-        dequeue(pool, tags).await
+    async fn dequeue(
+        pool: &Self::Pool,
+        tags: &[String],
+        queues: &[String],
+        worker_opts: &HashMap<String, WorkerOpts>,
+    ) -> crate::Result<Option<Job>> {
+        dequeue(pool, tags, queues, worker_opts).await
     }
 
     async fn complete_job(
@@ -46,6 +57,47 @@ impl Driver for PgDriver {
     async fn fail_job(pool: &Self::Pool, id: &JobId, error: &crate::Error) -> crate::Result<()> {
         fail_job(pool, id, error).await
     }
+
+    async fn cancel_job(pool: &Self::Pool, id: &JobId, error: &crate::Error) -> crate::Result<()> {
+        cancel_job(pool, id, error).await
+    }
+
+    async fn cancel_requested(pool: &Self::Pool, id: &JobId) -> crate::Result<bool> {
+        cancel_requested(pool, id).await
+    }
+
+    async fn requeue_job(pool: &Self::Pool, id: &JobId) -> crate::Result<()> {
+        requeue_job(pool, id).await
+    }
+
+    async fn retry_job(
+        pool: &Self::Pool,
+        id: &JobId,
+        run_at: DateTime<Utc>,
+        error: &crate::Error,
+    ) -> crate::Result<()> {
+        retry_job(pool, id, run_at, error).await
+    }
+
+    async fn record_attempt(
+        pool: &Self::Pool,
+        id: &JobId,
+        attempt: &JobAttempt,
+    ) -> crate::Result<()> {
+        record_attempt(pool, id, attempt).await
+    }
+
+    async fn set_progress(pool: &Self::Pool, id: &JobId, percent: u8) -> crate::Result<()> {
+        set_progress(pool, id, percent).await
+    }
+
+    async fn set_result(
+        pool: &Self::Pool,
+        id: &JobId,
+        result: serde_json::Value,
+    ) -> crate::Result<()> {
+        set_result(pool, id, result).await
+    }
 }
 
 /// The Postgres [`QueueProvider`].
@@ -54,6 +106,10 @@ pub struct PgQueue {
     pub registry: Arc<tokio::sync::Mutex<JobRegistry>>,
     pub run_opts: RunOpts,
     pub token: CancellationToken,
+    /// Per-worker limits from the queue config, applied on registration.
+    pub limits: HashMap<String, WorkerLimitsConfig>,
+    /// Whether idle workers `LISTEN` for enqueued jobs (see [`listen`]).
+    pub listen: bool,
 }
 
 #[async_trait]
@@ -61,29 +117,46 @@ impl QueueProvider for PgQueue {
     async fn enqueue(
         &self,
         class: String,
-        _queue: Option<String>,
         args: serde_json::Value,
-        tags: Option<Vec<String>>,
-        priority: Option<i32>,
+        options: EnqueueOptions,
     ) -> Result<Option<String>> {
-        Ok(Some(
-            enqueue(
-                &self.pool,
-                &class,
-                args,
-                chrono::Utc::now(),
-                None,
-                tags,
-                priority,
-            )
-            .await
-            .map_err(Box::from)?,
-        ))
+        let id = enqueue(&self.pool, &class, args, None, &options).await;
+        Ok(Some(id.map_err(Box::from)?))
+    }
+
+    #[cfg(feature = "with-db")]
+    async fn enqueue_in_transaction(
+        &self,
+        txn: &sea_orm::DatabaseTransaction,
+        class: String,
+        args: serde_json::Value,
+        options: EnqueueOptions,
+    ) -> Result<Option<String>> {
+        let id = enqueue_in_transaction(txn, &class, args, &options).await?;
+        Ok(Some(id))
+    }
+
+    async fn enqueue_chain(&self, job: JobSpec) -> Result<Option<String>> {
+        Ok(Some(enqueue_chain(&self.pool, &job).await?))
+    }
+
+    async fn enqueue_batch(&self, batch: BatchSpec) -> Result<Option<String>> {
+        Ok(Some(enqueue_batch(&self.pool, &batch).await?))
     }
 
-    async fn register_handler(&self, name: String, handler: JobHandler) -> Result<()> {
+    async fn get_batches(&self) -> Result<Vec<JobBatch>> {
+        get_batches(&self.pool).await
+    }
+
+    async fn register_handler(
+        &self,
+        name: String,
+        handler: JobHandler,
+        opts: WorkerOpts,
+    ) -> Result<()> {
+        let opts = opts.with_limits(self.limits.get(&name));
         let mut registry = self.registry.lock().await;
-        registry.insert_handler(name, handler)
+        registry.insert_handler(name, handler, opts)
     }
 
     async fn run(&self, tags: Vec<String>) -> Result<()> {
@@ -104,11 +177,22 @@ impl QueueProvider for PgQueue {
                 }
             });
         }
-        let handles = self.registry.lock().await.run::<PgDriver>(
+        let wakeups = self.listen.then(|| {
+            let (wakeup, wakeups) = watch::channel(());
+            tokio::spawn(listen(
+                self.pool.clone(),
+                self.token.clone(),
+                wakeup,
+                Duration::from_secs(self.run_opts.poll_interval_sec.into()),
+            ));
+            wakeups
+        });
+        let handles = self.registry.lock().await.run_with_wakeups::<PgDriver>(
             &self.pool,
             &self.run_opts,
             &self.token.clone(),
             &tags,
+            wakeups,
         );
         super::process_worker_handles(handles).await
     }
@@ -136,6 +220,22 @@ impl QueueProvider for PgQueue {
         get_jobs(&self.pool, status, age_days).await
     }
 
+    async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        get_job(&self.pool, id).await
+    }
+
+    async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<Job>> {
+        list_jobs(&self.pool, filter).await
+    }
+
+    async fn count_by_status(&self) -> Result<HashMap<JobStatus, u64>> {
+        count_by_status(&self.pool).await
+    }
+
+    async fn stats(&self) -> Result<super::stats::QueueStats> {
+        stats(&self.pool).await
+    }
+
     async fn cancel_jobs_by_name(&self, name: &str) -> Result<()> {
         cancel_jobs_by_name(&self.pool, name).await
     }
@@ -170,7 +270,7 @@ impl QueueProvider for PgQueue {
     }
 }
 
-async fn connect(cfg: &PostgresQueueConfig) -> Result<PgPool> {
+pub(crate) async fn connect(cfg: &PostgresQueueConfig) -> Result<PgPool> {
     let mut conn_opts: PgConnectOptions = cfg.uri.parse()?;
     if !cfg.enable_logging {
         conn_opts = conn_opts.disable_statement_logging();
@@ -185,6 +285,103 @@ async fn connect(cfg: &PostgresQueueConfig) -> Result<PgPool> {
     Ok(pool)
 }
 
+/// The channel enqueueing a job notifies, and idle workers listen on.
+const NOTIFY_CHANNEL: &str = "pg_loco_queue";
+
+/// The savepoint a notification inside a transaction is sent under.
+const NOTIFY_SAVEPOINT: &str = "loco_queue_notify";
+
+/// Tells idle workers a job is ready.
+///
+/// The job is already written when this runs, and workers poll for it anyway,
+/// so a failed notification is logged rather than failing the enqueue.
+async fn notify_enqueued<'e, E>(executor: E)
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    if let Err(err) = sqlx::query("SELECT pg_notify($1, '')")
+        .bind(NOTIFY_CHANNEL)
+        .execute(executor)
+        .await
+    {
+        warn!(err = %err, "Failed to notify workers of an enqueued job");
+    }
+}
+
+/// [`notify_enqueued`] inside the transaction `conn` is in; the notification
+/// goes out on commit. It runs under a savepoint, so a failed notification
+/// does not abort the transaction.
+///
+/// # Errors
+///
+/// This function will return an error if the savepoint cannot be set or
+/// rolled back to.
+async fn notify_enqueued_in_transaction(conn: &mut PgConnection) -> Result<()> {
+    sqlx::query(AssertSqlSafe(format!("SAVEPOINT {NOTIFY_SAVEPOINT}")))
+        .execute(&mut *conn)
+        .await?;
+    let end = match sqlx::query("SELECT pg_notify($1, '')")
+        .bind(NOTIFY_CHANNEL)
+        .execute(&mut *conn)
+        .await
+    {
+        Ok(_) => "RELEASE SAVEPOINT",
+        Err(err) => {
+            warn!(err = %err, "Failed to notify workers of an enqueued job");
+            "ROLLBACK TO SAVEPOINT"
+        }
+    };
+    sqlx::query(AssertSqlSafe(format!("{end} {NOTIFY_SAVEPOINT}")))
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Listens on [`NOTIFY_CHANNEL`] until `token` is cancelled, waking the
+/// workers behind `wakeup` whenever a job is enqueued. Notifications sent
+/// while the connection is down are lost, so workers are also woken after
+/// it comes back; if listening fails altogether, they keep polling every
+/// `poll_interval`.
+async fn listen(
+    pool: PgPool,
+    token: CancellationToken,
+    wakeup: watch::Sender<()>,
+    poll_interval: Duration,
+) {
+    let mut listener = match PgListener::connect_with(&pool).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!(error = %err, "Failed to listen for enqueued jobs, falling back to polling");
+            return;
+        }
+    };
+    if let Err(err) = listener.listen(NOTIFY_CHANNEL).await {
+        error!(error = %err, "Failed to listen for enqueued jobs, falling back to polling");
+        return;
+    }
+    loop {
+        tokio::select! {
+            () = token.cancelled() => break,
+            notification = listener.try_recv() => match notification {
+                Ok(Some(_)) => {
+                    wakeup.send_replace(());
+                }
+                Ok(None) => {
+                    debug!("Job notification connection lost, reconnecting");
+                    wakeup.send_replace(());
+                }
+                Err(err) => {
+                    error!(error = %err, "Failed to receive job notification");
+                    tokio::select! {
+                        () = token.cancelled() => break,
+                        () = tokio::time::sleep(poll_interval) => {}
+                    }
+                }
+            },
+        }
+    }
+}
+
 /// Initialize job tables
 ///
 /// # Errors
@@ -204,23 +401,20 @@ pub async fn initialize_database(pool: &PgPool) -> Result<()> {
     .await?;
 
     if table_exists {
-        // Auto-migrate: add the priority column to pre-existing tables.
-        let priority_exists: bool = sqlx::query_scalar(
-            "SELECT EXISTS (
-                SELECT FROM information_schema.columns
-                WHERE table_name = 'pg_loco_queue'
-                AND column_name = 'priority'
-            )",
-        )
-        .fetch_one(pool)
-        .await?;
-
-        if !priority_exists {
-            debug!("Adding priority column to existing pg_loco_queue table");
-            sqlx::query("ALTER TABLE pg_loco_queue ADD COLUMN priority INT NOT NULL DEFAULT 0")
-                .execute(pool)
-                .await?;
-        }
+        // Auto-migrate: add columns introduced after the table was created.
+        add_column_if_missing(pool, "priority", "INT NOT NULL DEFAULT 0").await?;
+        add_column_if_missing(pool, "attempts", "INT NOT NULL DEFAULT 0").await?;
+        add_column_if_missing(pool, "last_error", "TEXT").await?;
+        add_column_if_missing(pool, "history", "JSONB NOT NULL DEFAULT '[]'::jsonb").await?;
+        add_column_if_missing(pool, "unique_key", "TEXT").await?;
+        add_column_if_missing(pool, "progress", "INT").await?;
+        add_column_if_missing(pool, "result", "JSONB").await?;
+        add_column_if_missing(pool, "batch_id", "VARCHAR").await?;
+        add_column_if_missing(pool, "chain", "JSONB NOT NULL DEFAULT '[]'::jsonb").await?;
+        add_column_if_missing(pool, "started_at", "TIMESTAMPTZ").await?;
+        add_column_if_missing(pool, "cancel_requested", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+        add_column_if_missing(pool, "queue", "VARCHAR NOT NULL DEFAULT 'default'").await?;
+        add_column_if_missing(pool, "args_version", "INT NOT NULL DEFAULT 1").await?;
     } else {
         sqlx::raw_sql(AssertSqlSafe(format!(
             r"
@@ -234,7 +428,19 @@ pub async fn initialize_database(pool: &PgPool) -> Result<()> {
                     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
                     updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
                     tags JSONB,
-                    priority INT NOT NULL DEFAULT 0
+                    priority INT NOT NULL DEFAULT 0,
+                    attempts INT NOT NULL DEFAULT 0,
+                    last_error TEXT,
+                    history JSONB NOT NULL DEFAULT '[]'::jsonb,
+                    unique_key TEXT,
+                    progress INT,
+                    result JSONB,
+                    batch_id VARCHAR,
+                    chain JSONB NOT NULL DEFAULT '[]'::jsonb,
+                    started_at TIMESTAMPTZ,
+                    cancel_requested BOOLEAN NOT NULL DEFAULT FALSE,
+                    queue VARCHAR NOT NULL DEFAULT 'default',
+                    args_version INT NOT NULL DEFAULT 1
                 );
                 ",
             JobStatus::Queued
@@ -243,10 +449,62 @@ pub async fn initialize_database(pool: &PgPool) -> Result<()> {
         .await?;
     }
 
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS pg_loco_queue_unique_key ON pg_loco_queue (unique_key) \
+         WHERE unique_key IS NOT NULL",
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::raw_sql(AssertSqlSafe(format!(
+        r"
+            CREATE TABLE IF NOT EXISTS pg_loco_batches (
+                id VARCHAR NOT NULL PRIMARY KEY,
+                status VARCHAR NOT NULL DEFAULT '{}',
+                total INT NOT NULL,
+                completed INT NOT NULL DEFAULT 0,
+                failed INT NOT NULL DEFAULT 0,
+                on_complete JSONB,
+                on_failure JSONB,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+            ",
+        BatchStatus::Pending
+    )))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Adds `column` with the given SQL `definition` to a pre-existing
+/// `pg_loco_queue` table, if it's not there yet.
+async fn add_column_if_missing(pool: &PgPool, column: &str, definition: &str) -> Result<()> {
+    let column_exists: bool = sqlx::query_scalar(
+        "SELECT EXISTS (
+            SELECT FROM information_schema.columns
+            WHERE table_name = 'pg_loco_queue'
+            AND column_name = $1
+        )",
+    )
+    .bind(column)
+    .fetch_one(pool)
+    .await?;
+
+    if !column_exists {
+        debug!(column, "Adding column to existing pg_loco_queue table");
+        sqlx::query(AssertSqlSafe(format!(
+            "ALTER TABLE pg_loco_queue ADD COLUMN {column} {definition}"
+        )))
+        .execute(pool)
+        .await?;
+    }
     Ok(())
 }
 
-/// Add a job
+/// Add a job as `options` say, recurring every `interval` if given. A
+/// unique job goes through [`enqueue_unique`], and does not recur.
 ///
 /// # Errors
 ///
@@ -255,13 +513,15 @@ pub async fn enqueue(
     pool: &PgPool,
     name: &str,
     data: JobData,
-    run_at: DateTime<Utc>,
     interval: Option<Duration>,
-    tags: Option<Vec<String>>,
-    priority: Option<i32>,
+    options: &EnqueueOptions,
 ) -> Result<JobId> {
+    if let Some(unique) = &options.unique {
+        return enqueue_unique(pool, unique, name, data, options).await;
+    }
+    let run_at = options.run_at.unwrap_or_else(Utc::now);
     let data_json = serde_json::to_value(data)?;
-    let tags_json = match &tags {
+    let tags_json = match &options.tags {
         Some(tags) => Some(serde_json::to_value(tags)?),
         None => None,
     };
@@ -270,10 +530,10 @@ pub async fn enqueue(
     let interval_ms: Option<i64> = interval.map(|i| i.as_millis() as i64);
 
     let id = Ulid::new().to_string();
-    debug!(job_id = %id, job_name = %name, run_at = %run_at, tags = ?tags, "Enqueueing job");
+    debug!(job_id = %id, job_name = %name, run_at = %run_at, tags = ?options.tags, "Enqueueing job");
     sqlx::query(
-        "INSERT INTO pg_loco_queue (id, task_data, name, run_at, interval, tags, priority) VALUES \
-         ($1, $2, $3, $4, $5, $6, $7)",
+        "INSERT INTO pg_loco_queue (id, task_data, name, run_at, interval, tags, priority, queue, \
+         args_version) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
     )
     .bind(id.clone())
     .bind(data_json)
@@ -281,147 +541,716 @@ pub async fn enqueue(
     .bind(run_at)
     .bind(interval_ms)
     .bind(tags_json)
-    .bind(priority.unwrap_or(0))
+    .bind(options.priority.unwrap_or(0))
+    .bind(options.queue.as_deref().unwrap_or(DEFAULT_QUEUE))
+    .bind(i64::from(options.args_version))
     .execute(pool)
     .await?;
+    if run_at <= Utc::now() {
+        notify_enqueued(pool).await;
+    }
     Ok(id)
 }
 
-async fn dequeue(client: &PgPool, worker_tags: &[String]) -> Result<Option<Job>> {
-    let mut tx = client.begin().await?;
+/// SQL condition matching jobs still inside `window`; a [`UniqueWindow::For`]
+/// takes its length as the second bind parameter.
+fn in_unique_window(window: &UniqueWindow) -> String {
+    match window {
+        UniqueWindow::Queued => format!("status = '{}'", JobStatus::Queued),
+        UniqueWindow::QueuedOrProcessing => format!(
+            "status IN ('{}', '{}')",
+            JobStatus::Queued,
+            JobStatus::Processing
+        ),
+        UniqueWindow::For(_) => "created_at > NOW() - $2 * INTERVAL '1 second'".to_string(),
+    }
+}
 
-    let mut query = String::from(
-        "SELECT id, name, task_data, status, run_at, interval, tags, priority FROM pg_loco_queue WHERE status = $1 AND run_at <= NOW() ",
+/// Add a job as `options` say, unless one holding the same
+/// [`UniqueJob::key`] as `unique` is still inside its window, in which case
+/// that job's id is returned instead.
+///
+/// A key is released lazily: the job keeps it in its row until the next
+/// enqueue with the same key finds the job outside the window.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_unique(
+    pool: &PgPool,
+    unique: &UniqueJob,
+    name: &str,
+    data: JobData,
+    options: &EnqueueOptions,
+) -> Result<JobId> {
+    let run_at = options.run_at.unwrap_or_else(Utc::now);
+    let tags_json = match &options.tags {
+        Some(tags) => Some(serde_json::to_value(tags)?),
+        None => None,
+    };
+    let in_window = in_unique_window(&unique.window);
+
+    let mut tx = pool.begin().await?;
+    let query = format!(
+        "UPDATE pg_loco_queue SET unique_key = NULL WHERE unique_key = $1 AND NOT ({in_window})"
     );
+    let mut release = sqlx::query(AssertSqlSafe(query)).bind(&unique.key);
+    if let UniqueWindow::For(window) = &unique.window {
+        release = release.bind(window.as_secs_f64());
+    }
+    release.execute(&mut *tx).await?;
 
-    // An untagged worker takes only untagged jobs; a tagged one takes any job
-    // carrying at least one of its tags. `?` is jsonb's "array contains this
-    // string" operator, one bind per tag starting at $2.
-    if worker_tags.is_empty() {
-        query.push_str("AND (tags IS NULL) ");
+    let id = Ulid::new().to_string();
+    let inserted = sqlx::query(
+        "INSERT INTO pg_loco_queue (id, task_data, name, run_at, tags, priority, unique_key, \
+         queue, args_version) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT DO NOTHING",
+    )
+    .bind(&id)
+    .bind(data)
+    .bind(name)
+    .bind(run_at)
+    .bind(tags_json)
+    .bind(options.priority.unwrap_or(0))
+    .bind(&unique.key)
+    .bind(options.queue.as_deref().unwrap_or(DEFAULT_QUEUE))
+    .bind(i64::from(options.args_version))
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let id = if inserted == 0 {
+        let existing: Option<String> =
+            sqlx::query_scalar("SELECT id FROM pg_loco_queue WHERE unique_key = $1")
+                .bind(&unique.key)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let existing =
+            existing.ok_or_else(|| crate::Error::string("unique job key is held by no job"))?;
+        debug!(job_id = %existing, job_name = %name, unique_key = %unique.key, "Skipping duplicate of unique job");
+        existing
     } else {
-        let any_tag_matches = (0..worker_tags.len())
-            .map(|i| format!("(tags)::jsonb ? ${}", i + 2))
-            .collect::<Vec<_>>()
-            .join(" OR ");
-        query.push_str("AND (tags IS NOT NULL) AND (");
-        query.push_str(&any_tag_matches);
-        query.push(')');
+        debug!(job_id = %id, job_name = %name, run_at = %run_at, unique_key = %unique.key, "Enqueueing unique job");
+        id
+    };
+    tx.commit().await?;
+    if inserted > 0 && run_at <= Utc::now() {
+        notify_enqueued(pool).await;
     }
+    Ok(id)
+}
 
-    query.push_str(" ORDER BY priority DESC, run_at, id LIMIT 1 FOR UPDATE SKIP LOCKED");
+/// Add a job as part of the sea-orm transaction `txn`, so that workers only
+/// see it once `txn` commits and never if it rolls back. `txn` has to be on
+/// the database holding `pg_loco_queue`. Unique jobs are deduplicated as in
+/// [`enqueue_unique`].
+///
+/// # Errors
+///
+/// This function will return an error if it fails, or if `txn` is not on a
+/// Postgres database
+#[cfg(feature = "with-db")]
+pub async fn enqueue_in_transaction(
+    txn: &sea_orm::DatabaseTransaction,
+    name: &str,
+    data: JobData,
+    options: &EnqueueOptions,
+) -> Result<JobId> {
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
 
-    // Create the query
-    let mut db_query = sqlx::query(AssertSqlSafe(query)).bind(JobStatus::Queued.to_string());
+    let unique = options.unique.as_ref();
+    let run_at = options.run_at.unwrap_or_else(Utc::now);
 
-    // Bind tag parameters
-    for tag in worker_tags {
-        db_query = db_query.bind(tag);
+    if txn.get_database_backend() != DbBackend::Postgres {
+        return Err(Error::string(
+            "the postgres queue can only enqueue in a transaction on a Postgres database",
+        ));
+    }
+    let tags_json = options
+        .tags
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()?;
+
+    if let Some(unique) = unique {
+        let mut values: Vec<sea_orm::Value> = vec![unique.key.clone().into()];
+        if let UniqueWindow::For(window) = &unique.window {
+            values.push(window.as_secs_f64().into());
+        }
+        txn.execute_raw(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            format!(
+                "UPDATE pg_loco_queue SET unique_key = NULL WHERE unique_key = $1 AND NOT ({})",
+                in_unique_window(&unique.window)
+            ),
+            values,
+        ))
+        .await?;
     }
 
-    let row = db_query
-        .map(|row: PgRow| to_job(&row).ok())
-        .fetch_optional(&mut *tx)
+    let id = Ulid::new().to_string();
+    let inserted = txn
+        .execute_raw(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "INSERT INTO pg_loco_queue (id, task_data, name, run_at, tags, priority, unique_key, \
+             queue, args_version) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) ON CONFLICT DO \
+             NOTHING",
+            [
+                id.clone().into(),
+                data.into(),
+                name.into(),
+                run_at.into(),
+                tags_json.into(),
+                options.priority.unwrap_or(0).into(),
+                unique.map(|unique| unique.key.clone()).into(),
+                options.queue.as_deref().unwrap_or(DEFAULT_QUEUE).into(),
+                i64::from(options.args_version).into(),
+            ],
+        ))
         .await?
-        .flatten();
-
-    if let Some(job) = row {
-        trace!(job_id = %job.id, job_name = %job.name, job_tags = ?job.tags, "Dequeueing job for processing");
-        sqlx::query("UPDATE pg_loco_queue SET status = $1, updated_at = NOW() WHERE id = $2")
-            .bind(JobStatus::Processing.to_string())
-            .bind(&job.id)
-            .execute(&mut *tx)
-            .await?;
-
-        tx.commit().await?;
-
-        Ok(Some(job))
-    } else {
-        Ok(None)
+        .rows_affected();
+
+    if let Some(unique) = unique.filter(|_| inserted == 0) {
+        let existing = txn
+            .query_one_raw(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT id FROM pg_loco_queue WHERE unique_key = $1",
+                [unique.key.clone().into()],
+            ))
+            .await?
+            .ok_or_else(|| crate::Error::string("unique job key is held by no job"))?
+            .try_get::<String>("", "id")?;
+        debug!(job_id = %existing, job_name = %name, unique_key = %unique.key, "Skipping duplicate of unique job");
+        return Ok(existing);
     }
-}
-
-async fn complete_job(pool: &PgPool, id: &JobId, interval_ms: Option<i64>) -> Result<()> {
-    if let Some(interval_ms) = interval_ms {
-        let next_run_at = Utc::now() + chrono::Duration::milliseconds(interval_ms);
-        trace!(
-            job_id = %id,
-            status = "queued",
-            run_at = %next_run_at,
-            "Rescheduling recurring job"
-        );
-        sqlx::query(
-            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), run_at = $2 WHERE id = $3",
-        )
-        .bind(JobStatus::Queued.to_string())
-        .bind(next_run_at)
-        .bind(id)
-        .execute(pool)
-        .await?;
-    } else {
-        trace!(job_id = %id, status = "completed", "Marking job as completed");
-        sqlx::query("UPDATE pg_loco_queue SET status = $1, updated_at = NOW() WHERE id = $2")
-            .bind(JobStatus::Completed.to_string())
-            .bind(id)
-            .execute(pool)
+    debug!(job_id = %id, job_name = %name, run_at = %run_at, "Enqueueing job in transaction");
+    if run_at <= Utc::now() {
+        // As in `notify_enqueued_in_transaction`: a failed notification must
+        // not abort the caller's transaction.
+        txn.execute_unprepared(&format!("SAVEPOINT {NOTIFY_SAVEPOINT}"))
+            .await?;
+        let end = match txn
+            .execute_raw(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                "SELECT pg_notify($1, '')",
+                [NOTIFY_CHANNEL.into()],
+            ))
+            .await
+        {
+            Ok(_) => "RELEASE SAVEPOINT",
+            Err(err) => {
+                warn!(err = %err, "Failed to notify workers of an enqueued job");
+                "ROLLBACK TO SAVEPOINT"
+            }
+        };
+        txn.execute_unprepared(&format!("{end} {NOTIFY_SAVEPOINT}"))
             .await?;
     }
-
-    Ok(())
+    Ok(id)
 }
 
-async fn fail_job(pool: &PgPool, id: &JobId, error: &crate::Error) -> Result<()> {
-    let msg = error.to_string();
-    debug!(job_id = %id, error = %msg, "Marking job as failed");
-    let error_json = serde_json::json!({ "error": msg });
+/// Inserts the job described by `spec`, as a member of `batch_id` if given,
+/// in the transaction `conn` is in.
+async fn insert_spec(
+    conn: &mut PgConnection,
+    spec: &JobSpec,
+    batch_id: Option<&str>,
+) -> Result<JobId> {
+    let tags_json = match &spec.tags {
+        Some(tags) => Some(serde_json::to_value(tags)?),
+        None => None,
+    };
+    let id = Ulid::new().to_string();
+    debug!(job_id = %id, job_name = %spec.class, batch_id = ?batch_id, steps_left = spec.chain.len(), "Enqueueing job");
     sqlx::query(
-        "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), task_data = task_data || \
-         $2::jsonb WHERE id = $3",
+        "INSERT INTO pg_loco_queue (id, task_data, name, run_at, tags, priority, batch_id, \
+         chain, queue, args_version) VALUES ($1, $2, $3, NOW(), $4, $5, $6, $7, $8, $9)",
     )
-    .bind(JobStatus::Failed.to_string())
-    .bind(error_json)
-    .bind(id)
-    .execute(pool)
+    .bind(&id)
+    .bind(&spec.args)
+    .bind(&spec.class)
+    .bind(tags_json)
+    .bind(spec.priority.unwrap_or(0))
+    .bind(batch_id)
+    .bind(serde_json::to_value(&spec.chain)?)
+    .bind(spec.queue.as_deref().unwrap_or(DEFAULT_QUEUE))
+    .bind(i64::from(spec.args_version))
+    .execute(&mut *conn)
     .await?;
-    Ok(())
+    notify_enqueued_in_transaction(conn).await?;
+    Ok(id)
 }
 
-/// Cancels jobs in the `pg_loco_queue` table by their name.
-///
-/// This function updates the status of all jobs with the given `name` and a status of
-/// [`JobStatus::Queued`] to [`JobStatus::Cancelled`]. The update also sets the `updated_at` timestamp to the
-/// current time.
+/// Add the first job of a chain, carrying the remaining steps
 ///
 /// # Errors
 ///
 /// This function will return an error if it fails
-pub async fn cancel_jobs_by_name(pool: &PgPool, name: &str) -> Result<()> {
-    debug!(job_name = %name, "Cancelling queued jobs by name");
-    sqlx::query(
-        "UPDATE pg_loco_queue SET status = $1, updated_at = NOW() WHERE name = $2 AND status = $3",
-    )
-    .bind(JobStatus::Cancelled.to_string())
-    .bind(name)
-    .bind(JobStatus::Queued.to_string())
-    .execute(pool)
-    .await?;
-    Ok(())
+pub async fn enqueue_chain(pool: &PgPool, job: &JobSpec) -> Result<JobId> {
+    let mut tx = pool.begin().await?;
+    let id = insert_spec(&mut tx, job, None).await?;
+    tx.commit().await?;
+    Ok(id)
 }
 
-/// Clear all jobs
+/// Record a batch and add its jobs, returning the batch id
 ///
 /// # Errors
 ///
 /// This function will return an error if it fails
-pub async fn clear(pool: &PgPool) -> Result<()> {
-    sqlx::query("DELETE FROM pg_loco_queue")
-        .execute(pool)
+pub async fn enqueue_batch(pool: &PgPool, batch: &BatchSpec) -> Result<String> {
+    let total = i32::try_from(batch.jobs.len()).map_err(|_| Error::string("batch is too large"))?;
+    let id = Ulid::new().to_string();
+    debug!(batch_id = %id, total, "Enqueueing batch");
+
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO pg_loco_batches (id, total, on_complete, on_failure) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&id)
+    .bind(total)
+    .bind(
+        batch
+            .on_complete
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?,
+    )
+    .bind(
+        batch
+            .on_failure
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?,
+    )
+    .execute(&mut *tx)
+    .await?;
+    for job in &batch.jobs {
+        insert_spec(&mut tx, job, Some(&id)).await?;
+    }
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Enqueues what follows a completed job: the next step of its chain, or,
+/// when it was the last step, its batch's `on_complete` if this was the last
+/// member of the batch to complete.
+async fn enqueue_completion_follow_ups(
+    conn: &mut PgConnection,
+    batch_id: Option<String>,
+    chain: &[ChainStep],
+    result: Option<serde_json::Value>,
+) -> Result<()> {
+    if let Some(next) = JobSpec::continue_chain(chain, result) {
+        insert_spec(conn, &next, batch_id.as_deref()).await?;
+        return Ok(());
+    }
+    let Some(batch_id) = batch_id else {
+        return Ok(());
+    };
+    let on_complete: Option<Option<serde_json::Value>> =
+        sqlx::query_scalar(AssertSqlSafe(format!(
+            "UPDATE pg_loco_batches SET completed = completed + 1, status = CASE WHEN completed \
+             + 1 = total AND status = '{pending}' THEN '{completed}' ELSE status END, updated_at \
+             = NOW() WHERE id = $1 RETURNING CASE WHEN status = '{completed}' AND completed = \
+             total THEN on_complete END",
+            pending = BatchStatus::Pending,
+            completed = BatchStatus::Completed,
+        )))
+        .bind(&batch_id)
+        .fetch_optional(&mut *conn)
         .await?;
+    if let Some(on_complete) = on_complete.flatten() {
+        debug!(batch_id = %batch_id, "Batch completed, enqueueing its callback");
+        insert_spec(conn, &serde_json::from_value(on_complete)?, None).await?;
+    }
     Ok(())
 }
 
-/// Deletes jobs from the `pg_loco_queue` table based on their status.
-///
+/// Enqueues a failed job's batch's `on_failure` if this was the first member
+/// of the batch to fail.
+async fn enqueue_failure_follow_ups(
+    conn: &mut PgConnection,
+    batch_id: Option<String>,
+) -> Result<()> {
+    let Some(batch_id) = batch_id else {
+        return Ok(());
+    };
+    let on_failure: Option<Option<serde_json::Value>> = sqlx::query_scalar(AssertSqlSafe(format!(
+        "UPDATE pg_loco_batches SET failed = failed + 1, status = '{}', updated_at = NOW() \
+             WHERE id = $1 RETURNING CASE WHEN failed = 1 THEN on_failure END",
+        BatchStatus::Failed
+    )))
+    .bind(&batch_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(on_failure) = on_failure.flatten() {
+        debug!(batch_id = %batch_id, "Batch failed, enqueueing its callback");
+        insert_spec(conn, &serde_json::from_value(on_failure)?, None).await?;
+    }
+    Ok(())
+}
+
+async fn dequeue(
+    client: &PgPool,
+    worker_tags: &[String],
+    queues: &[String],
+    worker_opts: &HashMap<String, WorkerOpts>,
+) -> Result<Option<Job>> {
+    let limited = worker_opts
+        .iter()
+        .filter(|(_, opts)| opts.is_limited())
+        .collect::<Vec<_>>();
+
+    let mut query = String::from(
+        "SELECT id, name, task_data, status, run_at, interval, tags, priority, attempts, last_error, history, unique_key, batch_id, chain, queue, args_version FROM pg_loco_queue WHERE status = $1 AND run_at <= NOW() ",
+    );
+
+    // An untagged worker takes only untagged jobs; a tagged one takes any job
+    // carrying at least one of its tags. `?` is jsonb's "array contains this
+    // string" operator, one bind per tag starting at $2.
+    if worker_tags.is_empty() {
+        query.push_str("AND (tags IS NULL) ");
+    } else {
+        let any_tag_matches = (0..worker_tags.len())
+            .map(|i| format!("(tags)::jsonb ? ${}", i + 2))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        query.push_str("AND (tags IS NOT NULL) AND (");
+        query.push_str(&any_tag_matches);
+        query.push(')');
+    }
+
+    // Hold back a limited class once it is at its cap or rate limit, one bind
+    // per class (its name) after the tags.
+    for (idx, (_, opts)) in limited.iter().enumerate() {
+        let name = idx + worker_tags.len() + 2;
+        let _ = write!(
+            query,
+            " AND NOT (name = ${name} AND ({}))",
+            over_limit(opts, &format!("${name}"))
+        );
+    }
+
+    // Leave jobs stored at a newer args version than their worker knows for
+    // a worker that knows it; one bind, the versions by class, after the
+    // limited classes.
+    let versions_bind = limited.len() + worker_tags.len() + 2;
+    let _ = write!(
+        query,
+        " AND NOT EXISTS (SELECT 1 FROM jsonb_each_text(${versions_bind}::jsonb) AS known WHERE \
+         known.key = pg_loco_queue.name AND pg_loco_queue.args_version > known.value::int)"
+    );
+
+    // Take jobs from the worker's queues only, in the order given; one bind,
+    // an array, after the versions.
+    let queues_bind = versions_bind + 1;
+    if queues.is_empty() {
+        query.push_str(" ORDER BY");
+    } else {
+        let _ = write!(
+            query,
+            " AND queue = ANY(${queues_bind}) ORDER BY array_position(${queues_bind}, queue),"
+        );
+    }
+    query.push_str(" priority DESC, run_at, id LIMIT 1 FOR UPDATE SKIP LOCKED");
+
+    let versions = super::sql::args_versions(worker_opts);
+    loop {
+        let mut tx = client.begin().await?;
+        let mut db_query =
+            sqlx::query(AssertSqlSafe(query.clone())).bind(JobStatus::Queued.to_string());
+        for tag in worker_tags {
+            db_query = db_query.bind(tag);
+        }
+        for (name, _) in &limited {
+            db_query = db_query.bind(name.as_str());
+        }
+        db_query = db_query.bind(&versions);
+        if !queues.is_empty() {
+            db_query = db_query.bind(queues);
+        }
+
+        let row = db_query
+            .map(|row: PgRow| to_job(&row).ok())
+            .fetch_optional(&mut *tx)
+            .await?
+            .flatten();
+        let Some(mut job) = row else {
+            return Ok(None);
+        };
+
+        // The limits above are checked by counting rows, a check two
+        // concurrent dequeues would both pass. Taking turns per limited class,
+        // and checking again, makes them hold across the cluster.
+        if let Some(opts) = worker_opts.get(&job.name).filter(|opts| opts.is_limited()) {
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtext('pg_loco_queue_limits:' || $1))")
+                .bind(&job.name)
+                .execute(&mut *tx)
+                .await?;
+            let over: bool =
+                sqlx::query_scalar(AssertSqlSafe(format!("SELECT {}", over_limit(opts, "$1"))))
+                    .bind(&job.name)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if over {
+                // Another worker took the class's last slot meanwhile; the
+                // next look leaves the class out.
+                tx.rollback().await?;
+                continue;
+            }
+        }
+
+        trace!(job_id = %job.id, job_name = %job.name, job_tags = ?job.tags, "Dequeueing job for processing");
+        sqlx::query(
+            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), started_at = NOW(), \
+             history = CASE WHEN attempts = 0 THEN '[]'::jsonb ELSE history END, attempts = \
+             attempts + 1, progress = NULL, result = NULL, cancel_requested = FALSE WHERE id = $2",
+        )
+        .bind(JobStatus::Processing.to_string())
+        .bind(&job.id)
+        .execute(&mut *tx)
+        .await?;
+        if job.attempts == 0 {
+            job.history.clear();
+        }
+        job.attempts += 1;
+
+        tx.commit().await?;
+
+        return Ok(Some(job));
+    }
+}
+
+/// When a job of a limited class, whose name the SQL expression `name`
+/// gives, is held back: the class is at its concurrency cap or rate limit.
+fn over_limit(opts: &WorkerOpts, name: &str) -> String {
+    let mut over_limit = Vec::new();
+    if let Some(concurrency) = opts.concurrency {
+        over_limit.push(format!(
+            "(SELECT COUNT(*) FROM pg_loco_queue WHERE name = {name} AND status = '{}') >= \
+             {concurrency}",
+            JobStatus::Processing
+        ));
+    }
+    if let Some(rate_limit) = &opts.rate_limit {
+        over_limit.push(format!(
+            "(SELECT COUNT(*) FROM pg_loco_queue WHERE name = {name} AND started_at > NOW() - \
+             INTERVAL '{} milliseconds') >= {}",
+            rate_limit.period.as_millis(),
+            rate_limit.limit
+        ));
+    }
+    over_limit.join(" OR ")
+}
+
+async fn complete_job(pool: &PgPool, id: &JobId, interval_ms: Option<i64>) -> Result<()> {
+    if let Some(interval_ms) = interval_ms {
+        let next_run_at = Utc::now() + chrono::Duration::milliseconds(interval_ms);
+        trace!(
+            job_id = %id,
+            status = "queued",
+            run_at = %next_run_at,
+            "Rescheduling recurring job"
+        );
+        sqlx::query(
+            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), run_at = $2, attempts = 0 \
+             WHERE id = $3",
+        )
+        .bind(JobStatus::Queued.to_string())
+        .bind(next_run_at)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    } else {
+        trace!(job_id = %id, status = "completed", "Marking job as completed");
+        let mut tx = pool.begin().await?;
+        let row = sqlx::query(
+            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW() WHERE id = $2 RETURNING \
+             batch_id, chain, result",
+        )
+        .bind(JobStatus::Completed.to_string())
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(row) = row {
+            let chain: Vec<ChainStep> =
+                serde_json::from_value(row.try_get("chain")?).unwrap_or_default();
+            enqueue_completion_follow_ups(
+                &mut tx,
+                row.try_get("batch_id")?,
+                &chain,
+                row.try_get("result")?,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+async fn fail_job(pool: &PgPool, id: &JobId, error: &crate::Error) -> Result<()> {
+    debug!(job_id = %id, error = %error, "Marking job as failed");
+    finish_with_error(pool, id, error, JobStatus::Failed).await
+}
+
+/// Marks a running job that stopped with `error` after it was asked to as
+/// [`JobStatus::Cancelled`]. To its batch, it counts as a failed member.
+async fn cancel_job(pool: &PgPool, id: &JobId, error: &crate::Error) -> Result<()> {
+    debug!(job_id = %id, error = %error, "Marking running job as cancelled");
+    finish_with_error(pool, id, error, JobStatus::Cancelled).await
+}
+
+/// Shared body of [`fail_job`] and [`cancel_job`].
+async fn finish_with_error(
+    pool: &PgPool,
+    id: &JobId,
+    error: &crate::Error,
+    status: JobStatus,
+) -> Result<()> {
+    let msg = super::error_message(error);
+    let error_json = serde_json::json!({ "error": msg });
+    let mut tx = pool.begin().await?;
+    let batch_id: Option<Option<String>> = sqlx::query_scalar(
+        "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), task_data = task_data || \
+         $2::jsonb, last_error = $3 WHERE id = $4 RETURNING batch_id",
+    )
+    .bind(status.to_string())
+    .bind(error_json)
+    .bind(&msg)
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    enqueue_failure_follow_ups(&mut tx, batch_id.flatten()).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Puts job `id`, interrupted by a worker shutdown, back in the queue, and
+/// gives back the attempt it was on, so the interruption does not count
+/// against its retries.
+async fn requeue_job(pool: &PgPool, id: &JobId) -> Result<()> {
+    debug!(job_id = %id, "Requeueing job interrupted by shutdown");
+    sqlx::query(
+        "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), attempts = GREATEST(attempts - 1, 0) \
+         WHERE id = $2 AND status = $3",
+    )
+    .bind(JobStatus::Queued.to_string())
+    .bind(id)
+    .bind(JobStatus::Processing.to_string())
+    .execute(pool)
+    .await?;
+    notify_enqueued(pool).await;
+    Ok(())
+}
+
+async fn retry_job(
+    pool: &PgPool,
+    id: &JobId,
+    run_at: DateTime<Utc>,
+    error: &crate::Error,
+) -> Result<()> {
+    debug!(job_id = %id, error = %error, run_at = %run_at, "Rescheduling failed job for retry");
+    sqlx::query(
+        "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), run_at = $2, last_error = $3 \
+         WHERE id = $4",
+    )
+    .bind(JobStatus::Queued.to_string())
+    .bind(run_at)
+    .bind(super::error_message(error))
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn record_attempt(pool: &PgPool, id: &JobId, attempt: &JobAttempt) -> Result<()> {
+    trace!(job_id = %id, attempt = attempt.attempt, "Recording job attempt");
+    sqlx::query("UPDATE pg_loco_queue SET history = history || $1::jsonb WHERE id = $2")
+        .bind(serde_json::to_value(attempt)?)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+async fn set_progress(pool: &PgPool, id: &JobId, percent: u8) -> Result<()> {
+    trace!(job_id = %id, percent, "Recording job progress");
+    sqlx::query("UPDATE pg_loco_queue SET progress = $1, updated_at = NOW() WHERE id = $2")
+        .bind(i32::from(percent))
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+async fn set_result(pool: &PgPool, id: &JobId, result: serde_json::Value) -> Result<()> {
+    trace!(job_id = %id, "Recording job result");
+    sqlx::query("UPDATE pg_loco_queue SET result = $1, updated_at = NOW() WHERE id = $2")
+        .bind(result)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+async fn cancel_requested(pool: &PgPool, id: &JobId) -> Result<bool> {
+    let requested: Option<bool> =
+        sqlx::query_scalar("SELECT cancel_requested FROM pg_loco_queue WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(requested.unwrap_or(false))
+}
+
+/// Cancels jobs in the `pg_loco_queue` table by their name.
+///
+/// This function updates the status of all jobs with the given `name` and a status of
+/// [`JobStatus::Queued`] to [`JobStatus::Cancelled`]. The update also sets the `updated_at` timestamp to the
+/// current time. Jobs with a status of [`JobStatus::Processing`] get their
+/// `cancel_requested` flag set instead, which their worker checks while running them.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn cancel_jobs_by_name(pool: &PgPool, name: &str) -> Result<()> {
+    debug!(job_name = %name, "Cancelling jobs by name");
+    sqlx::query(
+        "UPDATE pg_loco_queue SET status = $1, updated_at = NOW() WHERE name = $2 AND status = $3",
+    )
+    .bind(JobStatus::Cancelled.to_string())
+    .bind(name)
+    .bind(JobStatus::Queued.to_string())
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "UPDATE pg_loco_queue SET cancel_requested = TRUE, updated_at = NOW() WHERE name = $1 AND \
+         status = $2",
+    )
+    .bind(name)
+    .bind(JobStatus::Processing.to_string())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Clear all jobs
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn clear(pool: &PgPool) -> Result<()> {
+    sqlx::query("DELETE FROM pg_loco_queue")
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM pg_loco_batches")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Deletes jobs from the `pg_loco_queue` table based on their status.
+///
 /// This function removes all jobs with a status that matches any of the statuses provided
 /// in the `status` argument. The statuses are checked against the `status` column in the
 /// database, and any matching rows are deleted.
@@ -498,11 +1327,15 @@ pub async fn requeue(pool: &PgPool, age_minutes: &i64) -> Result<()> {
     );
 
     debug!(age_minutes = age_minutes, "Requeueing stalled jobs");
-    sqlx::query(AssertSqlSafe(query))
+    let requeued = sqlx::query(AssertSqlSafe(query))
         .bind(JobStatus::Queued.to_string())
         .bind(JobStatus::Processing.to_string())
         .execute(pool)
-        .await?;
+        .await?
+        .rows_affected();
+    if requeued > 0 {
+        notify_enqueued(pool).await;
+    }
 
     Ok(())
 }
@@ -522,8 +1355,8 @@ pub async fn retry_failed(pool: &PgPool, id: Option<&str>) -> Result<u64> {
     let result = if let Some(id) = id {
         debug!(job_id = %id, "Retrying failed job");
         sqlx::query(
-            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), run_at = NOW() WHERE \
-             status = $2 AND id::text = $3",
+            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), run_at = NOW(), \
+             attempts = 0 WHERE status = $2 AND id::text = $3",
         )
         .bind(JobStatus::Queued.to_string())
         .bind(JobStatus::Failed.to_string())
@@ -533,14 +1366,17 @@ pub async fn retry_failed(pool: &PgPool, id: Option<&str>) -> Result<u64> {
     } else {
         debug!("Retrying every failed job");
         sqlx::query(
-            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), run_at = NOW() WHERE \
-             status = $2",
+            "UPDATE pg_loco_queue SET status = $1, updated_at = NOW(), run_at = NOW(), \
+             attempts = 0 WHERE status = $2",
         )
         .bind(JobStatus::Queued.to_string())
         .bind(JobStatus::Failed.to_string())
         .execute(pool)
         .await?
     };
+    if result.rows_affected() > 0 {
+        notify_enqueued(pool).await;
+    }
 
     Ok(result.rows_affected())
 }
@@ -594,28 +1430,126 @@ pub async fn get_jobs(
     Ok(jobs)
 }
 
+/// Retrieves the newest jobs passing `filter` from the `pg_loco_queue` table,
+/// filtered and limited in the query.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn list_jobs(pool: &PgPool, filter: &JobFilter) -> Result<Vec<Job>> {
+    let mut query = sqlx::query_builder::QueryBuilder::<sqlx::Postgres>::new(
+        "SELECT * FROM pg_loco_queue WHERE true",
+    );
+    if !filter.statuses.is_empty() {
+        query.push(" AND status IN (");
+        let mut statuses = query.separated(", ");
+        for status in &filter.statuses {
+            statuses.push_bind(status.to_string());
+        }
+        statuses.push_unseparated(")");
+    }
+    if let Some(name) = &filter.name {
+        query.push(" AND name = ").push_bind(name.clone());
+    }
+    if let Some(tag) = &filter.tag {
+        query.push(" AND (tags)::jsonb ? ").push_bind(tag.clone());
+    }
+    if let Some(since) = filter.since {
+        query.push(" AND updated_at >= ").push_bind(since);
+    }
+    query.push(" ORDER BY created_at DESC, id DESC");
+    if let Some(limit) = filter.limit {
+        query
+            .push(" LIMIT ")
+            .push_bind(i64::try_from(limit).unwrap_or(i64::MAX));
+    }
+
+    debug!(filter = ?filter, "Listing jobs");
+    let rows = query.build().fetch_all(pool).await?;
+    Ok(rows.iter().filter_map(|row| to_job(row).ok()).collect())
+}
+
+/// Counts the jobs in the `pg_loco_queue` table per status.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn count_by_status(pool: &PgPool) -> Result<HashMap<JobStatus, u64>> {
+    let rows: Vec<(String, i64)> =
+        sqlx::query_as("SELECT status, COUNT(*) FROM pg_loco_queue GROUP BY status")
+            .fetch_all(pool)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(status, count)| Some((status.parse().ok()?, u64::try_from(count).ok()?)))
+        .collect())
+}
+
+/// Retrieves the job with the given `id`, if any.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn get_job(pool: &PgPool, id: &str) -> Result<Option<Job>> {
+    let row = sqlx::query("SELECT * FROM pg_loco_queue WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    row.map(|row| to_job(&row)).transpose()
+}
+
+/// Retrieves every batch, newest first.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn get_batches(pool: &PgPool) -> Result<Vec<JobBatch>> {
+    let rows = sqlx::query("SELECT * FROM pg_loco_batches ORDER BY created_at DESC, id DESC")
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(to_batch).collect()
+}
+
 /// Builds the [`PgQueue`] provider (pool, registry, run options, token) from
 /// config. Factored out of [`create_provider`] so tests can inspect the
 /// resulting `run_opts` without needing to downcast the opaque [`Queue`].
 async fn build_provider(qcfg: &PostgresQueueConfig) -> Result<PgQueue> {
+    if qcfg.use_app_db {
+        return Err(Error::string(
+            "a queue with `use_app_db` needs the application's database connection",
+        ));
+    }
+    let pool = connect(qcfg).await.map_err(Box::from)?;
+    Ok(build_provider_on(qcfg, pool))
+}
+
+/// Builds the provider around an existing `pool`.
+fn build_provider_on(qcfg: &PostgresQueueConfig, pool: PgPool) -> PgQueue {
     debug!(
         num_workers = qcfg.num_workers,
         poll_interval = qcfg.poll_interval_sec,
         "Creating job queue provider"
     );
-    let pool = connect(qcfg).await.map_err(Box::from)?;
     let registry = JobRegistry::new();
     let token = CancellationToken::new(); // Create the token
-    Ok(PgQueue {
+    PgQueue {
         pool,
         registry: Arc::new(tokio::sync::Mutex::new(registry)),
         run_opts: RunOpts {
             num_workers: qcfg.num_workers,
             poll_interval_sec: qcfg.poll_interval_sec,
+            queues: qcfg
+                .queues
+                .iter()
+                .map(|(queue, weight)| (queue.clone(), *weight))
+                .collect(),
             reaper: qcfg.reaper.clone(),
+            shutdown_timeout: Duration::from_secs(qcfg.shutdown_timeout_sec),
         },
         token, // Pass the token
-    })
+        limits: qcfg.limits.clone(),
+        listen: qcfg.listen,
+    }
 }
 
 /// Create this provider
@@ -627,18 +1561,70 @@ pub async fn create_provider(qcfg: &PostgresQueueConfig) -> Result<Queue> {
     Ok(Queue::from_provider(Arc::new(build_provider(qcfg).await?)))
 }
 
-#[cfg(test)]
-mod tests {
-    use chrono::{NaiveDate, NaiveTime, TimeZone};
-    use insta::{assert_debug_snapshot, with_settings};
-    use serde::Serialize;
-    use sqlx::{query_as, FromRow};
-    use tokio::time::sleep;
-
-    use super::*;
-    use crate::{
-        bgworker::BackgroundWorker,
-        tests_cfg::{self, postgres::setup_postgres_container},
+/// Create this provider on the application's database, sharing its pool, for
+/// a queue with `use_app_db`.
+///
+/// # Errors
+///
+/// This function will return an error if `db` is not a Postgres database
+#[cfg(feature = "with-db")]
+pub fn create_provider_with_db(
+    qcfg: &PostgresQueueConfig,
+    db: &sea_orm::DatabaseConnection,
+) -> Result<Queue> {
+    if db.get_database_backend() != sea_orm::DbBackend::Postgres {
+        return Err(Error::string(
+            "a Postgres queue with `use_app_db` needs a Postgres application database",
+        ));
+    }
+    let pool = db.get_postgres_connection_pool().clone();
+    Ok(Queue::from_provider(Arc::new(build_provider_on(
+        qcfg, pool,
+    ))))
+}
+
+/// Computes the queue's metrics with `GROUP BY` queries instead of loading
+/// every job. See [`super::stats::QueueStats`].
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn stats(pool: &PgPool) -> Result<super::stats::QueueStats> {
+    let counts: Vec<(String, String, String, i64, Option<i64>)> = sqlx::query_as(
+        "SELECT queue, name, status, COUNT(*), FLOOR(EXTRACT(EPOCH FROM NOW() - MIN(CASE WHEN status = 'queued' AND run_at <= NOW() THEN run_at END)))::BIGINT FROM pg_loco_queue GROUP BY queue, name, \
+         status",
+    )
+    .fetch_all(pool)
+    .await?;
+    let attempts = sqlx::query(AssertSqlSafe(super::sql::attempt_stats_query(
+        "SELECT name AS class, (attempt->>'error') IS NOT NULL AS failed, GREATEST(0, \
+         FLOOR(EXTRACT(EPOCH FROM (attempt->>'finished_at')::timestamptz - \
+         (attempt->>'started_at')::timestamptz) * 1000))::BIGINT AS duration_ms FROM \
+         pg_loco_queue, jsonb_array_elements(history) AS attempt",
+    )))
+    .fetch_all(pool)
+    .await?;
+    Ok(super::sql::to_queue_stats(
+        counts,
+        attempts
+            .iter()
+            .map(super::sql::to_attempt_summary)
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime, TimeZone};
+    use insta::{assert_debug_snapshot, with_settings};
+    use serde::Serialize;
+    use sqlx::{query_as, FromRow};
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::{
+        bgworker::{BackgroundWorker, Backoff, JobInfo},
+        tests_cfg::{self, postgres::setup_postgres_container},
     };
 
     fn reduction() -> &'static [(&'static str, &'static str)] {
@@ -735,10 +1721,11 @@ mod tests {
             &pool,
             "PasswordChangeNotification",
             job_data,
-            run_at,
             None,
-            None,
-            None
+            &EnqueueOptions {
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -768,10 +1755,11 @@ mod tests {
             &pool,
             "PasswordChangeNotification",
             job_data,
-            run_at,
-            None,
             None,
-            None
+            &EnqueueOptions {
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -786,7 +1774,7 @@ mod tests {
 
         std::thread::sleep(std::time::Duration::from_secs(1));
 
-        assert!(dequeue(&pool, &[]).await.is_ok());
+        assert!(dequeue(&pool, &[], &[], &HashMap::new()).await.is_ok());
 
         let job_after_dequeue = get_all_jobs(&pool)
             .await
@@ -1204,6 +2192,7 @@ mod tests {
         let (pg_url, _container) = tests_cfg::postgres::setup_postgres_container().await;
         let qcfg = PostgresQueueConfig {
             uri: pg_url,
+            use_app_db: false,
             dangerously_flush: false,
             enable_logging: false,
             max_connections: 1,
@@ -1211,11 +2200,15 @@ mod tests {
             connect_timeout: 500,
             idle_timeout: 500,
             poll_interval_sec: 1,
+            listen: true,
             num_workers: 1,
             reaper: Some(crate::config::ReaperConfig {
                 age_minutes: 5,
                 interval_seconds: 30,
             }),
+            queues: Default::default(),
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         };
 
         let provider = build_provider(&qcfg).await.expect("build provider");
@@ -1232,6 +2225,7 @@ mod tests {
         let (pg_url, _container) = tests_cfg::postgres::setup_postgres_container().await;
         let qcfg = PostgresQueueConfig {
             uri: pg_url,
+            use_app_db: false,
             dangerously_flush: false,
             enable_logging: false,
             max_connections: 1,
@@ -1239,8 +2233,12 @@ mod tests {
             connect_timeout: 500,
             idle_timeout: 500,
             poll_interval_sec: 1,
+            listen: true,
             num_workers: 1,
             reaper: None,
+            queues: Default::default(),
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         };
 
         let provider = build_provider(&qcfg).await.expect("build provider");
@@ -1265,17 +2263,21 @@ mod tests {
                 &pool,
                 name,
                 serde_json::json!({ "index": index }),
-                base_time + chrono::Duration::minutes(minute_offset),
-                None,
                 None,
-                Some(priority),
+                &EnqueueOptions {
+                    priority: Some(priority),
+                    run_at: Some(base_time + chrono::Duration::minutes(minute_offset)),
+                    ..EnqueueOptions::default()
+                },
             )
             .await
             .expect("enqueue test job");
         }
 
         for expected_index in [1, 2, 3, 4, 5] {
-            let job = dequeue(&pool, &[]).await.expect("dequeue failed");
+            let job = dequeue(&pool, &[], &[], &HashMap::new())
+                .await
+                .expect("dequeue failed");
             assert!(job.is_some());
             let job = job.unwrap();
             assert_eq!(
@@ -1287,18 +2289,104 @@ mod tests {
                 .expect("complete job");
         }
 
-        let job = dequeue(&pool, &[]).await.expect("dequeue failed");
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue failed");
         assert!(job.is_none());
     }
 
+    #[tokio::test]
+    async fn shutdown_lets_running_jobs_finish_until_the_deadline() {
+        let (pool, _container) = setup_pg_test().await;
+
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct SleepArgs {
+            millis: u64,
+        }
+
+        struct SleepWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<SleepArgs> for SleepWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, args: SleepArgs) -> crate::Result<()> {
+                sleep(Duration::from_millis(args.millis)).await;
+                Ok(())
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        let handler = crate::bgworker::erase_worker(SleepWorker);
+        assert!(registry
+            .insert_handler("SleepJob".to_string(), handler, WorkerOpts::default())
+            .is_ok());
+
+        let short = enqueue(
+            &pool,
+            "SleepJob",
+            serde_json::json!({ "millis": 300 }),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+        let long = enqueue(
+            &pool,
+            "SleepJob",
+            serde_json::json!({ "millis": 60_000 }),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        let opts = RunOpts {
+            num_workers: 2,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(1),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<PgDriver>(&pool, &opts, &token, &[]);
+
+        // Both jobs are running when the shutdown starts.
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(get_job(&pool, &long).await.status, JobStatus::Processing);
+        token.cancel();
+
+        let drained = tokio::time::timeout(Duration::from_secs(10), async {
+            for handle in handles {
+                handle.await.expect("worker task");
+            }
+        })
+        .await;
+        assert!(drained.is_ok(), "workers should stop at the deadline");
+
+        // The short one finished in time, the long one went back to the
+        // queue without using up an attempt.
+        assert_eq!(get_job(&pool, &short).await.status, JobStatus::Completed);
+        let requeued = get_job(&pool, &long).await;
+        assert_eq!(requeued.status, JobStatus::Queued);
+        assert_eq!(requeued.attempts, 0);
+        assert!(requeued.history.is_empty());
+    }
+
     #[tokio::test]
     async fn can_handle_worker_panic() {
         let (pool, _container) = setup_pg_test().await;
 
         let job_data: JobData = serde_json::json!(null);
-        let job_id = enqueue(&pool, "PanicJob", job_data, Utc::now(), None, None, None)
-            .await
-            .expect("Failed to enqueue job");
+        let job_id = enqueue(
+            &pool,
+            "PanicJob",
+            job_data,
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
 
         struct PanicWorker;
         #[async_trait::async_trait]
@@ -1314,7 +2402,7 @@ mod tests {
         let mut registry = JobRegistry::new();
         let handler = crate::bgworker::erase_worker(PanicWorker);
         assert!(registry
-            .insert_handler("PanicJob".to_string(), handler)
+            .insert_handler("PanicJob".to_string(), handler, WorkerOpts::default())
             .is_ok());
 
         // Get the initial job state
@@ -1325,7 +2413,9 @@ mod tests {
         let opts = RunOpts {
             num_workers: 1,
             poll_interval_sec: 1,
+            queues: Vec::new(),
             reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
         };
         let token = CancellationToken::new();
         let handles = registry.run::<PgDriver>(&pool, &opts, &token, &[]);
@@ -1355,6 +2445,399 @@ mod tests {
             error_msg.contains("intentional panic for testing"),
             "Error message '{error_msg}' did not contain expected text"
         );
+        assert_eq!(
+            failed_job.last_error.as_deref(),
+            Some("worker panicked: intentional panic for testing")
+        );
+        assert_eq!(failed_job.history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn can_retry_failing_job_until_max_retries() {
+        let (pool, _container) = setup_pg_test().await;
+
+        let job_id = enqueue(
+            &pool,
+            "FailingJob",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        struct FailingWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for FailingWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                Err(crate::Error::string("always fails"))
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        let handler = crate::bgworker::erase_worker(FailingWorker);
+        let worker_opts = WorkerOpts {
+            max_retries: 2,
+            backoff: Backoff::Fixed(Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(registry
+            .insert_handler("FailingJob".to_string(), handler, worker_opts)
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<PgDriver>(&pool, &opts, &token, &[]);
+
+        sleep(Duration::from_secs(3)).await;
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        // One initial attempt plus two retries, then the job is given up on.
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.attempts, 3);
+        assert_eq!(job.last_error.as_deref(), Some("always fails"));
+        assert_eq!(
+            job.history
+                .iter()
+                .map(|attempt| (attempt.attempt, attempt.error.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, Some("always fails")),
+                (2, Some("always fails")),
+                (3, Some("always fails"))
+            ]
+        );
+
+        // A manual retry starts the attempt count over.
+        assert_eq!(retry_failed(&pool, Some(&job_id)).await.expect("retry"), 1);
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Queued);
+        assert_eq!(job.attempts, 0);
+    }
+
+    #[cfg(feature = "with-db")]
+    #[tokio::test]
+    async fn job_enqueued_in_a_transaction_exists_only_once_it_commits() {
+        use sea_orm::TransactionTrait;
+
+        let (pg_url, _container) = setup_postgres_container().await;
+        let pool = PgPool::connect(&pg_url)
+            .await
+            .expect("Failed to connect to PostgreSQL");
+        initialize_database(&pool)
+            .await
+            .expect("Failed to initialize database");
+        let db = sea_orm::Database::connect(&pg_url)
+            .await
+            .expect("connect sea-orm");
+
+        let txn = db.begin().await.expect("begin");
+        let rolled_back = enqueue_in_transaction(
+            &txn,
+            "UserAccountActivation",
+            serde_json::json!({"user_id": 1}),
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue in transaction");
+        txn.rollback().await.expect("rollback");
+        assert!(super::get_job(&pool, &rolled_back)
+            .await
+            .expect("get job")
+            .is_none());
+
+        let unique = UniqueJob {
+            key: "UserAccountActivation:2".to_string(),
+            window: UniqueWindow::Queued,
+        };
+        let txn = db.begin().await.expect("begin");
+        let committed = enqueue_in_transaction(
+            &txn,
+            "UserAccountActivation",
+            serde_json::json!({"user_id": 2}),
+            &EnqueueOptions {
+                tags: Some(vec!["email".to_string()]),
+                priority: Some(3),
+                unique: Some(unique.clone()),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue in transaction");
+        // Not visible to workers before the commit.
+        assert!(super::get_job(&pool, &committed)
+            .await
+            .expect("get job")
+            .is_none());
+        let duplicate = enqueue_in_transaction(
+            &txn,
+            "UserAccountActivation",
+            serde_json::json!({"user_id": 2}),
+            &EnqueueOptions {
+                unique: Some(unique.clone()),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue duplicate in transaction");
+        assert_eq!(duplicate, committed);
+        txn.commit().await.expect("commit");
+
+        let job = get_job(&pool, &committed).await;
+        assert_eq!(job.status, JobStatus::Queued);
+        assert_eq!(job.data, serde_json::json!({"user_id": 2}));
+        assert_eq!(job.tags, Some(vec!["email".to_string()]));
+        assert_eq!(job.priority, 3);
+        assert_eq!(get_all_jobs(&pool).await.len(), 1);
+    }
+
+    #[cfg(feature = "with-db")]
+    #[tokio::test]
+    async fn a_failed_notify_leaves_the_callers_transaction_usable() {
+        use sea_orm::{ConnectionTrait, TransactionTrait};
+
+        let (pg_url, _container) = setup_postgres_container().await;
+        let pool = PgPool::connect(&pg_url)
+            .await
+            .expect("Failed to connect to PostgreSQL");
+        initialize_database(&pool)
+            .await
+            .expect("Failed to initialize database");
+        let db = sea_orm::Database::connect(&pg_url)
+            .await
+            .expect("connect sea-orm");
+
+        // A `pg_notify` found ahead of `pg_catalog`'s, which always fails.
+        for statement in [
+            "CREATE SCHEMA failing_notify",
+            "CREATE FUNCTION failing_notify.pg_notify(text, text) RETURNS void AS $$ BEGIN \
+             RAISE EXCEPTION 'notify failed'; END $$ LANGUAGE plpgsql",
+        ] {
+            db.execute_unprepared(statement)
+                .await
+                .expect("create failing pg_notify");
+        }
+
+        let txn = db.begin().await.expect("begin");
+        txn.execute_unprepared("SET LOCAL search_path = failing_notify, pg_catalog, public")
+            .await
+            .expect("set search path");
+        let id = enqueue_in_transaction(
+            &txn,
+            "UserAccountActivation",
+            serde_json::json!({"user_id": 1}),
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue in transaction");
+        txn.execute_unprepared("SELECT 1")
+            .await
+            .expect("the transaction is not aborted");
+        txn.commit().await.expect("commit");
+
+        assert!(super::get_job(&pool, &id).await.expect("get job").is_some());
+    }
+
+    #[tokio::test]
+    async fn notify_wakes_idle_workers_before_the_next_poll() {
+        let (pool, _container) = setup_pg_test().await;
+
+        struct QuickWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for QuickWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "QuickJob".to_string(),
+                crate::bgworker::erase_worker(QuickWorker),
+                WorkerOpts::default(),
+            )
+            .is_ok());
+
+        // Polling alone would not pick the job up within the test.
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 60,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let (wakeup, wakeups) = watch::channel(());
+        tokio::spawn(listen(
+            pool.clone(),
+            token.clone(),
+            wakeup,
+            Duration::from_secs(60),
+        ));
+        let handles =
+            registry.run_with_wakeups::<PgDriver>(&pool, &opts, &token, &[], Some(wakeups));
+
+        sleep(Duration::from_millis(500)).await;
+        let job_id = enqueue(
+            &pool,
+            "QuickJob",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+        sleep(Duration::from_secs(1)).await;
+
+        token.cancel();
+        for handle in handles {
+            handle.abort();
+        }
+
+        assert_eq!(get_job(&pool, &job_id).await.status, JobStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn job_over_its_timeout_is_recorded_as_failed() {
+        let (pool, _container) = setup_pg_test().await;
+
+        let job_id = enqueue(
+            &pool,
+            "SlowJob",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        struct SlowWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for SlowWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            fn timeout() -> Option<Duration> {
+                Some(Duration::from_millis(200))
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                sleep(Duration::from_secs(30)).await;
+                Ok(())
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "SlowJob".to_string(),
+                crate::bgworker::erase_worker(SlowWorker),
+                WorkerOpts::of::<(), SlowWorker>(),
+            )
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<PgDriver>(&pool, &opts, &token, &[]);
+
+        sleep(Duration::from_secs(2)).await;
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.last_error.as_deref(), Some("job timed out after 200ms"));
+    }
+
+    #[tokio::test]
+    async fn cancel_jobs_by_name_signals_a_running_job() {
+        let (pool, _container) = setup_pg_test().await;
+
+        let job_id = enqueue(
+            &pool,
+            "StoppableJob",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        struct StoppableWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for StoppableWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            fn max_retries() -> u32 {
+                3
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                crate::bgworker::cancellation_token().cancelled().await;
+                Err(crate::Error::string("stopped on request"))
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "StoppableJob".to_string(),
+                crate::bgworker::erase_worker(StoppableWorker),
+                WorkerOpts::of::<(), StoppableWorker>(),
+            )
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<PgDriver>(&pool, &opts, &token, &[]);
+
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(get_job(&pool, &job_id).await.status, JobStatus::Processing);
+
+        cancel_jobs_by_name(&pool, "StoppableJob")
+            .await
+            .expect("cancel jobs");
+        sleep(Duration::from_secs(2)).await;
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        // Cancelled rather than retried, even though retries were left.
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert_eq!(job.attempts, 1);
+        assert_eq!(job.last_error.as_deref(), Some("stopped on request"));
     }
 
     #[tokio::test]
@@ -1371,10 +2854,12 @@ mod tests {
             &pool,
             "EmailNotification",
             job_data.clone(),
-            run_at,
-            None,
-            email_tags,
             None,
+            &EnqueueOptions {
+                tags: email_tags,
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("Failed to enqueue email job");
@@ -1385,10 +2870,12 @@ mod tests {
             &pool,
             "SmsNotification",
             job_data.clone(),
-            run_at,
-            None,
-            sms_tags,
             None,
+            &EnqueueOptions {
+                tags: sms_tags,
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("Failed to enqueue sms job");
@@ -1399,10 +2886,12 @@ mod tests {
             &pool,
             "PriorityEmail",
             job_data.clone(),
-            run_at,
-            None,
-            multi_tags,
             None,
+            &EnqueueOptions {
+                tags: multi_tags,
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("Failed to enqueue multi-tag job");
@@ -1412,10 +2901,11 @@ mod tests {
             &pool,
             "GenericNotification",
             job_data.clone(),
-            run_at,
-            None,
-            None,
             None,
+            &EnqueueOptions {
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("Failed to enqueue untagged job");
@@ -1425,7 +2915,9 @@ mod tests {
         assert_eq!(all_jobs.len(), 4);
 
         // 1. Worker with no tags should only get untagged jobs
-        let job = dequeue(&pool, &[]).await.expect("dequeue failed");
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue failed");
         assert!(job.is_some());
         let job = job.unwrap();
         assert_eq!(job.id, no_tag_id);
@@ -1437,7 +2929,7 @@ mod tests {
             .expect("Failed to complete job");
 
         // 2. Worker with "email" tag should get one of the email-tagged jobs
-        let job = dequeue(&pool, &["email".to_string()])
+        let job = dequeue(&pool, &["email".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_some());
@@ -1454,7 +2946,7 @@ mod tests {
             .expect("Failed to complete job");
 
         // 3. Worker with "email" tag should get the remaining email job
-        let job = dequeue(&pool, &["email".to_string()])
+        let job = dequeue(&pool, &["email".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_some());
@@ -1471,7 +2963,7 @@ mod tests {
             .expect("Failed to complete job");
 
         // 4. Worker with "sms" tag should get the sms job
-        let job = dequeue(&pool, &["sms".to_string()])
+        let job = dequeue(&pool, &["sms".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_some());
@@ -1485,13 +2977,467 @@ mod tests {
             .expect("Failed to complete job");
 
         // 5. No more jobs should be available
-        let job = dequeue(&pool, &["email".to_string()])
+        let job = dequeue(&pool, &["email".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_none());
 
         // 6. No more jobs should be available for untagged worker
-        let job = dequeue(&pool, &[]).await.expect("dequeue failed");
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue failed");
         assert!(job.is_none());
     }
+
+    #[tokio::test]
+    async fn dequeues_from_the_given_queues_in_order() {
+        let (pool, _container) = setup_pg_test().await;
+
+        let run_at = Utc::now() - chrono::Duration::minutes(5);
+        let mut ids = HashMap::new();
+        for queue in [None, Some("mailer"), Some("critical")] {
+            let id = enqueue(
+                &pool,
+                "QueuedJob",
+                serde_json::json!({"queue": queue}),
+                None,
+                &EnqueueOptions {
+                    queue: queue.map(ToString::to_string),
+                    run_at: Some(run_at),
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .expect("Failed to enqueue job");
+            ids.insert(queue.unwrap_or("default"), id);
+        }
+        assert_eq!(
+            get_job(&pool, &ids["default"]).await.queue.as_deref(),
+            Some("default")
+        );
+
+        let queues = ["critical".to_string(), "default".to_string()];
+        let job = dequeue(&pool, &[], &queues, &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        assert_eq!(job.id, ids["critical"]);
+        assert_eq!(job.queue.as_deref(), Some("critical"));
+        let job = dequeue(&pool, &[], &queues, &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        assert_eq!(job.id, ids["default"]);
+        // `mailer` is not among the worker's queues.
+        assert!(dequeue(&pool, &[], &queues, &HashMap::new())
+            .await
+            .expect("dequeue")
+            .is_none());
+
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        assert_eq!(job.id, ids["mailer"]);
+    }
+
+    #[tokio::test]
+    async fn unique_job_returns_existing_id_while_in_window() {
+        let (pool, _container) = setup_pg_test().await;
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let enqueue_reindex = |window: UniqueWindow| async {
+            let unique = UniqueJob {
+                key: "Reindex:42".to_string(),
+                window,
+            };
+            let data = serde_json::json!({"account_id": 42});
+            enqueue_unique(&pool, &unique, "Reindex", data, &EnqueueOptions::default()).await
+        };
+
+        let first = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        let duplicate = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        assert_eq!(first, duplicate);
+        assert_eq!(get_all_jobs(&pool).await.len(), 1);
+
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("job");
+        assert_eq!(job.unique_key.as_deref(), Some("Reindex:42"));
+
+        // Still processing: inside a queued-or-processing window, outside a
+        // queued-only one.
+        let duplicate = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        assert_eq!(first, duplicate);
+        let second = enqueue_reindex(UniqueWindow::Queued)
+            .await
+            .expect("enqueue");
+        assert_ne!(first, second);
+
+        assert_eq!(get_job(&pool, &first).await.unique_key, None);
+        assert_eq!(get_all_jobs(&pool).await.len(), 2);
+
+        let third = enqueue_reindex(UniqueWindow::For(Duration::from_secs(60)))
+            .await
+            .expect("enqueue");
+        assert_eq!(second, third);
+    }
+
+    #[tokio::test]
+    async fn worker_can_report_progress_and_result() {
+        let (pool, _container) = setup_pg_test().await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let job_id = enqueue(
+            &pool,
+            "ExportWorker",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        struct ExportWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for ExportWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                crate::bgworker::report_progress(73).await?;
+                crate::bgworker::report_result(&serde_json::json!({"path": "exports/42.csv"})).await
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        let handler = crate::bgworker::erase_worker(ExportWorker);
+        assert!(registry
+            .insert_handler("ExportWorker".to_string(), handler, WorkerOpts::default())
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<PgDriver>(&pool, &opts, &token, &[]);
+        sleep(Duration::from_secs(1)).await;
+        for handle in handles {
+            handle.abort();
+        }
+
+        let info = JobInfo::from(
+            super::get_job(&pool, &job_id)
+                .await
+                .expect("get job")
+                .expect("job exists"),
+        );
+        assert_eq!(info.status, JobStatus::Completed);
+        assert_eq!(info.progress, Some(73));
+        assert_eq!(
+            info.result,
+            Some(serde_json::json!({"path": "exports/42.csv"}))
+        );
+        assert!(super::get_job(&pool, "missing")
+            .await
+            .expect("get job")
+            .is_none());
+    }
+    fn spec(class: &str, args: serde_json::Value, chain: Vec<ChainStep>) -> JobSpec {
+        JobSpec {
+            class: class.to_string(),
+            queue: None,
+            args,
+            tags: None,
+            priority: None,
+            chain,
+            args_version: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_passes_results_and_batch_runs_on_complete() {
+        let (pool, _container) = setup_pg_test().await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let double = ChainStep {
+            class: "DoubleWorker".to_string(),
+            queue: None,
+            tags: None,
+            args_version: 1,
+        };
+        let batch_id = enqueue_batch(
+            &pool,
+            &BatchSpec {
+                jobs: vec![
+                    spec("DoubleWorker", serde_json::json!({"n": 2}), vec![double]),
+                    spec("DoubleWorker", serde_json::json!({"n": 5}), vec![]),
+                ],
+                on_complete: Some(spec(
+                    "DoneWorker",
+                    serde_json::json!({"outcome": "done"}),
+                    vec![],
+                )),
+                on_failure: Some(spec(
+                    "DoneWorker",
+                    serde_json::json!({"outcome": "failed"}),
+                    vec![],
+                )),
+            },
+        )
+        .await
+        .expect("enqueue batch");
+
+        struct DoubleWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<serde_json::Value> for DoubleWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, args: serde_json::Value) -> crate::Result<()> {
+                let n = args["n"].as_i64().unwrap_or_default();
+                crate::bgworker::report_result(&serde_json::json!({"n": n * 2})).await
+            }
+        }
+
+        struct DoneWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<serde_json::Value> for DoneWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, _args: serde_json::Value) -> crate::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "DoubleWorker".to_string(),
+                crate::bgworker::erase_worker(DoubleWorker),
+                WorkerOpts::default()
+            )
+            .is_ok());
+        assert!(registry
+            .insert_handler(
+                "DoneWorker".to_string(),
+                crate::bgworker::erase_worker(DoneWorker),
+                WorkerOpts::default()
+            )
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<PgDriver>(&pool, &opts, &token, &[]);
+        sleep(Duration::from_secs(2)).await;
+        for handle in handles {
+            handle.abort();
+        }
+
+        let jobs = get_jobs(&pool, None, None).await.expect("get jobs");
+        let mut results = jobs
+            .iter()
+            .filter(|job| job.name == "DoubleWorker")
+            .map(|job| (job.data.clone(), job.result.clone(), job.status.clone()))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(args, _, _)| args["n"].as_i64());
+        assert_eq!(
+            results,
+            vec![
+                (
+                    serde_json::json!({"n": 2}),
+                    Some(serde_json::json!({"n": 4})),
+                    JobStatus::Completed
+                ),
+                (
+                    serde_json::json!({"n": 4}),
+                    Some(serde_json::json!({"n": 8})),
+                    JobStatus::Completed
+                ),
+                (
+                    serde_json::json!({"n": 5}),
+                    Some(serde_json::json!({"n": 10})),
+                    JobStatus::Completed
+                ),
+            ]
+        );
+        assert!(jobs
+            .iter()
+            .filter(|job| job.name == "DoubleWorker")
+            .all(|job| job.batch_id.as_deref() == Some(batch_id.as_str())));
+
+        let callbacks = jobs
+            .iter()
+            .filter(|job| job.name == "DoneWorker")
+            .collect::<Vec<_>>();
+        assert_eq!(callbacks.len(), 1);
+        assert_eq!(callbacks[0].data, serde_json::json!({"outcome": "done"}));
+        assert_eq!(callbacks[0].status, JobStatus::Completed);
+
+        let batches = get_batches(&pool).await.expect("get batches");
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].id, batch_id);
+        assert_eq!(batches[0].status, BatchStatus::Completed);
+        assert_eq!((batches[0].completed, batches[0].total), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn first_failed_batch_member_enqueues_on_failure() {
+        let (pool, _container) = setup_pg_test().await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        enqueue_batch(
+            &pool,
+            &BatchSpec {
+                jobs: vec![
+                    spec("ImportWorker", serde_json::json!({"file": 1}), vec![]),
+                    spec("ImportWorker", serde_json::json!({"file": 2}), vec![]),
+                ],
+                on_complete: None,
+                on_failure: Some(spec("AlertWorker", serde_json::json!({}), vec![])),
+            },
+        )
+        .await
+        .expect("enqueue batch");
+
+        for job in get_jobs(&pool, None, None).await.expect("get jobs") {
+            fail_job(&pool, &job.id, &crate::Error::string("boom"))
+                .await
+                .expect("fail job");
+        }
+
+        let jobs = get_jobs(&pool, None, None).await.expect("get jobs");
+        let alerts = jobs
+            .iter()
+            .filter(|job| job.name == "AlertWorker")
+            .collect::<Vec<_>>();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].status, JobStatus::Queued);
+        assert_eq!(alerts[0].batch_id, None);
+
+        let batches = get_batches(&pool).await.expect("get batches");
+        assert_eq!(batches[0].status, BatchStatus::Failed);
+        assert_eq!((batches[0].failed, batches[0].completed), (2, 0));
+    }
+
+    fn limited(name: &str, opts: WorkerOpts) -> HashMap<String, WorkerOpts> {
+        HashMap::from([(name.to_string(), opts)])
+    }
+
+    #[tokio::test]
+    async fn concurrency_cap_holds_back_jobs_of_the_capped_class() {
+        let (pool, _container) = setup_pg_test().await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        for name in ["Transcode", "Transcode", "Transcode", "Thumbnail"] {
+            enqueue(
+                &pool,
+                name,
+                serde_json::json!({}),
+                None,
+                &EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue");
+        }
+        let worker_opts = limited(
+            "Transcode",
+            WorkerOpts {
+                concurrency: Some(2),
+                ..Default::default()
+            },
+        );
+
+        let mut names = Vec::new();
+        while let Some(job) = dequeue(&pool, &[], &[], &worker_opts)
+            .await
+            .expect("dequeue")
+        {
+            names.push(job.name);
+        }
+        assert_eq!(names, vec!["Transcode", "Transcode", "Thumbnail"]);
+
+        let running = get_jobs(&pool, Some(&vec![JobStatus::Processing]), None)
+            .await
+            .expect("get jobs");
+        let transcode = running
+            .iter()
+            .find(|job| job.name == "Transcode")
+            .expect("a transcode job is running");
+        complete_job(&pool, &transcode.id, None)
+            .await
+            .expect("complete job");
+
+        let job = dequeue(&pool, &[], &[], &worker_opts)
+            .await
+            .expect("dequeue")
+            .expect("a slot was freed");
+        assert_eq!(job.name, "Transcode");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_holds_back_jobs_once_reached() {
+        let (pool, _container) = setup_pg_test().await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        for _ in 0..3 {
+            enqueue(
+                &pool,
+                "Webhook",
+                serde_json::json!({}),
+                None,
+                &EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue");
+        }
+        let worker_opts = limited(
+            "Webhook",
+            WorkerOpts {
+                rate_limit: Some(crate::bgworker::RateLimit::per_minute(2)),
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..2 {
+            let job = dequeue(&pool, &[], &[], &worker_opts)
+                .await
+                .expect("dequeue")
+                .expect("within the rate limit");
+            complete_job(&pool, &job.id, None)
+                .await
+                .expect("complete job");
+        }
+        assert!(dequeue(&pool, &[], &[], &worker_opts)
+            .await
+            .expect("dequeue")
+            .is_none());
+        assert!(dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .is_some());
+    }
 }
@@ -0,0 +1,333 @@
+//! Recurring jobs.
+//!
+//! A recurring job is declared in `Hooks::connect_workers`, next to the
+//! registration of its worker:
+//!
+//! ```rust,ignore
+//! async fn connect_workers(ctx: &AppContext, queue: &Queue) -> Result<()> {
+//!     queue.register(ReportWorker::build(ctx)).await?;
+//!     queue.recurring::<ReportWorker, _>(
+//!         "nightly-report",
+//!         Schedule::cron("0 0 3 * * *")?,
+//!         ReportArgs { days: 1 },
+//!     )?;
+//!     queue.recurring::<PingWorker, _>(
+//!         "ping",
+//!         Schedule::every(Duration::from_secs(30)),
+//!         (),
+//!     )?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Every process running the queue's workers ticks the recurring jobs, and
+//! enqueues each tick as a unique job keyed by the recurring job's name and
+//! the tick's time. The queue backend keeps only the first of them, so a
+//! tick is enqueued once however many worker processes run, and no separate
+//! scheduler process is needed.
+//!
+//! Ticks fall on the same instants in every process: cron ticks are
+//! absolute, and interval ticks are counted from the Unix epoch. A tick that
+//! passed while no worker process was running is not caught up.
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use croner::Cron;
+
+use super::{batch::JobSpec, EnqueueOptions, Queue, UniqueJob, UniqueWindow};
+use crate::{scheduler, Error, Result};
+
+/// The shortest a tick's uniqueness window gets, so that processes whose
+/// clocks are slightly apart still agree on a frequent schedule's ticks.
+const MIN_UNIQUE_WINDOW: Duration = Duration::from_secs(60);
+
+/// When a recurring job ticks.
+#[derive(Clone, Debug)]
+pub enum Schedule {
+    /// On the instants matching a cron expression, in UTC.
+    Cron(Box<Cron>),
+    /// Every period, counted from the Unix epoch.
+    Every(Duration),
+}
+
+impl Schedule {
+    /// Ticks on `expr`: a cron expression with seconds, as in
+    /// `scheduler.yaml` (`0 0 3 * * *`), or its English description (`every
+    /// day at 3am`).
+    ///
+    /// # Errors
+    /// Fails if `expr` is neither.
+    pub fn cron(expr: &str) -> Result<Self> {
+        let cron = scheduler::parse_cron(expr)?;
+        Ok(Self::Cron(Box::new(cron)))
+    }
+
+    /// Ticks every `period`, on multiples of it since the Unix epoch: every
+    /// 5 minutes ticks at `:00`, `:05`, `:10`...
+    #[must_use]
+    pub const fn every(period: Duration) -> Self {
+        Self::Every(period)
+    }
+
+    /// The first tick strictly after `time`.
+    ///
+    /// # Errors
+    /// Fails if the schedule has no tick after `time`, or is an interval
+    /// under a millisecond.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        match self {
+            Self::Cron(cron) => cron
+                .find_next_occurrence(&time, false)
+                .map_err(|err| Error::string(&format!("no next tick: {err}"))),
+            Self::Every(period) => {
+                let period = i64::try_from(period.as_millis())
+                    .ok()
+                    .filter(|period| *period > 0)
+                    .ok_or_else(|| {
+                        Error::string("recurring interval must be at least a millisecond")
+                    })?;
+                let next = (time.timestamp_millis().div_euclid(period) + 1) * period;
+                DateTime::from_timestamp_millis(next)
+                    .ok_or_else(|| Error::string("no next tick: out of range"))
+            }
+        }
+    }
+}
+
+/// A recurring job: the job to enqueue on each tick of its schedule.
+#[derive(Clone, Debug)]
+pub(crate) struct Recurring {
+    pub(crate) name: String,
+    pub(crate) schedule: Schedule,
+    pub(crate) job: JobSpec,
+}
+
+impl Recurring {
+    /// The uniqueness constraint of the job enqueued for `tick`. It holds
+    /// until the next tick, so that a process enqueueing the same tick late
+    /// still finds it.
+    fn unique(&self, tick: DateTime<Utc>) -> UniqueJob {
+        let window = self
+            .schedule
+            .next_after(tick)
+            .ok()
+            .and_then(|next| (next - tick).to_std().ok())
+            .map_or(MIN_UNIQUE_WINDOW, |period| period.max(MIN_UNIQUE_WINDOW));
+        UniqueJob {
+            key: format!("recurring:{}:{}", self.name, tick.timestamp_millis()),
+            window: UniqueWindow::For(window),
+        }
+    }
+}
+
+/// The recurring jobs added to a [`Queue`].
+#[derive(Clone, Default)]
+pub(crate) struct Registry(Arc<Mutex<Vec<Recurring>>>);
+
+impl Registry {
+    pub(crate) fn add(&self, recurring: Recurring) -> Result<()> {
+        recurring.schedule.next_after(Utc::now())?;
+        let mut jobs = self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if jobs.iter().any(|job| job.name == recurring.name) {
+            return Err(Error::string(&format!(
+                "recurring job `{}` is already added",
+                recurring.name
+            )));
+        }
+        jobs.push(recurring);
+        Ok(())
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<Recurring> {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// Enqueues the job of `recurring` for `tick`, unless a process already did.
+async fn enqueue_tick(
+    queue: &Queue,
+    recurring: &Recurring,
+    tick: DateTime<Utc>,
+) -> Result<Option<String>> {
+    let job = recurring.job.clone();
+    queue
+        .enqueue(
+            job.class,
+            job.args,
+            EnqueueOptions {
+                queue: job.queue,
+                tags: job.tags,
+                priority: job.priority,
+                unique: Some(recurring.unique(tick)),
+                args_version: job.args_version,
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+}
+
+async fn tick(queue: &Queue, recurring: Recurring) {
+    let mut after = Utc::now();
+    loop {
+        let tick = match recurring.schedule.next_after(after) {
+            Ok(tick) => tick,
+            Err(err) => {
+                tracing::error!(
+                    recurring = recurring.name,
+                    err = err.to_string(),
+                    "stopping recurring job"
+                );
+                return;
+            }
+        };
+        if let Ok(wait) = (tick - Utc::now()).to_std() {
+            tokio::time::sleep(wait).await;
+        }
+        match enqueue_tick(queue, &recurring, tick).await {
+            Ok(job_id) => {
+                tracing::debug!(recurring = recurring.name, %tick, job_id = ?job_id, "Enqueued recurring job");
+            }
+            Err(err) => {
+                tracing::error!(recurring = recurring.name, %tick, err = err.to_string(), "failed to enqueue recurring job");
+            }
+        }
+        after = tick.max(Utc::now());
+    }
+}
+
+/// Ticks `jobs` on `queue` until dropped.
+pub(crate) async fn run(queue: &Queue, jobs: Vec<Recurring>) {
+    tracing::info!(
+        recurring = ?jobs.iter().map(|job| job.name.as_str()).collect::<Vec<_>>(),
+        "Starting recurring jobs"
+    );
+    futures_util::future::join_all(jobs.into_iter().map(|job| tick(queue, job))).await;
+    // Every schedule ran out; the queue's workers keep running regardless.
+    std::future::pending::<()>().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        app::AppContext,
+        bgworker::{inmem::InMemQueue, BackgroundWorker, QueueProvider},
+    };
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ReportArgs {
+        days: u32,
+    }
+
+    struct ReportWorker;
+
+    #[async_trait::async_trait]
+    impl BackgroundWorker<ReportArgs> for ReportWorker {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+
+        async fn perform(&self, _args: ReportArgs) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn schedules_tick_after_the_given_time() {
+        let every = Schedule::every(Duration::from_secs(300));
+        assert_eq!(
+            every.next_after(at("2026-03-01T10:02:30Z")).unwrap(),
+            at("2026-03-01T10:05:00Z")
+        );
+        assert_eq!(
+            every.next_after(at("2026-03-01T10:05:00Z")).unwrap(),
+            at("2026-03-01T10:10:00Z")
+        );
+        assert!(Schedule::every(Duration::ZERO)
+            .next_after(at("2026-03-01T10:05:00Z"))
+            .is_err());
+
+        let cron = Schedule::cron("0 0 3 * * *").unwrap();
+        assert_eq!(
+            cron.next_after(at("2026-03-01T03:00:00Z")).unwrap(),
+            at("2026-03-02T03:00:00Z")
+        );
+        let english = Schedule::cron("every day at 3am").unwrap();
+        assert_eq!(
+            english.next_after(at("2026-03-01T02:00:00Z")).unwrap(),
+            at("2026-03-01T03:00:00Z")
+        );
+        assert!(Schedule::cron("0 0 3 * *").is_err());
+    }
+
+    #[tokio::test]
+    async fn a_tick_is_enqueued_once_across_processes() {
+        let provider = Arc::new(InMemQueue::default());
+        // Two worker processes, sharing a backend.
+        let queues = [
+            Queue::from_provider(provider.clone()),
+            Queue::from_provider(provider.clone()),
+        ];
+        for queue in &queues {
+            queue
+                .recurring::<ReportWorker, _>(
+                    "report",
+                    Schedule::every(Duration::from_secs(60)),
+                    ReportArgs { days: 1 },
+                )
+                .unwrap();
+        }
+
+        let tick = at("2026-03-01T10:05:00Z");
+        let mut ids = Vec::new();
+        for queue in &queues {
+            let recurring = queue.recurring.snapshot().remove(0);
+            ids.push(enqueue_tick(queue, &recurring, tick).await.unwrap());
+            ids.push(enqueue_tick(queue, &recurring, tick).await.unwrap());
+        }
+        assert!(ids.iter().all(|id| id.is_some() && *id == ids[0]));
+
+        let recurring = queues[0].recurring.snapshot().remove(0);
+        let next = enqueue_tick(&queues[0], &recurring, at("2026-03-01T10:06:00Z"))
+            .await
+            .unwrap();
+        assert_ne!(next, ids[0]);
+
+        let jobs = provider.get_jobs(None, None).await.unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert!(jobs
+            .iter()
+            .all(|job| job.name == "ReportWorker" && job.data == serde_json::json!({"days": 1})));
+    }
+
+    #[test]
+    fn recurring_names_are_unique_per_queue() {
+        let queue = crate::bgworker::inmem::create_provider();
+        let add = |name: &str| {
+            queue.recurring::<ReportWorker, _>(
+                name,
+                Schedule::every(Duration::from_secs(60)),
+                ReportArgs { days: 1 },
+            )
+        };
+        add("report").unwrap();
+        assert!(add("report").is_err());
+        add("weekly-report").unwrap();
+        assert_eq!(queue.recurring.snapshot().len(), 2);
+    }
+}
@@ -5,14 +5,20 @@ use std::{
     time::Duration,
 };
 
-pub use super::{Job, JobData, JobId};
-use super::{JobHandler, JobStatus, Queue, QueueProvider};
+use super::{
+    batch::{BatchSpec, BatchStatus, JobBatch, JobSpec},
+    stats::QueueStats,
+    upcast::INITIAL_ARGS_VERSION,
+    EnqueueOptions, JobHandler, JobReporter, JobStatus, Queue, QueueProvider, UniqueJob,
+    UniqueWindow, WorkerOpts,
+};
+pub use super::{Job, JobAttempt, JobData, JobId};
 use crate::{
-    config::{ReaperConfig, RedisQueueConfig},
+    config::{ReaperConfig, RedisQueueConfig, WorkerLimitsConfig},
     Error, Result,
 };
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use redis::{aio::MultiplexedConnection as Connection, AsyncCommands, Client, Script};
 use serde_json::Value as JsonValue;
 use tokio::{task::JoinHandle, time::sleep};
@@ -23,8 +29,14 @@ use ulid::Ulid;
 pub type RedisPool = Client;
 
 const QUEUE_KEY_PREFIX: &str = "queue:";
+const SCHEDULED_KEY_PREFIX: &str = "scheduled:";
 const JOB_KEY_PREFIX: &str = "job:";
 const PROCESSING_KEY_PREFIX: &str = "processing:";
+const UNIQUE_KEY_PREFIX: &str = "unique:";
+const BATCH_KEY_PREFIX: &str = "batch:";
+const ACTIVE_KEY_PREFIX: &str = "active:";
+const RATE_KEY_PREFIX: &str = "rate:";
+const CANCEL_REQUESTED_KEY: &str = "cancel_requested";
 
 // Implementation for job creation and serialization
 impl Job {
@@ -41,6 +53,16 @@ impl Job {
             updated_at: Some(now),
             tags: None,
             priority: 0,
+            attempts: 0,
+            last_error: None,
+            history: Vec::new(),
+            unique_key: None,
+            progress: None,
+            result: None,
+            batch_id: None,
+            chain: Vec::new(),
+            queue: None,
+            args_version: INITIAL_ARGS_VERSION,
         }
     }
 
@@ -57,6 +79,7 @@ impl Job {
 
 pub struct JobRegistry {
     handlers: Arc<HashMap<String, JobHandler>>,
+    worker_opts: Arc<HashMap<String, WorkerOpts>>,
 }
 
 impl JobRegistry {
@@ -65,16 +88,25 @@ impl JobRegistry {
     pub fn new() -> Self {
         Self {
             handlers: Arc::new(HashMap::new()),
+            worker_opts: Arc::new(HashMap::new()),
         }
     }
 
     /// Inserts a pre-erased job handler under `name` (see
-    /// [`super::erase_worker`]).
+    /// [`super::erase_worker`]), with the worker's [`WorkerOpts`].
     ///
     /// # Errors
     ///
     /// Fails if cannot register worker
-    pub fn insert_handler(&mut self, name: String, handler: JobHandler) -> Result<()> {
+    pub fn insert_handler(
+        &mut self,
+        name: String,
+        handler: JobHandler,
+        opts: WorkerOpts,
+    ) -> Result<()> {
+        Arc::get_mut(&mut self.worker_opts)
+            .ok_or_else(|| Error::string("cannot register worker"))?
+            .insert(name.clone(), opts);
         Arc::get_mut(&mut self.handlers)
             .ok_or_else(|| Error::string("cannot register worker"))?
             .insert(name, handler);
@@ -97,15 +129,24 @@ impl JobRegistry {
         tags: &[String],
     ) -> Vec<JoinHandle<()>> {
         let mut jobs = Vec::new();
-        let queues = get_queues(&opts.queues);
+        let queues = opts
+            .worker_queues
+            .clone()
+            .unwrap_or_else(|| get_queues(&opts.queues));
         let interval = opts.poll_interval_sec;
+        let drain = super::Drain {
+            token: token.clone(),
+            timeout: opts.shutdown_timeout,
+        };
 
         for idx in 0..opts.num_workers {
             let handlers = self.handlers.clone();
+            let worker_opts = self.worker_opts.clone();
             let worker_token = token.clone();
             let client = client.clone();
             let queues = queues.clone();
             let tags = tags.to_owned();
+            let drain = drain.clone();
 
             let job = tokio::spawn(async move {
                 let mut conn = match client.get_multiplexed_async_connection().await {
@@ -115,6 +156,7 @@ impl JobRegistry {
                         return;
                     }
                 };
+                let reporter: Arc<dyn JobReporter> = Arc::new(ConnReporter { conn: conn.clone() });
 
                 loop {
                     // Check for cancellation before potentially blocking on dequeue
@@ -123,18 +165,47 @@ impl JobRegistry {
                         break;
                     }
 
-                    let job_opt = match dequeue_with_conn(&mut conn, &queues, &tags).await {
-                        Ok(t) => t,
-                        Err(err) => {
-                            error!(err = err.to_string(), "cannot fetch from queue");
-                            None
-                        }
-                    };
+                    let job_opt =
+                        match dequeue_with_conn(&mut conn, &queues, &tags, &worker_opts).await {
+                            Ok(t) => t,
+                            Err(err) => {
+                                error!(err = err.to_string(), "cannot fetch from queue");
+                                None
+                            }
+                        };
 
                     if let Some((job, queue_name)) = job_opt {
                         debug!(job_id = job.id, name = job.name, "working on job");
                         if let Some(handler) = handlers.get(&job.name) {
-                            match handler(job.id.clone(), job.data.clone()).await {
+                            let started_at = Utc::now();
+                            let super::JobRun {
+                                result,
+                                cancelled,
+                                interrupted,
+                            } = super::run_job(
+                                job.id.clone(),
+                                reporter.clone(),
+                                worker_opts.get(&job.name),
+                                Duration::from_secs(interval.into()),
+                                &drain,
+                                super::call_handler(handler, worker_opts.get(&job.name), &job),
+                            )
+                            .await;
+                            if interrupted {
+                                if let Err(err) =
+                                    requeue_job_with_conn(&mut conn, &job.id, &queue_name).await
+                                {
+                                    error!(err = err.to_string(), job = ?job, "cannot requeue interrupted job");
+                                }
+                                break;
+                            }
+                            let attempt = JobAttempt::new(job.attempts, started_at, &result);
+                            if let Err(err) =
+                                record_attempt_with_conn(&mut conn, &job.id, &attempt).await
+                            {
+                                error!(err = err.to_string(), job = ?job, "cannot record job attempt");
+                            }
+                            match result {
                                 Ok(()) => {
                                     if let Err(err) = complete_job_with_conn(
                                         &mut conn,
@@ -147,8 +218,31 @@ impl JobRegistry {
                                         error!(err = err.to_string(), job = ?job, "cannot complete job");
                                     }
                                 }
-                                Err(err) => {
+                                Err(err) if cancelled => {
                                     if let Err(err) =
+                                        cancel_job_with_conn(&mut conn, &job.id, &queue_name, &err)
+                                            .await
+                                    {
+                                        error!(err = err.to_string(), job = ?job, "cannot cancel job");
+                                    }
+                                }
+                                Err(err) => {
+                                    let next_run_at = worker_opts
+                                        .get(&job.name)
+                                        .and_then(|opts| opts.next_run_at(job.attempts));
+                                    if let Some(run_at) = next_run_at {
+                                        if let Err(err) = retry_job_with_conn(
+                                            &mut conn,
+                                            &job.id,
+                                            &queue_name,
+                                            run_at,
+                                            &err,
+                                        )
+                                        .await
+                                        {
+                                            error!(err = err.to_string(), job = ?job, "cannot retry job");
+                                        }
+                                    } else if let Err(err) =
                                         fail_job_with_conn(&mut conn, &job.id, &queue_name, &err)
                                             .await
                                     {
@@ -204,7 +298,8 @@ pub async fn clear(client: &RedisPool) -> Result<()> {
     Ok(())
 }
 
-/// Add a task
+/// Add a task as `options` say. A unique one goes through
+/// [`enqueue_unique`].
 ///
 /// # Errors
 ///
@@ -212,36 +307,385 @@ pub async fn clear(client: &RedisPool) -> Result<()> {
 pub async fn enqueue(
     client: &RedisPool,
     class: String,
-    queue: Option<String>,
     args: impl serde::Serialize + Send,
-    tags: Option<Vec<String>>,
-    priority: Option<i32>,
+    options: EnqueueOptions,
 ) -> Result<JobId> {
+    let job = build_job(
+        class,
+        args,
+        options.tags,
+        options.priority,
+        options.run_at,
+        options.args_version,
+    )?;
+    if let Some(unique) = &options.unique {
+        return enqueue_unique(client, unique, options.queue, job).await;
+    }
     let mut conn = get_connection(client).await?;
-    let queue_name = queue.unwrap_or_else(|| "default".to_string());
-    let queue_key = format!("{QUEUE_KEY_PREFIX}{queue_name}");
+    let queue_name = options.queue.unwrap_or_else(|| "default".to_string());
+    push_job_with_conn(&mut conn, &queue_name, &job).await?;
+    Ok(job.id)
+}
+
+/// Stores `job` in its job key and in the `queue_name` queue (see
+/// [`add_to_queue`]).
+async fn push_job_with_conn(conn: &mut Connection, queue_name: &str, job: &Job) -> Result<()> {
+    let job_key = format!("{JOB_KEY_PREFIX}{}", job.id);
+    let mut pipe = redis::pipe();
+    pipe.set(&job_key, job.to_json()?);
+    add_to_queue(&mut pipe, queue_name, job);
+    let _: () = pipe.query_async(conn).await?;
+    Ok(())
+}
+
+/// Adds `job` to `queue_name`: to its ZSET, scored by priority, when the job
+/// is due, or until then to its `scheduled:` ZSET, scored by `run_at`.
+///
+/// Jobs waiting for their `run_at` stay out of the queue ZSET, where they
+/// would fill the part of it a dequeue scans and hide the due jobs behind
+/// them. [`PROMOTE_DUE_SCRIPT`] moves them over once due.
+fn add_to_queue(pipe: &mut redis::Pipeline, queue_name: &str, job: &Job) {
+    if job.run_at > Utc::now() {
+        pipe.zadd(
+            format!("{SCHEDULED_KEY_PREFIX}{queue_name}"),
+            &job.id,
+            job.run_at.timestamp_millis(),
+        );
+    } else {
+        pipe.zadd(
+            format!("{QUEUE_KEY_PREFIX}{queue_name}"),
+            &job.id,
+            calculate_score(job.priority),
+        );
+    }
+}
+
+/// Every queue and `scheduled:` ZSET, with the name of the queue it belongs
+/// to.
+async fn all_queue_keys(conn: &mut Connection) -> Result<Vec<(String, String)>> {
+    let mut keys = Vec::new();
+    for prefix in [QUEUE_KEY_PREFIX, SCHEDULED_KEY_PREFIX] {
+        let found: Vec<String> = redis::cmd("KEYS")
+            .arg(format!("{prefix}*"))
+            .query_async(conn)
+            .await?;
+        keys.extend(found.into_iter().map(|key| {
+            let queue_name = key.trim_start_matches(prefix).to_string();
+            (key, queue_name)
+        }));
+    }
+    Ok(keys)
+}
+
+/// Creates the job described by `spec`, as a member of `batch_id` if given.
+fn build_spec_job(spec: &JobSpec, batch_id: Option<&str>) -> Result<Job> {
+    let mut job = build_job(
+        spec.class.clone(),
+        &spec.args,
+        spec.tags.clone(),
+        spec.priority,
+        None,
+        spec.args_version,
+    )?;
+    job.batch_id = batch_id.map(ToString::to_string);
+    job.chain = spec.chain.clone();
+    Ok(job)
+}
+
+/// Enqueues the job described by `spec`, as a member of `batch_id` if given.
+async fn push_spec_with_conn(
+    conn: &mut Connection,
+    spec: &JobSpec,
+    batch_id: Option<&str>,
+) -> Result<JobId> {
+    let job = build_spec_job(spec, batch_id)?;
+    debug!(job_id = %job.id, job_name = %job.name, batch_id = ?batch_id, steps_left = job.chain.len(), "Enqueueing job");
+    push_job_with_conn(conn, spec.queue.as_deref().unwrap_or("default"), &job).await?;
+    Ok(job.id)
+}
 
-    // Convert args to JSON
-    let args_json = serde_json::to_value(args)?;
+/// Add the first job of a chain, carrying the remaining steps
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_chain(client: &RedisPool, job: &JobSpec) -> Result<JobId> {
+    let mut conn = get_connection(client).await?;
+    push_spec_with_conn(&mut conn, job, None).await
+}
 
-    // Create a job ID using ULID
-    let job_id = Ulid::new().to_string();
+/// Record a batch in its `batch:<id>` hash and add its jobs, returning the
+/// batch id
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_batch(client: &RedisPool, batch: &BatchSpec) -> Result<String> {
+    let mut conn = get_connection(client).await?;
+    let id = Ulid::new().to_string();
+    let now = Utc::now().to_rfc3339();
+    debug!(batch_id = %id, total = batch.jobs.len(), "Enqueueing batch");
+
+    let mut fields = vec![
+        ("total", batch.jobs.len().to_string()),
+        ("completed", "0".to_string()),
+        ("failed", "0".to_string()),
+        ("status", BatchStatus::Pending.to_string()),
+        ("created_at", now.clone()),
+        ("updated_at", now),
+    ];
+    if let Some(on_complete) = &batch.on_complete {
+        fields.push(("on_complete", serde_json::to_string(on_complete)?));
+    }
+    if let Some(on_failure) = &batch.on_failure {
+        fields.push(("on_failure", serde_json::to_string(on_failure)?));
+    }
+
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .hset_multiple(format!("{BATCH_KEY_PREFIX}{id}"), &fields);
+    for spec in &batch.jobs {
+        let job = build_spec_job(spec, Some(&id))?;
+        pipe.set(format!("{JOB_KEY_PREFIX}{}", job.id), job.to_json()?);
+        add_to_queue(&mut pipe, spec.queue.as_deref().unwrap_or("default"), &job);
+    }
+    let _: () = pipe.query_async(&mut conn).await?;
+    Ok(id)
+}
 
-    // Create job
-    let mut job = Job::new(job_id.clone(), class, args_json);
+/// Counts a completed member of a batch; once all members completed (and none
+/// failed), marks the batch completed and returns its `on_complete`.
+const BATCH_COMPLETE_SCRIPT: &str = r"
+local batch_key = KEYS[1]
+if redis.call('EXISTS', batch_key) == 0 then
+    return nil
+end
+local completed = redis.call('HINCRBY', batch_key, 'completed', 1)
+redis.call('HSET', batch_key, 'updated_at', ARGV[1])
+local total = tonumber(redis.call('HGET', batch_key, 'total'))
+if completed == total and redis.call('HGET', batch_key, 'status') == ARGV[2] then
+    redis.call('HSET', batch_key, 'status', ARGV[3])
+    return redis.call('HGET', batch_key, 'on_complete')
+end
+return nil
+";
+
+/// Counts a failed member of a batch and marks the batch failed, returning
+/// its `on_failure` for the first failure only.
+const BATCH_FAIL_SCRIPT: &str = r"
+local batch_key = KEYS[1]
+if redis.call('EXISTS', batch_key) == 0 then
+    return nil
+end
+local failed = redis.call('HINCRBY', batch_key, 'failed', 1)
+redis.call('HSET', batch_key, 'status', ARGV[2], 'updated_at', ARGV[1])
+if failed == 1 then
+    return redis.call('HGET', batch_key, 'on_failure')
+end
+return nil
+";
+
+/// Enqueues what follows a completed job: the next step of its chain, or,
+/// when it was the last step, its batch's `on_complete` if this was the last
+/// member of the batch to complete.
+async fn enqueue_completion_follow_ups(conn: &mut Connection, job: &Job) -> Result<()> {
+    if let Some(next) = JobSpec::continue_chain(&job.chain, job.result.clone()) {
+        push_spec_with_conn(conn, &next, job.batch_id.as_deref()).await?;
+        return Ok(());
+    }
+    let Some(batch_id) = &job.batch_id else {
+        return Ok(());
+    };
+    let on_complete: Option<String> = Script::new(BATCH_COMPLETE_SCRIPT)
+        .key(format!("{BATCH_KEY_PREFIX}{batch_id}"))
+        .arg(Utc::now().to_rfc3339())
+        .arg(BatchStatus::Pending.to_string())
+        .arg(BatchStatus::Completed.to_string())
+        .invoke_async(conn)
+        .await?;
+    if let Some(on_complete) = on_complete {
+        debug!(batch_id = %batch_id, "Batch completed, enqueueing its callback");
+        push_spec_with_conn(conn, &serde_json::from_str(&on_complete)?, None).await?;
+    }
+    Ok(())
+}
+
+/// Enqueues a failed job's batch's `on_failure` if this was the first member
+/// of the batch to fail.
+async fn enqueue_failure_follow_ups(conn: &mut Connection, job: &Job) -> Result<()> {
+    let Some(batch_id) = &job.batch_id else {
+        return Ok(());
+    };
+    let on_failure: Option<String> = Script::new(BATCH_FAIL_SCRIPT)
+        .key(format!("{BATCH_KEY_PREFIX}{batch_id}"))
+        .arg(Utc::now().to_rfc3339())
+        .arg(BatchStatus::Failed.to_string())
+        .invoke_async(conn)
+        .await?;
+    if let Some(on_failure) = on_failure {
+        debug!(batch_id = %batch_id, "Batch failed, enqueueing its callback");
+        push_spec_with_conn(conn, &serde_json::from_str(&on_failure)?, None).await?;
+    }
+    Ok(())
+}
+
+/// Retrieves every batch, newest first.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn get_batches(client: &RedisPool) -> Result<Vec<JobBatch>> {
+    let mut conn = get_connection(client).await?;
+    let keys: Vec<String> = conn.keys(format!("{BATCH_KEY_PREFIX}*")).await?;
+    let mut batches = Vec::with_capacity(keys.len());
+    for key in keys {
+        let fields: HashMap<String, String> = conn.hgetall(&key).await?;
+        let Some(id) = key.strip_prefix(BATCH_KEY_PREFIX) else {
+            continue;
+        };
+        let count = |field: &str| {
+            fields
+                .get(field)
+                .and_then(|value| value.parse().ok())
+                .unwrap_or_default()
+        };
+        let timestamp = |field: &str| {
+            fields
+                .get(field)
+                .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                .map(|at| at.with_timezone(&Utc))
+        };
+        batches.push(JobBatch {
+            id: id.to_string(),
+            status: fields
+                .get("status")
+                .map(|status| status.parse::<BatchStatus>())
+                .transpose()
+                .map_err(|err| Error::string(&err))?
+                .unwrap_or(BatchStatus::Pending),
+            total: count("total"),
+            completed: count("completed"),
+            failed: count("failed"),
+            created_at: timestamp("created_at"),
+            updated_at: timestamp("updated_at"),
+        });
+    }
+    batches.sort_by(|a, b| (&b.created_at, &b.id).cmp(&(&a.created_at, &a.id)));
+    Ok(batches)
+}
+
+/// Creates a new queued [`Job`] with a fresh ULID id.
+fn build_job(
+    class: String,
+    args: impl serde::Serialize + Send,
+    tags: Option<Vec<String>>,
+    priority: Option<i32>,
+    run_at: Option<DateTime<Utc>>,
+    args_version: u32,
+) -> Result<Job> {
+    let mut job = Job::new(Ulid::new().to_string(), class, serde_json::to_value(args)?);
     job.tags = tags;
     job.priority = priority.unwrap_or(0);
+    job.args_version = args_version;
+    if let Some(run_at) = run_at {
+        job.run_at = run_at;
+    }
+    Ok(job)
+}
 
-    // Serialize job for Redis storage
-    let job_json = job.to_json()?;
+/// Claims `unique:<key>` for the new job and stores it, unless the key is
+/// held by a job still inside the window, whose id is returned instead.
+///
+/// Modes: `queued` and `active` check whether the holder is still in the
+/// queue or waiting for its `run_at` (or, for `active`, in the processing
+/// set); `ttl` trusts the key's own expiry. The job goes to the ZSET
+/// `ARGV[6]` names, at score `ARGV[5]` (see [`add_to_queue`]).
+const ENQUEUE_UNIQUE_SCRIPT: &str = r"
+local unique_key = KEYS[1]
+local queue_key = KEYS[2]
+local processing_key = KEYS[3]
+local job_key = KEYS[4]
+local scheduled_key = KEYS[5]
+local job_id = ARGV[1]
+local mode = ARGV[2]
+
+local existing = redis.call('GET', unique_key)
+if existing then
+    if mode == 'ttl' then
+        return existing
+    end
+    if redis.call('ZSCORE', queue_key, existing) or redis.call('ZSCORE', scheduled_key, existing) then
+        return existing
+    end
+    if mode == 'active' and redis.call('SISMEMBER', processing_key, existing) == 1 then
+        return existing
+    end
+end
 
-    // Store job in Redis queue (ZSET, scored by priority) and in its job key.
-    let score = calculate_score(job.priority);
-    let job_key = format!("{JOB_KEY_PREFIX}{}", job.id);
-    let _: () = conn.set(&job_key, &job_json).await?;
-    let _: () = conn.zadd(&queue_key, &job.id, score).await?;
+if mode == 'ttl' then
+    redis.call('SET', unique_key, job_id, 'EX', ARGV[3])
+else
+    redis.call('SET', unique_key, job_id)
+end
+redis.call('SET', job_key, ARGV[4])
+if ARGV[6] == 'scheduled' then
+    redis.call('ZADD', scheduled_key, ARGV[5], job_id)
+else
+    redis.call('ZADD', queue_key, ARGV[5], job_id)
+end
+return nil
+";
 
-    Ok(job_id)
+/// Add `job` unless one holding the same [`UniqueJob::key`] is still inside
+/// its window, in which case that job's id is returned instead.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_unique(
+    client: &RedisPool,
+    unique: &UniqueJob,
+    queue: Option<String>,
+    mut job: Job,
+) -> Result<JobId> {
+    let mut conn = get_connection(client).await?;
+    let queue_name = queue.unwrap_or_else(|| "default".to_string());
+    let (mode, ttl_secs) = match &unique.window {
+        UniqueWindow::Queued => ("queued", 0),
+        UniqueWindow::QueuedOrProcessing => ("active", 0),
+        UniqueWindow::For(window) => ("ttl", window.as_secs().max(1)),
+    };
+    job.unique_key = Some(unique.key.clone());
+    let (target, score) = if job.run_at > Utc::now() {
+        #[allow(clippy::cast_precision_loss)]
+        let run_at_ms = job.run_at.timestamp_millis() as f64;
+        ("scheduled", run_at_ms)
+    } else {
+        ("queue", calculate_score(job.priority))
+    };
+
+    let existing: Option<String> = Script::new(ENQUEUE_UNIQUE_SCRIPT)
+        .key(format!("{UNIQUE_KEY_PREFIX}{}", unique.key))
+        .key(format!("{QUEUE_KEY_PREFIX}{queue_name}"))
+        .key(format!("{PROCESSING_KEY_PREFIX}{queue_name}"))
+        .key(format!("{JOB_KEY_PREFIX}{}", job.id))
+        .key(format!("{SCHEDULED_KEY_PREFIX}{queue_name}"))
+        .arg(&job.id)
+        .arg(mode)
+        .arg(ttl_secs)
+        .arg(job.to_json()?)
+        .arg(score)
+        .arg(target)
+        .invoke_async(&mut conn)
+        .await?;
+
+    if let Some(existing) = existing {
+        debug!(job_id = %existing, job_name = %job.name, unique_key = %unique.key, "Skipping duplicate of unique job");
+        Ok(existing)
+    } else {
+        debug!(job_id = %job.id, job_name = %job.name, unique_key = %unique.key, "Enqueueing unique job");
+        Ok(job.id)
+    }
 }
 
 /// Redis ZSET score for a job, derived from priority only.
@@ -255,25 +699,92 @@ fn calculate_score(priority: i32) -> f64 {
     -f64::from(priority)
 }
 
+/// Moves a job from its queue to the processing set, unless another worker
+/// took it first or its class is at its limit, and stores the claimed job
+/// (`ARGV[9]`) in place of the one the worker read (`ARGV[8]`) in the same
+/// step. Returns [`JOB_CHANGED`] if the job was written to since it was read,
+/// say cancelled, rather than overwrite that write.
+///
+/// `active:<class>` maps the class's claimed job ids to their queue; an entry
+/// counts towards the concurrency cap while the job is still in that queue's
+/// processing set, and is dropped otherwise. `rate:<class>` holds one entry
+/// per start, scored by its time in milliseconds.
 const ACQUIRE_JOB_SCRIPT: &str = r"
 local queue_key = KEYS[1]
 local processing_key = KEYS[2]
+local active_key = KEYS[3]
+local rate_key = KEYS[4]
 local job_id = ARGV[1]
+local concurrency = tonumber(ARGV[2])
+local rate_limit = tonumber(ARGV[3])
+local period_ms = tonumber(ARGV[4])
+local now_ms = tonumber(ARGV[5])
 
 local score = redis.call('ZSCORE', queue_key, job_id)
-if score then
-    redis.call('ZREM', queue_key, job_id)
-    redis.call('SADD', processing_key, job_id)
-    return score
-else
+if not score then
     return nil
 end
+if redis.call('GET', KEYS[5]) ~= ARGV[8] then
+    return 'changed'
+end
+
+if concurrency > 0 then
+    local running = 0
+    local active = redis.call('HGETALL', active_key)
+    for i = 1, #active, 2 do
+        if redis.call('SISMEMBER', ARGV[6] .. active[i + 1], active[i]) == 1 then
+            running = running + 1
+        else
+            redis.call('HDEL', active_key, active[i])
+        end
+    end
+    if running >= concurrency then
+        return nil
+    end
+end
+
+if rate_limit > 0 then
+    redis.call('ZREMRANGEBYSCORE', rate_key, '-inf', now_ms - period_ms)
+    if redis.call('ZCARD', rate_key) >= rate_limit then
+        return nil
+    end
+    redis.call('ZADD', rate_key, now_ms, job_id .. ':' .. now_ms)
+    redis.call('PEXPIRE', rate_key, period_ms)
+end
+
+redis.call('ZREM', queue_key, job_id)
+redis.call('SADD', processing_key, job_id)
+if concurrency > 0 then
+    redis.call('HSET', active_key, job_id, ARGV[7])
+end
+redis.call('SET', KEYS[5], ARGV[9])
+return score
+";
+
+/// What [`ACQUIRE_JOB_SCRIPT`] returns for a job written to since it was read.
+const JOB_CHANGED: &str = "changed";
+
+/// Moves the jobs of a `scheduled:` ZSET (`KEYS[1]`) that are due by
+/// `ARGV[1]` (in milliseconds) to their queue ZSET (`KEYS[2]`), scored by
+/// their priority, at most `ARGV[2]` of them. Returns how many moved.
+const PROMOTE_DUE_SCRIPT: &str = r"
+local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, tonumber(ARGV[2]))
+for _, job_id in ipairs(due) do
+    redis.call('ZREM', KEYS[1], job_id)
+    local json = redis.call('GET', ARGV[3] .. job_id)
+    if json then
+        local priority = tonumber(cjson.decode(json).priority) or 0
+        redis.call('ZADD', KEYS[2], -priority, job_id)
+    end
+end
+return #due
 ";
 
 async fn dequeue_with_conn(
     conn: &mut Connection,
     queues: &[String],
     tags: &[String],
+    worker_opts: &HashMap<String, WorkerOpts>,
 ) -> Result<Option<(Job, String)>> {
     // Paging bounds for scanning the priority-ordered queue.
     const BATCH_SIZE: isize = 50;
@@ -284,15 +795,32 @@ async fn dequeue_with_conn(
     }
 
     let script = Script::new(ACQUIRE_JOB_SCRIPT);
+    let promote = Script::new(PROMOTE_DUE_SCRIPT);
 
     for queue_name in queues {
         let queue_key = format!("{QUEUE_KEY_PREFIX}{queue_name}");
         let processing_key = format!("{PROCESSING_KEY_PREFIX}{queue_name}");
 
+        let promoted: i64 = promote
+            .key(format!("{SCHEDULED_KEY_PREFIX}{queue_name}"))
+            .key(&queue_key)
+            .arg(Utc::now().timestamp_millis())
+            .arg(MAX_SEARCH)
+            .arg(JOB_KEY_PREFIX)
+            .invoke_async(conn)
+            .await?;
+        if promoted > 0 {
+            trace!(
+                queue = queue_name,
+                promoted,
+                "Moved due jobs into the queue"
+            );
+        }
+
         // Page through the queue (ordered by ZSET score = priority) collecting
         // jobs whose tags match the worker's tag filter.
         let mut offset = 0;
-        let mut candidates: Vec<(String, Job)> = Vec::new();
+        let mut candidates: Vec<(String, Job, String)> = Vec::new();
         while offset < MAX_SEARCH {
             let job_ids: Vec<String> = conn
                 .zrange(&queue_key, offset, offset + BATCH_SIZE - 1)
@@ -327,6 +855,17 @@ async fn dequeue_with_conn(
                                     worker_tags = ?tags,
                                     "Job doesn't match tag criteria, skipping"
                                 );
+                            } else if worker_opts
+                                .get(&job.name)
+                                .is_some_and(|opts| job.args_version > opts.args_version)
+                            {
+                                // Enqueued by a newer release of the app: leave it
+                                // for a worker that knows its args.
+                                trace!(
+                                    job_id = job_id,
+                                    args_version = job.args_version,
+                                    "Job args newer than the worker's, skipping"
+                                );
                             } else if job.run_at > Utc::now() {
                                 // Not yet due (e.g. an interval-rescheduled job):
                                 // mirror the SQL backends' `run_at <= NOW()` filter
@@ -337,7 +876,7 @@ async fn dequeue_with_conn(
                                     "Job not due yet, skipping"
                                 );
                             } else {
-                                candidates.push((job_id.clone(), job));
+                                candidates.push((job_id.clone(), job, json));
                             }
                         }
                         Err(err) => {
@@ -363,7 +902,7 @@ async fn dequeue_with_conn(
         // 1. Higher priority first.
         // 2. Earlier `run_at` first for equal priority.
         // 3. Smaller id first as a final deterministic tiebreaker.
-        candidates.sort_by(|(id_a, job_a), (id_b, job_b)| {
+        candidates.sort_by(|(id_a, job_a, _), (id_b, job_b, _)| {
             job_b
                 .priority
                 .cmp(&job_a.priority)
@@ -371,18 +910,52 @@ async fn dequeue_with_conn(
                 .then_with(|| id_a.cmp(id_b))
         });
 
-        for (job_id, job) in candidates {
+        for (job_id, mut job, json) in candidates {
             // Atomically claim the job: move it from the queue ZSET to the
-            // processing set. Returns None if another worker took it first.
-            let result: Option<f64> = script
+            // processing set. Returns None if another worker took it first,
+            // or if its class is at its concurrency cap or rate limit.
+            let opts = worker_opts.get(&job.name);
+            let concurrency = opts.and_then(|opts| opts.concurrency).unwrap_or(0);
+            let (rate_limit, period_ms) =
+                opts.and_then(|opts| opts.rate_limit.as_ref())
+                    .map_or((0, 0), |rate_limit| {
+                        (
+                            rate_limit.limit,
+                            u64::try_from(rate_limit.period.as_millis()).unwrap_or(u64::MAX),
+                        )
+                    });
+            if job.attempts == 0 {
+                job.history.clear();
+            }
+            job.attempts += 1;
+            job.progress = None;
+            job.result = None;
+            let result: Option<String> = script
                 .key(&queue_key)
                 .key(&processing_key)
+                .key(format!("{ACTIVE_KEY_PREFIX}{}", job.name))
+                .key(format!("{RATE_KEY_PREFIX}{}", job.name))
+                .key(format!("{JOB_KEY_PREFIX}{job_id}"))
                 .arg(&job_id)
+                .arg(concurrency)
+                .arg(rate_limit)
+                .arg(period_ms)
+                .arg(Utc::now().timestamp_millis())
+                .arg(PROCESSING_KEY_PREFIX)
+                .arg(queue_name)
+                .arg(&json)
+                .arg(job.to_json()?)
                 .invoke_async(conn)
                 .await?;
 
-            if result.is_some() {
-                return Ok(Some((job, queue_name.clone())));
+            match result.as_deref() {
+                // Taken by another worker, or its class is at its limit.
+                None => {}
+                // Written to since the scan; the next poll reads it afresh.
+                Some(JOB_CHANGED) => {
+                    trace!(job_id = job_id, "Job changed while being claimed, skipping");
+                }
+                Some(_) => return Ok(Some((job, queue_name.clone()))),
             }
         }
     }
@@ -405,21 +978,143 @@ async fn complete_job_with_conn(
         if let Some(interval) = interval_ms {
             job.run_at = Utc::now() + chrono::Duration::milliseconds(interval);
             job.status = JobStatus::Queued;
-            let new_json = job.to_json()?;
-            let queue_key = format!("{QUEUE_KEY_PREFIX}{queue_name}");
-            let score = calculate_score(job.priority);
-            let _: () = redis::pipe()
-                .set(&job_key, &new_json)
-                .zadd(&queue_key, id, score)
-                .query_async(conn)
-                .await?;
+            job.attempts = 0;
+            let mut pipe = redis::pipe();
+            pipe.set(&job_key, job.to_json()?);
+            add_to_queue(&mut pipe, queue_name, &job);
+            let _: () = pipe.query_async(conn).await?;
         } else {
             job.status = JobStatus::Completed;
             job.updated_at = Some(Utc::now());
             let updated_json = job.to_json()?;
             let _: () = conn.set(&job_key, &updated_json).await?;
+            enqueue_completion_follow_ups(conn, &job).await?;
         }
         let _: () = conn.srem(&processing_key, id).await?;
+        let _: () = conn.srem(CANCEL_REQUESTED_KEY, id).await?;
+    }
+    Ok(())
+}
+
+async fn retry_job_with_conn(
+    conn: &mut Connection,
+    id: &JobId,
+    queue_name: &str,
+    run_at: DateTime<Utc>,
+    error: &crate::Error,
+) -> Result<()> {
+    let job_key = format!("{JOB_KEY_PREFIX}{id}");
+    let processing_key = format!("{PROCESSING_KEY_PREFIX}{queue_name}");
+
+    let job_json: Option<String> = conn.get(&job_key).await?;
+    if let Some(json) = job_json
+        && let Ok(mut job) = Job::from_json(&json)
+    {
+        debug!(job_id = %id, error = %error, run_at = %run_at, "Rescheduling failed job for retry");
+        job.run_at = run_at;
+        job.status = JobStatus::Queued;
+        job.updated_at = Some(Utc::now());
+        job.last_error = Some(super::error_message(error));
+        // Waits out its backoff in the scheduled set, not ahead of due jobs.
+        let mut pipe = redis::pipe();
+        pipe.set(&job_key, job.to_json()?);
+        add_to_queue(&mut pipe, queue_name, &job);
+        let _: () = pipe.query_async(conn).await?;
+    }
+    let _: () = conn.srem(&processing_key, id).await?;
+    let _: () = conn.srem(CANCEL_REQUESTED_KEY, id).await?;
+    Ok(())
+}
+
+/// Puts job `id`, interrupted by a worker shutdown, back in `queue_name`,
+/// and gives back the attempt it was on, so the interruption does not count
+/// against its retries.
+async fn requeue_job_with_conn(conn: &mut Connection, id: &JobId, queue_name: &str) -> Result<()> {
+    let job_key = format!("{JOB_KEY_PREFIX}{id}");
+    let processing_key = format!("{PROCESSING_KEY_PREFIX}{queue_name}");
+
+    let job_json: Option<String> = conn.get(&job_key).await?;
+    if let Some(json) = job_json
+        && let Ok(mut job) = Job::from_json(&json)
+    {
+        debug!(job_id = %id, "Requeueing job interrupted by shutdown");
+        job.attempts = (job.attempts - 1).max(0);
+        job.updated_at = Some(Utc::now());
+        let queue_key = format!("{QUEUE_KEY_PREFIX}{queue_name}");
+        let score = calculate_score(job.priority);
+        let _: () = redis::pipe()
+            .set(&job_key, job.to_json()?)
+            .zadd(&queue_key, id, score)
+            .query_async(conn)
+            .await?;
+    }
+    let _: () = conn.srem(&processing_key, id).await?;
+    Ok(())
+}
+
+async fn record_attempt_with_conn(
+    conn: &mut Connection,
+    id: &JobId,
+    attempt: &JobAttempt,
+) -> Result<()> {
+    let job_key = format!("{JOB_KEY_PREFIX}{id}");
+    let job_json: Option<String> = conn.get(&job_key).await?;
+    if let Some(json) = job_json
+        && let Ok(mut job) = Job::from_json(&json)
+    {
+        job.history.push(attempt.clone());
+        let _: () = conn.set(&job_key, job.to_json()?).await?;
+    }
+    Ok(())
+}
+
+/// [`JobReporter`] updating the job JSON over a worker's connection.
+struct ConnReporter {
+    conn: Connection,
+}
+
+#[async_trait]
+impl JobReporter for ConnReporter {
+    async fn set_progress(&self, id: &JobId, percent: u8) -> Result<()> {
+        trace!(job_id = %id, percent, "Recording job progress");
+        update_job_with_conn(&mut self.conn.clone(), id, |job| {
+            job.progress = Some(percent);
+        })
+        .await
+    }
+
+    async fn set_result(&self, id: &JobId, result: JsonValue) -> Result<()> {
+        trace!(job_id = %id, "Recording job result");
+        update_job_with_conn(&mut self.conn.clone(), id, |job| {
+            job.result = Some(result);
+        })
+        .await
+    }
+
+    async fn cancel_requested(&self, id: &JobId) -> Result<bool> {
+        Ok(self
+            .conn
+            .clone()
+            .sismember(CANCEL_REQUESTED_KEY, id)
+            .await?)
+    }
+}
+
+/// Applies `update` to the stored job `id` and bumps its `updated_at`. A job
+/// that no longer exists is left alone.
+async fn update_job_with_conn(
+    conn: &mut Connection,
+    id: &JobId,
+    update: impl FnOnce(&mut Job) + Send,
+) -> Result<()> {
+    let job_key = format!("{JOB_KEY_PREFIX}{id}");
+    let job_json: Option<String> = conn.get(&job_key).await?;
+    if let Some(json) = job_json
+        && let Ok(mut job) = Job::from_json(&json)
+    {
+        update(&mut job);
+        job.updated_at = Some(Utc::now());
+        let _: () = conn.set(&job_key, job.to_json()?).await?;
     }
     Ok(())
 }
@@ -429,6 +1124,30 @@ async fn fail_job_with_conn(
     id: &JobId,
     queue_name: &str,
     error: &crate::Error,
+) -> Result<()> {
+    finish_with_error_with_conn(conn, id, queue_name, error, JobStatus::Failed).await
+}
+
+/// Marks a running job that stopped with `error` after it was asked to as
+/// [`JobStatus::Cancelled`]. To its batch, it counts as a failed member.
+async fn cancel_job_with_conn(
+    conn: &mut Connection,
+    id: &JobId,
+    queue_name: &str,
+    error: &crate::Error,
+) -> Result<()> {
+    finish_with_error_with_conn(conn, id, queue_name, error, JobStatus::Cancelled).await?;
+    let _: () = conn.sadd(format!("cancelled:{queue_name}"), id).await?;
+    Ok(())
+}
+
+/// Shared body of [`fail_job_with_conn`] and [`cancel_job_with_conn`].
+async fn finish_with_error_with_conn(
+    conn: &mut Connection,
+    id: &JobId,
+    queue_name: &str,
+    error: &crate::Error,
+    status: JobStatus,
 ) -> Result<()> {
     let job_key = format!("{JOB_KEY_PREFIX}{id}");
     let processing_key = format!("{PROCESSING_KEY_PREFIX}{queue_name}");
@@ -440,20 +1159,21 @@ async fn fail_job_with_conn(
         // Preserve the original task arguments and attach the error alongside
         // them, mirroring the SQL backends (`task_data = task_data || {error}`)
         // instead of overwriting the args with the error payload.
+        let msg = super::error_message(error);
         if let Some(obj) = job.data.as_object_mut() {
-            obj.insert(
-                "error".to_string(),
-                serde_json::Value::String(error.to_string()),
-            );
+            obj.insert("error".to_string(), serde_json::Value::String(msg.clone()));
         } else {
-            job.data = serde_json::json!({ "args": job.data, "error": error.to_string() });
+            job.data = serde_json::json!({ "args": job.data, "error": msg.clone() });
         }
-        job.status = JobStatus::Failed;
+        job.status = status;
         job.updated_at = Some(Utc::now());
+        job.last_error = Some(msg);
         let updated_json = job.to_json()?;
         let _: () = conn.set(&job_key, &updated_json).await?;
+        enqueue_failure_follow_ups(conn, &job).await?;
     }
     let _: () = conn.srem(&processing_key, id).await?;
+    let _: () = conn.srem(CANCEL_REQUESTED_KEY, id).await?;
     Ok(())
 }
 
@@ -534,6 +1254,62 @@ pub async fn get_jobs(
     Ok(jobs)
 }
 
+/// Retrieves the job with the given `id`, if any.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn get_job(client: &RedisPool, id: &str) -> Result<Option<Job>> {
+    let mut conn = get_connection(client).await?;
+    let job_json: Option<String> = conn.get(format!("{JOB_KEY_PREFIX}{id}")).await?;
+    let Some(json) = job_json else {
+        return Ok(None);
+    };
+    let mut job = Job::from_json(&json)?;
+
+    // As in `get_jobs`: a job a worker is holding is still stored as `queued`.
+    if job.status == JobStatus::Queued {
+        let processing_keys: Vec<String> = redis::cmd("KEYS")
+            .arg(format!("{PROCESSING_KEY_PREFIX}*"))
+            .query_async(&mut conn)
+            .await?;
+        for processing_key in processing_keys {
+            if conn.sismember(&processing_key, &job.id).await? {
+                job.status = JobStatus::Processing;
+                break;
+            }
+        }
+    }
+    Ok(Some(job))
+}
+
+/// Computes the queue's metrics, placing each queued job in the named queue
+/// whose ZSET holds it.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn stats(client: &RedisPool) -> Result<QueueStats> {
+    let jobs = get_jobs(client, None, None).await?;
+
+    let mut conn = get_connection(client).await?;
+    let mut queue_of: HashMap<String, String> = HashMap::new();
+    for (queue_key, queue_name) in all_queue_keys(&mut conn).await? {
+        let job_ids: Vec<String> = conn.zrange(&queue_key, 0, -1).await?;
+        for job_id in job_ids {
+            queue_of.insert(job_id, queue_name.clone());
+        }
+    }
+
+    Ok(QueueStats::from_jobs(
+        jobs.iter().map(|job| {
+            let queue = queue_of.get(&job.id).map_or("default", String::as_str);
+            (queue, job)
+        }),
+        Utc::now(),
+    ))
+}
+
 // Helper function to check if a job matches the filter criteria
 fn should_include_job(job: &Job, status: Option<&Vec<JobStatus>>, age_days: Option<i64>) -> bool {
     if let Some(status_list) = status
@@ -564,12 +1340,8 @@ fn should_include_job(job: &Job, status: Option<&Vec<JobStatus>>, age_days: Opti
 pub async fn clear_by_status(client: &RedisPool, status: Vec<JobStatus>) -> Result<()> {
     let mut conn = get_connection(client).await?;
 
-    // Get all queue keys
-    let queue_pattern = format!("{QUEUE_KEY_PREFIX}*");
-    let queue_keys: Vec<String> = redis::cmd("KEYS")
-        .arg(&queue_pattern)
-        .query_async(&mut conn)
-        .await?;
+    // Get all queue keys, with those of jobs waiting for their `run_at`
+    let queue_keys = all_queue_keys(&mut conn).await?;
 
     // Get all processing keys
     let processing_pattern = format!("{PROCESSING_KEY_PREFIX}*");
@@ -586,7 +1358,7 @@ pub async fn clear_by_status(client: &RedisPool, status: Vec<JobStatus>) -> Resu
         .await?;
 
     // Process queues
-    for queue_key in queue_keys {
+    for (queue_key, _) in queue_keys {
         // Get all jobs in the queue
         let job_ids: Vec<String> = conn.zrange(&queue_key, 0, -1).await?;
 
@@ -653,12 +1425,8 @@ pub async fn clear_jobs_older_than(
     let mut conn = get_connection(client).await?;
     let cutoff_date = Utc::now() - chrono::Duration::days(age_days);
 
-    // Get all queue keys
-    let queue_pattern = format!("{QUEUE_KEY_PREFIX}*");
-    let queue_keys: Vec<String> = redis::cmd("KEYS")
-        .arg(&queue_pattern)
-        .query_async(&mut conn)
-        .await?;
+    // Get all queue keys, with those of jobs waiting for their `run_at`
+    let queue_keys = all_queue_keys(&mut conn).await?;
 
     // Get all processing keys
     let processing_pattern = format!("{PROCESSING_KEY_PREFIX}*");
@@ -675,7 +1443,7 @@ pub async fn clear_jobs_older_than(
         .await?;
 
     // Process queues
-    for queue_key in queue_keys {
+    for (queue_key, _) in queue_keys {
         // Get all jobs in the queue
         let job_ids: Vec<String> = conn.zrange(&queue_key, 0, -1).await?;
 
@@ -766,6 +1534,7 @@ pub async fn retry_failed(client: &RedisPool, id: Option<&str>) -> Result<u64> {
         job.status = JobStatus::Queued;
         job.run_at = Utc::now();
         job.updated_at = Some(Utc::now());
+        job.attempts = 0;
 
         let job_key = format!("{JOB_KEY_PREFIX}{}", job.id);
         let queue_key = format!("{QUEUE_KEY_PREFIX}default");
@@ -889,6 +1658,8 @@ pub async fn requeue(client: &RedisPool, age_minutes: &i64) -> Result<()> {
 /// This function updates the status of jobs that match the provided `job_name`
 /// from [`JobStatus::Queued`] to [`JobStatus::Cancelled`]. Jobs are searched for in all queue keys,
 /// and only those that are currently in the [`JobStatus::Queued`] state will be affected.
+/// Matching jobs in the processing sets are flagged in the `cancel_requested`
+/// set instead, which their worker checks while running them.
 ///
 /// # Errors
 ///
@@ -896,15 +1667,11 @@ pub async fn requeue(client: &RedisPool, age_minutes: &i64) -> Result<()> {
 pub async fn cancel_jobs_by_name(client: &RedisPool, job_name: &str) -> Result<()> {
     let mut conn = get_connection(client).await?;
 
-    // Get all queue keys
-    let queue_pattern = format!("{QUEUE_KEY_PREFIX}*");
-    let queue_keys: Vec<String> = redis::cmd("KEYS")
-        .arg(&queue_pattern)
-        .query_async(&mut conn)
-        .await?;
+    // Get all queue keys, with those of jobs waiting for their `run_at`
+    let queue_keys = all_queue_keys(&mut conn).await?;
 
     // Process each queue
-    for queue_key in queue_keys {
+    for (queue_key, queue_name) in queue_keys {
         // Get all jobs in the queue
         let job_ids: Vec<String> = conn.zrange(&queue_key, 0, -1).await?;
         for job_id in job_ids {
@@ -920,14 +1687,30 @@ pub async fn cancel_jobs_by_name(client: &RedisPool, job_name: &str) -> Result<(
                 let updated_json = job.to_json()?;
                 let _: () = conn.zrem(&queue_key, &job_id).await?;
                 let _: () = conn.set(&job_key, &updated_json).await?;
-                let cancelled_key = format!(
-                    "cancelled:{}",
-                    queue_key.trim_start_matches(QUEUE_KEY_PREFIX)
-                );
+                let cancelled_key = format!("cancelled:{queue_name}");
                 let _: () = conn.sadd(&cancelled_key, &job_id).await?;
             }
         }
     }
+
+    let processing_pattern = format!("{PROCESSING_KEY_PREFIX}*");
+    let processing_keys: Vec<String> = redis::cmd("KEYS")
+        .arg(&processing_pattern)
+        .query_async(&mut conn)
+        .await?;
+    for processing_key in processing_keys {
+        let job_ids: Vec<String> = conn.smembers(&processing_key).await?;
+        for job_id in job_ids {
+            let job_json: Option<String> = conn.get(format!("{JOB_KEY_PREFIX}{job_id}")).await?;
+            if let Some(json) = job_json
+                && let Ok(job) = Job::from_json(&json)
+                && job.name == job_name
+                && job.status == JobStatus::Processing
+            {
+                let _: () = conn.sadd(CANCEL_REQUESTED_KEY, &job_id).await?;
+            }
+        }
+    }
     Ok(())
 }
 
@@ -952,9 +1735,15 @@ pub struct RunOpts {
     pub num_workers: u32,
     pub poll_interval_sec: u32,
     pub queues: Option<Vec<String>>,
+    /// The queues to take jobs from, in this order, instead of the default
+    /// queues and `queues`.
+    pub worker_queues: Option<Vec<String>>,
     /// Opt-in visibility-timeout reaper settings, populated from the queue
     /// config. `None` disables the reaper (default, backward-compatible).
     pub reaper: Option<ReaperConfig>,
+    /// On shutdown, how long a running job gets to finish before it is
+    /// interrupted and requeued.
+    pub shutdown_timeout: Duration,
 }
 
 /// Redis [`QueueProvider`]: holds the client, job registry, run options and
@@ -965,6 +1754,8 @@ pub struct RedisQueue {
     pub registry: Arc<tokio::sync::Mutex<JobRegistry>>,
     pub run_opts: RunOpts,
     pub token: CancellationToken,
+    /// Per-worker limits from the queue config, applied on registration.
+    pub limits: HashMap<String, WorkerLimitsConfig>,
 }
 
 #[async_trait]
@@ -972,19 +1763,33 @@ impl QueueProvider for RedisQueue {
     async fn enqueue(
         &self,
         class: String,
-        queue: Option<String>,
         args: JsonValue,
-        tags: Option<Vec<String>>,
-        priority: Option<i32>,
+        options: EnqueueOptions,
     ) -> Result<Option<String>> {
-        Ok(Some(
-            enqueue(&self.client, class, queue, args, tags, priority).await?,
-        ))
+        Ok(Some(enqueue(&self.client, class, args, options).await?))
+    }
+
+    async fn enqueue_chain(&self, job: JobSpec) -> Result<Option<String>> {
+        Ok(Some(enqueue_chain(&self.client, &job).await?))
+    }
+
+    async fn enqueue_batch(&self, batch: BatchSpec) -> Result<Option<String>> {
+        Ok(Some(enqueue_batch(&self.client, &batch).await?))
+    }
+
+    async fn get_batches(&self) -> Result<Vec<JobBatch>> {
+        get_batches(&self.client).await
     }
 
-    async fn register_handler(&self, name: String, handler: JobHandler) -> Result<()> {
+    async fn register_handler(
+        &self,
+        name: String,
+        handler: JobHandler,
+        opts: WorkerOpts,
+    ) -> Result<()> {
+        let opts = opts.with_limits(self.limits.get(&name));
         let mut registry = self.registry.lock().await;
-        registry.insert_handler(name, handler)
+        registry.insert_handler(name, handler, opts)
     }
 
     async fn run(&self, tags: Vec<String>) -> Result<()> {
@@ -1034,6 +1839,14 @@ impl QueueProvider for RedisQueue {
         get_jobs(&self.client, status, age_days).await
     }
 
+    async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        get_job(&self.client, id).await
+    }
+
+    async fn stats(&self) -> Result<QueueStats> {
+        stats(&self.client).await
+    }
+
     async fn cancel_jobs_by_name(&self, name: &str) -> Result<()> {
         cancel_jobs_by_name(&self.client, name).await
     }
@@ -1080,10 +1893,13 @@ async fn build_provider(qcfg: &RedisQueueConfig) -> Result<RedisQueue> {
         num_workers: qcfg.num_workers,
         poll_interval_sec: 1,
         queues: qcfg.queues.clone(),
+        worker_queues: qcfg.worker_queues.clone(),
         reaper: qcfg.reaper.clone(),
+        shutdown_timeout: Duration::from_secs(qcfg.shutdown_timeout_sec),
     };
     debug!(
         queues = ?qcfg.queues,
+        worker_queues = ?qcfg.worker_queues,
         num_workers = qcfg.num_workers,
         "creating Redis queue provider"
     );
@@ -1092,6 +1908,7 @@ async fn build_provider(qcfg: &RedisQueueConfig) -> Result<RedisQueue> {
         registry: Arc::new(tokio::sync::Mutex::new(registry)),
         run_opts,
         token,
+        limits: qcfg.limits.clone(),
     })
 }
 
@@ -1139,6 +1956,16 @@ mod tests {
                 updated_at: Some(now - chrono::Duration::days(15)),
                 tags: None,
                 priority: 0,
+                attempts: 0,
+                last_error: None,
+                history: Vec::new(),
+                unique_key: None,
+                progress: None,
+                result: None,
+                batch_id: None,
+                chain: Vec::new(),
+                queue: None,
+                args_version: 1,
             };
 
             let mut conn = get_connection(client).await?;
@@ -1150,17 +1977,24 @@ mod tests {
 
         // Create queued jobs
         let args = serde_json::json!({"hello": "world"});
-        enqueue(client, "TestJob".to_string(), None, args, None, None).await?;
+        enqueue(
+            client,
+            "TestJob".to_string(),
+            args,
+            EnqueueOptions::default(),
+        )
+        .await?;
 
         // Create job with tags
         let args = serde_json::json!({"hello": "tagged"});
         enqueue(
             client,
             "TaggedJob".to_string(),
-            None,
             args,
-            Some(vec!["important".to_string(), "urgent".to_string()]),
-            None,
+            EnqueueOptions {
+                tags: Some(vec!["important".to_string(), "urgent".to_string()]),
+                ..EnqueueOptions::default()
+            },
         )
         .await?;
 
@@ -1179,7 +2013,7 @@ mod tests {
         // Dequeue job
         let queues = vec!["default".to_string()];
         let mut conn = get_test_connection(&client).await;
-        let job_opt = dequeue_with_conn(&mut conn, &queues, &[])
+        let job_opt = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue");
 
@@ -1187,6 +2021,55 @@ mod tests {
         assert!(job_opt.is_some());
     }
 
+    #[tokio::test]
+    async fn test_claim_keeps_a_job_written_since_the_scan() {
+        let (client, _container) = setup_redis().await;
+        let id = enqueue(
+            &client,
+            "TestJob".to_string(),
+            serde_json::json!({}),
+            EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue");
+        let mut conn = get_test_connection(&client).await;
+        let job_key = format!("{JOB_KEY_PREFIX}{id}");
+        let scanned: String = conn.get(&job_key).await.expect("get job");
+        // A write landing between the scan and the claim, as a cancel does.
+        let mut written = Job::from_json(&scanned).expect("parse job");
+        written.last_error = Some("written meanwhile".to_string());
+        let written = written.to_json().expect("serialize job");
+        let _: () = conn.set(&job_key, &written).await.expect("set job");
+
+        let result: Option<String> = Script::new(ACQUIRE_JOB_SCRIPT)
+            .key(format!("{QUEUE_KEY_PREFIX}default"))
+            .key(format!("{PROCESSING_KEY_PREFIX}default"))
+            .key(format!("{ACTIVE_KEY_PREFIX}TestJob"))
+            .key(format!("{RATE_KEY_PREFIX}TestJob"))
+            .key(&job_key)
+            .arg(&id)
+            .arg(0)
+            .arg(0)
+            .arg(0)
+            .arg(Utc::now().timestamp_millis())
+            .arg(PROCESSING_KEY_PREFIX)
+            .arg("default")
+            .arg(&scanned)
+            .arg("claimed")
+            .invoke_async(&mut conn)
+            .await
+            .expect("run claim script");
+
+        assert_eq!(result.as_deref(), Some(JOB_CHANGED));
+        let stored: String = conn.get(&job_key).await.expect("get job");
+        assert_eq!(stored, written);
+        // Still queued, so the next poll claims it.
+        let job_opt = dequeue_with_conn(&mut conn, &["default".to_string()], &[], &HashMap::new())
+            .await
+            .expect("dequeue");
+        assert_eq!(job_opt.map(|(job, _)| job.attempts), Some(1));
+    }
+
     #[tokio::test]
     async fn test_can_clear_redis() {
         // Setup Redis directly with testcontainer
@@ -1203,19 +2086,281 @@ mod tests {
             .arg("*")
             .query_async(&mut conn)
             .await
-            .expect("get keys");
-        assert!(!keys.is_empty(), "Should have keys before clearing");
+            .expect("get keys");
+        assert!(!keys.is_empty(), "Should have keys before clearing");
+
+        // Clear data
+        assert!(clear(&client).await.is_ok());
+
+        // Verify data is gone
+        let keys: Vec<String> = redis::cmd("KEYS")
+            .arg("*")
+            .query_async(&mut conn)
+            .await
+            .expect("get keys");
+        assert!(keys.is_empty(), "All keys should be removed after clearing");
+    }
+
+    #[tokio::test]
+    async fn test_stats_place_queued_jobs_in_their_queue_redis() {
+        let (client, _container) = setup_redis().await;
+        redis_seed_data(&client).await.expect("seed data");
+        enqueue(
+            &client,
+            "TestJob".to_string(),
+            serde_json::json!({}),
+            EnqueueOptions {
+                queue: Some("mailer".to_string()),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue");
+
+        let stats = stats(&client).await.expect("stats");
+
+        let depth = |queue: &str, class: &str| {
+            stats
+                .queues
+                .iter()
+                .find(|d| d.queue == queue && d.class == class)
+                .map(|d| d.queued)
+        };
+        assert_eq!(depth("default", "TestJob"), Some(1));
+        assert_eq!(depth("mailer", "TestJob"), Some(1));
+        assert_eq!(depth("default", "TaggedJob"), Some(1));
+        assert_eq!(stats.workers["TestJob"].queued, 2);
+        assert_eq!(stats.workers["TestJob"].completed, 5);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_job_waits_for_run_at_redis() {
+        let (client, _container) = setup_redis().await;
+
+        let run_at = Utc::now() + chrono::Duration::hours(24);
+        enqueue(
+            &client,
+            "Reminder".to_string(),
+            serde_json::json!({"user_id": 1}),
+            EnqueueOptions {
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue");
+
+        let jobs = get_all_jobs(&client).await;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].run_at, run_at);
+
+        let mut conn = get_test_connection(&client).await;
+        let queues = vec!["default".to_string()];
+        assert!(dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_jobs_do_not_hide_due_ones_redis() {
+        let (client, _container) = setup_redis().await;
+
+        // More jobs waiting for their `run_at` than a dequeue scans, all
+        // older than the due job and at the same priority.
+        let later = Utc::now() + chrono::Duration::hours(1);
+        for i in 0..1001 {
+            enqueue(
+                &client,
+                "Reminder".to_string(),
+                serde_json::json!({"user_id": i}),
+                EnqueueOptions {
+                    run_at: Some(later),
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .expect("enqueue");
+        }
+        let due = enqueue(
+            &client,
+            "Reminder".to_string(),
+            serde_json::json!({"user_id": "due"}),
+            EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue");
+        let soon = enqueue(
+            &client,
+            "Reminder".to_string(),
+            serde_json::json!({"user_id": "soon"}),
+            EnqueueOptions {
+                run_at: Some(Utc::now() + chrono::Duration::milliseconds(200)),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue");
+
+        let mut conn = get_test_connection(&client).await;
+        let queues = vec!["default".to_string()];
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("the due job");
+        assert_eq!(job.id, due);
+
+        // Moved into the queue once due.
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("the job that came due");
+        assert_eq!(job.id, soon);
+        assert!(dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retry_waits_out_its_backoff_in_the_scheduled_set_redis() {
+        let (client, _container) = setup_redis().await;
+        enqueue(
+            &client,
+            "Flaky".to_string(),
+            serde_json::json!({}),
+            EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue");
+
+        let mut conn = get_test_connection(&client).await;
+        let queues = vec!["default".to_string()];
+        let (job, queue) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        retry_job_with_conn(
+            &mut conn,
+            &job.id,
+            &queue,
+            Utc::now() + chrono::Duration::hours(1),
+            &crate::Error::string("boom"),
+        )
+        .await
+        .expect("retry");
+
+        let scheduled: i64 = conn
+            .zcard(format!("{SCHEDULED_KEY_PREFIX}default"))
+            .await
+            .expect("scheduled length");
+        let queued: i64 = conn
+            .zcard(format!("{QUEUE_KEY_PREFIX}default"))
+            .await
+            .expect("queue length");
+        assert_eq!((scheduled, queued), (1, 0));
+
+        let due = enqueue(
+            &client,
+            "Flaky".to_string(),
+            serde_json::json!({}),
+            EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue");
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("the due job");
+        assert_eq!(job.id, due);
+    }
+
+    #[tokio::test]
+    async fn test_unique_job_returns_existing_id_redis() {
+        let (client, _container) = setup_redis().await;
+
+        let enqueue_reindex = |window: UniqueWindow| async {
+            let unique = UniqueJob {
+                key: "Reindex:42".to_string(),
+                window,
+            };
+            let data = serde_json::json!({"account_id": 42});
+            let job = build_job("Reindex".to_string(), data, None, None, None, 1)?;
+            enqueue_unique(&client, &unique, None, job).await
+        };
+
+        let first = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        let duplicate = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        assert_eq!(first, duplicate);
+        assert_eq!(get_all_jobs(&client).await.len(), 1);
+
+        let mut conn = get_test_connection(&client).await;
+        let queues = vec!["default".to_string()];
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("job");
+        assert_eq!(job.unique_key.as_deref(), Some("Reindex:42"));
+
+        // Still processing: inside a queued-or-processing window, outside a
+        // queued-only one.
+        let duplicate = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        assert_eq!(first, duplicate);
+        let second = enqueue_reindex(UniqueWindow::Queued)
+            .await
+            .expect("enqueue");
+        assert_ne!(first, second);
+        assert_eq!(get_all_jobs(&client).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_job_with_progress_and_result_redis() {
+        let (client, _container) = setup_redis().await;
+
+        let job_id = enqueue(
+            &client,
+            "ExportWorker".to_string(),
+            serde_json::json!(null),
+            EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue");
+
+        let mut conn = get_test_connection(&client).await;
+        let queues = vec!["default".to_string()];
+        dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("job");
 
-        // Clear data
-        assert!(clear(&client).await.is_ok());
+        let reporter = ConnReporter { conn };
+        reporter.set_progress(&job_id, 73).await.expect("progress");
+        reporter
+            .set_result(&job_id, serde_json::json!({"path": "exports/42.csv"}))
+            .await
+            .expect("result");
 
-        // Verify data is gone
-        let keys: Vec<String> = redis::cmd("KEYS")
-            .arg("*")
-            .query_async(&mut conn)
+        let job = get_job(&client, &job_id)
             .await
-            .expect("get keys");
-        assert!(keys.is_empty(), "All keys should be removed after clearing");
+            .expect("get job")
+            .expect("job exists");
+        assert_eq!(job.status, JobStatus::Processing);
+        assert_eq!(job.progress, Some(73));
+        assert_eq!(
+            job.result,
+            Some(serde_json::json!({"path": "exports/42.csv"}))
+        );
+        assert!(get_job(&client, "missing")
+            .await
+            .expect("get job")
+            .is_none());
     }
 
     #[tokio::test]
@@ -1225,11 +2370,14 @@ mod tests {
 
         // Test enqueue
         let args = serde_json::json!({"user_id": 42});
-        assert!(
-            enqueue(&client, "PasswordReset".to_string(), None, args, None, None)
-                .await
-                .is_ok()
-        );
+        assert!(enqueue(
+            &client,
+            "PasswordReset".to_string(),
+            args,
+            EnqueueOptions::default()
+        )
+        .await
+        .is_ok());
 
         // Verify job was created
         let jobs = get_all_jobs(&client).await;
@@ -1250,10 +2398,11 @@ mod tests {
         assert!(enqueue(
             &client,
             "EmailNotification".to_string(),
-            Some("mailer".to_string()),
             args,
-            None,
-            None
+            EnqueueOptions {
+                queue: Some("mailer".to_string()),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -1266,7 +2415,7 @@ mod tests {
 
         // Test dequeue from mailer queue
         let queues = vec!["mailer".to_string()];
-        let _job_opt = dequeue_with_conn(&mut conn, &queues, &[])
+        let _job_opt = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue");
 
@@ -1281,16 +2430,19 @@ mod tests {
 
         // Add job
         let args = serde_json::json!({"task": "test"});
-        assert!(
-            enqueue(&client, "TestJob".to_string(), None, args, None, None)
-                .await
-                .is_ok()
-        );
+        assert!(enqueue(
+            &client,
+            "TestJob".to_string(),
+            args,
+            EnqueueOptions::default()
+        )
+        .await
+        .is_ok());
 
         // Dequeue job
         let queues = vec!["default".to_string()];
         let mut conn = get_test_connection(&client).await;
-        let job_opt = dequeue_with_conn(&mut conn, &queues, &[])
+        let job_opt = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue");
         let (job, queue) = job_opt.unwrap();
@@ -1319,22 +2471,195 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_chain_and_batch_follow_ups_redis() {
+        let (client, _container) = setup_redis().await;
+
+        let spec = |class: &str, args: JsonValue, chain| JobSpec {
+            class: class.to_string(),
+            queue: None,
+            args,
+            tags: None,
+            priority: None,
+            chain,
+            args_version: 1,
+        };
+        let step = crate::bgworker::batch::ChainStep {
+            class: "LoadJob".to_string(),
+            queue: None,
+            tags: None,
+            args_version: 1,
+        };
+        let batch_id = enqueue_batch(
+            &client,
+            &BatchSpec {
+                jobs: vec![
+                    spec("ExtractJob", serde_json::json!({"n": 1}), vec![step]),
+                    spec("ExtractJob", serde_json::json!({"n": 2}), vec![]),
+                ],
+                on_complete: Some(spec("NotifyJob", serde_json::json!({}), vec![])),
+                on_failure: None,
+            },
+        )
+        .await
+        .expect("enqueue batch");
+
+        let queues = vec!["default".to_string()];
+        let mut conn = get_test_connection(&client).await;
+        while let Some((job, queue)) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+        {
+            if job.name == "NotifyJob" {
+                break;
+            }
+            update_job_with_conn(&mut conn, &job.id, |job| {
+                job.result = Some(serde_json::json!({"rows": 3}));
+            })
+            .await
+            .expect("set result");
+            complete_job_with_conn(&mut conn, &job.id, &queue, None)
+                .await
+                .expect("complete job");
+        }
+
+        let jobs = get_all_jobs(&client).await;
+        let load = jobs
+            .iter()
+            .find(|job| job.name == "LoadJob")
+            .expect("chain step enqueued");
+        assert_eq!(load.data, serde_json::json!({"rows": 3}));
+        assert_eq!(load.batch_id.as_deref(), Some(batch_id.as_str()));
+        assert_eq!(jobs.iter().filter(|job| job.name == "NotifyJob").count(), 1);
+
+        let batches = get_batches(&client).await.expect("get batches");
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].status, BatchStatus::Completed);
+        assert_eq!((batches[0].completed, batches[0].total), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_cap_and_rate_limit_redis() {
+        let (client, _container) = setup_redis().await;
+
+        for name in ["Transcode", "Transcode", "Webhook", "Webhook"] {
+            enqueue(
+                &client,
+                name.to_string(),
+                serde_json::json!({}),
+                EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue");
+        }
+        let worker_opts = HashMap::from([
+            (
+                "Transcode".to_string(),
+                WorkerOpts {
+                    concurrency: Some(1),
+                    ..Default::default()
+                },
+            ),
+            (
+                "Webhook".to_string(),
+                WorkerOpts {
+                    rate_limit: Some(crate::bgworker::RateLimit::per_minute(1)),
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let queues = vec!["default".to_string()];
+        let mut conn = get_test_connection(&client).await;
+        let mut claimed = Vec::new();
+        while let Some((job, queue)) = dequeue_with_conn(&mut conn, &queues, &[], &worker_opts)
+            .await
+            .expect("dequeue")
+        {
+            claimed.push((job, queue));
+        }
+        let mut names = claimed
+            .iter()
+            .map(|(job, _)| job.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, vec!["Transcode", "Webhook"]);
+
+        // Finishing the running transcode frees its slot; the webhook stays
+        // rate limited.
+        for (job, queue) in &claimed {
+            complete_job_with_conn(&mut conn, &job.id, queue, None)
+                .await
+                .expect("complete job");
+        }
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &worker_opts)
+            .await
+            .expect("dequeue")
+            .expect("a slot was freed");
+        assert_eq!(job.name, "Transcode");
+        assert!(dequeue_with_conn(&mut conn, &queues, &[], &worker_opts)
+            .await
+            .expect("dequeue")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_with_newer_args_stay_queued_redis() {
+        let (client, _container) = setup_redis().await;
+
+        enqueue(
+            &client,
+            "VersionedJob".to_string(),
+            serde_json::json!({"emails": []}),
+            EnqueueOptions {
+                args_version: 3,
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue");
+        let opts = |args_version| {
+            HashMap::from([(
+                "VersionedJob".to_string(),
+                WorkerOpts {
+                    args_version,
+                    ..Default::default()
+                },
+            )])
+        };
+
+        let queues = vec!["default".to_string()];
+        let mut conn = get_test_connection(&client).await;
+        assert!(dequeue_with_conn(&mut conn, &queues, &[], &opts(2))
+            .await
+            .expect("dequeue")
+            .is_none());
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &opts(3))
+            .await
+            .expect("dequeue")
+            .expect("a worker that knows the version");
+        assert_eq!((job.name.as_str(), job.attempts), ("VersionedJob", 1));
+    }
+
     #[tokio::test]
     async fn test_can_complete_job_with_interval_redis() {
         let (client, _container) = setup_redis().await;
 
         // Add job
         let args = serde_json::json!({"task": "recurring"});
-        assert!(
-            enqueue(&client, "RecurringJob".to_string(), None, args, None, None)
-                .await
-                .is_ok()
-        );
+        assert!(enqueue(
+            &client,
+            "RecurringJob".to_string(),
+            args,
+            EnqueueOptions::default()
+        )
+        .await
+        .is_ok());
 
         // Dequeue job
         let queues = vec!["default".to_string()];
         let mut conn = get_test_connection(&client).await;
-        let job_opt = dequeue_with_conn(&mut conn, &queues, &[])
+        let job_opt = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue");
         let (job, queue) = job_opt.unwrap();
@@ -1346,8 +2671,8 @@ mod tests {
                 .is_ok()
         );
 
-        // Verify job is back in queue
-        let queue_key = format!("{QUEUE_KEY_PREFIX}{queue}");
+        // Verify job waits for its next run, out of the queue until then
+        let queue_key = format!("{SCHEDULED_KEY_PREFIX}{queue}");
         let queue_len: i64 = conn.zcard(&queue_key).await.expect("get queue length");
         assert_eq!(queue_len, 1);
 
@@ -1370,16 +2695,19 @@ mod tests {
 
         // Add job
         let args = serde_json::json!({"task": "test"});
-        assert!(
-            enqueue(&client, "TestJob".to_string(), None, args, None, None)
-                .await
-                .is_ok()
-        );
+        assert!(enqueue(
+            &client,
+            "TestJob".to_string(),
+            args,
+            EnqueueOptions::default()
+        )
+        .await
+        .is_ok());
 
         // Dequeue job
         let queues = vec!["default".to_string()];
         let mut conn = get_test_connection(&client).await;
-        let job_opt = dequeue_with_conn(&mut conn, &queues, &[])
+        let job_opt = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue");
         let (job, queue) = job_opt.unwrap();
@@ -1422,19 +2750,18 @@ mod tests {
         enqueue(
             &client,
             "TestJob".to_string(),
-            None,
             serde_json::json!({"hello": "world"}),
-            None,
-            None,
+            EnqueueOptions::default(),
         )
         .await
         .expect("enqueue");
 
         let mut conn = get_test_connection(&client).await;
-        let (job, queue) = dequeue_with_conn(&mut conn, &["default".to_string()], &[])
-            .await
-            .expect("dequeue")
-            .expect("a job to dequeue");
+        let (job, queue) =
+            dequeue_with_conn(&mut conn, &["default".to_string()], &[], &HashMap::new())
+                .await
+                .expect("dequeue")
+                .expect("a job to dequeue");
         fail_job_with_conn(&mut conn, &job.id, &queue, &Error::string("test failure"))
             .await
             .expect("fail the job");
@@ -1454,19 +2781,18 @@ mod tests {
         enqueue(
             &client,
             "TestJob".to_string(),
-            None,
             serde_json::json!({"hello": "world"}),
-            None,
-            None,
+            EnqueueOptions::default(),
         )
         .await
         .expect("enqueue");
 
         let mut conn = get_test_connection(&client).await;
-        let (job, queue) = dequeue_with_conn(&mut conn, &["default".to_string()], &[])
-            .await
-            .expect("dequeue")
-            .expect("a job to dequeue");
+        let (job, queue) =
+            dequeue_with_conn(&mut conn, &["default".to_string()], &[], &HashMap::new())
+                .await
+                .expect("dequeue")
+                .expect("a job to dequeue");
         fail_job_with_conn(&mut conn, &job.id, &queue, &Error::string("test failure"))
             .await
             .expect("fail the job");
@@ -1479,10 +2805,11 @@ mod tests {
 
         // Queued is not enough: the id must be back in a queue ZSET, or no
         // worker will ever see it again.
-        let (again, _) = dequeue_with_conn(&mut conn, &["default".to_string()], &[])
-            .await
-            .expect("dequeue")
-            .expect("the retried job is dequeueable");
+        let (again, _) =
+            dequeue_with_conn(&mut conn, &["default".to_string()], &[], &HashMap::new())
+                .await
+                .expect("dequeue")
+                .expect("the retried job is dequeueable");
         assert_eq!(again.id, job.id);
         // The failure trail is kept — a retry should not erase why it failed.
         assert!(again.data.get("error").is_some());
@@ -1572,23 +2899,28 @@ mod tests {
         // Register worker
         let handler = crate::bgworker::erase_worker(TestWorker);
         assert!(registry
-            .insert_handler("TestJob".to_string(), handler)
+            .insert_handler("TestJob".to_string(), handler, WorkerOpts::default())
             .is_ok());
 
         // Add job
         let args = serde_json::json!("test args");
-        assert!(
-            enqueue(&client, "TestJob".to_string(), None, args, None, None)
-                .await
-                .is_ok()
-        );
+        assert!(enqueue(
+            &client,
+            "TestJob".to_string(),
+            args,
+            EnqueueOptions::default()
+        )
+        .await
+        .is_ok());
 
         // Run registry with worker for a short time
         let opts = RunOpts {
             num_workers: 1,
             poll_interval_sec: 1,
             queues: None,
+            worker_queues: None,
             reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
         };
 
         let token = CancellationToken::new();
@@ -1604,6 +2936,148 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_failing_job_is_retried_then_failed_redis() {
+        let (client, _container) = setup_redis().await;
+
+        struct FailingWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for FailingWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                Err(crate::Error::string("always fails"))
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        let handler = crate::bgworker::erase_worker(FailingWorker);
+        let worker_opts = WorkerOpts {
+            max_retries: 2,
+            backoff: crate::bgworker::Backoff::Fixed(Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(registry
+            .insert_handler("FailingJob".to_string(), handler, worker_opts)
+            .is_ok());
+
+        let job_id = enqueue(
+            &client,
+            "FailingJob".to_string(),
+            serde_json::json!(null),
+            EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue");
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: None,
+            worker_queues: None,
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let worker_handles = registry.run(&client, &opts, &token, &[] as &[String]);
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        token.cancel();
+        for handle in worker_handles {
+            let _ = handle.await;
+        }
+
+        let job = get_all_jobs(&client)
+            .await
+            .into_iter()
+            .find(|job| job.id == job_id)
+            .expect("job exists");
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.attempts, 3);
+        assert_eq!(job.last_error.as_deref(), Some("always fails"));
+        assert_eq!(
+            job.history
+                .iter()
+                .map(|attempt| attempt.attempt)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_jobs_signals_a_running_job_redis() {
+        let (client, _container) = setup_redis().await;
+
+        struct StoppableWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for StoppableWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+
+            fn max_retries() -> u32 {
+                3
+            }
+
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                crate::bgworker::cancellation_token().cancelled().await;
+                Err(crate::Error::string("stopped on request"))
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "StoppableJob".to_string(),
+                crate::bgworker::erase_worker(StoppableWorker),
+                WorkerOpts::of::<(), StoppableWorker>(),
+            )
+            .is_ok());
+
+        let job_id = enqueue(
+            &client,
+            "StoppableJob".to_string(),
+            serde_json::json!(null),
+            EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue");
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: None,
+            worker_queues: None,
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let worker_handles = registry.run(&client, &opts, &token, &[] as &[String]);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        cancel_jobs_by_name(&client, "StoppableJob")
+            .await
+            .expect("cancel jobs");
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        token.cancel();
+        for handle in worker_handles {
+            let _ = handle.await;
+        }
+
+        let job = get_all_jobs(&client)
+            .await
+            .into_iter()
+            .find(|job| job.id == job_id)
+            .expect("job exists");
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert_eq!(job.attempts, 1);
+        assert_eq!(job.last_error.as_deref(), Some("stopped on request"));
+    }
+
     #[tokio::test]
     async fn test_job_filtering_by_tags() {
         let (client, _container) = setup_redis().await;
@@ -1616,10 +3090,12 @@ mod tests {
         assert!(enqueue(
             &client,
             "TaggedJob".to_string(),
-            Some("default".to_string()),
             args1,
-            Some(vec!["tag1".to_string(), "common".to_string()]),
-            None
+            EnqueueOptions {
+                queue: Some("default".to_string()),
+                tags: Some(vec!["tag1".to_string(), "common".to_string()]),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -1628,10 +3104,12 @@ mod tests {
         assert!(enqueue(
             &client,
             "TaggedJob".to_string(),
-            Some("default".to_string()),
             args2,
-            Some(vec!["tag2".to_string(), "common".to_string()]),
-            None
+            EnqueueOptions {
+                queue: Some("default".to_string()),
+                tags: Some(vec!["tag2".to_string(), "common".to_string()]),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -1640,10 +3118,12 @@ mod tests {
         assert!(enqueue(
             &client,
             "TaggedJob".to_string(),
-            Some("default".to_string()),
             args3,
-            Some(vec!["tag3".to_string()]),
-            None
+            EnqueueOptions {
+                queue: Some("default".to_string()),
+                tags: Some(vec!["tag3".to_string()]),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -1651,7 +3131,7 @@ mod tests {
         // Test dequeue with tag1 filter
         let queues = vec!["default".to_string()];
         let mut conn = get_test_connection(&client).await;
-        let job_opt = dequeue_with_conn(&mut conn, &queues, &["tag1".to_string()])
+        let job_opt = dequeue_with_conn(&mut conn, &queues, &["tag1".to_string()], &HashMap::new())
             .await
             .expect("dequeue with tag1");
 
@@ -1745,6 +3225,16 @@ mod tests {
             updated_at: Some(Utc::now() - chrono::Duration::days(15)),
             tags: None,
             priority: 0,
+            attempts: 0,
+            last_error: None,
+            history: Vec::new(),
+            unique_key: None,
+            progress: None,
+            result: None,
+            batch_id: None,
+            chain: Vec::new(),
+            queue: None,
+            args_version: 1,
         };
 
         // Create an old completed job (older than 10 days)
@@ -1759,6 +3249,16 @@ mod tests {
             updated_at: Some(Utc::now() - chrono::Duration::days(15)),
             tags: None,
             priority: 0,
+            attempts: 0,
+            last_error: None,
+            history: Vec::new(),
+            unique_key: None,
+            progress: None,
+            result: None,
+            batch_id: None,
+            chain: Vec::new(),
+            queue: None,
+            args_version: 1,
         };
 
         // Store both jobs directly
@@ -1870,7 +3370,7 @@ mod tests {
 
         let queues = vec!["default".to_string()];
         for expected_index in [1, 2, 3, 4, 5] {
-            let (job, _) = dequeue_with_conn(&mut conn, &queues, &[])
+            let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
                 .await
                 .expect("dequeue failed")
                 .expect("expected a job");
@@ -1883,7 +3383,7 @@ mod tests {
                 .expect("complete job");
         }
 
-        let job_opt = dequeue_with_conn(&mut conn, &queues, &[])
+        let job_opt = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job_opt.is_none());
@@ -1898,20 +3398,19 @@ mod tests {
         enqueue(
             &client,
             "PriorityJob".to_string(),
-            None,
             args.clone(),
-            None,
-            Some(42),
+            EnqueueOptions {
+                priority: Some(42),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("enqueue with priority");
         enqueue(
             &client,
             "DefaultPriorityJob".to_string(),
-            None,
             args,
-            None,
-            None,
+            EnqueueOptions::default(),
         )
         .await
         .expect("enqueue without priority");
@@ -1942,20 +3441,22 @@ mod tests {
         enqueue(
             &client,
             "NegativePriorityJob".to_string(),
-            None,
             serde_json::json!({"task": "negative_priority"}),
-            None,
-            Some(-10),
+            EnqueueOptions {
+                priority: Some(-10),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("enqueue negative priority");
         enqueue(
             &client,
             "ZeroPriorityJob".to_string(),
-            None,
             serde_json::json!({"task": "zero_priority"}),
-            None,
-            Some(0),
+            EnqueueOptions {
+                priority: Some(0),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("enqueue zero priority");
@@ -1964,7 +3465,7 @@ mod tests {
         let mut conn = get_test_connection(&client).await;
 
         // Zero priority is dequeued before the negative one.
-        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[])
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue failed")
             .expect("expected a job");
@@ -1974,7 +3475,7 @@ mod tests {
             .await
             .expect("complete job");
 
-        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[])
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue failed")
             .expect("expected a job");
@@ -2021,7 +3522,7 @@ mod tests {
         let _: () = conn.zadd(&queue_key, "job2", score2).await.unwrap();
 
         let queues = vec!["default".to_string()];
-        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[])
+        let (job, _) = dequeue_with_conn(&mut conn, &queues, &[], &HashMap::new())
             .await
             .expect("dequeue")
             .expect("should have dequeued the untagged job");
@@ -2041,6 +3542,9 @@ mod tests {
                 age_minutes: 5,
                 interval_seconds: 30,
             }),
+            worker_queues: None,
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         };
 
         let provider = build_provider(&qcfg).await.expect("build provider");
@@ -2060,6 +3564,9 @@ mod tests {
             queues: None,
             num_workers: 1,
             reaper: None,
+            worker_queues: None,
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         };
 
         let provider = build_provider(&qcfg).await.expect("build provider");
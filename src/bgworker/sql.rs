@@ -3,15 +3,25 @@
 /// This module holds the code that is identical between the Postgres
 /// (`pg.rs`) and `SQLite` (`sqlt.rs`) queue providers: the `Job` model, the
 /// `JobRegistry` (worker registration + run loop), and `RunOpts`. Each
-/// backend supplies its own pool type and the three DB-coupled operations
-/// (`dequeue`/`complete_job`/`fail_job`) through the [`Driver`] trait.
+/// backend supplies its own pool type and the DB-coupled operations
+/// (`dequeue`/`complete_job`/`fail_job`/...) through the [`Driver`] trait.
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use tokio::{task::JoinHandle, time::sleep};
+use rand::Rng;
+use tokio::{sync::watch, task::JoinHandle, time::sleep};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, trace};
 
-pub use super::{Job, JobData, JobHandler, JobId};
+use async_trait::async_trait;
+
+use super::{
+    batch::JobBatch,
+    stats::{
+        AttemptSummary, DurationHistogram, HistogramBucket, QueueStats, StatusCount,
+        DURATION_BUCKETS_MS,
+    },
+};
+pub use super::{Job, JobAttempt, JobData, JobHandler, JobId, JobReporter, WorkerOpts};
 use crate::{config::ReaperConfig, Error, Result};
 
 /// Pings the job queue database by selecting a single row's `id` from the
@@ -50,6 +60,7 @@ where
     R: sqlx::Row,
     for<'a> &'a str: sqlx::ColumnIndex<R>,
     for<'r> String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> Option<String>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
     for<'r> serde_json::Value: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
     for<'r> Option<serde_json::Value>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
     for<'r> chrono::DateTime<chrono::Utc>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
@@ -57,6 +68,7 @@ where
         sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
     for<'r> Option<i64>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
     for<'r> i32: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> Option<i32>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
 {
     let tags_json: Option<serde_json::Value> = row.try_get("tags").unwrap_or_default();
     let tags = tags_json.and_then(|json_val| {
@@ -73,6 +85,16 @@ where
         }
     });
 
+    let history_json: Option<serde_json::Value> = row.try_get("history").unwrap_or_default();
+    let history = history_json
+        .and_then(|json_val| serde_json::from_value(json_val).ok())
+        .unwrap_or_default();
+
+    let chain_json: Option<serde_json::Value> = row.try_get("chain").unwrap_or_default();
+    let chain = chain_json
+        .and_then(|json_val| serde_json::from_value(json_val).ok())
+        .unwrap_or_default();
+
     Ok(Job {
         id: row.get("id"),
         name: row.get("name"),
@@ -88,16 +110,178 @@ where
         updated_at: row.try_get("updated_at").unwrap_or_default(),
         tags,
         priority: row.get("priority"),
+        attempts: row.try_get("attempts").unwrap_or_default(),
+        last_error: row.try_get("last_error").unwrap_or_default(),
+        history,
+        unique_key: row.try_get("unique_key").unwrap_or_default(),
+        progress: row
+            .try_get::<Option<i32>, _>("progress")
+            .unwrap_or_default()
+            .and_then(|progress| u8::try_from(progress).ok()),
+        result: row.try_get("result").unwrap_or_default(),
+        batch_id: row.try_get("batch_id").unwrap_or_default(),
+        chain,
+        queue: row.try_get("queue").unwrap_or_default(),
+        args_version: row
+            .try_get::<i32, _>("args_version")
+            .ok()
+            .and_then(|version| u32::try_from(version).ok())
+            .unwrap_or(super::upcast::INITIAL_ARGS_VERSION),
+    })
+}
+
+/// Converts a row of the batches table into a [`JobBatch`], shared between
+/// the Postgres and `SQLite` backends like [`to_job`].
+///
+/// # Errors
+///
+/// Fails if the row has an unknown status.
+pub fn to_batch<R>(row: &R) -> Result<JobBatch>
+where
+    R: sqlx::Row,
+    for<'a> &'a str: sqlx::ColumnIndex<R>,
+    for<'r> String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> i32: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> Option<chrono::DateTime<chrono::Utc>>:
+        sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    Ok(JobBatch {
+        id: row.get("id"),
+        status: row.get::<String, _>("status").parse().map_err(|err| {
+            let status: String = row.get("status");
+            tracing::error!(status, err = %err, "Unsupported batch status in database");
+            Error::string("invalid batch status")
+        })?,
+        total: row.get("total"),
+        completed: row.get("completed"),
+        failed: row.get("failed"),
+        created_at: row.try_get("created_at").unwrap_or_default(),
+        updated_at: row.try_get("updated_at").unwrap_or_default(),
     })
 }
 
+/// Builds the query that aggregates job attempts per worker class for
+/// [`QueueStats`]. `attempts` selects one row per attempt, with its `class`,
+/// whether it `failed` and its `duration_ms`; the backends differ only in how
+/// they read the `history` column.
+pub fn attempt_stats_query(attempts: &str) -> String {
+    let mut buckets = String::new();
+    let mut lower = None;
+    for (index, le) in DURATION_BUCKETS_MS.iter().enumerate() {
+        let above = lower.map_or(String::new(), |lower| format!("duration_ms > {lower} AND "));
+        buckets.push_str(&format!(
+            ", CAST(SUM(CASE WHEN {above}duration_ms <= {le} THEN 1 ELSE 0 END) AS BIGINT) AS \
+             bucket_{index}"
+        ));
+        lower = Some(le);
+    }
+    if let Some(lower) = lower {
+        buckets.push_str(&format!(
+            ", CAST(SUM(CASE WHEN duration_ms > {lower} THEN 1 ELSE 0 END) AS BIGINT) AS \
+             bucket_{}",
+            DURATION_BUCKETS_MS.len()
+        ));
+    }
+    format!(
+        "WITH attempts AS ({attempts}), ranked AS (SELECT class, failed, duration_ms, \
+         ROW_NUMBER() OVER (PARTITION BY class ORDER BY duration_ms) AS nth, COUNT(*) OVER \
+         (PARTITION BY class) AS total FROM attempts) SELECT class, COUNT(*) AS count, \
+         CAST(SUM(CASE WHEN failed THEN 1 ELSE 0 END) AS BIGINT) AS failed, \
+         CAST(SUM(duration_ms) AS BIGINT) AS sum_ms, MAX(duration_ms) AS max_ms, MAX(CASE WHEN \
+         nth = (total * 50 + 99) / 100 THEN duration_ms END) AS p50_ms, MAX(CASE WHEN nth = \
+         (total * 95 + 99) / 100 THEN duration_ms END) AS p95_ms{buckets} FROM ranked GROUP BY \
+         class"
+    )
+}
+
+/// Converts a row of [`attempt_stats_query`] into the attempt aggregates of
+/// one worker class.
+pub fn to_attempt_summary<R>(row: &R) -> AttemptSummary
+where
+    R: sqlx::Row,
+    for<'a> &'a str: sqlx::ColumnIndex<R>,
+    for<'r> String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> Option<i64>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    let number = |column: &str| {
+        row.try_get::<Option<i64>, _>(column)
+            .ok()
+            .flatten()
+            .and_then(|value| u64::try_from(value).ok())
+    };
+    let buckets = DURATION_BUCKETS_MS
+        .iter()
+        .map(|le| Some(*le))
+        .chain(std::iter::once(None))
+        .enumerate()
+        .map(|(index, le_ms)| HistogramBucket {
+            le_ms,
+            count: number(&format!("bucket_{index}")).unwrap_or_default(),
+        })
+        .collect();
+    AttemptSummary {
+        class: row.get("class"),
+        failed: number("failed").unwrap_or_default(),
+        duration: DurationHistogram {
+            count: number("count").unwrap_or_default(),
+            sum_ms: number("sum_ms").unwrap_or_default(),
+            p50_ms: number("p50_ms"),
+            p95_ms: number("p95_ms"),
+            max_ms: number("max_ms"),
+            buckets,
+        },
+    }
+}
+
+/// Builds [`QueueStats`] from rows of `queue, name, status, COUNT(*)` and the
+/// age of the oldest due job, grouped by the first three, and the attempt
+/// aggregates of [`attempt_stats_query`].
+pub fn to_queue_stats(
+    counts: Vec<(String, String, String, i64, Option<i64>)>,
+    attempts: Vec<AttemptSummary>,
+) -> QueueStats {
+    let counts =
+        counts
+            .into_iter()
+            .filter_map(|(queue, class, status, count, oldest_queued_age_secs)| {
+                Some(StatusCount {
+                    queue,
+                    class,
+                    status: status.parse().ok()?,
+                    count: u64::try_from(count).ok()?,
+                    oldest_queued_age_secs,
+                })
+            });
+    QueueStats::from_aggregates(counts, attempts, chrono::Utc::now())
+}
+
+/// The `args_version` of every registered worker by class name, as a JSON
+/// object. Dequeue leaves the jobs stored at a newer version, by a newer
+/// release of the app, for a worker that knows it.
+pub fn args_versions(worker_opts: &HashMap<String, WorkerOpts>) -> serde_json::Value {
+    worker_opts
+        .iter()
+        .map(|(name, opts)| (name.clone(), opts.args_version.into()))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// The queue a job goes to when its worker does not name one.
+pub const DEFAULT_QUEUE: &str = "default";
+
 #[derive(Debug)]
 pub struct RunOpts {
     pub num_workers: u32,
     pub poll_interval_sec: u32,
+    /// The named queues to take jobs from, with their weights. Empty takes
+    /// jobs from every queue. See [`crate::config::QueueWeights`].
+    pub queues: Vec<(String, u32)>,
     /// Opt-in visibility-timeout reaper settings, populated from the queue
     /// config. `None` disables the reaper (default, backward-compatible).
     pub reaper: Option<ReaperConfig>,
+    /// On shutdown, how long a running job gets to finish before it is
+    /// interrupted and requeued.
+    pub shutdown_timeout: Duration,
 }
 
 /// Abstracts the backend-coupled operations of the SQL-based job queue
@@ -108,9 +292,15 @@ pub trait Driver: Send + Sync + 'static {
 
     fn idle_count(pool: &Self::Pool) -> usize;
 
+    /// Claims the next due job for a worker with `tags`, holding back
+    /// classes that `worker_opts` caps or rate limits once they reach their
+    /// limit. With `queues`, only takes jobs from those, preferring them in
+    /// that order.
     fn dequeue(
         pool: &Self::Pool,
         tags: &[String],
+        queues: &[String],
+        worker_opts: &HashMap<String, WorkerOpts>,
     ) -> impl std::future::Future<Output = crate::Result<Option<Job>>> + Send;
 
     fn complete_job(
@@ -124,10 +314,77 @@ pub trait Driver: Send + Sync + 'static {
         id: &JobId,
         error: &crate::Error,
     ) -> impl std::future::Future<Output = crate::Result<()>> + Send;
+
+    /// Marks a running job that stopped with `error` after it was asked to
+    /// as [`super::JobStatus::Cancelled`].
+    fn cancel_job(
+        pool: &Self::Pool,
+        id: &JobId,
+        error: &crate::Error,
+    ) -> impl std::future::Future<Output = crate::Result<()>> + Send;
+
+    /// Whether cancelling the running job `id` was requested.
+    fn cancel_requested(
+        pool: &Self::Pool,
+        id: &JobId,
+    ) -> impl std::future::Future<Output = crate::Result<bool>> + Send;
+
+    /// Puts a running job that was interrupted by a worker shutdown back in
+    /// [`super::JobStatus::Queued`], giving back the attempt it was on.
+    fn requeue_job(
+        pool: &Self::Pool,
+        id: &JobId,
+    ) -> impl std::future::Future<Output = crate::Result<()>> + Send;
+
+    fn retry_job(
+        pool: &Self::Pool,
+        id: &JobId,
+        run_at: chrono::DateTime<chrono::Utc>,
+        error: &crate::Error,
+    ) -> impl std::future::Future<Output = crate::Result<()>> + Send;
+
+    fn record_attempt(
+        pool: &Self::Pool,
+        id: &JobId,
+        attempt: &JobAttempt,
+    ) -> impl std::future::Future<Output = crate::Result<()>> + Send;
+
+    fn set_progress(
+        pool: &Self::Pool,
+        id: &JobId,
+        percent: u8,
+    ) -> impl std::future::Future<Output = crate::Result<()>> + Send;
+
+    fn set_result(
+        pool: &Self::Pool,
+        id: &JobId,
+        result: serde_json::Value,
+    ) -> impl std::future::Future<Output = crate::Result<()>> + Send;
+}
+
+/// [`JobReporter`] writing through a [`Driver`]'s `set_progress`/`set_result`.
+struct DriverReporter<D: Driver> {
+    pool: D::Pool,
+}
+
+#[async_trait]
+impl<D: Driver> JobReporter for DriverReporter<D> {
+    async fn set_progress(&self, id: &JobId, percent: u8) -> Result<()> {
+        D::set_progress(&self.pool, id, percent).await
+    }
+
+    async fn set_result(&self, id: &JobId, result: serde_json::Value) -> Result<()> {
+        D::set_result(&self.pool, id, result).await
+    }
+
+    async fn cancel_requested(&self, id: &JobId) -> Result<bool> {
+        D::cancel_requested(&self.pool, id).await
+    }
 }
 
 pub struct JobRegistry {
     handlers: Arc<HashMap<String, JobHandler>>,
+    worker_opts: Arc<HashMap<String, WorkerOpts>>,
 }
 
 impl JobRegistry {
@@ -136,15 +393,24 @@ impl JobRegistry {
     pub fn new() -> Self {
         Self {
             handlers: Arc::new(HashMap::new()),
+            worker_opts: Arc::new(HashMap::new()),
         }
     }
 
     /// Inserts a pre-erased job handler under `name` (see
-    /// [`super::erase_worker`]).
+    /// [`super::erase_worker`]), with the worker's [`WorkerOpts`].
     ///
     /// # Errors
     /// Fails if cannot register worker
-    pub fn insert_handler(&mut self, name: String, handler: JobHandler) -> Result<()> {
+    pub fn insert_handler(
+        &mut self,
+        name: String,
+        handler: JobHandler,
+        opts: WorkerOpts,
+    ) -> Result<()> {
+        Arc::get_mut(&mut self.worker_opts)
+            .ok_or_else(|| Error::string("cannot register worker"))?
+            .insert(name.clone(), opts);
         Arc::get_mut(&mut self.handlers)
             .ok_or_else(|| Error::string("cannot register worker"))?
             .insert(name, handler);
@@ -165,16 +431,40 @@ impl JobRegistry {
         opts: &RunOpts,
         token: &CancellationToken,
         tags: &[String],
+    ) -> Vec<JoinHandle<()>> {
+        self.run_with_wakeups::<D>(pool, opts, token, tags, None)
+    }
+
+    /// Like [`JobRegistry::run`], but idle workers also stop waiting for the
+    /// next poll whenever `wakeups` signals that a job was enqueued.
+    #[must_use]
+    pub fn run_with_wakeups<D: Driver>(
+        &self,
+        pool: &D::Pool,
+        opts: &RunOpts,
+        token: &CancellationToken,
+        tags: &[String],
+        wakeups: Option<watch::Receiver<()>>,
     ) -> Vec<JoinHandle<()>> {
         let mut jobs = Vec::new();
 
         let interval = opts.poll_interval_sec;
+        let drain = super::Drain {
+            token: token.clone(),
+            timeout: opts.shutdown_timeout,
+        };
         for idx in 0..opts.num_workers {
             let handlers = self.handlers.clone();
+            let worker_opts = self.worker_opts.clone();
             let worker_token = token.clone(); // Clone token for this worker
             let worker_tags = tags.to_vec();
+            let worker_queues = opts.queues.clone();
+            let mut wakeups = wakeups.clone();
+            let drain = drain.clone();
 
             let pool = pool.clone();
+            let reporter: Arc<dyn JobReporter> =
+                Arc::new(DriverReporter::<D> { pool: pool.clone() });
             let job = tokio::spawn(async move {
                 loop {
                     // Check for cancellation before potentially blocking on dequeue
@@ -187,7 +477,15 @@ impl JobRegistry {
                         worker_id = idx,
                         "Connection pool stats"
                     );
-                    let job_opt = match D::dequeue(&pool, &worker_tags).await {
+                    // Anything enqueued from here on is either dequeued
+                    // below or wakes the worker from its sleep.
+                    if let Some(wakeups) = &mut wakeups {
+                        wakeups.borrow_and_update();
+                    }
+                    let queues =
+                        weighted_order(&worker_queues, |total| rand::rng().random_range(0..total));
+                    let job_opt = match D::dequeue(&pool, &worker_tags, &queues, &worker_opts).await
+                    {
                         Ok(t) => t,
                         Err(err) => {
                             error!(error = %err, "Failed to fetch job from queue");
@@ -198,34 +496,115 @@ impl JobRegistry {
                     if let Some(job) = job_opt {
                         debug!(job_id = %job.id, job_name = %job.name, "Processing job");
                         if let Some(handler) = handlers.get(&job.name) {
-                            match handler(job.id.clone(), job.data.clone()).await {
-                                Ok(()) => match D::complete_job(&pool, &job.id, job.interval).await
-                                {
+                            let started_at = chrono::Utc::now();
+                            let super::JobRun {
+                                result,
+                                cancelled,
+                                interrupted,
+                            } = super::run_job(
+                                job.id.clone(),
+                                reporter.clone(),
+                                worker_opts.get(&job.name),
+                                Duration::from_secs(interval.into()),
+                                &drain,
+                                super::call_handler(handler, worker_opts.get(&job.name), &job),
+                            )
+                            .await;
+                            if interrupted {
+                                match D::requeue_job(&pool, &job.id).await {
                                     Err(err) => {
                                         error!(
                                             error = %err,
                                             job_id = %job.id,
                                             job_name = %job.name,
-                                            "Failed to mark job as completed"
+                                            "Failed to requeue interrupted job"
                                         );
                                     }
                                     _ => {
-                                        debug!(job_id = %job.id, "Job completed successfully");
+                                        debug!(job_id = %job.id, "Interrupted job requeued");
                                     }
-                                },
-                                Err(err) => match D::fail_job(&pool, &job.id, &err).await {
-                                    Err(fail_err) => {
+                                }
+                                break;
+                            }
+                            let attempt = JobAttempt::new(job.attempts, started_at, &result);
+                            if let Err(err) = D::record_attempt(&pool, &job.id, &attempt).await {
+                                error!(
+                                    error = %err,
+                                    job_id = %job.id,
+                                    job_name = %job.name,
+                                    "Failed to record job attempt"
+                                );
+                            }
+                            match result {
+                                Ok(()) => match D::complete_job(&pool, &job.id, job.interval).await
+                                {
+                                    Err(err) => {
                                         error!(
-                                            error = %fail_err,
+                                            error = %err,
                                             job_id = %job.id,
                                             job_name = %job.name,
-                                            "Failed to mark job as failed"
+                                            "Failed to mark job as completed"
                                         );
                                     }
                                     _ => {
-                                        debug!(job_id = %job.id, error = %err, "Job execution failed");
+                                        debug!(job_id = %job.id, "Job completed successfully");
                                     }
                                 },
+                                Err(err) if cancelled => {
+                                    match D::cancel_job(&pool, &job.id, &err).await {
+                                        Err(cancel_err) => {
+                                            error!(
+                                                error = %cancel_err,
+                                                job_id = %job.id,
+                                                job_name = %job.name,
+                                                "Failed to mark job as cancelled"
+                                            );
+                                        }
+                                        _ => {
+                                            debug!(job_id = %job.id, error = %err, "Job cancelled while running");
+                                        }
+                                    }
+                                }
+                                Err(err) => {
+                                    let next_run_at = worker_opts
+                                        .get(&job.name)
+                                        .and_then(|opts| opts.next_run_at(job.attempts));
+                                    if let Some(run_at) = next_run_at {
+                                        match D::retry_job(&pool, &job.id, run_at, &err).await {
+                                            Err(retry_err) => {
+                                                error!(
+                                                    error = %retry_err,
+                                                    job_id = %job.id,
+                                                    job_name = %job.name,
+                                                    "Failed to reschedule job for retry"
+                                                );
+                                            }
+                                            _ => {
+                                                debug!(
+                                                    job_id = %job.id,
+                                                    attempts = job.attempts,
+                                                    run_at = %run_at,
+                                                    error = %err,
+                                                    "Job execution failed, retry scheduled"
+                                                );
+                                            }
+                                        }
+                                    } else {
+                                        match D::fail_job(&pool, &job.id, &err).await {
+                                            Err(fail_err) => {
+                                                error!(
+                                                    error = %fail_err,
+                                                    job_id = %job.id,
+                                                    job_name = %job.name,
+                                                    "Failed to mark job as failed"
+                                                );
+                                            }
+                                            _ => {
+                                                debug!(job_id = %job.id, error = %err, "Job execution failed");
+                                            }
+                                        }
+                                    }
+                                }
                             }
                         } else {
                             error!(job_name = %job.name, "No handler registered for job");
@@ -241,6 +620,9 @@ impl JobRegistry {
                             () = sleep(Duration::from_secs(interval.into())) => {
                                 // Interval elapsed, continue loop
                             }
+                            () = woken(&mut wakeups) => {
+                                trace!(worker_id = idx, "Woken up by an enqueued job");
+                            }
                         }
                     }
                 }
@@ -253,8 +635,91 @@ impl JobRegistry {
     }
 }
 
+/// Orders weighted `queues` for one dequeue: each queue comes first with a
+/// chance proportional to its weight, then the next among the rest, and so
+/// on; queues weighing `0` come last. `roll(total)` picks a number below
+/// `total`.
+fn weighted_order(queues: &[(String, u32)], mut roll: impl FnMut(u64) -> u64) -> Vec<String> {
+    let mut left = queues.iter().filter(|(_, w)| *w > 0).collect::<Vec<_>>();
+    let mut order = Vec::with_capacity(queues.len());
+    while !left.is_empty() {
+        let total = left.iter().map(|(_, w)| u64::from(*w)).sum();
+        let mut pick = roll(total);
+        let idx = left
+            .iter()
+            .position(|(_, w)| {
+                let weight = u64::from(*w);
+                if pick < weight {
+                    return true;
+                }
+                pick -= weight;
+                false
+            })
+            .unwrap_or(left.len() - 1);
+        order.push(left.remove(idx).0.clone());
+    }
+    order.extend(
+        queues
+            .iter()
+            .filter(|(_, w)| *w == 0)
+            .map(|(queue, _)| queue.clone()),
+    );
+    order
+}
+
+/// Resolves once `wakeups` signals an enqueued job; never without a channel,
+/// or once its sender is gone.
+async fn woken(wakeups: &mut Option<watch::Receiver<()>>) {
+    if let Some(wakeups) = wakeups
+        && wakeups.changed().await.is_ok()
+    {
+        return;
+    }
+    std::future::pending().await
+}
+
 impl Default for JobRegistry {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weights(queues: &[(&str, u32)]) -> Vec<(String, u32)> {
+        queues
+            .iter()
+            .map(|(queue, weight)| ((*queue).to_string(), *weight))
+            .collect()
+    }
+
+    #[test]
+    fn weighted_order_favors_heavier_queues() {
+        let queues = weights(&[("critical", 6), ("default", 3), ("idle", 0), ("mailer", 1)]);
+
+        // Rolls below 6 land on `critical`, then on what is left.
+        let mut rolls = vec![0, 0, 0].into_iter();
+        assert_eq!(
+            weighted_order(&queues, |_| rolls.next().unwrap()),
+            ["critical", "default", "mailer", "idle"]
+        );
+        let mut rolls = vec![9, 6, 0].into_iter();
+        assert_eq!(
+            weighted_order(&queues, |_| rolls.next().unwrap()),
+            ["mailer", "default", "critical", "idle"]
+        );
+
+        let mut firsts = HashMap::new();
+        for _ in 0..10_000 {
+            let order = weighted_order(&queues, |total| rand::rng().random_range(0..total));
+            *firsts.entry(order[0].clone()).or_insert(0) += 1;
+        }
+        assert!((5_500..6_500).contains(&firsts["critical"]), "{firsts:?}");
+        assert!((700..1_300).contains(&firsts["mailer"]), "{firsts:?}");
+        assert!(!firsts.contains_key("idle"));
+
+        assert!(weighted_order(&[], |_| unreachable!()).is_empty());
+    }
+}
@@ -1,18 +1,23 @@
 /// `SQLite` based background job queue provider
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-pub use super::sql::{Job, JobData, JobId, JobRegistry, RunOpts};
+pub use super::sql::{Job, JobAttempt, JobData, JobId, JobRegistry, RunOpts};
 use super::{
-    sql::{to_job, Driver},
-    JobHandler, JobStatus, Queue, QueueProvider,
+    batch::{BatchSpec, BatchStatus, ChainStep, JobBatch, JobSpec},
+    sql::{to_batch, to_job, Driver, DEFAULT_QUEUE},
+    EnqueueOptions, JobFilter, JobHandler, JobStatus, Queue, QueueProvider, UniqueJob,
+    UniqueWindow, WorkerOpts,
+};
+use crate::{
+    config::{SqliteQueueConfig, WorkerLimitsConfig},
+    Error, Result,
 };
-use crate::{config::SqliteQueueConfig, Result};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 pub use sqlx::SqlitePool;
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
-    AssertSqlSafe, ConnectOptions, QueryBuilder,
+    AssertSqlSafe, ConnectOptions, QueryBuilder, Row, SqliteConnection,
 };
 use std::fmt::Write;
 use std::sync::Arc;
@@ -21,7 +26,7 @@ use tracing::{debug, trace};
 use ulid::Ulid;
 
 /// [`Driver`] implementation delegating to the `SQLite`-specific
-/// `dequeue`/`complete_job`/`fail_job` free functions below.
+/// `dequeue`/`complete_job`/`fail_job`/`retry_job` free functions below.
 pub struct SqliteDriver;
 
 impl Driver for SqliteDriver {
@@ -31,8 +36,13 @@ impl Driver for SqliteDriver {
         pool.num_idle()
     }
 
-    async fn dequeue(pool: &Self::Pool, tags: &[String]) -> crate::Result<Option<Job>> {
-        dequeue(pool, tags).await
+    async fn dequeue(
+        pool: &Self::Pool,
+        tags: &[String],
+        queues: &[String],
+        worker_opts: &HashMap<String, WorkerOpts>,
+    ) -> crate::Result<Option<Job>> {
+        dequeue(pool, tags, queues, worker_opts).await
     }
 
     async fn complete_job(
@@ -46,6 +56,47 @@ impl Driver for SqliteDriver {
     async fn fail_job(pool: &Self::Pool, id: &JobId, error: &crate::Error) -> crate::Result<()> {
         fail_job(pool, id, error).await
     }
+
+    async fn cancel_job(pool: &Self::Pool, id: &JobId, error: &crate::Error) -> crate::Result<()> {
+        cancel_job(pool, id, error).await
+    }
+
+    async fn cancel_requested(pool: &Self::Pool, id: &JobId) -> crate::Result<bool> {
+        cancel_requested(pool, id).await
+    }
+
+    async fn requeue_job(pool: &Self::Pool, id: &JobId) -> crate::Result<()> {
+        requeue_job(pool, id).await
+    }
+
+    async fn retry_job(
+        pool: &Self::Pool,
+        id: &JobId,
+        run_at: DateTime<Utc>,
+        error: &crate::Error,
+    ) -> crate::Result<()> {
+        retry_job(pool, id, run_at, error).await
+    }
+
+    async fn record_attempt(
+        pool: &Self::Pool,
+        id: &JobId,
+        attempt: &JobAttempt,
+    ) -> crate::Result<()> {
+        record_attempt(pool, id, attempt).await
+    }
+
+    async fn set_progress(pool: &Self::Pool, id: &JobId, percent: u8) -> crate::Result<()> {
+        set_progress(pool, id, percent).await
+    }
+
+    async fn set_result(
+        pool: &Self::Pool,
+        id: &JobId,
+        result: serde_json::Value,
+    ) -> crate::Result<()> {
+        set_result(pool, id, result).await
+    }
 }
 
 /// The `SQLite` [`QueueProvider`].
@@ -54,6 +105,8 @@ pub struct SqliteQueue {
     pub registry: Arc<tokio::sync::Mutex<JobRegistry>>,
     pub run_opts: RunOpts,
     pub token: CancellationToken,
+    /// Per-worker limits from the queue config, applied on registration.
+    pub limits: HashMap<String, WorkerLimitsConfig>,
 }
 
 #[async_trait]
@@ -61,29 +114,46 @@ impl QueueProvider for SqliteQueue {
     async fn enqueue(
         &self,
         class: String,
-        _queue: Option<String>,
         args: serde_json::Value,
-        tags: Option<Vec<String>>,
-        priority: Option<i32>,
+        options: EnqueueOptions,
     ) -> Result<Option<String>> {
-        Ok(Some(
-            enqueue(
-                &self.pool,
-                &class,
-                args,
-                chrono::Utc::now(),
-                None,
-                tags,
-                priority,
-            )
-            .await
-            .map_err(Box::from)?,
-        ))
+        let id = enqueue(&self.pool, &class, args, None, &options).await;
+        Ok(Some(id.map_err(Box::from)?))
+    }
+
+    #[cfg(feature = "with-db")]
+    async fn enqueue_in_transaction(
+        &self,
+        txn: &sea_orm::DatabaseTransaction,
+        class: String,
+        args: serde_json::Value,
+        options: EnqueueOptions,
+    ) -> Result<Option<String>> {
+        let id = enqueue_in_transaction(txn, &class, args, &options).await?;
+        Ok(Some(id))
+    }
+
+    async fn enqueue_chain(&self, job: JobSpec) -> Result<Option<String>> {
+        Ok(Some(enqueue_chain(&self.pool, &job).await?))
+    }
+
+    async fn enqueue_batch(&self, batch: BatchSpec) -> Result<Option<String>> {
+        Ok(Some(enqueue_batch(&self.pool, &batch).await?))
+    }
+
+    async fn get_batches(&self) -> Result<Vec<JobBatch>> {
+        get_batches(&self.pool).await
     }
 
-    async fn register_handler(&self, name: String, handler: JobHandler) -> Result<()> {
+    async fn register_handler(
+        &self,
+        name: String,
+        handler: JobHandler,
+        opts: WorkerOpts,
+    ) -> Result<()> {
+        let opts = opts.with_limits(self.limits.get(&name));
         let mut registry = self.registry.lock().await;
-        registry.insert_handler(name, handler)
+        registry.insert_handler(name, handler, opts)
     }
 
     async fn run(&self, tags: Vec<String>) -> Result<()> {
@@ -139,6 +209,22 @@ impl QueueProvider for SqliteQueue {
         Ok(jobs)
     }
 
+    async fn get_job(&self, id: &str) -> Result<Option<Job>> {
+        get_job(&self.pool, id).await
+    }
+
+    async fn list_jobs(&self, filter: &JobFilter) -> Result<Vec<Job>> {
+        list_jobs(&self.pool, filter).await
+    }
+
+    async fn count_by_status(&self) -> Result<HashMap<JobStatus, u64>> {
+        count_by_status(&self.pool).await
+    }
+
+    async fn stats(&self) -> Result<super::stats::QueueStats> {
+        stats(&self.pool).await
+    }
+
     async fn cancel_jobs_by_name(&self, name: &str) -> Result<()> {
         cancel_jobs_by_name(&self.pool, name).await
     }
@@ -173,7 +259,7 @@ impl QueueProvider for SqliteQueue {
     }
 }
 
-async fn connect(cfg: &SqliteQueueConfig) -> Result<SqlitePool> {
+pub(crate) async fn connect(cfg: &SqliteQueueConfig) -> Result<SqlitePool> {
     let mut conn_opts: SqliteConnectOptions = cfg.uri.parse()?;
     if !cfg.enable_logging {
         conn_opts = conn_opts.disable_statement_logging();
@@ -206,37 +292,91 @@ pub async fn initialize_database(pool: &SqlitePool) -> Result<()> {
                 created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
                 updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
                 tags JSON,
-                priority INTEGER NOT NULL DEFAULT 0
+                priority INTEGER NOT NULL DEFAULT 0,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT,
+                history JSON NOT NULL DEFAULT '[]',
+                unique_key TEXT,
+                progress INTEGER,
+                result JSON,
+                batch_id TEXT,
+                chain JSON NOT NULL DEFAULT '[]',
+                started_at TIMESTAMP,
+                cancel_requested BOOLEAN NOT NULL DEFAULT FALSE,
+                queue TEXT NOT NULL DEFAULT 'default',
+                args_version INTEGER NOT NULL DEFAULT 1
             );
 
             CREATE INDEX IF NOT EXISTS idx_sqlt_queue_status_run_at ON sqlt_loco_queue(status, run_at);
 
+            CREATE TABLE IF NOT EXISTS sqlt_loco_batches (
+                id TEXT NOT NULL PRIMARY KEY,
+                status TEXT NOT NULL DEFAULT '{}',
+                total INTEGER NOT NULL,
+                completed INTEGER NOT NULL DEFAULT 0,
+                failed INTEGER NOT NULL DEFAULT 0,
+                on_complete JSON,
+                on_failure JSON,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+
             DROP TABLE IF EXISTS sqlt_loco_queue_lock;
-            ", JobStatus::Queued),
+            ", JobStatus::Queued, BatchStatus::Pending),
     ))
     .execute(pool)
     .await?;
 
-    // Auto-migrate: add the priority column to pre-existing databases.
-    let priority_exists: bool = sqlx::query_scalar(
+    // Auto-migrate: add columns introduced after the table was created.
+    add_column_if_missing(pool, "priority", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "attempts", "INTEGER NOT NULL DEFAULT 0").await?;
+    add_column_if_missing(pool, "last_error", "TEXT").await?;
+    add_column_if_missing(pool, "history", "JSON NOT NULL DEFAULT '[]'").await?;
+    add_column_if_missing(pool, "unique_key", "TEXT").await?;
+    add_column_if_missing(pool, "progress", "INTEGER").await?;
+    add_column_if_missing(pool, "result", "JSON").await?;
+    add_column_if_missing(pool, "batch_id", "TEXT").await?;
+    add_column_if_missing(pool, "chain", "JSON NOT NULL DEFAULT '[]'").await?;
+    add_column_if_missing(pool, "started_at", "TIMESTAMP").await?;
+    add_column_if_missing(pool, "cancel_requested", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    add_column_if_missing(pool, "queue", "TEXT NOT NULL DEFAULT 'default'").await?;
+    add_column_if_missing(pool, "args_version", "INTEGER NOT NULL DEFAULT 1").await?;
+
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_sqlt_queue_unique_key ON \
+         sqlt_loco_queue(unique_key) WHERE unique_key IS NOT NULL",
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Adds `column` with the given SQL `definition` to a pre-existing
+/// `sqlt_loco_queue` table, if it's not there yet.
+async fn add_column_if_missing(pool: &SqlitePool, column: &str, definition: &str) -> Result<()> {
+    let column_exists: bool = sqlx::query_scalar(
         "SELECT EXISTS (
             SELECT 1 FROM pragma_table_info('sqlt_loco_queue')
-            WHERE name = 'priority'
+            WHERE name = ?
         )",
     )
+    .bind(column)
     .fetch_one(pool)
     .await?;
-    if !priority_exists {
-        debug!("Adding priority column to existing sqlt_loco_queue table");
-        sqlx::query("ALTER TABLE sqlt_loco_queue ADD COLUMN priority INTEGER NOT NULL DEFAULT 0")
-            .execute(pool)
-            .await?;
+    if !column_exists {
+        debug!(column, "Adding column to existing sqlt_loco_queue table");
+        sqlx::query(AssertSqlSafe(format!(
+            "ALTER TABLE sqlt_loco_queue ADD COLUMN {column} {definition}"
+        )))
+        .execute(pool)
+        .await?;
     }
-
     Ok(())
 }
 
-/// Add a job
+/// Add a job as `options` say, recurring every `interval` if given. A
+/// unique job goes through [`enqueue_unique`], and does not recur.
 ///
 /// # Errors
 ///
@@ -245,13 +385,15 @@ pub async fn enqueue(
     pool: &SqlitePool,
     name: &str,
     data: JobData,
-    run_at: DateTime<Utc>,
     interval: Option<Duration>,
-    tags: Option<Vec<String>>,
-    priority: Option<i32>,
+    options: &EnqueueOptions,
 ) -> Result<JobId> {
+    if let Some(unique) = &options.unique {
+        return enqueue_unique(pool, unique, name, data, options).await;
+    }
+    let run_at = options.run_at.unwrap_or_else(Utc::now);
     let data = serde_json::to_value(data)?;
-    let tags_json = match &tags {
+    let tags_json = match &options.tags {
         Some(tags) => Some(serde_json::to_value(tags)?),
         None => None,
     };
@@ -260,10 +402,10 @@ pub async fn enqueue(
     let interval_ms: Option<i64> = interval.map(|i| i.as_millis() as i64);
 
     let id = Ulid::new().to_string();
-    debug!(job_id = %id, job_name = %name, run_at = %run_at, tags = ?tags, priority = ?priority, "Enqueueing job");
+    debug!(job_id = %id, job_name = %name, run_at = %run_at, tags = ?options.tags, priority = ?options.priority, "Enqueueing job");
     sqlx::query(
-        "INSERT INTO sqlt_loco_queue (id, task_data, name, run_at, interval, tags, priority) VALUES \
-         ($1, $2, $3, DATETIME($4), $5, $6, $7)",
+        "INSERT INTO sqlt_loco_queue (id, task_data, name, run_at, interval, tags, priority, \
+         queue, args_version) VALUES ($1, $2, $3, DATETIME($4), $5, $6, $7, $8, $9)",
     )
     .bind(id.clone())
     .bind(data)
@@ -271,13 +413,334 @@ pub async fn enqueue(
     .bind(run_at)
     .bind(interval_ms)
     .bind(tags_json)
-    .bind(priority.unwrap_or(0))
+    .bind(options.priority.unwrap_or(0))
+    .bind(options.queue.as_deref().unwrap_or(DEFAULT_QUEUE))
+    .bind(options.args_version)
     .execute(pool)
     .await?;
     Ok(id)
 }
 
-async fn dequeue(client: &SqlitePool, worker_tags: &[String]) -> Result<Option<Job>> {
+/// SQL condition matching jobs still inside `window`; a [`UniqueWindow::For`]
+/// takes its length as the second bind parameter.
+fn in_unique_window(window: &UniqueWindow) -> String {
+    match window {
+        UniqueWindow::Queued => format!("status = '{}'", JobStatus::Queued),
+        UniqueWindow::QueuedOrProcessing => format!(
+            "status IN ('{}', '{}')",
+            JobStatus::Queued,
+            JobStatus::Processing
+        ),
+        UniqueWindow::For(_) => "created_at > DATETIME('now', $2)".to_string(),
+    }
+}
+
+/// Add a job as `options` say, unless one holding the same
+/// [`UniqueJob::key`] as `unique` is still inside its window, in which case
+/// that job's id is returned instead.
+///
+/// A key is released lazily: the job keeps it in its row until the next
+/// enqueue with the same key finds the job outside the window.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_unique(
+    pool: &SqlitePool,
+    unique: &UniqueJob,
+    name: &str,
+    data: JobData,
+    options: &EnqueueOptions,
+) -> Result<JobId> {
+    let run_at = options.run_at.unwrap_or_else(Utc::now);
+    let tags_json = match &options.tags {
+        Some(tags) => Some(serde_json::to_value(tags)?),
+        None => None,
+    };
+    let in_window = in_unique_window(&unique.window);
+
+    // Take the write lock up front (see `dequeue`) so the release, insert and
+    // lookup below see a consistent table.
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+    let query = format!(
+        "UPDATE sqlt_loco_queue SET unique_key = NULL WHERE unique_key = $1 AND NOT ({in_window})"
+    );
+    let mut release = sqlx::query(AssertSqlSafe(query)).bind(&unique.key);
+    if let UniqueWindow::For(window) = &unique.window {
+        release = release.bind(format!("-{} seconds", window.as_secs_f64()));
+    }
+    release.execute(&mut *tx).await?;
+
+    let id = Ulid::new().to_string();
+    let inserted = sqlx::query(
+        "INSERT INTO sqlt_loco_queue (id, task_data, name, run_at, tags, priority, unique_key, \
+         queue, args_version) VALUES ($1, $2, $3, DATETIME($4), $5, $6, $7, $8, $9) ON CONFLICT \
+         DO NOTHING",
+    )
+    .bind(&id)
+    .bind(data)
+    .bind(name)
+    .bind(run_at)
+    .bind(tags_json)
+    .bind(options.priority.unwrap_or(0))
+    .bind(&unique.key)
+    .bind(options.queue.as_deref().unwrap_or(DEFAULT_QUEUE))
+    .bind(options.args_version)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    let id = if inserted == 0 {
+        let existing: Option<String> =
+            sqlx::query_scalar("SELECT id FROM sqlt_loco_queue WHERE unique_key = $1")
+                .bind(&unique.key)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let existing =
+            existing.ok_or_else(|| crate::Error::string("unique job key is held by no job"))?;
+        debug!(job_id = %existing, job_name = %name, unique_key = %unique.key, "Skipping duplicate of unique job");
+        existing
+    } else {
+        debug!(job_id = %id, job_name = %name, run_at = %run_at, unique_key = %unique.key, "Enqueueing unique job");
+        id
+    };
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Add a job as part of the sea-orm transaction `txn`, so that workers only
+/// see it once `txn` commits and never if it rolls back. `txn` has to be on
+/// the database holding `sqlt_loco_queue`. Unique jobs are deduplicated as in
+/// [`enqueue_unique`].
+///
+/// # Errors
+///
+/// This function will return an error if it fails, or if `txn` is not on a
+/// SQLite database
+#[cfg(feature = "with-db")]
+pub async fn enqueue_in_transaction(
+    txn: &sea_orm::DatabaseTransaction,
+    name: &str,
+    data: JobData,
+    options: &EnqueueOptions,
+) -> Result<JobId> {
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+    let unique = options.unique.as_ref();
+    let run_at = options.run_at.unwrap_or_else(Utc::now);
+
+    if txn.get_database_backend() != DbBackend::Sqlite {
+        return Err(Error::string(
+            "the sqlite queue can only enqueue in a transaction on a SQLite database",
+        ));
+    }
+    let tags_json = options
+        .tags
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()?;
+
+    if let Some(unique) = unique {
+        let mut values: Vec<sea_orm::Value> = vec![unique.key.clone().into()];
+        if let UniqueWindow::For(window) = &unique.window {
+            values.push(format!("-{} seconds", window.as_secs_f64()).into());
+        }
+        txn.execute_raw(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            format!(
+                "UPDATE sqlt_loco_queue SET unique_key = NULL WHERE unique_key = $1 AND NOT ({})",
+                in_unique_window(&unique.window)
+            ),
+            values,
+        ))
+        .await?;
+    }
+
+    let id = Ulid::new().to_string();
+    let inserted = txn
+        .execute_raw(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "INSERT INTO sqlt_loco_queue (id, task_data, name, run_at, tags, priority, unique_key, \
+             queue, args_version) VALUES ($1, $2, $3, DATETIME($4), $5, $6, $7, $8, $9) ON \
+             CONFLICT DO NOTHING",
+            [
+                id.clone().into(),
+                data.into(),
+                name.into(),
+                run_at.into(),
+                tags_json.into(),
+                options.priority.unwrap_or(0).into(),
+                unique.map(|unique| unique.key.clone()).into(),
+                options.queue.as_deref().unwrap_or(DEFAULT_QUEUE).into(),
+                options.args_version.into(),
+            ],
+        ))
+        .await?
+        .rows_affected();
+
+    if let Some(unique) = unique.filter(|_| inserted == 0) {
+        let existing = txn
+            .query_one_raw(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "SELECT id FROM sqlt_loco_queue WHERE unique_key = $1",
+                [unique.key.clone().into()],
+            ))
+            .await?
+            .ok_or_else(|| crate::Error::string("unique job key is held by no job"))?
+            .try_get::<String>("", "id")?;
+        debug!(job_id = %existing, job_name = %name, unique_key = %unique.key, "Skipping duplicate of unique job");
+        return Ok(existing);
+    }
+    debug!(job_id = %id, job_name = %name, run_at = %run_at, "Enqueueing job in transaction");
+    Ok(id)
+}
+
+/// Inserts the job described by `spec`, as a member of `batch_id` if given.
+async fn insert_spec(
+    conn: &mut SqliteConnection,
+    spec: &JobSpec,
+    batch_id: Option<&str>,
+) -> Result<JobId> {
+    let tags_json = match &spec.tags {
+        Some(tags) => Some(serde_json::to_value(tags)?),
+        None => None,
+    };
+    let id = Ulid::new().to_string();
+    debug!(job_id = %id, job_name = %spec.class, batch_id = ?batch_id, steps_left = spec.chain.len(), "Enqueueing job");
+    sqlx::query(
+        "INSERT INTO sqlt_loco_queue (id, task_data, name, run_at, tags, priority, batch_id, \
+         chain, queue, args_version) VALUES ($1, $2, $3, DATETIME($4), $5, $6, $7, $8, $9, $10)",
+    )
+    .bind(&id)
+    .bind(&spec.args)
+    .bind(&spec.class)
+    .bind(Utc::now())
+    .bind(tags_json)
+    .bind(spec.priority.unwrap_or(0))
+    .bind(batch_id)
+    .bind(serde_json::to_value(&spec.chain)?)
+    .bind(spec.queue.as_deref().unwrap_or(DEFAULT_QUEUE))
+    .bind(spec.args_version)
+    .execute(conn)
+    .await?;
+    Ok(id)
+}
+
+/// Add the first job of a chain, carrying the remaining steps
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_chain(pool: &SqlitePool, job: &JobSpec) -> Result<JobId> {
+    let mut conn = pool.acquire().await?;
+    insert_spec(&mut conn, job, None).await
+}
+
+/// Record a batch and add its jobs, returning the batch id
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn enqueue_batch(pool: &SqlitePool, batch: &BatchSpec) -> Result<String> {
+    let total = i32::try_from(batch.jobs.len()).map_err(|_| Error::string("batch is too large"))?;
+    let id = Ulid::new().to_string();
+    debug!(batch_id = %id, total, "Enqueueing batch");
+
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+    sqlx::query(
+        "INSERT INTO sqlt_loco_batches (id, total, on_complete, on_failure) VALUES ($1, $2, $3, \
+         $4)",
+    )
+    .bind(&id)
+    .bind(total)
+    .bind(
+        batch
+            .on_complete
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?,
+    )
+    .bind(
+        batch
+            .on_failure
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()?,
+    )
+    .execute(&mut *tx)
+    .await?;
+    for job in &batch.jobs {
+        insert_spec(&mut tx, job, Some(&id)).await?;
+    }
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Enqueues what follows a completed job: the next step of its chain, or,
+/// when it was the last step, its batch's `on_complete` if this was the last
+/// member of the batch to complete.
+async fn enqueue_completion_follow_ups(
+    conn: &mut SqliteConnection,
+    batch_id: Option<String>,
+    chain: &[ChainStep],
+    result: Option<serde_json::Value>,
+) -> Result<()> {
+    if let Some(next) = JobSpec::continue_chain(chain, result) {
+        insert_spec(conn, &next, batch_id.as_deref()).await?;
+        return Ok(());
+    }
+    let Some(batch_id) = batch_id else {
+        return Ok(());
+    };
+    let on_complete: Option<Option<serde_json::Value>> =
+        sqlx::query_scalar(AssertSqlSafe(format!(
+            "UPDATE sqlt_loco_batches SET completed = completed + 1, status = CASE WHEN \
+             completed + 1 = total AND status = '{pending}' THEN '{completed}' ELSE status END, \
+             updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING CASE WHEN status = \
+             '{completed}' AND completed = total THEN on_complete END",
+            pending = BatchStatus::Pending,
+            completed = BatchStatus::Completed,
+        )))
+        .bind(&batch_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    if let Some(on_complete) = on_complete.flatten() {
+        debug!(batch_id = %batch_id, "Batch completed, enqueueing its callback");
+        insert_spec(conn, &serde_json::from_value(on_complete)?, None).await?;
+    }
+    Ok(())
+}
+
+/// Enqueues a failed job's batch's `on_failure` if this was the first member
+/// of the batch to fail.
+async fn enqueue_failure_follow_ups(
+    conn: &mut SqliteConnection,
+    batch_id: Option<String>,
+) -> Result<()> {
+    let Some(batch_id) = batch_id else {
+        return Ok(());
+    };
+    let on_failure: Option<Option<serde_json::Value>> = sqlx::query_scalar(AssertSqlSafe(format!(
+        "UPDATE sqlt_loco_batches SET failed = failed + 1, status = '{}', updated_at = \
+             CURRENT_TIMESTAMP WHERE id = $1 RETURNING CASE WHEN failed = 1 THEN on_failure END",
+        BatchStatus::Failed
+    )))
+    .bind(&batch_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(on_failure) = on_failure.flatten() {
+        debug!(batch_id = %batch_id, "Batch failed, enqueueing its callback");
+        insert_spec(conn, &serde_json::from_value(on_failure)?, None).await?;
+    }
+    Ok(())
+}
+
+async fn dequeue(
+    client: &SqlitePool,
+    worker_tags: &[String],
+    queues: &[String],
+    worker_opts: &HashMap<String, WorkerOpts>,
+) -> Result<Option<Job>> {
     // `BEGIN IMMEDIATE` takes SQLite's write lock up front, so the SELECT below
     // and the UPDATE that claims the row cannot interleave with another worker's
     // — two workers can never be handed the same job. A plain `BEGIN` defers the
@@ -286,7 +749,7 @@ async fn dequeue(client: &SqlitePool, worker_tags: &[String]) -> Result<Option<J
     let mut tx = client.begin_with("BEGIN IMMEDIATE").await?;
 
     let mut query = String::from(
-        "SELECT id, name, task_data, status, run_at, interval, tags, priority
+        "SELECT id, name, task_data, status, run_at, interval, tags, priority, attempts, last_error, history, unique_key, batch_id, chain, queue, args_version
         FROM sqlt_loco_queue
         WHERE
             status = ? AND
@@ -305,7 +768,59 @@ async fn dequeue(client: &SqlitePool, worker_tags: &[String]) -> Result<Option<J
         query.push(')');
     }
 
-    query.push_str(" ORDER BY priority DESC, run_at, id LIMIT 1");
+    // Hold back a limited class once it is at its cap or rate limit. The
+    // write lock taken above makes the counts hold across worker processes.
+    let mut limited_names = Vec::new();
+    for (name, opts) in worker_opts.iter().filter(|(_, opts)| opts.is_limited()) {
+        let mut over_limit = Vec::new();
+        if let Some(concurrency) = opts.concurrency {
+            over_limit.push(format!(
+                "(SELECT COUNT(*) FROM sqlt_loco_queue WHERE name = ? AND status = '{}') >= \
+                 {concurrency}",
+                JobStatus::Processing
+            ));
+            limited_names.push(name.as_str());
+        }
+        if let Some(rate_limit) = &opts.rate_limit {
+            over_limit.push(format!(
+                "(SELECT COUNT(*) FROM sqlt_loco_queue WHERE name = ? AND started_at > \
+                 STRFTIME('%Y-%m-%d %H:%M:%f', 'now', '-{} seconds')) >= {}",
+                rate_limit.period.as_secs_f64(),
+                rate_limit.limit
+            ));
+            limited_names.push(name.as_str());
+        }
+        let _ = write!(
+            query,
+            " AND NOT (name = ? AND ({}))",
+            over_limit.join(" OR ")
+        );
+        limited_names.push(name.as_str());
+    }
+
+    // Leave jobs stored at a newer args version than their worker knows for
+    // a worker that knows it.
+    query.push_str(
+        " AND NOT EXISTS (SELECT 1 FROM json_each(?) AS known WHERE known.key = \
+         sqlt_loco_queue.name AND sqlt_loco_queue.args_version > known.value)",
+    );
+
+    // Take jobs from the worker's queues only, in the order given: the names
+    // are bound once for the filter and once more for the ordering.
+    if queues.is_empty() {
+        query.push_str(" ORDER BY");
+    } else {
+        let _ = write!(
+            query,
+            " AND queue IN ({}) ORDER BY CASE queue {} END,",
+            vec!["?"; queues.len()].join(", "),
+            (0..queues.len())
+                .map(|idx| format!("WHEN ? THEN {idx}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+    }
+    query.push_str(" priority DESC, run_at, id LIMIT 1");
 
     let mut db_query = sqlx::query(AssertSqlSafe(query)).bind(JobStatus::Queued.to_string());
 
@@ -313,6 +828,13 @@ async fn dequeue(client: &SqlitePool, worker_tags: &[String]) -> Result<Option<J
         // The tags column is a JSON array; match the quoted element as a substring.
         db_query = db_query.bind(format!("%\"{tag}\"%"));
     }
+    for name in limited_names {
+        db_query = db_query.bind(name);
+    }
+    db_query = db_query.bind(super::sql::args_versions(worker_opts).to_string());
+    for queue in queues.iter().chain(queues) {
+        db_query = db_query.bind(queue);
+    }
 
     let row = db_query
         .map(|row: SqliteRow| to_job(&row).ok())
@@ -320,15 +842,22 @@ async fn dequeue(client: &SqlitePool, worker_tags: &[String]) -> Result<Option<J
         .await?
         .flatten();
 
-    if let Some(job) = row {
+    if let Some(mut job) = row {
         trace!(job_id = %job.id, job_name = %job.name, job_tags = ?job.tags, "Dequeueing job for processing");
         sqlx::query(
-            "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+            "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP, started_at \
+             = STRFTIME('%Y-%m-%d %H:%M:%f', 'now'), history = CASE WHEN attempts = 0 THEN '[]' \
+             ELSE history END, attempts = attempts + 1, progress = NULL, result = NULL, cancel_requested = FALSE WHERE id = \
+             $2",
         )
         .bind(JobStatus::Processing.to_string())
         .bind(&job.id)
         .execute(&mut *tx)
         .await?;
+        if job.attempts == 0 {
+            job.history.clear();
+        }
+        job.attempts += 1;
 
         tx.commit().await?;
         Ok(Some(job))
@@ -350,7 +879,7 @@ async fn complete_job(pool: &SqlitePool, id: &JobId, interval_ms: Option<i64>) -
         );
         sqlx::query(
             "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP, run_at = \
-             DATETIME($2) WHERE id = $3",
+             DATETIME($2), attempts = 0 WHERE id = $3",
         )
         .bind(JobStatus::Queued.to_string())
         .bind(next_run_at)
@@ -359,56 +888,183 @@ async fn complete_job(pool: &SqlitePool, id: &JobId, interval_ms: Option<i64>) -
         .await?;
     } else {
         trace!(job_id = %id, status = "completed", "Marking job as completed");
-        sqlx::query(
-            "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+        let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+        let row = sqlx::query(
+            "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2 \
+             RETURNING batch_id, chain, result",
         )
         .bind(JobStatus::Completed.to_string())
         .bind(id)
-        .execute(pool)
+        .fetch_optional(&mut *tx)
         .await?;
+        if let Some(row) = row {
+            let chain_json: Option<serde_json::Value> = row.try_get("chain")?;
+            let chain: Vec<ChainStep> = chain_json
+                .and_then(|chain| serde_json::from_value(chain).ok())
+                .unwrap_or_default();
+            enqueue_completion_follow_ups(
+                &mut tx,
+                row.try_get("batch_id")?,
+                &chain,
+                row.try_get("result")?,
+            )
+            .await?;
+        }
+        tx.commit().await?;
     }
     Ok(())
 }
 
 async fn fail_job(pool: &SqlitePool, id: &JobId, error: &crate::Error) -> Result<()> {
-    let msg = error.to_string();
-    debug!(job_id = %id, error = %msg, "Marking job as failed");
+    debug!(job_id = %id, error = %error, "Marking job as failed");
+    finish_with_error(pool, id, error, JobStatus::Failed).await
+}
+
+/// Marks a running job that stopped with `error` after it was asked to as
+/// [`JobStatus::Cancelled`]. To its batch, it counts as a failed member.
+async fn cancel_job(pool: &SqlitePool, id: &JobId, error: &crate::Error) -> Result<()> {
+    debug!(job_id = %id, error = %error, "Marking running job as cancelled");
+    finish_with_error(pool, id, error, JobStatus::Cancelled).await
+}
+
+/// Shared body of [`fail_job`] and [`cancel_job`].
+async fn finish_with_error(
+    pool: &SqlitePool,
+    id: &JobId,
+    error: &crate::Error,
+    status: JobStatus,
+) -> Result<()> {
+    let msg = super::error_message(error);
     let error_json = serde_json::json!({ "error": msg });
-    sqlx::query(
+    let mut tx = pool.begin_with("BEGIN IMMEDIATE").await?;
+    let batch_id: Option<Option<String>> = sqlx::query_scalar(
         "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP, task_data = \
-         json_patch(task_data, $2) WHERE id = $3",
+         json_patch(task_data, $2), last_error = $3 WHERE id = $4 RETURNING batch_id",
     )
-    .bind(JobStatus::Failed.to_string())
+    .bind(status.to_string())
     .bind(error_json)
+    .bind(&msg)
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?;
+    enqueue_failure_follow_ups(&mut tx, batch_id.flatten()).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Puts job `id`, interrupted by a worker shutdown, back in the queue, and
+/// gives back the attempt it was on, so the interruption does not count
+/// against its retries.
+async fn requeue_job(pool: &SqlitePool, id: &JobId) -> Result<()> {
+    debug!(job_id = %id, "Requeueing job interrupted by shutdown");
+    sqlx::query(
+        "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP, attempts = MAX(attempts - 1, 0) \
+         WHERE id = $2 AND status = $3",
+    )
+    .bind(JobStatus::Queued.to_string())
+    .bind(id)
+    .bind(JobStatus::Processing.to_string())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn retry_job(
+    pool: &SqlitePool,
+    id: &JobId,
+    run_at: DateTime<Utc>,
+    error: &crate::Error,
+) -> Result<()> {
+    debug!(job_id = %id, error = %error, run_at = %run_at, "Rescheduling failed job for retry");
+    sqlx::query(
+        "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP, run_at = \
+         DATETIME($2), last_error = $3 WHERE id = $4",
+    )
+    .bind(JobStatus::Queued.to_string())
+    .bind(run_at)
+    .bind(super::error_message(error))
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn record_attempt(pool: &SqlitePool, id: &JobId, attempt: &JobAttempt) -> Result<()> {
+    trace!(job_id = %id, attempt = attempt.attempt, "Recording job attempt");
+    sqlx::query(
+        "UPDATE sqlt_loco_queue SET history = json_insert(history, '$[#]', json($1)) WHERE id = $2",
+    )
+    .bind(serde_json::to_value(attempt)?)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn set_progress(pool: &SqlitePool, id: &JobId, percent: u8) -> Result<()> {
+    trace!(job_id = %id, percent, "Recording job progress");
+    sqlx::query(
+        "UPDATE sqlt_loco_queue SET progress = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+    )
+    .bind(i32::from(percent))
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn set_result(pool: &SqlitePool, id: &JobId, result: serde_json::Value) -> Result<()> {
+    trace!(job_id = %id, "Recording job result");
+    sqlx::query(
+        "UPDATE sqlt_loco_queue SET result = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+    )
+    .bind(result)
     .bind(id)
     .execute(pool)
     .await?;
     Ok(())
 }
 
+async fn cancel_requested(pool: &SqlitePool, id: &JobId) -> Result<bool> {
+    let requested: Option<bool> =
+        sqlx::query_scalar("SELECT cancel_requested FROM sqlt_loco_queue WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(requested.unwrap_or(false))
+}
+
 /// Cancels jobs in the `sqlt_loco_queue` table by their name.
 ///
 /// This function updates the status of all jobs with the given `name` and a status of
 /// [`JobStatus::Queued`] to [`JobStatus::Cancelled`]. The update also sets the `updated_at` timestamp to the
-/// current time.
+/// current time. Jobs with a status of [`JobStatus::Processing`] get their
+/// `cancel_requested` flag set instead, which their worker checks while running them.
 ///
 /// # Errors
 ///
 /// This function will return an error if it fails
 pub async fn cancel_jobs_by_name(pool: &SqlitePool, name: &str) -> Result<()> {
-    debug!(job_name = %name, "Cancelling queued jobs by name");
+    debug!(job_name = %name, "Cancelling jobs by name");
     sqlx::query(
-        "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP WHERE name = $2 \
-         AND status = $3",
+        "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP WHERE name = $2 AND status = $3",
     )
     .bind(JobStatus::Cancelled.to_string())
     .bind(name)
     .bind(JobStatus::Queued.to_string())
     .execute(pool)
     .await?;
-    Ok(())
-}
-
+    sqlx::query(
+        "UPDATE sqlt_loco_queue SET cancel_requested = TRUE, updated_at = CURRENT_TIMESTAMP WHERE name = $1 AND \
+         status = $2",
+    )
+    .bind(name)
+    .bind(JobStatus::Processing.to_string())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
 /// Clear all jobs
 ///
 /// # Errors
@@ -418,6 +1074,9 @@ pub async fn clear(pool: &SqlitePool) -> Result<()> {
     sqlx::query("DELETE FROM sqlt_loco_queue")
         .execute(pool)
         .await?;
+    sqlx::query("DELETE FROM sqlt_loco_batches")
+        .execute(pool)
+        .await?;
     Ok(())
 }
 
@@ -487,7 +1146,7 @@ pub async fn retry_failed(pool: &SqlitePool, id: Option<&str>) -> Result<u64> {
         debug!(job_id = %id, "Retrying failed job");
         sqlx::query(
             "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP, run_at = \
-             CURRENT_TIMESTAMP WHERE status = $2 AND id = $3",
+             CURRENT_TIMESTAMP, attempts = 0 WHERE status = $2 AND id = $3",
         )
         .bind(JobStatus::Queued.to_string())
         .bind(JobStatus::Failed.to_string())
@@ -498,7 +1157,7 @@ pub async fn retry_failed(pool: &SqlitePool, id: Option<&str>) -> Result<u64> {
         debug!("Retrying every failed job");
         sqlx::query(
             "UPDATE sqlt_loco_queue SET status = $1, updated_at = CURRENT_TIMESTAMP, run_at = \
-             CURRENT_TIMESTAMP WHERE status = $2",
+             CURRENT_TIMESTAMP, attempts = 0 WHERE status = $2",
         )
         .bind(JobStatus::Queued.to_string())
         .bind(JobStatus::Failed.to_string())
@@ -559,28 +1218,70 @@ pub async fn ping(pool: &SqlitePool) -> Result<()> {
     super::sql::ping(pool, "sqlt_loco_queue").await
 }
 
+/// Retrieves the job with the given `id`, if any.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn get_job(pool: &SqlitePool, id: &str) -> Result<Option<Job>> {
+    let row = sqlx::query("SELECT * FROM sqlt_loco_queue WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    row.map(|row| to_job(&row)).transpose()
+}
+
+/// Retrieves every batch, newest first.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn get_batches(pool: &SqlitePool) -> Result<Vec<JobBatch>> {
+    let rows = sqlx::query("SELECT * FROM sqlt_loco_batches ORDER BY created_at DESC, id DESC")
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(to_batch).collect()
+}
+
 /// Builds the [`SqliteQueue`] provider (pool, registry, run options, token)
 /// from config. Factored out of [`create_provider`] so tests can inspect the
 /// resulting `run_opts` without needing to downcast the opaque [`Queue`].
 async fn build_provider(qcfg: &SqliteQueueConfig) -> Result<SqliteQueue> {
+    if qcfg.use_app_db {
+        return Err(Error::string(
+            "a queue with `use_app_db` needs the application's database connection",
+        ));
+    }
+    let pool = connect(qcfg).await.map_err(Box::from)?;
+    Ok(build_provider_on(qcfg, pool))
+}
+
+/// Builds the provider around an existing `pool`.
+fn build_provider_on(qcfg: &SqliteQueueConfig, pool: SqlitePool) -> SqliteQueue {
     debug!(
         num_workers = qcfg.num_workers,
         poll_interval = qcfg.poll_interval_sec,
         "Creating job queue provider"
     );
-    let pool = connect(qcfg).await.map_err(Box::from)?;
     let registry = JobRegistry::new();
     let token = CancellationToken::new();
-    Ok(SqliteQueue {
+    SqliteQueue {
         pool,
         registry: Arc::new(tokio::sync::Mutex::new(registry)),
         run_opts: RunOpts {
             num_workers: qcfg.num_workers,
             poll_interval_sec: qcfg.poll_interval_sec,
+            queues: qcfg
+                .queues
+                .iter()
+                .map(|(queue, weight)| (queue.clone(), *weight))
+                .collect(),
             reaper: qcfg.reaper.clone(),
+            shutdown_timeout: Duration::from_secs(qcfg.shutdown_timeout_sec),
         },
         token,
-    })
+        limits: qcfg.limits.clone(),
+    }
 }
 
 /// Create this provider
@@ -592,6 +1293,28 @@ pub async fn create_provider(qcfg: &SqliteQueueConfig) -> Result<Queue> {
     Ok(Queue::from_provider(Arc::new(build_provider(qcfg).await?)))
 }
 
+/// Create this provider on the application's database, sharing its pool, for
+/// a queue with `use_app_db`.
+///
+/// # Errors
+///
+/// This function will return an error if `db` is not a `SQLite` database
+#[cfg(feature = "with-db")]
+pub fn create_provider_with_db(
+    qcfg: &SqliteQueueConfig,
+    db: &sea_orm::DatabaseConnection,
+) -> Result<Queue> {
+    if db.get_database_backend() != sea_orm::DbBackend::Sqlite {
+        return Err(Error::string(
+            "a SQLite queue with `use_app_db` needs a SQLite application database",
+        ));
+    }
+    let pool = db.get_sqlite_connection_pool().clone();
+    Ok(Queue::from_provider(Arc::new(build_provider_on(
+        qcfg, pool,
+    ))))
+}
+
 /// Retrieves a list of jobs from the `sqlt_loco_queue` table in the database.
 ///
 /// This function queries the database for jobs, optionally filtering by their
@@ -633,6 +1356,95 @@ pub async fn get_jobs(
     Ok(jobs)
 }
 
+/// Retrieves the newest jobs passing `filter` from the `sqlt_loco_queue` table,
+/// filtered and limited in the query.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn list_jobs(pool: &SqlitePool, filter: &JobFilter) -> Result<Vec<Job>> {
+    let mut query = QueryBuilder::<sqlx::Sqlite>::new("SELECT * FROM sqlt_loco_queue WHERE 1 = 1");
+    if !filter.statuses.is_empty() {
+        query.push(" AND status IN (");
+        let mut statuses = query.separated(", ");
+        for status in &filter.statuses {
+            statuses.push_bind(status.to_string());
+        }
+        statuses.push_unseparated(")");
+    }
+    if let Some(name) = &filter.name {
+        query.push(" AND name = ").push_bind(name.clone());
+    }
+    if let Some(tag) = &filter.tag {
+        query
+            .push(" AND EXISTS (SELECT 1 FROM json_each(tags) WHERE json_each.value = ")
+            .push_bind(tag.clone())
+            .push(")");
+    }
+    if let Some(since) = filter.since {
+        query
+            .push(" AND unixepoch(updated_at, 'subsec') >= unixepoch(")
+            .push_bind(since)
+            .push(", 'subsec')");
+    }
+    query.push(" ORDER BY created_at DESC, id DESC");
+    if let Some(limit) = filter.limit {
+        query
+            .push(" LIMIT ")
+            .push_bind(i64::try_from(limit).unwrap_or(i64::MAX));
+    }
+
+    debug!(filter = ?filter, "Listing jobs");
+    let rows = query.build().fetch_all(pool).await?;
+    Ok(rows.iter().filter_map(|row| to_job(row).ok()).collect())
+}
+
+/// Counts the jobs in the `sqlt_loco_queue` table per status.
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn count_by_status(pool: &SqlitePool) -> Result<HashMap<JobStatus, u64>> {
+    let rows: Vec<(String, i64)> =
+        sqlx::query_as("SELECT status, COUNT(*) FROM sqlt_loco_queue GROUP BY status")
+            .fetch_all(pool)
+            .await?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(status, count)| Some((status.parse().ok()?, u64::try_from(count).ok()?)))
+        .collect())
+}
+
+/// Computes the queue's metrics with `GROUP BY` queries instead of loading
+/// every job. See [`super::stats::QueueStats`].
+///
+/// # Errors
+///
+/// This function will return an error if it fails
+pub async fn stats(pool: &SqlitePool) -> Result<super::stats::QueueStats> {
+    let counts: Vec<(String, String, String, i64, Option<i64>)> = sqlx::query_as(
+        "SELECT queue, name, status, COUNT(*), CAST(unixepoch('now', 'subsec') - MIN(CASE WHEN status = 'queued' AND unixepoch(run_at, 'subsec') <= unixepoch('now', 'subsec') THEN unixepoch(run_at, 'subsec') END) AS INTEGER) FROM sqlt_loco_queue GROUP BY queue, name, \
+         status",
+    )
+    .fetch_all(pool)
+    .await?;
+    let attempts = sqlx::query(AssertSqlSafe(super::sql::attempt_stats_query(
+        "SELECT name AS class, json_extract(attempt.value, '$.error') IS NOT NULL AS failed, \
+         MAX(0, CAST(ROUND((unixepoch(json_extract(attempt.value, '$.finished_at'), 'subsec') - \
+         unixepoch(json_extract(attempt.value, '$.started_at'), 'subsec')) * 1000) AS INTEGER)) AS \
+         duration_ms FROM sqlt_loco_queue, json_each(history) AS attempt",
+    )))
+    .fetch_all(pool)
+    .await?;
+    Ok(super::sql::to_queue_stats(
+        counts,
+        attempts
+            .iter()
+            .map(super::sql::to_attempt_summary)
+            .collect(),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -645,7 +1457,10 @@ mod tests {
     use tokio::time::sleep;
 
     use super::*;
-    use crate::{bgworker::BackgroundWorker, tests_cfg};
+    use crate::{
+        bgworker::{BackgroundWorker, Backoff, JobInfo},
+        tests_cfg,
+    };
 
     #[derive(Debug, Serialize, FromRow)]
     pub struct TableInfo {
@@ -681,6 +1496,7 @@ mod tests {
                 "sqlite://{}?mode=rwc",
                 db_path.join("sample.sqlite").display()
             ),
+            use_app_db: false,
             dangerously_flush: false,
             enable_logging: false,
             max_connections: 1,
@@ -690,6 +1506,9 @@ mod tests {
             poll_interval_sec: 1,
             num_workers: 1,
             reaper: None,
+            queues: Default::default(),
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         }
     }
 
@@ -761,10 +1580,12 @@ mod tests {
             &pool,
             "PasswordChangeNotification",
             job_data,
-            run_at,
             None,
-            tags,
-            None
+            &EnqueueOptions {
+                tags,
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -804,10 +1625,11 @@ mod tests {
             &pool,
             "PasswordChangeNotification",
             job_data,
-            run_at,
             None,
-            None,
-            None
+            &EnqueueOptions {
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            }
         )
         .await
         .is_ok());
@@ -821,7 +1643,7 @@ mod tests {
 
         std::thread::sleep(std::time::Duration::from_secs(1));
 
-        assert!(dequeue(&pool, &[]).await.is_ok());
+        assert!(dequeue(&pool, &[], &[], &HashMap::new()).await.is_ok());
 
         let job_after_dequeue = get_all_jobs(&pool)
             .await
@@ -1010,10 +1832,8 @@ mod tests {
                 &pool,
                 &format!("Job{i}"),
                 serde_json::json!({}),
-                Utc::now(),
-                None,
-                None,
                 None,
+                &EnqueueOptions::default(),
             )
             .await
             .expect("enqueue");
@@ -1021,9 +1841,11 @@ mod tests {
 
         // More pollers than jobs, so every job is contended and the tail of the
         // run also proves an empty queue stays empty.
-        let claimed = futures_util::future::join_all(
-            (0..JOBS * 2).map(|_| async { dequeue(&pool, &[]).await.expect("dequeue") }),
-        )
+        let claimed = futures_util::future::join_all((0..JOBS * 2).map(|_| async {
+            dequeue(&pool, &[], &[], &HashMap::new())
+                .await
+                .expect("dequeue")
+        }))
         .await;
 
         let mut ids: Vec<String> = claimed.into_iter().flatten().map(|j| j.id).collect();
@@ -1052,15 +1874,15 @@ mod tests {
             &pool,
             "PostCleanupJob",
             serde_json::json!({}),
-            Utc::now(),
-            None,
-            None,
             None,
+            &EnqueueOptions::default(),
         )
         .await
         .expect("enqueue");
 
-        let job = dequeue(&pool, &[]).await.expect("dequeue");
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue");
         assert_eq!(
             job.map(|j| j.name),
             Some("PostCleanupJob".to_string()),
@@ -1209,6 +2031,176 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn can_list_and_count_jobs_in_the_query() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+        assert!(initialize_database(&pool).await.is_ok());
+
+        sqlx::query(
+            r#"INSERT INTO sqlt_loco_queue (id, name, task_data, status, run_at, created_at, updated_at, tags) VALUES
+            ('job1', 'Mailer', '{}', 'failed', CURRENT_TIMESTAMP, DATETIME('now', '-3 days'), CURRENT_TIMESTAMP, '["email"]'),
+            ('job2', 'Mailer', '{}', 'failed', CURRENT_TIMESTAMP, DATETIME('now', '-2 days'), CURRENT_TIMESTAMP, '["email","urgent"]'),
+            ('job3', 'Mailer', '{}', 'failed', CURRENT_TIMESTAMP, DATETIME('now', '-1 days'), CURRENT_TIMESTAMP, NULL),
+            ('job4', 'Report', '{}', 'queued', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, '["email"]')"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let ids = |jobs: Vec<Job>| jobs.into_iter().map(|job| job.id).collect::<Vec<_>>();
+        let failed_email = JobFilter {
+            statuses: vec![JobStatus::Failed],
+            tag: Some("email".to_string()),
+            ..JobFilter::default()
+        };
+        assert_eq!(
+            ids(list_jobs(&pool, &failed_email).await.expect("list jobs")),
+            ["job2", "job1"]
+        );
+        assert_eq!(
+            ids(list_jobs(
+                &pool,
+                &JobFilter {
+                    limit: Some(2),
+                    ..JobFilter::default()
+                }
+            )
+            .await
+            .expect("list jobs")),
+            ["job4", "job3"]
+        );
+        assert_eq!(
+            ids(list_jobs(
+                &pool,
+                &JobFilter {
+                    statuses: vec![JobStatus::Failed, JobStatus::Queued],
+                    name: Some("Mailer".to_string()),
+                    since: Some(Utc::now() - chrono::Duration::hours(1)),
+                    ..JobFilter::default()
+                }
+            )
+            .await
+            .expect("list jobs")),
+            ["job3", "job2", "job1"]
+        );
+        assert!(list_jobs(
+            &pool,
+            &JobFilter {
+                since: Some(Utc::now() + chrono::Duration::hours(1)),
+                ..JobFilter::default()
+            }
+        )
+        .await
+        .expect("list jobs")
+        .is_empty());
+
+        let counts = count_by_status(&pool).await.expect("count jobs");
+        assert_eq!(counts.get(&JobStatus::Failed), Some(&3));
+        assert_eq!(counts.get(&JobStatus::Queued), Some(&1));
+        assert_eq!(counts.get(&JobStatus::Completed), None);
+    }
+
+    #[tokio::test]
+    async fn can_compute_stats_in_the_query() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let attempt = |number: i32, started_at: &str, finished_at: &str, error: Option<&str>| {
+            serde_json::json!({
+                "attempt": number,
+                "started_at": started_at,
+                "finished_at": finished_at,
+                "error": error,
+            })
+        };
+        let history =
+            |attempts: Vec<serde_json::Value>| serde_json::Value::from(attempts).to_string();
+        let mailer_history = history(vec![
+            attempt(
+                1,
+                "2024-01-01T00:00:00.000Z",
+                "2024-01-01T00:00:00.020Z",
+                Some("boom"),
+            ),
+            attempt(
+                2,
+                "2024-01-01T00:01:00.000Z",
+                "2024-01-01T00:01:00.800Z",
+                None,
+            ),
+        ]);
+        let report_history = history(vec![attempt(
+            1,
+            "2024-01-01T00:00:00.000Z",
+            "2024-01-01T00:07:00.000Z",
+            None,
+        )]);
+
+        sqlx::query(
+            r"INSERT INTO sqlt_loco_queue (id, name, task_data, status, run_at, created_at, updated_at, queue, history) VALUES
+            ('job1', 'Mailer', '{}', 'queued', DATETIME('now', '-300 seconds'), CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, 'mailers', '[]'),
+            ('job2', 'Mailer', '{}', 'queued', DATETIME('now', '-60 seconds'), CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, 'default', '[]'),
+            ('job3', 'Mailer', '{}', 'queued', DATETIME('now', '+1 hour'), CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, 'mailers', '[]'),
+            ('job4', 'Mailer', '{}', 'completed', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, 'mailers', ?),
+            ('job5', 'Report', '{}', 'failed', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, 'default', ?),
+            ('job6', 'Report', '{}', 'processing', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, 'default', '[]')",
+        )
+        .bind(&mailer_history)
+        .bind(&report_history)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut stats = stats(&pool).await.expect("compute stats");
+        let jobs = get_jobs(&pool, None, None).await.expect("get jobs");
+        let mut expected = super::super::stats::QueueStats::from_jobs(
+            jobs.iter()
+                .map(|job| (job.queue.as_deref().unwrap_or(DEFAULT_QUEUE), job)),
+            stats.generated_at,
+        );
+
+        let ages = |stats: &mut super::super::stats::QueueStats| {
+            let queues = stats
+                .queues
+                .iter_mut()
+                .map(|depth| depth.oldest_queued_age_secs.take())
+                .collect::<Vec<_>>();
+            let workers = stats
+                .workers
+                .values_mut()
+                .map(|worker| worker.oldest_queued_age_secs.take())
+                .collect::<Vec<_>>();
+            (queues, workers)
+        };
+        assert_eq!(
+            ages(&mut stats),
+            (vec![Some(60), Some(300)], vec![Some(300), None])
+        );
+        ages(&mut expected);
+        assert_eq!(stats, expected);
+
+        let mailer = &stats.workers["Mailer"];
+        assert_eq!((mailer.queued, mailer.completed), (3, 1));
+        assert_eq!((mailer.succeeded_attempts, mailer.failed_attempts), (1, 1));
+        assert_eq!(
+            (
+                mailer.duration.sum_ms,
+                mailer.duration.p50_ms,
+                mailer.duration.p95_ms
+            ),
+            (820, Some(20), Some(800))
+        );
+        assert_eq!(stats.workers["Report"].duration.max_ms, Some(420_000));
+    }
+
     #[tokio::test]
     async fn can_get_jobs_with_age() {
         let tree_fs = tree_fs::TreeBuilder::default()
@@ -1382,6 +2374,7 @@ mod tests {
                 "sqlite://{}?mode=rwc",
                 tree_fs.root.join("reaper.sqlite").display()
             ),
+            use_app_db: false,
             dangerously_flush: false,
             enable_logging: false,
             max_connections: 1,
@@ -1394,6 +2387,9 @@ mod tests {
                 age_minutes: 5,
                 interval_seconds: 30,
             }),
+            queues: Default::default(),
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         };
 
         let provider = build_provider(&qcfg).await.expect("build provider");
@@ -1416,6 +2412,7 @@ mod tests {
                 "sqlite://{}?mode=rwc",
                 tree_fs.root.join("no_reaper.sqlite").display()
             ),
+            use_app_db: false,
             dangerously_flush: false,
             enable_logging: false,
             max_connections: 1,
@@ -1425,12 +2422,65 @@ mod tests {
             poll_interval_sec: 1,
             num_workers: 1,
             reaper: None,
+            queues: Default::default(),
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         };
 
         let provider = build_provider(&qcfg).await.expect("build provider");
         assert!(provider.run_opts.reaper.is_none());
     }
 
+    #[tokio::test]
+    async fn scheduled_job_waits_for_run_at() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let provider = build_provider(&config(&tree_fs.root))
+            .await
+            .expect("build provider");
+        assert!(initialize_database(&provider.pool).await.is_ok());
+
+        let later = provider
+            .enqueue(
+                "Reminder".to_string(),
+                serde_json::json!({"user_id": 1}),
+                EnqueueOptions {
+                    run_at: Some(Utc::now() + chrono::Duration::hours(24)),
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .expect("enqueue")
+            .expect("job id");
+
+        assert!(dequeue(&provider.pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .is_none());
+
+        let job = get_job(&provider.pool, &later).await;
+        assert_eq!(job.status, JobStatus::Queued);
+        assert!(job.run_at > Utc::now() + chrono::Duration::hours(23));
+
+        let now = provider
+            .enqueue(
+                "Reminder".to_string(),
+                serde_json::json!({"user_id": 2}),
+                EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue")
+            .expect("job id");
+
+        let job = dequeue(&provider.pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("the unscheduled job is due");
+        assert_eq!(job.id, now);
+    }
+
     #[tokio::test]
     async fn can_dequeue_with_priority_ordering() {
         let tree_fs = tree_fs::TreeBuilder::default()
@@ -1451,10 +2501,12 @@ mod tests {
             &pool,
             "Task1",
             serde_json::json!({"task": "low_priority_late"}),
-            run_at_1,
             None,
-            None,
-            Some(10),
+            &EnqueueOptions {
+                priority: Some(10),
+                run_at: Some(run_at_1),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("enqueue job 1");
@@ -1465,10 +2517,12 @@ mod tests {
             &pool,
             "Task2",
             serde_json::json!({"task": "high_priority_late"}),
-            run_at_2,
-            None,
             None,
-            Some(20),
+            &EnqueueOptions {
+                priority: Some(20),
+                run_at: Some(run_at_2),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("enqueue job 2");
@@ -1479,10 +2533,12 @@ mod tests {
             &pool,
             "Task3",
             serde_json::json!({"task": "low_priority_early"}),
-            run_at_3,
             None,
-            None,
-            Some(10),
+            &EnqueueOptions {
+                priority: Some(10),
+                run_at: Some(run_at_3),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("enqueue job 3");
@@ -1493,10 +2549,12 @@ mod tests {
             &pool,
             "Task4",
             serde_json::json!({"task": "lowest_priority_early"}),
-            run_at_4,
-            None,
             None,
-            Some(5),
+            &EnqueueOptions {
+                priority: Some(5),
+                run_at: Some(run_at_4),
+                ..EnqueueOptions::default()
+            },
         )
         .await
         .expect("enqueue job 4");
@@ -1509,7 +2567,7 @@ mod tests {
             (&job_id_1, 10),
             (&job_id_4, 5),
         ] {
-            let job = dequeue(&pool, &[])
+            let job = dequeue(&pool, &[], &[], &HashMap::new())
                 .await
                 .expect("dequeue failed")
                 .expect("expected a job");
@@ -1521,7 +2579,9 @@ mod tests {
         }
 
         // No more jobs
-        let job = dequeue(&pool, &[]).await.expect("dequeue failed");
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue failed");
         assert!(job.is_none());
     }
 
@@ -1536,9 +2596,15 @@ mod tests {
         assert!(initialize_database(&pool).await.is_ok());
 
         let job_data = serde_json::json!(null);
-        let job_id = enqueue(&pool, "PanicJob", job_data, Utc::now(), None, None, None)
-            .await
-            .expect("Failed to enqueue job");
+        let job_id = enqueue(
+            &pool,
+            "PanicJob",
+            job_data,
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
 
         struct PanicWorker;
         #[async_trait::async_trait]
@@ -1554,7 +2620,7 @@ mod tests {
         let mut registry = JobRegistry::new();
         let handler = crate::bgworker::erase_worker(PanicWorker);
         assert!(registry
-            .insert_handler("PanicJob".to_string(), handler)
+            .insert_handler("PanicJob".to_string(), handler, WorkerOpts::default())
             .is_ok());
 
         // Get the initial job state
@@ -1565,7 +2631,9 @@ mod tests {
         let opts = RunOpts {
             num_workers: 1,
             poll_interval_sec: 1,
+            queues: Vec::new(),
             reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
         };
         let token = CancellationToken::new();
         let handles = registry.run::<SqliteDriver>(&pool, &opts, &token, &[]);
@@ -1594,10 +2662,25 @@ mod tests {
             error_msg.contains("intentional panic for testing"),
             "Error message '{error_msg}' did not contain expected text"
         );
+        assert_eq!(
+            failed_job.last_error.as_deref(),
+            Some("worker panicked: intentional panic for testing")
+        );
+        assert_eq!(failed_job.history.len(), 1);
+        // The panic's backtrace, when backtraces are on, points at the worker.
+        let captured = std::backtrace::Backtrace::capture().status()
+            == std::backtrace::BacktraceStatus::Captured;
+        assert_eq!(
+            failed_job.history[0]
+                .backtrace
+                .as_deref()
+                .is_some_and(|backtrace| backtrace.contains("sqlt.rs")),
+            captured
+        );
     }
 
     #[tokio::test]
-    async fn can_dequeue_with_tags() {
+    async fn shutdown_lets_running_jobs_finish_until_the_deadline() {
         let tree_fs = tree_fs::TreeBuilder::default()
             .drop(true)
             .create()
@@ -1606,71 +2689,454 @@ mod tests {
 
         assert!(initialize_database(&pool).await.is_ok());
 
-        // Add a job with email tag
-        let run_at = Utc::now() - chrono::Duration::minutes(5); // In the past so it's ready to process
-        let job_data = serde_json::json!({"user_id": 1});
-        let email_tags = Some(vec!["email".to_string()]);
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct SleepArgs {
+            millis: u64,
+        }
 
-        // Insert email job
-        let email_id = enqueue(
-            &pool,
-            "EmailNotification",
-            job_data.clone(),
-            run_at,
-            None,
-            email_tags,
-            None,
-        )
-        .await
-        .expect("Failed to enqueue email job");
+        struct SleepWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<SleepArgs> for SleepWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, args: SleepArgs) -> crate::Result<()> {
+                sleep(Duration::from_millis(args.millis)).await;
+                Ok(())
+            }
+        }
 
-        // Insert job with "sms" tag
-        let sms_tags = Some(vec!["sms".to_string()]);
-        let sms_id = enqueue(
+        let mut registry = JobRegistry::new();
+        let handler = crate::bgworker::erase_worker(SleepWorker);
+        assert!(registry
+            .insert_handler("SleepJob".to_string(), handler, WorkerOpts::default())
+            .is_ok());
+
+        let short = enqueue(
             &pool,
-            "SmsNotification",
-            job_data.clone(),
-            run_at,
-            None,
-            sms_tags,
+            "SleepJob",
+            serde_json::json!({ "millis": 300 }),
             None,
+            &EnqueueOptions::default(),
         )
         .await
-        .expect("Failed to enqueue sms job");
-
-        // Insert job with multiple tags
-        let multi_tags = Some(vec!["email".to_string(), "priority".to_string()]);
-        let multi_id = enqueue(
+        .expect("Failed to enqueue job");
+        let long = enqueue(
             &pool,
-            "PriorityEmail",
-            job_data.clone(),
-            run_at,
-            None,
-            multi_tags,
+            "SleepJob",
+            serde_json::json!({ "millis": 60_000 }),
             None,
+            &EnqueueOptions::default(),
         )
         .await
-        .expect("Failed to enqueue multi-tag job");
+        .expect("Failed to enqueue job");
 
-        // Insert job with no tags
-        let no_tag_id = enqueue(
+        let opts = RunOpts {
+            num_workers: 2,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(1),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<SqliteDriver>(&pool, &opts, &token, &[]);
+
+        // Both jobs are running when the shutdown starts.
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(get_job(&pool, &long).await.status, JobStatus::Processing);
+        token.cancel();
+
+        let drained = tokio::time::timeout(Duration::from_secs(10), async {
+            for handle in handles {
+                handle.await.expect("worker task");
+            }
+        })
+        .await;
+        assert!(drained.is_ok(), "workers should stop at the deadline");
+
+        // The short one finished in time, the long one went back to the
+        // queue without using up an attempt.
+        assert_eq!(get_job(&pool, &short).await.status, JobStatus::Completed);
+        let requeued = get_job(&pool, &long).await;
+        assert_eq!(requeued.status, JobStatus::Queued);
+        assert_eq!(requeued.attempts, 0);
+        assert!(requeued.history.is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_retry_failing_job_until_max_retries() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let job_id = enqueue(
             &pool,
-            "GenericNotification",
-            job_data.clone(),
-            run_at,
-            None,
-            None,
+            "FailingJob",
+            serde_json::json!(null),
             None,
+            &EnqueueOptions::default(),
         )
         .await
-        .expect("Failed to enqueue untagged job");
+        .expect("Failed to enqueue job");
+
+        struct FailingWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for FailingWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                Err(crate::Error::string("always fails"))
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        let handler = crate::bgworker::erase_worker(FailingWorker);
+        let worker_opts = WorkerOpts {
+            max_retries: 2,
+            backoff: Backoff::Fixed(Duration::ZERO),
+            ..Default::default()
+        };
+        assert!(registry
+            .insert_handler("FailingJob".to_string(), handler, worker_opts)
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<SqliteDriver>(&pool, &opts, &token, &[]);
+
+        sleep(Duration::from_secs(3)).await;
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        // One initial attempt plus two retries, then the job is given up on.
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.attempts, 3);
+        assert_eq!(job.last_error.as_deref(), Some("always fails"));
+        assert_eq!(
+            job.history
+                .iter()
+                .map(|attempt| (attempt.attempt, attempt.error.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, Some("always fails")),
+                (2, Some("always fails")),
+                (3, Some("always fails"))
+            ]
+        );
+
+        // A manual retry starts the attempt count over.
+        assert_eq!(retry_failed(&pool, Some(&job_id)).await.expect("retry"), 1);
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Queued);
+        assert_eq!(job.attempts, 0);
+    }
+
+    #[cfg(feature = "with-db")]
+    #[tokio::test]
+    async fn job_enqueued_in_a_transaction_exists_only_once_it_commits() {
+        use sea_orm::TransactionTrait;
+
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+        assert!(initialize_database(&pool).await.is_ok());
+        let db = sea_orm::Database::connect(config(&tree_fs.root).uri)
+            .await
+            .expect("connect sea-orm");
+
+        let txn = db.begin().await.expect("begin");
+        let rolled_back = enqueue_in_transaction(
+            &txn,
+            "UserAccountActivation",
+            serde_json::json!({"user_id": 1}),
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("enqueue in transaction");
+        txn.rollback().await.expect("rollback");
+        assert!(super::get_job(&pool, &rolled_back)
+            .await
+            .expect("get job")
+            .is_none());
+
+        let unique = UniqueJob {
+            key: "UserAccountActivation:2".to_string(),
+            window: UniqueWindow::Queued,
+        };
+        let txn = db.begin().await.expect("begin");
+        let committed = enqueue_in_transaction(
+            &txn,
+            "UserAccountActivation",
+            serde_json::json!({"user_id": 2}),
+            &EnqueueOptions {
+                tags: Some(vec!["email".to_string()]),
+                priority: Some(3),
+                unique: Some(unique.clone()),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue in transaction");
+        let duplicate = enqueue_in_transaction(
+            &txn,
+            "UserAccountActivation",
+            serde_json::json!({"user_id": 2}),
+            &EnqueueOptions {
+                unique: Some(unique.clone()),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("enqueue duplicate in transaction");
+        assert_eq!(duplicate, committed);
+        txn.commit().await.expect("commit");
+
+        let job = get_job(&pool, &committed).await;
+        assert_eq!(job.status, JobStatus::Queued);
+        assert_eq!(job.data, serde_json::json!({"user_id": 2}));
+        assert_eq!(job.tags, Some(vec!["email".to_string()]));
+        assert_eq!(job.priority, 3);
+        assert_eq!(get_all_jobs(&pool).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn job_over_its_timeout_is_recorded_as_failed() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let job_id = enqueue(
+            &pool,
+            "SlowJob",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        struct SlowWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for SlowWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            fn timeout() -> Option<Duration> {
+                Some(Duration::from_millis(200))
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                sleep(Duration::from_secs(30)).await;
+                Ok(())
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "SlowJob".to_string(),
+                crate::bgworker::erase_worker(SlowWorker),
+                WorkerOpts::of::<(), SlowWorker>(),
+            )
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<SqliteDriver>(&pool, &opts, &token, &[]);
+
+        sleep(Duration::from_secs(2)).await;
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.last_error.as_deref(), Some("job timed out after 200ms"));
+    }
+
+    #[tokio::test]
+    async fn cancel_jobs_by_name_signals_a_running_job() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let job_id = enqueue(
+            &pool,
+            "StoppableJob",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        struct StoppableWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for StoppableWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            fn max_retries() -> u32 {
+                3
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                crate::bgworker::cancellation_token().cancelled().await;
+                Err(crate::Error::string("stopped on request"))
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "StoppableJob".to_string(),
+                crate::bgworker::erase_worker(StoppableWorker),
+                WorkerOpts::of::<(), StoppableWorker>(),
+            )
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<SqliteDriver>(&pool, &opts, &token, &[]);
+
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(get_job(&pool, &job_id).await.status, JobStatus::Processing);
+
+        cancel_jobs_by_name(&pool, "StoppableJob")
+            .await
+            .expect("cancel jobs");
+        sleep(Duration::from_secs(2)).await;
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        // Cancelled rather than retried, even though retries were left.
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!(job.status, JobStatus::Cancelled);
+        assert_eq!(job.attempts, 1);
+        assert_eq!(job.last_error.as_deref(), Some("stopped on request"));
+    }
+
+    #[tokio::test]
+    async fn can_dequeue_with_tags() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        // Add a job with email tag
+        let run_at = Utc::now() - chrono::Duration::minutes(5); // In the past so it's ready to process
+        let job_data = serde_json::json!({"user_id": 1});
+        let email_tags = Some(vec!["email".to_string()]);
+
+        // Insert email job
+        let email_id = enqueue(
+            &pool,
+            "EmailNotification",
+            job_data.clone(),
+            None,
+            &EnqueueOptions {
+                tags: email_tags,
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("Failed to enqueue email job");
+
+        // Insert job with "sms" tag
+        let sms_tags = Some(vec!["sms".to_string()]);
+        let sms_id = enqueue(
+            &pool,
+            "SmsNotification",
+            job_data.clone(),
+            None,
+            &EnqueueOptions {
+                tags: sms_tags,
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("Failed to enqueue sms job");
+
+        // Insert job with multiple tags
+        let multi_tags = Some(vec!["email".to_string(), "priority".to_string()]);
+        let multi_id = enqueue(
+            &pool,
+            "PriorityEmail",
+            job_data.clone(),
+            None,
+            &EnqueueOptions {
+                tags: multi_tags,
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("Failed to enqueue multi-tag job");
+
+        // Insert job with no tags
+        let no_tag_id = enqueue(
+            &pool,
+            "GenericNotification",
+            job_data.clone(),
+            None,
+            &EnqueueOptions {
+                run_at: Some(run_at),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("Failed to enqueue untagged job");
 
         // Verify all jobs are in the database
         let all_jobs = get_all_jobs(&pool).await;
         assert_eq!(all_jobs.len(), 4);
 
         // 1. Worker with no tags should only get untagged jobs
-        let job = dequeue(&pool, &[]).await.expect("dequeue failed");
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue failed");
         assert!(job.is_some());
         let job = job.unwrap();
         assert_eq!(job.id, no_tag_id);
@@ -1682,7 +3148,7 @@ mod tests {
             .expect("Failed to complete job");
 
         // 2. Worker with "email" tag should get one of the email-tagged jobs
-        let job = dequeue(&pool, &["email".to_string()])
+        let job = dequeue(&pool, &["email".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_some());
@@ -1700,7 +3166,7 @@ mod tests {
             .expect("Failed to complete job");
 
         // 3. Worker with "email" tag should get the remaining email job
-        let job = dequeue(&pool, &["email".to_string()])
+        let job = dequeue(&pool, &["email".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_some());
@@ -1718,7 +3184,7 @@ mod tests {
             .expect("Failed to complete job");
 
         // 4. Worker with "sms" tag should get the sms job
-        let job = dequeue(&pool, &["sms".to_string()])
+        let job = dequeue(&pool, &["sms".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_some());
@@ -1733,13 +3199,552 @@ mod tests {
             .expect("Failed to complete job");
 
         // 5. No more jobs should be available
-        let job = dequeue(&pool, &["email".to_string()])
+        let job = dequeue(&pool, &["email".to_string()], &[], &HashMap::new())
             .await
             .expect("dequeue failed");
         assert!(job.is_none());
 
         // 6. No more jobs should be available for untagged worker
-        let job = dequeue(&pool, &[]).await.expect("dequeue failed");
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue failed");
         assert!(job.is_none());
     }
+
+    #[tokio::test]
+    async fn jobs_with_newer_args_wait_for_a_worker_that_knows_them() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let job_id = enqueue(
+            &pool,
+            "VersionedJob",
+            serde_json::json!({"emails": []}),
+            None,
+            &EnqueueOptions {
+                args_version: 3,
+                run_at: Some(Utc::now() - chrono::Duration::minutes(5)),
+                ..EnqueueOptions::default()
+            },
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        let opts = |args_version| {
+            HashMap::from([(
+                "VersionedJob".to_string(),
+                WorkerOpts {
+                    args_version,
+                    ..WorkerOpts::default()
+                },
+            )])
+        };
+        assert!(dequeue(&pool, &[], &[], &opts(2))
+            .await
+            .expect("dequeue")
+            .is_none());
+        let job = get_job(&pool, &job_id).await;
+        assert_eq!((job.status, job.attempts), (JobStatus::Queued, 0));
+
+        let job = dequeue(&pool, &[], &[], &opts(3))
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        assert_eq!((job.id, job.attempts), (job_id, 1));
+    }
+
+    #[tokio::test]
+    async fn dequeues_from_the_given_queues_in_order() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let run_at = Utc::now() - chrono::Duration::minutes(5);
+        let mut ids = HashMap::new();
+        for queue in [None, Some("mailer"), Some("critical")] {
+            let id = enqueue(
+                &pool,
+                "QueuedJob",
+                serde_json::json!({"queue": queue}),
+                None,
+                &EnqueueOptions {
+                    queue: queue.map(ToString::to_string),
+                    run_at: Some(run_at),
+                    ..EnqueueOptions::default()
+                },
+            )
+            .await
+            .expect("Failed to enqueue job");
+            ids.insert(queue.unwrap_or("default"), id);
+        }
+        assert_eq!(
+            get_job(&pool, &ids["default"]).await.queue.as_deref(),
+            Some("default")
+        );
+
+        let queues = ["critical".to_string(), "default".to_string()];
+        let job = dequeue(&pool, &[], &queues, &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        assert_eq!(job.id, ids["critical"]);
+        assert_eq!(job.queue.as_deref(), Some("critical"));
+        let job = dequeue(&pool, &[], &queues, &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        assert_eq!(job.id, ids["default"]);
+        // `mailer` is not among the worker's queues.
+        assert!(dequeue(&pool, &[], &queues, &HashMap::new())
+            .await
+            .expect("dequeue")
+            .is_none());
+
+        let job = dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("a job");
+        assert_eq!(job.id, ids["mailer"]);
+    }
+
+    #[tokio::test]
+    async fn unique_job_returns_existing_id_while_in_window() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let provider = build_provider(&config(&tree_fs.root))
+            .await
+            .expect("build provider");
+        assert!(initialize_database(&provider.pool).await.is_ok());
+
+        let enqueue_reindex = |window: UniqueWindow| async {
+            let unique = UniqueJob {
+                key: "Reindex:42".to_string(),
+                window,
+            };
+            let data = serde_json::json!({"account_id": 42});
+            enqueue_unique(
+                &provider.pool,
+                &unique,
+                "Reindex",
+                data,
+                &EnqueueOptions::default(),
+            )
+            .await
+        };
+
+        let first = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        let duplicate = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        assert_eq!(first, duplicate);
+        assert_eq!(get_all_jobs(&provider.pool).await.len(), 1);
+
+        let job = dequeue(&provider.pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .expect("job");
+        assert_eq!(job.unique_key.as_deref(), Some("Reindex:42"));
+
+        // Still processing: inside a queued-or-processing window, outside a
+        // queued-only one.
+        let duplicate = enqueue_reindex(UniqueWindow::QueuedOrProcessing)
+            .await
+            .expect("enqueue");
+        assert_eq!(first, duplicate);
+        let second = enqueue_reindex(UniqueWindow::Queued)
+            .await
+            .expect("enqueue");
+        assert_ne!(first, second);
+
+        assert_eq!(get_job(&provider.pool, &first).await.unique_key, None);
+        assert_eq!(get_all_jobs(&provider.pool).await.len(), 2);
+
+        let third = enqueue_reindex(UniqueWindow::For(Duration::from_secs(60)))
+            .await
+            .expect("enqueue");
+        assert_eq!(second, third);
+    }
+
+    #[tokio::test]
+    async fn worker_can_report_progress_and_result() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let job_id = enqueue(
+            &pool,
+            "ExportWorker",
+            serde_json::json!(null),
+            None,
+            &EnqueueOptions::default(),
+        )
+        .await
+        .expect("Failed to enqueue job");
+
+        struct ExportWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<()> for ExportWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, _args: ()) -> crate::Result<()> {
+                crate::bgworker::report_progress(73).await?;
+                crate::bgworker::report_result(&serde_json::json!({"path": "exports/42.csv"})).await
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        let handler = crate::bgworker::erase_worker(ExportWorker);
+        assert!(registry
+            .insert_handler("ExportWorker".to_string(), handler, WorkerOpts::default())
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<SqliteDriver>(&pool, &opts, &token, &[]);
+        sleep(Duration::from_secs(1)).await;
+        for handle in handles {
+            handle.abort();
+        }
+
+        let info = JobInfo::from(
+            super::get_job(&pool, &job_id)
+                .await
+                .expect("get job")
+                .expect("job exists"),
+        );
+        assert_eq!(info.status, JobStatus::Completed);
+        assert_eq!(info.progress, Some(73));
+        assert_eq!(
+            info.result,
+            Some(serde_json::json!({"path": "exports/42.csv"}))
+        );
+        assert!(super::get_job(&pool, "missing")
+            .await
+            .expect("get job")
+            .is_none());
+    }
+
+    fn spec(class: &str, args: serde_json::Value, chain: Vec<ChainStep>) -> JobSpec {
+        JobSpec {
+            class: class.to_string(),
+            queue: None,
+            args,
+            tags: None,
+            priority: None,
+            chain,
+            args_version: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn chain_passes_results_and_batch_runs_on_complete() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        let double = ChainStep {
+            class: "DoubleWorker".to_string(),
+            queue: None,
+            tags: None,
+            args_version: 1,
+        };
+        let batch_id = enqueue_batch(
+            &pool,
+            &BatchSpec {
+                jobs: vec![
+                    spec("DoubleWorker", serde_json::json!({"n": 2}), vec![double]),
+                    spec("DoubleWorker", serde_json::json!({"n": 5}), vec![]),
+                ],
+                on_complete: Some(spec(
+                    "DoneWorker",
+                    serde_json::json!({"outcome": "done"}),
+                    vec![],
+                )),
+                on_failure: Some(spec(
+                    "DoneWorker",
+                    serde_json::json!({"outcome": "failed"}),
+                    vec![],
+                )),
+            },
+        )
+        .await
+        .expect("enqueue batch");
+
+        struct DoubleWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<serde_json::Value> for DoubleWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, args: serde_json::Value) -> crate::Result<()> {
+                let n = args["n"].as_i64().unwrap_or_default();
+                crate::bgworker::report_result(&serde_json::json!({"n": n * 2})).await
+            }
+        }
+
+        struct DoneWorker;
+        #[async_trait::async_trait]
+        impl BackgroundWorker<serde_json::Value> for DoneWorker {
+            fn build(_ctx: &crate::app::AppContext) -> Self {
+                Self
+            }
+            async fn perform(&self, _args: serde_json::Value) -> crate::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut registry = JobRegistry::new();
+        assert!(registry
+            .insert_handler(
+                "DoubleWorker".to_string(),
+                crate::bgworker::erase_worker(DoubleWorker),
+                WorkerOpts::default()
+            )
+            .is_ok());
+        assert!(registry
+            .insert_handler(
+                "DoneWorker".to_string(),
+                crate::bgworker::erase_worker(DoneWorker),
+                WorkerOpts::default()
+            )
+            .is_ok());
+
+        let opts = RunOpts {
+            num_workers: 1,
+            poll_interval_sec: 1,
+            queues: Vec::new(),
+            reaper: None,
+            shutdown_timeout: Duration::from_secs(30),
+        };
+        let token = CancellationToken::new();
+        let handles = registry.run::<SqliteDriver>(&pool, &opts, &token, &[]);
+        sleep(Duration::from_secs(2)).await;
+        for handle in handles {
+            handle.abort();
+        }
+
+        let jobs = get_jobs(&pool, None, None).await.expect("get jobs");
+        let mut results = jobs
+            .iter()
+            .filter(|job| job.name == "DoubleWorker")
+            .map(|job| (job.data.clone(), job.result.clone(), job.status.clone()))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(args, _, _)| args["n"].as_i64());
+        assert_eq!(
+            results,
+            vec![
+                (
+                    serde_json::json!({"n": 2}),
+                    Some(serde_json::json!({"n": 4})),
+                    JobStatus::Completed
+                ),
+                (
+                    serde_json::json!({"n": 4}),
+                    Some(serde_json::json!({"n": 8})),
+                    JobStatus::Completed
+                ),
+                (
+                    serde_json::json!({"n": 5}),
+                    Some(serde_json::json!({"n": 10})),
+                    JobStatus::Completed
+                ),
+            ]
+        );
+        assert!(jobs
+            .iter()
+            .filter(|job| job.name == "DoubleWorker")
+            .all(|job| job.batch_id.as_deref() == Some(batch_id.as_str())));
+
+        let callbacks = jobs
+            .iter()
+            .filter(|job| job.name == "DoneWorker")
+            .collect::<Vec<_>>();
+        assert_eq!(callbacks.len(), 1);
+        assert_eq!(callbacks[0].data, serde_json::json!({"outcome": "done"}));
+        assert_eq!(callbacks[0].status, JobStatus::Completed);
+
+        let batches = get_batches(&pool).await.expect("get batches");
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].id, batch_id);
+        assert_eq!(batches[0].status, BatchStatus::Completed);
+        assert_eq!((batches[0].completed, batches[0].total), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn first_failed_batch_member_enqueues_on_failure() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        enqueue_batch(
+            &pool,
+            &BatchSpec {
+                jobs: vec![
+                    spec("ImportWorker", serde_json::json!({"file": 1}), vec![]),
+                    spec("ImportWorker", serde_json::json!({"file": 2}), vec![]),
+                ],
+                on_complete: None,
+                on_failure: Some(spec("AlertWorker", serde_json::json!({}), vec![])),
+            },
+        )
+        .await
+        .expect("enqueue batch");
+
+        for job in get_jobs(&pool, None, None).await.expect("get jobs") {
+            fail_job(&pool, &job.id, &crate::Error::string("boom"))
+                .await
+                .expect("fail job");
+        }
+
+        let jobs = get_jobs(&pool, None, None).await.expect("get jobs");
+        let alerts = jobs
+            .iter()
+            .filter(|job| job.name == "AlertWorker")
+            .collect::<Vec<_>>();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].status, JobStatus::Queued);
+        assert_eq!(alerts[0].batch_id, None);
+
+        let batches = get_batches(&pool).await.expect("get batches");
+        assert_eq!(batches[0].status, BatchStatus::Failed);
+        assert_eq!((batches[0].failed, batches[0].completed), (2, 0));
+    }
+
+    fn limited(name: &str, opts: WorkerOpts) -> HashMap<String, WorkerOpts> {
+        HashMap::from([(name.to_string(), opts)])
+    }
+
+    #[tokio::test]
+    async fn concurrency_cap_holds_back_jobs_of_the_capped_class() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        for name in ["Transcode", "Transcode", "Transcode", "Thumbnail"] {
+            enqueue(
+                &pool,
+                name,
+                serde_json::json!({}),
+                None,
+                &EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue");
+        }
+        let worker_opts = limited(
+            "Transcode",
+            WorkerOpts {
+                concurrency: Some(2),
+                ..Default::default()
+            },
+        );
+
+        let mut names = Vec::new();
+        while let Some(job) = dequeue(&pool, &[], &[], &worker_opts)
+            .await
+            .expect("dequeue")
+        {
+            names.push(job.name);
+        }
+        assert_eq!(names, vec!["Transcode", "Transcode", "Thumbnail"]);
+
+        let running = get_jobs(&pool, Some(&vec![JobStatus::Processing]), None)
+            .await
+            .expect("get jobs");
+        let transcode = running
+            .iter()
+            .find(|job| job.name == "Transcode")
+            .expect("a transcode job is running");
+        complete_job(&pool, &transcode.id, None)
+            .await
+            .expect("complete job");
+
+        let job = dequeue(&pool, &[], &[], &worker_opts)
+            .await
+            .expect("dequeue")
+            .expect("a slot was freed");
+        assert_eq!(job.name, "Transcode");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_holds_back_jobs_once_reached() {
+        let tree_fs = tree_fs::TreeBuilder::default()
+            .drop(true)
+            .create()
+            .expect("create temp folder");
+        let pool = init(&tree_fs.root).await;
+
+        assert!(initialize_database(&pool).await.is_ok());
+
+        for _ in 0..3 {
+            enqueue(
+                &pool,
+                "Webhook",
+                serde_json::json!({}),
+                None,
+                &EnqueueOptions::default(),
+            )
+            .await
+            .expect("enqueue");
+        }
+        let worker_opts = limited(
+            "Webhook",
+            WorkerOpts {
+                rate_limit: Some(crate::bgworker::RateLimit::per_minute(2)),
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..2 {
+            let job = dequeue(&pool, &[], &[], &worker_opts)
+                .await
+                .expect("dequeue")
+                .expect("within the rate limit");
+            complete_job(&pool, &job.id, None)
+                .await
+                .expect("complete job");
+        }
+        assert!(dequeue(&pool, &[], &[], &worker_opts)
+            .await
+            .expect("dequeue")
+            .is_none());
+        assert!(dequeue(&pool, &[], &[], &HashMap::new())
+            .await
+            .expect("dequeue")
+            .is_some());
+    }
 }
@@ -0,0 +1,375 @@
+//! Queue metrics: how deep the queue is, how long jobs wait and run, and how
+//! often they fail, per worker class. See [`super::Queue::stats`].
+//!
+//! They are computed from the jobs the queue holds, so they cover whatever
+//! `cargo loco jobs tidy` and `purge` left behind: finished jobs count until
+//! they are deleted.
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{Job, JobStatus};
+
+/// Upper bounds of the [`DurationHistogram`] buckets, in milliseconds. A
+/// last, unbounded bucket holds the slower attempts.
+pub const DURATION_BUCKETS_MS: [u64; 12] = [
+    10, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000, 300_000,
+];
+
+/// A snapshot of a queue's metrics.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueueStats {
+    /// When the snapshot was taken; ages are relative to it.
+    pub generated_at: DateTime<Utc>,
+    /// The queued jobs per named queue and worker class. Providers without
+    /// named queues report everything under `default`.
+    pub queues: Vec<QueueDepth>,
+    /// The metrics of each worker class, by class name.
+    pub workers: BTreeMap<String, WorkerStats>,
+}
+
+/// How many jobs of a worker class wait in a named queue.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueueDepth {
+    pub queue: String,
+    pub class: String,
+    /// Jobs in [`JobStatus::Queued`], including ones whose `run_at` is still
+    /// in the future.
+    pub queued: u64,
+    /// How long the job that has been due the longest has been waiting, in
+    /// seconds. `None` when no job is due yet.
+    pub oldest_queued_age_secs: Option<i64>,
+}
+
+/// The metrics of one worker class.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub queued: u64,
+    pub processing: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    /// See [`QueueDepth::oldest_queued_age_secs`], across every queue.
+    pub oldest_queued_age_secs: Option<i64>,
+    /// Attempts that succeeded, from the jobs' [`Job::history`].
+    pub succeeded_attempts: u64,
+    /// Attempts that returned an error, panicked or timed out.
+    pub failed_attempts: u64,
+    /// How long attempts ran.
+    pub duration: DurationHistogram,
+}
+
+/// The distribution of attempt durations.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DurationHistogram {
+    /// How many attempts ran.
+    pub count: u64,
+    /// Their total duration, in milliseconds.
+    pub sum_ms: u64,
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub max_ms: Option<u64>,
+    /// How many attempts fell in each bucket: those no longer than the
+    /// bucket's `le_ms` and longer than the previous bucket's.
+    pub buckets: Vec<HistogramBucket>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HistogramBucket {
+    /// The upper bound of the bucket, in milliseconds; `None` for the last
+    /// one.
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+impl DurationHistogram {
+    fn from_durations(mut durations: Vec<u64>) -> Self {
+        durations.sort_unstable();
+        let mut buckets = DURATION_BUCKETS_MS
+            .iter()
+            .map(|le| HistogramBucket {
+                le_ms: Some(*le),
+                count: 0,
+            })
+            .chain(std::iter::once(HistogramBucket {
+                le_ms: None,
+                count: 0,
+            }))
+            .collect::<Vec<_>>();
+        for duration in &durations {
+            let index = DURATION_BUCKETS_MS
+                .iter()
+                .position(|le| duration <= le)
+                .unwrap_or(DURATION_BUCKETS_MS.len());
+            buckets[index].count += 1;
+        }
+        Self {
+            count: durations.len() as u64,
+            sum_ms: durations.iter().sum(),
+            p50_ms: percentile(&durations, 50),
+            p95_ms: percentile(&durations, 95),
+            max_ms: durations.last().copied(),
+            buckets,
+        }
+    }
+}
+
+/// The nearest-rank percentile of `sorted`.
+fn percentile(sorted: &[u64], percent: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted.get(rank - 1).copied()
+}
+
+impl QueueStats {
+    /// Computes the metrics of `jobs`, each paired with the named queue it
+    /// waits in, as of `now`.
+    pub fn from_jobs<'a>(
+        jobs: impl IntoIterator<Item = (&'a str, &'a Job)>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let mut depths: BTreeMap<(String, String), QueueDepth> = BTreeMap::new();
+        let mut workers: BTreeMap<String, WorkerStats> = BTreeMap::new();
+        let mut durations: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+
+        for (queue, job) in jobs {
+            let stats = workers.entry(job.name.clone()).or_default();
+            match job.status {
+                JobStatus::Queued => {
+                    stats.queued += 1;
+                    let age = (job.run_at <= now).then(|| (now - job.run_at).num_seconds());
+                    stats.oldest_queued_age_secs = stats.oldest_queued_age_secs.max(age);
+
+                    let depth = depths
+                        .entry((queue.to_string(), job.name.clone()))
+                        .or_insert_with(|| QueueDepth {
+                            queue: queue.to_string(),
+                            class: job.name.clone(),
+                            ..QueueDepth::default()
+                        });
+                    depth.queued += 1;
+                    depth.oldest_queued_age_secs = depth.oldest_queued_age_secs.max(age);
+                }
+                JobStatus::Processing => stats.processing += 1,
+                JobStatus::Completed => stats.completed += 1,
+                JobStatus::Failed => stats.failed += 1,
+                JobStatus::Cancelled => stats.cancelled += 1,
+            }
+
+            let class_durations = durations.entry(job.name.clone()).or_default();
+            for attempt in &job.history {
+                if attempt.error.is_some() {
+                    stats.failed_attempts += 1;
+                } else {
+                    stats.succeeded_attempts += 1;
+                }
+                let duration = (attempt.finished_at - attempt.started_at)
+                    .num_milliseconds()
+                    .max(0);
+                class_durations.push(u64::try_from(duration).unwrap_or_default());
+            }
+        }
+
+        for (class, durations) in durations {
+            if let Some(stats) = workers.get_mut(&class) {
+                stats.duration = DurationHistogram::from_durations(durations);
+            }
+        }
+
+        Self {
+            generated_at: now,
+            queues: depths.into_values().collect(),
+            workers,
+        }
+    }
+
+    /// Builds the metrics from counts and attempt aggregates a provider
+    /// computed itself, such as the SQL providers do with `GROUP BY`, instead
+    /// of loading every job like [`QueueStats::from_jobs`].
+    #[cfg(feature = "worker")]
+    pub(crate) fn from_aggregates(
+        counts: impl IntoIterator<Item = StatusCount>,
+        attempts: impl IntoIterator<Item = AttemptSummary>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let mut depths: BTreeMap<(String, String), QueueDepth> = BTreeMap::new();
+        let mut workers: BTreeMap<String, WorkerStats> = BTreeMap::new();
+
+        for count in counts {
+            let stats = workers.entry(count.class.clone()).or_default();
+            match count.status {
+                JobStatus::Queued => {
+                    stats.queued += count.count;
+                    stats.oldest_queued_age_secs = stats
+                        .oldest_queued_age_secs
+                        .max(count.oldest_queued_age_secs);
+
+                    let depth = depths
+                        .entry((count.queue.clone(), count.class.clone()))
+                        .or_insert_with(|| QueueDepth {
+                            queue: count.queue,
+                            class: count.class,
+                            ..QueueDepth::default()
+                        });
+                    depth.queued += count.count;
+                    depth.oldest_queued_age_secs = depth
+                        .oldest_queued_age_secs
+                        .max(count.oldest_queued_age_secs);
+                }
+                JobStatus::Processing => stats.processing += count.count,
+                JobStatus::Completed => stats.completed += count.count,
+                JobStatus::Failed => stats.failed += count.count,
+                JobStatus::Cancelled => stats.cancelled += count.count,
+            }
+        }
+
+        for summary in attempts {
+            let stats = workers.entry(summary.class).or_default();
+            stats.failed_attempts = summary.failed;
+            stats.succeeded_attempts = summary.duration.count.saturating_sub(summary.failed);
+            stats.duration = summary.duration;
+        }
+        for stats in workers.values_mut() {
+            if stats.duration.buckets.is_empty() {
+                stats.duration = DurationHistogram::from_durations(Vec::new());
+            }
+        }
+
+        Self {
+            generated_at: now,
+            queues: depths.into_values().collect(),
+            workers,
+        }
+    }
+}
+
+/// How many jobs of a worker class in a named queue have a status.
+#[cfg(feature = "worker")]
+#[derive(Clone, Debug)]
+pub(crate) struct StatusCount {
+    pub queue: String,
+    pub class: String,
+    pub status: JobStatus,
+    pub count: u64,
+    /// The age of the oldest due job among them, for [`JobStatus::Queued`].
+    pub oldest_queued_age_secs: Option<i64>,
+}
+
+/// The attempts of a worker class, aggregated.
+#[cfg(feature = "worker")]
+#[derive(Clone, Debug)]
+pub(crate) struct AttemptSummary {
+    pub class: String,
+    /// How many of the [`DurationHistogram::count`] attempts failed.
+    pub failed: u64,
+    pub duration: DurationHistogram,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::bgworker::JobAttempt;
+
+    fn job(name: &str, status: JobStatus, run_at: DateTime<Utc>) -> Job {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "name": name,
+            "task_data": null,
+            "status": status,
+            "run_at": run_at,
+            "interval": null,
+            "created_at": null,
+            "updated_at": null,
+            "tags": null,
+        }))
+        .unwrap()
+    }
+
+    fn attempt(at: DateTime<Utc>, ms: i64, error: Option<&str>) -> JobAttempt {
+        JobAttempt {
+            attempt: 1,
+            started_at: at,
+            finished_at: at + TimeDelta::milliseconds(ms),
+            error: error.map(ToString::to_string),
+            backtrace: None,
+        }
+    }
+
+    #[test]
+    fn computes_depth_latency_and_throughput_per_class() {
+        let now = Utc::now();
+        let minutes_ago = |m| now - TimeDelta::minutes(m);
+
+        let mut done = job("Mailer", JobStatus::Completed, minutes_ago(10));
+        done.history = vec![
+            attempt(minutes_ago(10), 20, Some("smtp down")),
+            attempt(minutes_ago(9), 800, None),
+        ];
+        let jobs = [
+            ("mailer", job("Mailer", JobStatus::Queued, minutes_ago(5))),
+            ("mailer", job("Mailer", JobStatus::Queued, minutes_ago(1))),
+            (
+                "default",
+                job("Mailer", JobStatus::Queued, now + TimeDelta::hours(1)),
+            ),
+            ("default", job("Report", JobStatus::Failed, minutes_ago(3))),
+            (
+                "default",
+                job("Report", JobStatus::Processing, minutes_ago(3)),
+            ),
+            ("mailer", done),
+        ];
+
+        let stats = QueueStats::from_jobs(jobs.iter().map(|(q, job)| (*q, job)), now);
+
+        assert_eq!(
+            stats.queues,
+            vec![
+                QueueDepth {
+                    queue: "default".to_string(),
+                    class: "Mailer".to_string(),
+                    queued: 1,
+                    oldest_queued_age_secs: None,
+                },
+                QueueDepth {
+                    queue: "mailer".to_string(),
+                    class: "Mailer".to_string(),
+                    queued: 2,
+                    oldest_queued_age_secs: Some(300),
+                },
+            ]
+        );
+
+        let mailer = &stats.workers["Mailer"];
+        assert_eq!((mailer.queued, mailer.completed), (3, 1));
+        assert_eq!(mailer.oldest_queued_age_secs, Some(300));
+        assert_eq!((mailer.succeeded_attempts, mailer.failed_attempts), (1, 1));
+        assert_eq!(mailer.duration.count, 2);
+        assert_eq!(mailer.duration.sum_ms, 820);
+        assert_eq!(mailer.duration.p50_ms, Some(20));
+        assert_eq!(mailer.duration.max_ms, Some(800));
+        let bucket = |le| {
+            mailer
+                .duration
+                .buckets
+                .iter()
+                .find(|b| b.le_ms == le)
+                .unwrap()
+                .count
+        };
+        assert_eq!(
+            (bucket(Some(50)), bucket(Some(1_000)), bucket(None)),
+            (1, 1, 0)
+        );
+
+        let report = &stats.workers["Report"];
+        assert_eq!((report.failed, report.processing, report.queued), (1, 1, 0));
+        assert_eq!(report.oldest_queued_age_secs, None);
+        assert_eq!(report.duration.count, 0);
+    }
+}
@@ -0,0 +1,152 @@
+//! Versioned job args.
+//!
+//! A job is stored with the [`super::BackgroundWorker::args_version`] its
+//! worker declared when it was enqueued. When a worker changes its args
+//! struct, it bumps the version and registers an upcaster that turns args
+//! of the previous version into the new shape, so that jobs enqueued before
+//! the change still deserialize:
+//!
+//! ```rust
+//! use loco_rs::{bgworker::Upcasters, prelude::*};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Deserialize, Serialize)]
+//! struct WelcomeArgs {
+//!     // Version 1 had a single `email: String`.
+//!     emails: Vec<String>,
+//! }
+//!
+//! struct WelcomeWorker;
+//!
+//! #[async_trait]
+//! impl BackgroundWorker<WelcomeArgs> for WelcomeWorker {
+//!     fn args_version() -> u32 {
+//!         2
+//!     }
+//!
+//!     fn upcasters() -> Upcasters {
+//!         Upcasters::new().add(1, |mut data| {
+//!             let email = data["email"].take();
+//!             Ok(serde_json::json!({ "emails": [email] }))
+//!         })
+//!     }
+//!
+//!     fn build(_ctx: &AppContext) -> Self {
+//!         Self
+//!     }
+//!
+//!     async fn perform(&self, _args: WelcomeArgs) -> Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! Upcasting happens in queue workers, in `BackgroundQueue` mode, right
+//! before the args are deserialized; the stored job is left as it was. A
+//! worker does not take jobs stored at a newer version than its own, as
+//! enqueued by a newer release of the app during a rolling deploy; they stay
+//! queued for a worker that knows them.
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use super::JobData;
+use crate::{Error, Result};
+
+/// The version of a job's args when its worker does not declare one, and of
+/// jobs stored before args were versioned.
+pub const INITIAL_ARGS_VERSION: u32 = 1;
+
+pub(crate) const fn initial_args_version() -> u32 {
+    INITIAL_ARGS_VERSION
+}
+
+type Upcaster = Arc<dyn Fn(JobData) -> Result<JobData> + Send + Sync>;
+
+/// The upcasters of a worker, each bringing args one version up. See the
+/// [module docs](self).
+#[derive(Clone, Default)]
+pub struct Upcasters(BTreeMap<u32, Upcaster>);
+
+impl Upcasters {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `upcast`, which turns args of version `from` into args of
+    /// version `from + 1`. A version without an upcaster is taken as is.
+    #[must_use]
+    pub fn add<F>(mut self, from: u32, upcast: F) -> Self
+    where
+        F: Fn(JobData) -> Result<JobData> + Send + Sync + 'static,
+    {
+        self.0.insert(from, Arc::new(upcast));
+        self
+    }
+
+    /// Brings `data`, stored at `version`, up to the `current` version by
+    /// running the upcasters in between, oldest first.
+    ///
+    /// # Errors
+    /// Returns the error of a failing upcaster, or an error if `version` is
+    /// newer than `current`, as with a job enqueued by a newer release of the
+    /// app.
+    pub fn upcast(&self, data: JobData, version: u32, current: u32) -> Result<JobData> {
+        if version > current {
+            return Err(Error::string(&format!(
+                "job args are version {version}, newer than the worker's version {current}"
+            )));
+        }
+        self.0
+            .range(version..current)
+            .try_fold(data, |data, (_, upcast)| upcast(data))
+    }
+}
+
+impl fmt::Debug for Upcasters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn upcasters() -> Upcasters {
+        Upcasters::new()
+            .add(1, |mut data| {
+                let email = data["email"].take();
+                Ok(json!({ "emails": [email] }))
+            })
+            .add(3, |mut data| {
+                data["locale"] = json!("en");
+                Ok(data)
+            })
+    }
+
+    #[test]
+    fn upcast_runs_the_steps_from_the_stored_version() {
+        let upcasters = upcasters();
+
+        assert_eq!(
+            upcasters
+                .upcast(json!({"email": "a@example.com"}), 1, 4)
+                .unwrap(),
+            json!({"emails": ["a@example.com"], "locale": "en"})
+        );
+        // No upcaster from 2 to 3: the args did not change shape.
+        assert_eq!(
+            upcasters
+                .upcast(json!({"emails": ["a@example.com"]}), 2, 4)
+                .unwrap(),
+            json!({"emails": ["a@example.com"], "locale": "en"})
+        );
+        assert_eq!(
+            upcasters.upcast(json!({"emails": []}), 1, 1).unwrap(),
+            json!({"emails": []})
+        );
+        assert!(upcasters.upcast(json!({}), 5, 4).is_err());
+    }
+}
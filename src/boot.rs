@@ -95,8 +95,12 @@ pub async fn start<H: Hooks>(
 ) -> Result<()> {
     if boot.run_scheduler {
         let scheduler = scheduler::<H>(&boot.app_context, None, None, None)?;
+        if boot.worker.is_none() && scheduler.enqueues_workers() {
+            register_workers::<H>(&boot.app_context).await?;
+        }
+        let app_context = boot.app_context.clone();
         tokio::spawn(async move {
-            if let Err(err) = scheduler.run().await {
+            if let Err(err) = scheduler.run_with_context(&app_context).await {
                 error!(err = err.to_string(), "error while running scheduler");
             }
         });
@@ -278,10 +282,84 @@ pub async fn run_scheduler<H: Hooks>(
         println!("{scheduler}");
         Ok(())
     } else {
-        Ok(scheduler.run().await?)
+        if scheduler.enqueues_workers() {
+            register_workers::<H>(app_context).await?;
+        }
+        Ok(scheduler.run_with_context(app_context).await?)
     }
 }
 
+/// Prints the recorded runs of the scheduler jobs, newest first: the latest
+/// `limit` runs of job `job`, or of every job, with the end of their output
+/// when `output` is true. With `name` or `tag`, only the runs of the jobs they
+/// select, as for [`run_scheduler_last_run`].
+///
+/// # Errors
+///
+/// When the scheduler config cannot be loaded, records no history, or its
+/// history cannot be read.
+pub async fn run_scheduler_history<H: Hooks>(
+    app_context: &AppContext,
+    config: Option<&PathBuf>,
+    name: Option<String>,
+    tag: Option<String>,
+    job: Option<String>,
+    limit: usize,
+    output: bool,
+) -> Result<()> {
+    let narrowed = name.is_some() || tag.is_some();
+    let scheduler = scheduler::<H>(app_context, config, name, tag)?;
+    let runs = if narrowed {
+        scheduler
+            .runs_of_jobs(Some(app_context), job.as_deref(), limit)
+            .await?
+    } else {
+        scheduler
+            .runs(Some(app_context), job.as_deref(), limit)
+            .await?
+    };
+    if runs.is_empty() {
+        println!("no runs");
+    }
+    for run in runs {
+        println!("{run}");
+        if output {
+            for (stream, text) in [("stdout", &run.stdout), ("stderr", &run.stderr)] {
+                if !text.is_empty() {
+                    println!("  {stream}:");
+                    for line in text.lines() {
+                        println!("    {line}");
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the latest recorded run of every scheduler job, or of the job
+/// `name` or the jobs tagged `tag`.
+///
+/// # Errors
+///
+/// When the scheduler config cannot be loaded, records no history, or its
+/// history cannot be read.
+pub async fn run_scheduler_last_run<H: Hooks>(
+    app_context: &AppContext,
+    config: Option<&PathBuf>,
+    name: Option<String>,
+    tag: Option<String>,
+) -> Result<()> {
+    let scheduler = scheduler::<H>(app_context, config, name, tag)?;
+    for (job_name, run) in scheduler.last_runs(Some(app_context)).await? {
+        match run {
+            Some(run) => println!("{run}"),
+            None => println!("{job_name:<20} never"),
+        }
+    }
+    Ok(())
+}
+
 /// Represents commands for handling database-related operations.
 #[derive(Debug)]
 pub enum RunDbCommand {
@@ -405,6 +483,9 @@ pub async fn create_context<H: Hooks>(
         None
     };
 
+    #[cfg(feature = "with-db")]
+    let queue_provider = bgworker::create_queue_provider_with_db(&config, &db).await?;
+    #[cfg(not(feature = "with-db"))]
     let queue_provider = bgworker::create_queue_provider(&config).await?;
     let ctx = AppContext {
         environment: environment.clone(),
@@ -476,6 +557,12 @@ pub async fn run_app<H: Hooks>(mode: &StartMode, app_context: AppContext) -> Res
 
     match mode {
         StartMode::ServerOnly => {
+            // Jobs in an in-memory queue can only ever be performed by this
+            // process, which is what tests draining it need.
+            #[cfg(feature = "testing")]
+            if matches!(app_context.config.queue, Some(config::QueueConfig::InMem)) {
+                register_workers::<H>(&app_context).await?;
+            }
             let router = setup_routes::<H>(&app_context, &initializers).await?;
             Ok(BootResult {
                 app_context,
@@ -36,8 +36,8 @@ use crate::controller;
 use crate::{
     app::{AppContext, Hooks},
     boot::{
-        create_app, create_context, list_middlewares, run_scheduler, run_task, start, RunDbCommand,
-        ServeParams, StartMode,
+        create_app, create_context, list_middlewares, run_scheduler, run_scheduler_history,
+        run_scheduler_last_run, run_task, start, RunDbCommand, ServeParams, StartMode,
     },
     config::Config,
     doctor,
@@ -84,6 +84,9 @@ enum Commands {
         /// Run the scheduler
         #[arg(long, action, conflicts_with = "all")]
         scheduler: bool,
+        /// Only take jobs from these named queues (e.g. --queue=mailer,default)
+        #[arg(long, value_delimiter = ',', num_args = 1.., requires = "start_mode")]
+        queue: Option<Vec<String>>,
         /// server bind address
         #[arg(short, long, action)]
         binding: Option<String>,
@@ -139,6 +142,8 @@ enum Commands {
         /// Show all configured jobs
         #[arg(short, long, action)]
         list: bool,
+        #[command(subcommand)]
+        command: Option<SchedulerCommands>,
     },
     /// code generation creates a set of files and code templates based on a
     /// predefined set of rules.
@@ -629,11 +634,29 @@ impl DeploymentKind {
     }
 }
 
+#[derive(Subcommand)]
+enum SchedulerCommands {
+    /// Shows the recorded runs of the scheduler jobs, newest first.
+    History {
+        /// Shows the runs of the job with this name only.
+        #[arg(short, long, alias = "job")]
+        name: Option<String>,
+        /// Maximum number of runs to show.
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Shows the end of the output of every run.
+        #[arg(long, action)]
+        output: bool,
+    },
+    /// Shows the latest recorded run of every scheduler job.
+    LastRun {},
+}
+
 #[cfg(feature = "worker")]
 #[derive(Subcommand)]
 enum JobsCommands {
     /// Cancels jobs with the specified names, setting their status to
-    /// `cancelled`.
+    /// `cancelled`, and asks running ones to stop.
     Cancel {
         /// Names of jobs to cancel.
         #[arg(long)]
@@ -680,6 +703,52 @@ enum JobsCommands {
         #[arg(long)]
         id: Option<String>,
     },
+    /// Lists failed jobs with the error and attempt history of their last
+    /// run.
+    Failures {
+        /// Only list failed jobs with this name.
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Lists jobs, newest first.
+    List {
+        /// Only list jobs with these statuses.
+        #[arg(long, use_value_delimiter = true)]
+        status: Option<Vec<JobStatus>>,
+        /// Only list jobs with this name.
+        #[arg(long)]
+        name: Option<String>,
+        /// Only list jobs carrying this tag.
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only list jobs created or updated within this long, such as `30m`,
+        /// `1h` or `7d`.
+        #[arg(long, value_parser = parse_since)]
+        since: Option<chrono::Duration>,
+        /// List at most this many jobs.
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Print a table, or the jobs as JSON.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Shows a job with its payload, error, attempts and result.
+    Show {
+        /// The job's id.
+        id: String,
+        /// Print the job as text, or as JSON.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Lists job batches with their progress.
+    Batches {},
+    /// Shows queue depth, wait times, run durations and failure counts per
+    /// worker class.
+    Stats {
+        /// Print a table, or JSON to feed a monitoring system.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
     /// Change `processing` status to `queue`.
     Requeue {
         /// Change `processing` jobs older than the specified
@@ -689,6 +758,53 @@ enum JobsCommands {
     },
 }
 
+#[cfg(feature = "worker")]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// Parses a `--since` age: a number followed by `s`, `m`, `h`, `d` or `w`.
+#[cfg(feature = "worker")]
+fn parse_since(s: &str) -> std::result::Result<chrono::Duration, String> {
+    let unit_at = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in `{s}`, expected e.g. `30m`, `1h` or `7d`"))?;
+    let (amount, unit) = s.split_at(unit_at);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("expected a number before the unit in `{s}`"))?;
+    match unit {
+        "s" => Ok(chrono::Duration::seconds(amount)),
+        "m" => Ok(chrono::Duration::minutes(amount)),
+        "h" => Ok(chrono::Duration::hours(amount)),
+        "d" => Ok(chrono::Duration::days(amount)),
+        "w" => Ok(chrono::Duration::weeks(amount)),
+        _ => Err(format!(
+            "unknown unit `{unit}` in `{s}`, expected s, m, h, d or w"
+        )),
+    }
+}
+
+/// The [`crate::bgworker::JobFilter`] for the `jobs list` arguments.
+#[cfg(feature = "worker")]
+fn list_filter(
+    status: Option<&[JobStatus]>,
+    name: Option<&str>,
+    tag: Option<&str>,
+    since: Option<chrono::Duration>,
+    limit: usize,
+) -> crate::bgworker::JobFilter {
+    crate::bgworker::JobFilter {
+        statuses: status.map(<[JobStatus]>::to_vec).unwrap_or_default(),
+        name: name.map(ToString::to_string),
+        tag: tag.map(ToString::to_string),
+        since: since.map(|since| chrono::Utc::now() - since),
+        limit: Some(limit),
+    }
+}
+
 /// Parse a single key-value pair
 fn parse_key_val<T, U>(
     s: &str,
@@ -773,7 +889,8 @@ pub async fn main<H: Hooks, M: MigratorTrait>() -> crate::Result<()> {
         _ => environment,
     };
 
-    let config = H::load_config(&environment).await?;
+    let mut config = H::load_config(&environment).await?;
+    select_worker_queues(&cli.command, &mut config);
     let app_context = create_context::<H>(&environment, config).await?;
 
     if !H::init_logger(&app_context)? {
@@ -789,6 +906,7 @@ pub async fn main<H: Hooks, M: MigratorTrait>() -> crate::Result<()> {
             server_and_worker,
             all,
             scheduler,
+            queue: _,
             binding,
             port,
             no_banner,
@@ -861,9 +979,31 @@ async fn dispatch_common<H: Hooks>(
             config_path,
             tag,
             list,
-        } => {
-            run_scheduler::<H>(&app_context, config_path.as_ref(), name, tag, list).await?;
-        }
+            command,
+        } => match command {
+            None => {
+                run_scheduler::<H>(&app_context, config_path.as_ref(), name, tag, list).await?;
+            }
+            Some(SchedulerCommands::History {
+                name: job,
+                limit,
+                output,
+            }) => {
+                run_scheduler_history::<H>(
+                    &app_context,
+                    config_path.as_ref(),
+                    name,
+                    tag,
+                    job,
+                    limit,
+                    output,
+                )
+                .await?;
+            }
+            Some(SchedulerCommands::LastRun {}) => {
+                run_scheduler_last_run::<H>(&app_context, config_path.as_ref(), name, tag).await?;
+            }
+        },
         #[cfg(debug_assertions)]
         Commands::Generate { component } => {
             handle_generate_command::<H>(component, &app_context.config)?;
@@ -950,7 +1090,8 @@ pub async fn main<H: Hooks>() -> crate::Result<()> {
     let cli = Cli::parse();
     let environment: Environment = cli.environment.unwrap_or_else(resolve_from_env).into();
 
-    let config = H::load_config(&environment).await?;
+    let mut config = H::load_config(&environment).await?;
+    select_worker_queues(&cli.command, &mut config);
     let app_context = create_context::<H>(&environment, config).await?;
 
     if !H::init_logger(&app_context)? {
@@ -966,6 +1107,7 @@ pub async fn main<H: Hooks>() -> crate::Result<()> {
             server_and_worker,
             all,
             scheduler,
+            queue: _,
             binding,
             port,
             no_banner,
@@ -985,6 +1127,19 @@ pub async fn main<H: Hooks>() -> crate::Result<()> {
     Ok(())
 }
 
+/// Narrows the queue config to the queues `start --queue` names, before the
+/// queue provider is created from it.
+fn select_worker_queues(command: &Commands, config: &mut Config) {
+    if let Commands::Start {
+        queue: Some(queues),
+        ..
+    } = command
+        && let Some(queue_config) = &mut config.queue
+    {
+        queue_config.select_queues(queues);
+    }
+}
+
 fn create_root_span(environment: &Environment) -> tracing::Span {
     tracing::span!(tracing::Level::DEBUG, "app", environment = %environment)
 }
@@ -1061,6 +1216,129 @@ async fn handle_job_command(command: JobsCommands, app_context: &AppContext) ->
             Ok(())
         }
         JobsCommands::Import { file } => queue.import(file.as_path()).await,
+        JobsCommands::Failures { name } => {
+            let failed = queue.get_jobs(Some(&vec![JobStatus::Failed]), None).await?;
+            let failed = failed
+                .iter()
+                .filter(|job| name.as_ref().is_none_or(|name| &job.name == name))
+                .collect::<Vec<_>>();
+            if failed.is_empty() {
+                println!("no failed jobs");
+            }
+            for job in failed {
+                println!(
+                    "{} {} ({} attempt(s))",
+                    job.id.bold(),
+                    job.name,
+                    job.attempts
+                );
+                println!(
+                    "  error: {}",
+                    job.last_error.as_deref().unwrap_or("<not recorded>").red()
+                );
+                for attempt in &job.history {
+                    println!(
+                        "  #{} {} -> {} {}",
+                        attempt.attempt,
+                        attempt.started_at.to_rfc3339(),
+                        attempt.finished_at.to_rfc3339(),
+                        attempt.error.as_deref().unwrap_or("ok")
+                    );
+                    if let Some(backtrace) = &attempt.backtrace {
+                        for line in backtrace.lines() {
+                            println!("      {}", line.dimmed());
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+        JobsCommands::List {
+            status,
+            name,
+            tag,
+            since,
+            limit,
+            format,
+        } => {
+            let jobs = queue
+                .list_jobs(&list_filter(
+                    status.as_deref(),
+                    name.as_deref(),
+                    tag.as_deref(),
+                    *since,
+                    *limit,
+                ))
+                .await?;
+            if *format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&jobs)?);
+                return Ok(());
+            }
+            if jobs.is_empty() {
+                println!("no jobs");
+            }
+            for job in &jobs {
+                println!(
+                    "{} {} {} ({} attempt(s)) run at {}{}",
+                    job.id.bold(),
+                    job.name,
+                    job.status,
+                    job.attempts,
+                    job.run_at.to_rfc3339(),
+                    job.tags
+                        .as_ref()
+                        .filter(|tags| !tags.is_empty())
+                        .map_or_else(String::new, |tags| format!(" [{}]", tags.join(", ")))
+                );
+                if let Some(error) = &job.last_error {
+                    println!(
+                        "  error: {}",
+                        error.lines().next().unwrap_or_default().red()
+                    );
+                }
+            }
+            Ok(())
+        }
+        JobsCommands::Show { id, format } => {
+            let Some(job) = queue.get_job(id).await? else {
+                return Err(Error::Message(format!("no job with id {id}")));
+            };
+            if *format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&job)?);
+                return Ok(());
+            }
+            print_job(&job)?;
+            Ok(())
+        }
+        JobsCommands::Batches {} => {
+            let batches = queue.get_batches().await?;
+            if batches.is_empty() {
+                println!("no batches");
+            }
+            for batch in batches {
+                println!(
+                    "{} {} ({}/{} done, {} failed) created {}",
+                    batch.id.bold(),
+                    batch.status,
+                    batch.completed,
+                    batch.total,
+                    batch.failed,
+                    batch
+                        .created_at
+                        .map_or_else(|| "<unknown>".to_string(), |at| at.to_rfc3339())
+                );
+            }
+            Ok(())
+        }
+        JobsCommands::Stats { format } => {
+            let stats = queue.stats().await?;
+            if *format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+                return Ok(());
+            }
+            print_queue_stats(&stats);
+            Ok(())
+        }
         JobsCommands::Retry { id } => {
             let retried = queue.retry_failed(id.as_deref()).await?;
             // The count is the whole point: `--id` on an already-retried or
@@ -1078,6 +1356,116 @@ async fn handle_job_command(command: JobsCommands, app_context: &AppContext) ->
     }
 }
 
+#[cfg(feature = "worker")]
+fn print_job(job: &crate::bgworker::Job) -> crate::Result<()> {
+    let time = |at: Option<chrono::DateTime<chrono::Utc>>| {
+        at.map_or_else(|| "-".to_string(), |at| at.to_rfc3339())
+    };
+    println!("{} {}", job.id.bold(), job.name);
+    println!("  status:     {}", job.status);
+    println!("  attempts:   {}", job.attempts);
+    println!("  priority:   {}", job.priority);
+    println!(
+        "  tags:       {}",
+        job.tags
+            .as_ref()
+            .map_or_else(String::new, |tags| tags.join(", "))
+    );
+    println!("  run at:     {}", job.run_at.to_rfc3339());
+    println!("  created at: {}", time(job.created_at));
+    println!("  updated at: {}", time(job.updated_at));
+    if let Some(interval) = job.interval {
+        println!("  interval:   {interval}ms");
+    }
+    if let Some(progress) = job.progress {
+        println!("  progress:   {progress}%");
+    }
+    if let Some(batch_id) = &job.batch_id {
+        println!("  batch:      {batch_id}");
+    }
+    println!("payload:");
+    println!("{}", serde_json::to_string_pretty(&job.data)?);
+    if let Some(error) = &job.last_error {
+        println!("error:");
+        println!("{}", error.red());
+    }
+    if !job.history.is_empty() {
+        println!("attempts:");
+        for attempt in &job.history {
+            println!(
+                "  #{} {} -> {} {}",
+                attempt.attempt,
+                attempt.started_at.to_rfc3339(),
+                attempt.finished_at.to_rfc3339(),
+                attempt.error.as_deref().unwrap_or("ok")
+            );
+        }
+    }
+    if let Some(result) = &job.result {
+        println!("result:");
+        println!("{}", serde_json::to_string_pretty(result)?);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "worker")]
+fn print_queue_stats(stats: &crate::bgworker::stats::QueueStats) {
+    let ms = |value: Option<u64>| value.map_or_else(|| "-".to_string(), |ms| format!("{ms}ms"));
+    let secs = |value: Option<i64>| value.map_or_else(|| "-".to_string(), |s| format!("{s}s"));
+
+    if stats.workers.is_empty() {
+        println!("no jobs");
+        return;
+    }
+    println!(
+        "{:<32} {:>7} {:>10} {:>9} {:>7} {:>9} {:>11} {:>8} {:>8} {:>8}",
+        "worker".bold(),
+        "queued",
+        "processing",
+        "completed",
+        "failed",
+        "cancelled",
+        "oldest wait",
+        "ok/err",
+        "p50",
+        "p95"
+    );
+    for (class, worker) in &stats.workers {
+        println!(
+            "{:<32} {:>7} {:>10} {:>9} {:>7} {:>9} {:>11} {:>8} {:>8} {:>8}",
+            class,
+            worker.queued,
+            worker.processing,
+            worker.completed,
+            worker.failed,
+            worker.cancelled,
+            secs(worker.oldest_queued_age_secs),
+            format!("{}/{}", worker.succeeded_attempts, worker.failed_attempts),
+            ms(worker.duration.p50_ms),
+            ms(worker.duration.p95_ms)
+        );
+    }
+    if !stats.queues.is_empty() {
+        println!();
+        println!(
+            "{:<16} {:<32} {:>7} {:>11}",
+            "queue".bold(),
+            "worker",
+            "queued",
+            "oldest wait"
+        );
+        for depth in &stats.queues {
+            println!(
+                "{:<16} {:<32} {:>7} {:>11}",
+                depth.queue,
+                depth.class,
+                depth.queued,
+                secs(depth.oldest_queued_age_secs)
+            );
+        }
+    }
+}
+
 #[cfg(debug_assertions)]
 fn handle_generate_command<H: Hooks>(
     component: ComponentArg,
@@ -1220,6 +1608,150 @@ mod tests {
             .contains("key:value"));
     }
 
+    #[test]
+    fn start_queue_narrows_the_queue_config() {
+        let mut config = crate::tests_cfg::config::test_config();
+        config.queue = Some(
+            serde_yaml::from_str(
+                "kind: Sqlite\nuri: \"sqlite::memory:\"\nqueues:\n  critical: 5\n  default: 1\n",
+            )
+            .unwrap(),
+        );
+        let cli = Cli::try_parse_from(["loco", "start", "--worker", "--queue", "critical,mailer"])
+            .unwrap();
+        select_worker_queues(&cli.command, &mut config);
+        let Some(crate::config::QueueConfig::Sqlite(queue)) = config.queue else {
+            panic!("expected a sqlite queue config");
+        };
+        assert_eq!(
+            queue.queues.into_iter().collect::<Vec<_>>(),
+            [("critical".to_string(), 5), ("mailer".to_string(), 1)]
+        );
+
+        // Without a worker there is no queue to narrow.
+        assert!(Cli::try_parse_from(["loco", "start", "--queue", "mailer"]).is_err());
+    }
+
+    #[test]
+    fn scheduler_history_takes_a_job_name() {
+        let cli =
+            Cli::try_parse_from(["loco", "scheduler", "history", "--name", "cleanup"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Scheduler {
+                command: Some(SchedulerCommands::History { name: Some(name), limit: 20, output: false }),
+                ..
+            } if name == "cleanup"
+        ));
+        let cli =
+            Cli::try_parse_from(["loco", "scheduler", "history", "--job", "cleanup"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Scheduler {
+                command: Some(SchedulerCommands::History { name: Some(name), .. }),
+                ..
+            } if name == "cleanup"
+        ));
+        let cli =
+            Cli::try_parse_from(["loco", "scheduler", "--tag", "nightly", "history"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Scheduler {
+                tag: Some(_),
+                command: Some(SchedulerCommands::History { name: None, .. }),
+                ..
+            }
+        ));
+        let cli = Cli::try_parse_from(["loco", "scheduler", "-t", "nightly", "last-run"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Scheduler {
+                tag: Some(_),
+                command: Some(SchedulerCommands::LastRun {}),
+                ..
+            }
+        ));
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn since_takes_an_amount_and_a_unit() {
+        assert_eq!(parse_since("90s").unwrap(), chrono::Duration::seconds(90));
+        assert_eq!(parse_since("1h").unwrap(), chrono::Duration::hours(1));
+        assert_eq!(parse_since("2w").unwrap(), chrono::Duration::days(14));
+        assert!(parse_since("1").unwrap_err().contains("missing unit"));
+        assert!(parse_since("h").unwrap_err().contains("expected a number"));
+        assert!(parse_since("1y").unwrap_err().contains("unknown unit"));
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn jobs_list_filters_by_name_tag_and_age() {
+        let now = chrono::Utc::now();
+        let job = |id: &str, name: &str, tags: &[&str], hours_ago: i64| {
+            serde_json::from_value::<crate::bgworker::Job>(serde_json::json!({
+                "id": id,
+                "name": name,
+                "task_data": null,
+                "status": "failed",
+                "run_at": now,
+                "interval": null,
+                "created_at": now - chrono::Duration::hours(hours_ago),
+                "updated_at": null,
+                "tags": tags,
+            }))
+            .unwrap()
+        };
+        let jobs = [
+            job("1", "Mailer", &["email"], 5),
+            job("2", "Mailer", &[], 3),
+            job("3", "Report", &["email"], 1),
+        ];
+        let ids = |filter: crate::bgworker::JobFilter| {
+            let mut listed = jobs
+                .iter()
+                .filter(|job| filter.matches(job))
+                .cloned()
+                .collect::<Vec<_>>();
+            filter.apply_limit(&mut listed);
+            listed.into_iter().map(|job| job.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(list_filter(None, None, None, None, 50)),
+            ["3", "2", "1"]
+        );
+        assert_eq!(ids(list_filter(None, None, None, None, 2)), ["3", "2"]);
+        assert_eq!(
+            ids(list_filter(None, Some("Mailer"), None, None, 50)),
+            ["2", "1"]
+        );
+        assert_eq!(
+            ids(list_filter(None, None, Some("email"), None, 50)),
+            ["3", "1"]
+        );
+        assert_eq!(
+            ids(list_filter(
+                Some(&[JobStatus::Queued]),
+                None,
+                None,
+                None,
+                50
+            )),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            ids(list_filter(
+                None,
+                Some("Mailer"),
+                None,
+                Some(parse_since("4h").unwrap()),
+                50
+            )),
+            ["2"]
+        );
+    }
+
     #[test]
     fn legacy_api_flag_is_a_noop() {
         // `--api` is the headless default now — accepted, generation proceeds.
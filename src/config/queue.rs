@@ -1,3 +1,5 @@
+use std::collections::{BTreeMap, HashMap};
+
 use serde::{Deserialize, Serialize};
 
 use super::database::{db_connect_timeout, db_idle_timeout, db_max_conn, db_min_conn};
@@ -12,6 +14,9 @@ pub enum QueueConfig {
     Postgres(PostgresQueueConfig),
     /// Sqlite queue
     Sqlite(SqliteQueueConfig),
+    #[cfg(feature = "testing")]
+    /// In-memory queue, for tests
+    InMem,
 }
 
 impl QueueConfig {
@@ -23,6 +28,43 @@ impl QueueConfig {
             Self::Redis(config) => config.dangerously_flush,
             Self::Postgres(config) => config.dangerously_flush,
             Self::Sqlite(config) => config.dangerously_flush,
+            #[cfg(feature = "testing")]
+            Self::InMem => false,
+        }
+    }
+
+    /// Whether this queue keeps its jobs in the application's database,
+    /// sharing its connection pool. See [`PostgresQueueConfig::use_app_db`].
+    #[must_use]
+    pub const fn use_app_db(&self) -> bool {
+        match self {
+            Self::Postgres(config) => config.use_app_db,
+            Self::Sqlite(config) => config.use_app_db,
+            Self::Redis(_) => false,
+            #[cfg(feature = "testing")]
+            Self::InMem => false,
+        }
+    }
+
+    /// Restricts this process's workers to the named `queues`, as
+    /// `cargo loco start --queue` does. A queue keeps the weight it has in
+    /// the config, or gets `1`.
+    pub fn select_queues(&mut self, queues: &[String]) {
+        match self {
+            Self::Redis(config) => config.worker_queues = Some(queues.to_vec()),
+            Self::Postgres(PostgresQueueConfig {
+                queues: weights, ..
+            })
+            | Self::Sqlite(SqliteQueueConfig {
+                queues: weights, ..
+            }) => {
+                *weights = queues
+                    .iter()
+                    .map(|queue| (queue.clone(), weights.get(queue).copied().unwrap_or(1)))
+                    .collect();
+            }
+            #[cfg(feature = "testing")]
+            Self::InMem => {}
         }
     }
 }
@@ -37,18 +79,44 @@ pub struct RedisQueueConfig {
     /// First queue in list is more important.
     pub queues: Option<Vec<String>>,
 
+    /// The queues this process's workers take jobs from, in this order,
+    /// instead of the default queues and `queues`. Set by
+    /// `cargo loco start --queue`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_queues: Option<Vec<String>>,
+
     #[serde(default = "num_workers")]
     pub num_workers: u32,
 
     /// Opt-in visibility-timeout reaper. See [`ReaperConfig`].
     #[serde(default)]
     pub reaper: Option<ReaperConfig>,
+
+    /// Per-worker concurrency caps and rate limits, keyed by worker class
+    /// name. See [`WorkerLimitsConfig`].
+    #[serde(default)]
+    pub limits: HashMap<String, WorkerLimitsConfig>,
+
+    /// On shutdown, how long running jobs get to finish before they are
+    /// interrupted and put back in the queue.
+    #[serde(default = "shutdown_timeout_sec")]
+    pub shutdown_timeout_sec: u64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PostgresQueueConfig {
+    /// Ignored with `use_app_db`.
+    #[serde(default)]
     pub uri: String,
 
+    /// Keeps the jobs in the application's database (`database.uri`),
+    /// sharing its connection pool instead of opening one from `uri`; the
+    /// pool settings below are then ignored too. The queue tables are still
+    /// created on startup; `bgworker::migration::Migration` also creates
+    /// them, for apps whose migrations refer to them.
+    #[serde(default)]
+    pub use_app_db: bool,
+
     #[serde(default)]
     pub dangerously_flush: bool,
 
@@ -70,18 +138,46 @@ pub struct PostgresQueueConfig {
     #[serde(default = "pgq_poll_interval")]
     pub poll_interval_sec: u32,
 
+    /// Wakes idle workers through `LISTEN`/`NOTIFY` as soon as a job is
+    /// enqueued, leaving `poll_interval_sec` as a fallback. Turn it off
+    /// behind a proxy that does not support `LISTEN`, such as `PgBouncer` in
+    /// transaction pooling mode.
+    #[serde(default = "pgq_listen")]
+    pub listen: bool,
+
+    /// Named queues with their weights. See [`QueueWeights`].
+    #[serde(default)]
+    pub queues: QueueWeights,
+
     #[serde(default = "num_workers")]
     pub num_workers: u32,
 
     /// Opt-in visibility-timeout reaper. See [`ReaperConfig`].
     #[serde(default)]
     pub reaper: Option<ReaperConfig>,
+
+    /// Per-worker concurrency caps and rate limits, keyed by worker class
+    /// name. See [`WorkerLimitsConfig`].
+    #[serde(default)]
+    pub limits: HashMap<String, WorkerLimitsConfig>,
+
+    /// On shutdown, how long running jobs get to finish before they are
+    /// interrupted and put back in the queue.
+    #[serde(default = "shutdown_timeout_sec")]
+    pub shutdown_timeout_sec: u64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SqliteQueueConfig {
+    /// Ignored with `use_app_db`.
+    #[serde(default)]
     pub uri: String,
 
+    /// Keeps the jobs in the application's database. See
+    /// [`PostgresQueueConfig::use_app_db`].
+    #[serde(default)]
+    pub use_app_db: bool,
+
     #[serde(default)]
     pub dangerously_flush: bool,
 
@@ -103,12 +199,88 @@ pub struct SqliteQueueConfig {
     #[serde(default = "sqlt_poll_interval")]
     pub poll_interval_sec: u32,
 
+    /// Named queues with their weights. See [`QueueWeights`].
+    #[serde(default)]
+    pub queues: QueueWeights,
+
     #[serde(default = "num_workers")]
     pub num_workers: u32,
 
     /// Opt-in visibility-timeout reaper. See [`ReaperConfig`].
     #[serde(default)]
     pub reaper: Option<ReaperConfig>,
+
+    /// Per-worker concurrency caps and rate limits, keyed by worker class
+    /// name. See [`WorkerLimitsConfig`].
+    #[serde(default)]
+    pub limits: HashMap<String, WorkerLimitsConfig>,
+
+    /// On shutdown, how long running jobs get to finish before they are
+    /// interrupted and put back in the queue.
+    #[serde(default = "shutdown_timeout_sec")]
+    pub shutdown_timeout_sec: u64,
+}
+
+/// The named queues a Postgres or `SQLite` queue's workers take jobs from,
+/// with their weights. A job goes to the queue its worker's
+/// `BackgroundWorker::queue()` names, or `default`.
+///
+/// Before each job, a worker orders the queues at random, each coming first
+/// with a chance proportional to its weight, and takes the first due job in
+/// that order. With every queue backed up, they are served in proportion to
+/// their weights; a queue with weight `0` is only served once the others
+/// are empty. Empty (the default), workers take jobs from every queue alike.
+///
+/// ```yaml
+/// queue:
+///   kind: Postgres
+///   uri: "..."
+///   queues:
+///     critical: 6
+///     default: 3
+///     mailer: 1
+/// ```
+///
+/// Jobs in a queue that is not listed stay queued, so list every queue jobs
+/// are enqueued to, including `mailer` for mailers.
+pub type QueueWeights = BTreeMap<String, u32>;
+
+/// Limits for one worker class, overriding what its `BackgroundWorker` impl
+/// declares (`concurrency()` and `rate_limit()`). They hold across every
+/// worker process sharing the queue.
+///
+/// ```yaml
+/// queue:
+///   kind: Postgres
+///   uri: "..."
+///   limits:
+///     VideoTranscode:
+///       # at most 2 running at once
+///       concurrency: 2
+///     WebhookDelivery:
+///       # at most 10 started per second
+///       rate_limit:
+///         limit: 10
+///         period_ms: 1000
+/// ```
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct WorkerLimitsConfig {
+    /// How many jobs of the class may be processing at once.
+    #[serde(default)]
+    pub concurrency: Option<u32>,
+
+    /// How many jobs of the class may start per period.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimitConfig {
+    pub limit: u32,
+
+    /// The period, in milliseconds, `limit` applies to.
+    #[serde(default = "default_rate_limit_period_ms")]
+    pub period_ms: u64,
 }
 
 /// Configuration for an opt-in visibility-timeout reaper.
@@ -146,10 +318,18 @@ fn pgq_poll_interval() -> u32 {
     1
 }
 
+const fn pgq_listen() -> bool {
+    true
+}
+
 fn sqlt_poll_interval() -> u32 {
     1
 }
 
+const fn shutdown_timeout_sec() -> u64 {
+    30
+}
+
 fn num_workers() -> u32 {
     2
 }
@@ -157,3 +337,7 @@ fn num_workers() -> u32 {
 fn default_reaper_interval_seconds() -> u64 {
     60
 }
+
+fn default_rate_limit_period_ms() -> u64 {
+    1000
+}
@@ -421,6 +421,9 @@ mod tests {
             queues: None,
             num_workers: 1,
             reaper: None,
+            worker_queues: None,
+            limits: Default::default(),
+            shutdown_timeout_sec: 30,
         }));
 
         // Create Redis queue provider directly with failing Redis connection
@@ -431,6 +434,9 @@ mod tests {
                 queues: None,
                 num_workers: 1,
                 reaper: None,
+                worker_queues: None,
+                limits: Default::default(),
+                shutdown_timeout_sec: 30,
             })
             .await
             .expect("Failed to create Redis queue provider"),
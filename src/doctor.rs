@@ -191,7 +191,7 @@ pub async fn run_all<H: crate::app::Hooks>(
     );
 
     if app_context.config.workers.mode == config::WorkerMode::BackgroundQueue {
-        checks.insert(Resource::Queue, check_queue(&app_context.config).await);
+        checks.insert(Resource::Queue, check_queue(app_context).await);
     }
 
     // Add initializer checks
@@ -359,9 +359,14 @@ pub async fn check_db(config: &crate::config::Database) -> Check {
     }
 }
 
-/// Checks the Redis connection.
-pub async fn check_queue(config: &Config) -> Check {
-    match bgworker::create_queue_provider(config).await {
+/// Checks the queue connection, on the application's database connection for
+/// a queue with `use_app_db`.
+pub async fn check_queue(ctx: &crate::app::AppContext) -> Check {
+    #[cfg(feature = "with-db")]
+    let queue = bgworker::create_queue_provider_with_db(&ctx.config, &ctx.db).await;
+    #[cfg(not(feature = "with-db"))]
+    let queue = bgworker::create_queue_provider(&ctx.config).await;
+    match queue {
         Ok(Some(queue)) => match queue.ping().await {
             Ok(()) => Check {
                 status: CheckStatus::Ok,
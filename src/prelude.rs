@@ -30,7 +30,7 @@ pub use crate::controller::extractor::{
 pub use crate::model::{query, Authenticable, ModelError, ModelResult};
 pub use crate::{
     app::{AppContext, Initializer},
-    bgworker::{BackgroundWorker, Queue},
+    bgworker::{BackgroundWorker, Backoff, Queue, RateLimit, UniqueWindow},
     controller::{
         bad_request, format,
         middleware::{
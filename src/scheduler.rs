@@ -1,20 +1,47 @@
 //! # Scheduler Module
 //! TBD
 
+pub mod history;
+pub mod lock;
+
 use std::{
     collections::HashMap,
-    fmt, io,
+    fmt,
+    future::Future,
+    io::{self, Read, Write},
     path::{Path, PathBuf},
-    sync::OnceLock,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
     time::{Duration, Instant},
 };
 
+use chrono::{DateTime, Offset, SubsecRound, TimeZone, Utc};
+use chrono_tz::Tz;
+use croner::{parser::CronParser, Cron};
+pub use history::History;
+use history::{Outcome, Run, RunStore};
+pub use lock::Lock;
+use lock::TickLock;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use tokio::{
+    sync::{Mutex, OwnedMutexGuard},
+    task::JoinSet,
+};
 use tokio_cron_scheduler::{JobScheduler, JobSchedulerError};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 use uuid::Uuid;
 
-use crate::{app::Hooks, environment::Environment, task::Tasks};
+use crate::{
+    app::{AppContext, Hooks},
+    environment::Environment,
+    task::{Tasks, Vars},
+};
 
 static RE_IS_CRON_SYNTAX: OnceLock<Regex> = OnceLock::new();
 
@@ -40,6 +67,33 @@ pub enum Error {
     #[error("Invalid cron {cron}. err: '{}'", error.as_display())]
     InvalidCronSyntax { cron: String, error: String },
 
+    #[error("job `{0}` enqueues a worker, which needs `mode: in_process`")]
+    WorkerNeedsInProcess(String),
+
+    #[error("worker `{0}` is not registered on the queue")]
+    WorkerNotFound(String),
+
+    #[error("invalid task argument `{0}`, expected `key:value`")]
+    InvalidTaskArg(String),
+
+    #[error(
+        "`mode: in_process`, `lock` and a queue `history` run jobs against the app \
+         context; use `run_with_context`"
+    )]
+    NoContext,
+
+    #[error("scheduler lock: {0}")]
+    Lock(String),
+
+    #[error("invalid timezone `{0}`, expected an IANA name such as `Europe/Berlin`")]
+    InvalidTimezone(String),
+
+    #[error("scheduler history: {0}")]
+    History(String),
+
+    #[error("scheduler history is not recorded; set `history` in the scheduler config")]
+    NoHistory,
+
     #[error(transparent)]
     Question(#[from] JobSchedulerError),
 
@@ -50,6 +104,96 @@ pub enum Error {
 /// Result type used in the module, with a custom error type.
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Turns a schedule, either a cron expression or an English description
+/// such as `every day at 3am`, into cron syntax.
+pub(crate) fn cron_syntax(schedule: &str) -> Result<String> {
+    if get_re_is_cron_syntax().is_match(schedule) {
+        return Ok(schedule.to_string());
+    }
+    english_to_cron::str_cron_syntax(schedule).map_err(|err| Error::InvalidCronSyntax {
+        cron: schedule.to_string(),
+        error: err.to_string(),
+    })
+}
+
+/// Parses a schedule, either a cron expression with seconds or an English
+/// description, as the scheduler ticks it.
+pub(crate) fn parse_cron(schedule: &str) -> Result<Cron> {
+    CronParser::builder()
+        .seconds(croner::parser::Seconds::Required)
+        .dom_and_dow(true)
+        .build()
+        .parse(&cron_syntax(schedule)?)
+        .map_err(|err| Error::InvalidCronSyntax {
+            cron: schedule.to_string(),
+            error: err.to_string(),
+        })
+}
+
+/// Parses an IANA timezone name.
+fn parse_timezone(name: &str) -> Result<Tz> {
+    name.parse()
+        .map_err(|_| Error::InvalidTimezone(name.to_string()))
+}
+
+/// When a job ticks: its cron schedule, read in its timezone.
+///
+/// Across a DST change, a time the clock skips ticks on the first instant
+/// after the gap. A time the clock repeats ticks on its first occurrence
+/// only, unless the schedule's hour field is a wildcard: such a job keeps
+/// ticking through the repeated hour, as through any other.
+#[derive(Clone, Debug)]
+struct Ticks {
+    cron: Cron,
+    timezone: Tz,
+}
+
+impl Ticks {
+    /// The first tick strictly after `time`.
+    fn next_after(&self, time: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        // Cron instants are whole seconds, and a search from a fraction of
+        // one keeps the fraction.
+        let time = time.trunc_subsecs(0);
+        let local = time.with_timezone(&self.timezone);
+        let mut tick = self.find_next(&local)?;
+        // In a repeated hour, the next local time resolves to its first
+        // occurrence, which may already be past.
+        while tick <= time {
+            tick = self.find_next(&tick.with_timezone(&self.timezone))?;
+        }
+        if !self.cron.pattern.hours.from_wildcard {
+            return Ok(tick);
+        }
+        // Read at a fixed UTC offset, the schedule goes on through the
+        // repeated hour; a tick found so is kept if the timezone agrees on
+        // its local time.
+        let offsets = [
+            local.offset().fix(),
+            tick.with_timezone(&self.timezone).offset().fix(),
+        ];
+        for offset in offsets {
+            let candidate = self.find_next(&time.with_timezone(&offset))?;
+            if candidate < tick
+                && candidate.with_timezone(&offset).naive_local()
+                    == candidate.with_timezone(&self.timezone).naive_local()
+            {
+                tick = candidate;
+            }
+        }
+        Ok(tick)
+    }
+
+    fn find_next<Z: TimeZone>(&self, time: &DateTime<Z>) -> Result<DateTime<Utc>> {
+        self.cron
+            .find_next_occurrence(time, false)
+            .map(|tick| tick.with_timezone(&Utc))
+            .map_err(|err| Error::InvalidCronSyntax {
+                cron: self.cron.pattern.to_string(),
+                error: err.to_string(),
+            })
+    }
+}
+
 /// Configuration structure for the scheduler.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -59,6 +203,35 @@ pub struct Config {
     /// The default output setting for the jobs.
     #[serde(default)]
     pub output: Output,
+    /// Where the jobs that are not `shell: true` run.
+    #[serde(default)]
+    pub mode: Mode,
+    /// Where the scheduler claims each tick before running it, so that
+    /// several processes running it run a tick once.
+    #[serde(default)]
+    pub lock: Lock,
+    /// The timezone of the jobs without one of their own, as an IANA name
+    /// (`Europe/Berlin`). Defaults to UTC.
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Where the scheduler records the runs of its jobs.
+    #[serde(default)]
+    pub history: History,
+}
+
+/// Where the scheduler runs a job that is not `shell: true`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+    /// In a new process of the app binary (`<binary> task ...`), which boots
+    /// the app again on every tick.
+    #[default]
+    #[serde(rename = "subprocess")]
+    Subprocess,
+    /// In the scheduler's own process, against its already booted
+    /// [`AppContext`]: tasks run in it, and `worker: true` jobs are
+    /// enqueued on its queue.
+    #[serde(rename = "in_process")]
+    InProcess,
 }
 
 /// Representing a single job in the scheduler.
@@ -70,8 +243,26 @@ pub struct Job {
     pub run: String,
     #[serde(default)]
     pub shell: bool,
+    /// `run` is the class name of a background worker to enqueue, with
+    /// `args`, rather than a task. Needs `mode: in_process`.
+    #[serde(default)]
+    pub worker: bool,
+    /// The args of a `worker: true` job.
+    #[serde(default)]
+    pub args: Option<JsonValue>,
     #[serde(default)]
     pub run_on_start: bool,
+    /// Whether a tick starts a run while the previous one is still going.
+    #[serde(default = "allow_overlap")]
+    pub allow_overlap: bool,
+    /// What becomes of a tick that would overlap, with `allow_overlap:
+    /// false`.
+    #[serde(default)]
+    pub on_overlap: Overlap,
+    /// The longest a run may take, in seconds: a command is killed, and an
+    /// in-process run cancelled, past it.
+    #[serde(default)]
+    pub timeout: Option<u64>,
     #[serde(rename = "schedule")]
     /// The cron expression defining the job's schedule.
     ///
@@ -79,6 +270,10 @@ pub struct Job {
     /// sec   min   hour   day of month   month   day of week   year
     /// * *     *      *              *       *             *
     pub cron: String,
+    /// The timezone `cron` is read in, as an IANA name (`Europe/Berlin`).
+    /// Defaults to the scheduler's `timezone`.
+    #[serde(default)]
+    pub timezone: Option<String>,
     /// Tags for tagging the job.
     pub tags: Option<Vec<String>>,
     /// Output settings for the job.
@@ -116,6 +311,24 @@ impl fmt::Display for Scheduler {
     }
 }
 
+const fn allow_overlap() -> bool {
+    true
+}
+
+/// What becomes of a tick while the job's previous run is still going, with
+/// `allow_overlap: false`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Overlap {
+    /// The tick is skipped.
+    #[default]
+    #[serde(rename = "skip")]
+    Skip,
+    /// The tick runs once the previous run finishes. A single tick waits:
+    /// the ones after it are skipped.
+    #[serde(rename = "queue")]
+    Queue,
+}
+
 /// Representing the scheduler itself.
 #[derive(Clone, Debug)]
 pub struct Scheduler {
@@ -123,6 +336,11 @@ pub struct Scheduler {
     binary_path: PathBuf,
     default_output: Output,
     environment: Environment,
+    mode: Mode,
+    lock: Lock,
+    timezone: Tz,
+    history: History,
+    tasks: Arc<Tasks>,
 }
 
 /// Specification used to filter all scheduler job with the given Spec.
@@ -184,6 +402,136 @@ impl Job {
             environment: environment.clone(),
         }
     }
+
+    /// The task name and vars of a task job: `run` is the task name followed
+    /// by `key:value` args, as given to `cargo loco task`.
+    fn task_args(&self) -> Result<(String, Vec<(String, String)>)> {
+        let mut words = self.run.split_whitespace();
+        let name = words.next().unwrap_or_default().to_string();
+        let args = words
+            .map(|arg| {
+                arg.split_once(':')
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .ok_or_else(|| Error::InvalidTaskArg(arg.to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok((name, args))
+    }
+}
+
+/// What a job does on every tick.
+#[derive(Clone)]
+enum Action {
+    /// Runs a command in a subprocess.
+    Command(JobDescription),
+    /// Runs a registered task in this process.
+    Task {
+        ctx: AppContext,
+        tasks: Arc<Tasks>,
+        name: String,
+        args: Vec<(String, String)>,
+    },
+    /// Enqueues a job of a registered worker.
+    Worker {
+        ctx: AppContext,
+        class: String,
+        args: JsonValue,
+    },
+}
+
+impl Action {
+    /// Runs the action, capturing a command's output with `capture`.
+    async fn perform(
+        self,
+        job_name: String,
+        uuid: Uuid,
+        timeout: Option<Duration>,
+        capture: bool,
+    ) -> Run {
+        if let Self::Command(job_description) = self {
+            let started_at = Utc::now();
+            // `job_description.run()` blocks the thread for the whole
+            // child-process lifetime; keep it off the async runtime's worker
+            // threads.
+            let blocking_name = job_name.clone();
+            return tokio::task::spawn_blocking(move || {
+                execute_job(&blocking_name, uuid, &job_description, timeout, capture)
+            })
+            .await
+            .unwrap_or_else(|err| {
+                let mut run = Run::new(&job_name, started_at, Outcome::Failed);
+                run.error = Some(err.to_string());
+                run
+            });
+        }
+        let task_span = tracing::span!(
+            tracing::Level::DEBUG,
+            "run_job",
+            job_name,
+            job_id = ?uuid,
+        );
+        async move {
+            let started_at = Utc::now();
+            let start = Instant::now();
+            let run = self.perform_in_process();
+            let result = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, run).await,
+                None => Ok(run.await),
+            };
+            match result {
+                Ok(Ok(())) => {
+                    tracing::debug!(
+                        duration = ?start.elapsed(),
+                        outcome = "succeeded",
+                        "execute scheduler job finished"
+                    );
+                    Run::new(&job_name, started_at, Outcome::Succeeded)
+                }
+                Ok(Err(err)) => {
+                    tracing::error!(
+                        duration = ?start.elapsed(),
+                        outcome = "failed",
+                        error = %err,
+                        "failed to execute scheduler job in process"
+                    );
+                    let mut run = Run::new(&job_name, started_at, Outcome::Failed);
+                    run.error = Some(err.to_string());
+                    run
+                }
+                Err(_) => {
+                    tracing::warn!(
+                        duration = ?start.elapsed(),
+                        outcome = "timed_out",
+                        timeout = ?timeout,
+                        "scheduler job timed out and was cancelled"
+                    );
+                    Run::new(&job_name, started_at, Outcome::TimedOut)
+                }
+            }
+        }
+        .instrument(task_span)
+        .await
+    }
+
+    async fn perform_in_process(self) -> crate::Result<()> {
+        match self {
+            Self::Command(_) => Ok(()),
+            Self::Task {
+                ctx,
+                tasks,
+                name,
+                args,
+            } => tasks.run(&ctx, &name, &Vars::from_cli_args(args)).await,
+            Self::Worker { ctx, class, args } => {
+                let queue = ctx
+                    .queue_provider
+                    .as_ref()
+                    .ok_or(crate::Error::QueueProviderMissing)?;
+                queue.enqueue_registered(&class, args).await?;
+                Ok(())
+            }
+        }
+    }
 }
 
 impl JobDescription {
@@ -194,6 +542,57 @@ impl JobDescription {
     /// In addition to all the IO errors possible
     pub fn run(&self) -> io::Result<std::process::Output> {
         tracing::info!(command = &self.command, "execute job command");
+        self.expression(false).run()
+    }
+
+    /// Executes the job command for the scheduler: whatever its exit code,
+    /// killing it past `timeout`, and keeping the end of its output with
+    /// `capture`. Captured output still goes to the process's own with
+    /// `output: stdout`, as the command writes it.
+    fn execute(&self, timeout: Option<Duration>, capture: bool) -> io::Result<Executed> {
+        tracing::info!(command = &self.command, "execute job command");
+        let mut expression = self.expression(capture).unchecked();
+        let mut tees = None;
+        if capture {
+            let (stdout, stdout_writer) = io::pipe()?;
+            let (stderr, stderr_writer) = io::pipe()?;
+            expression = expression
+                .stdout_file(stdout_writer)
+                .stderr_file(stderr_writer);
+            let live = matches!(self.output, Output::STDOUT);
+            tees = Some((
+                tee(stdout, live.then(io::stdout)),
+                tee(stderr, live.then(io::stderr)),
+            ));
+        }
+        if timeout.is_some() {
+            expression = in_own_group(expression);
+        }
+        let handle = expression.start()?;
+        // The command holds the write ends of the pipes now; closing ours
+        // lets the tees see the end of its output.
+        drop(expression);
+        let status = match timeout {
+            Some(timeout) => handle.wait_timeout(timeout)?.map(|output| output.status),
+            None => Some(handle.wait()?.status),
+        };
+        if status.is_none() {
+            kill(&handle)?;
+        }
+        let (stdout, stderr) = tees.map_or_else(Default::default, |(stdout, stderr)| {
+            (
+                stdout.join().unwrap_or_default(),
+                stderr.join().unwrap_or_default(),
+            )
+        });
+        Ok(Executed {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    fn expression(&self, capture: bool) -> duct::Expression {
         // Run the command through the platform shell (previously `duct_sh`).
         let shell = if cfg!(windows) {
             duct::cmd!("cmd.exe", "/C", &self.command)
@@ -201,13 +600,87 @@ impl JobDescription {
             duct::cmd!("/bin/sh", "-c", &self.command)
         };
         let mut exec_job = shell.env("LOCO_ENV", self.environment.to_string());
+        if capture {
+            // `execute` sends the output to its tees.
+            return exec_job;
+        }
         exec_job = match self.output {
             Output::Silent => exec_job.stdout_null().stderr_null(),
             Output::STDOUT => exec_job,
         };
 
-        exec_job.run()
+        exec_job
+    }
+}
+
+/// How a command run by [`JobDescription::execute`] ended.
+struct Executed {
+    /// Its exit status, or `None` when it was killed past its timeout.
+    status: Option<std::process::ExitStatus>,
+    /// The end of its stdout, when captured.
+    stdout: String,
+    /// The end of its stderr, when captured.
+    stderr: String,
+}
+
+/// Reads a command's output to its end on a thread of its own, copying it to
+/// `sink` as it comes and keeping its last [`history::MAX_OUTPUT`] bytes.
+fn tee<W: Write + Send + 'static>(
+    mut reader: io::PipeReader,
+    mut sink: Option<W>,
+) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut kept = Vec::new();
+        let mut chunk = [0; 8192];
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if let Some(sink) = &mut sink {
+                // The scheduler's own output going away must not stop the
+                // command's from being read.
+                let _ = sink.write_all(&chunk[..read]).and_then(|()| sink.flush());
+            }
+            kept.extend_from_slice(&chunk[..read]);
+            if kept.len() > 2 * history::MAX_OUTPUT {
+                let mut cut = kept.len() - history::MAX_OUTPUT;
+                while kept.get(cut).is_some_and(|byte| byte & 0xC0 == 0x80) {
+                    cut += 1;
+                }
+                kept.drain(..cut);
+            }
+        }
+        history::tail(&kept)
+    })
+}
+
+/// Starts a command in a process group of its own, so that killing it on a
+/// timeout kills the processes it started too, rather than only the shell
+/// running it.
+fn in_own_group(expression: duct::Expression) -> duct::Expression {
+    #[cfg(unix)]
+    let expression = expression.before_spawn(|command| {
+        std::os::unix::process::CommandExt::process_group(command, 0);
+        Ok(())
+    });
+    expression
+}
+
+/// Kills the command `handle` runs, and the process group of a command
+/// started with [`in_own_group`].
+fn kill(handle: &duct::Handle) -> io::Result<()> {
+    #[cfg(unix)]
+    for pid in handle.pids() {
+        if let Ok(pid) = i32::try_from(pid) {
+            // SAFETY: `kill` only sends a signal; a group that already exited
+            // makes it fail with `ESRCH`, which is fine.
+            unsafe { libc::kill(-pid, libc::SIGKILL) };
+        }
     }
+    handle.kill()
 }
 
 impl Scheduler {
@@ -241,17 +714,33 @@ impl Scheduler {
         let mut tasks = Tasks::default();
         H::register_tasks(&mut tasks);
 
+        let timezone = data
+            .timezone
+            .as_deref()
+            .map_or(Ok(Tz::UTC), parse_timezone)?;
         let mut jobs = HashMap::new();
         for (job_name, job) in &data.jobs {
+            if let Some(timezone) = &job.timezone {
+                parse_timezone(timezone)?;
+            }
             if job.shell {
                 jobs.insert(job_name.clone(), job.clone());
+            } else if job.worker {
+                // Workers are registered once the app boots; checked by
+                // `run_with_context`.
+                if data.mode != Mode::InProcess {
+                    return Err(Error::WorkerNeedsInProcess(job_name.clone()));
+                }
+                jobs.insert(job_name.clone(), job.clone());
             } else {
                 let task_name = job.run.split_whitespace().next().unwrap_or("");
-                if tasks.names().iter().any(|name| name.as_str() == task_name) {
-                    jobs.insert(job_name.clone(), job.clone());
-                } else {
+                if !tasks.names().iter().any(|name| name.as_str() == task_name) {
                     return Err(Error::TaskNotFound(task_name.to_string()));
                 }
+                if data.mode == Mode::InProcess {
+                    job.task_args()?;
+                }
+                jobs.insert(job_name.clone(), job.clone());
             }
         }
 
@@ -264,9 +753,21 @@ impl Scheduler {
             binary_path: std::env::current_exe()?,
             default_output: data.output.clone(),
             environment: environment.clone(),
+            mode: data.mode,
+            lock: data.lock,
+            timezone,
+            history: data.history.clone(),
+            tasks: Arc::new(tasks),
         })
     }
 
+    /// Whether some jobs enqueue a background worker, which has to be
+    /// registered on the app's queue before the scheduler runs.
+    #[must_use]
+    pub fn enqueues_workers(&self) -> bool {
+        self.jobs.values().any(|job| job.worker)
+    }
+
     /// Filters the scheduler's jobs based on the provided specification.
     #[must_use]
     pub fn by_spec(self, include_jobs: &Spec) -> Self {
@@ -295,101 +796,528 @@ impl Scheduler {
     ///
     /// # Errors
     ///
-    /// When could not add job to the scheduler
+    /// When could not add job to the scheduler, or with `mode: in_process`,
+    /// a `lock` or a `Queue` history, which need
+    /// [`Scheduler::run_with_context`].
     pub async fn run(self) -> Result<()> {
-        let mut sched = JobScheduler::new().await?;
+        self.run_jobs(None).await
+    }
+
+    /// Runs the scheduled jobs according to their cron expressions, with
+    /// `mode: in_process` performing them against `ctx`, and a `lock`
+    /// claiming ticks in the queue or cache of `ctx`.
+    ///
+    /// # Errors
+    ///
+    /// When could not add job to the scheduler, when a `worker: true`
+    /// job's worker is not registered on the queue of `ctx`, or when the
+    /// lock's store cannot be reached.
+    pub async fn run_with_context(self, ctx: &AppContext) -> Result<()> {
+        self.run_jobs(Some(ctx)).await
+    }
+
+    /// The latest `limit` recorded runs of job `job`, or of every job,
+    /// newest first. Only a `Queue` history needs `ctx`.
+    ///
+    /// # Errors
+    ///
+    /// When `history` is not set, or its store cannot be read.
+    pub async fn runs(
+        &self,
+        ctx: Option<&AppContext>,
+        job: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Run>> {
+        let history = history::connect(&self.history, ctx)
+            .await?
+            .ok_or(Error::NoHistory)?;
+        history.runs(job, limit).await
+    }
+
+    /// Like [`Scheduler::runs`], but only of the scheduler's jobs, such as
+    /// those left by [`Scheduler::by_spec`].
+    ///
+    /// # Errors
+    ///
+    /// When `history` is not set, or its store cannot be read.
+    pub async fn runs_of_jobs(
+        &self,
+        ctx: Option<&AppContext>,
+        job: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Run>> {
+        let history = history::connect(&self.history, ctx)
+            .await?
+            .ok_or(Error::NoHistory)?;
+        let mut runs = Vec::new();
+        for job_name in self
+            .jobs
+            .keys()
+            .filter(|job_name| job.is_none_or(|job| job == job_name.as_str()))
+        {
+            runs.extend(history.runs(Some(job_name), limit).await?);
+        }
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+        runs.truncate(limit);
+        Ok(runs)
+    }
+
+    /// The latest recorded run of every job, by job name, `None` for the jobs
+    /// that never ran. Only a `Queue` history needs `ctx`.
+    ///
+    /// # Errors
+    ///
+    /// When `history` is not set, or its store cannot be read.
+    pub async fn last_runs(&self, ctx: Option<&AppContext>) -> Result<Vec<(String, Option<Run>)>> {
+        let history = history::connect(&self.history, ctx)
+            .await?
+            .ok_or(Error::NoHistory)?;
+        let mut job_names: Vec<&String> = self.jobs.keys().collect();
+        job_names.sort();
+        let mut last_runs = Vec::new();
+        for job_name in job_names {
+            let run = history.runs(Some(job_name), 1).await?.pop();
+            last_runs.push((job_name.clone(), run));
+        }
+        Ok(last_runs)
+    }
+
+    /// What `job` does on every tick.
+    fn action(&self, job: &Job, ctx: Option<&AppContext>) -> Result<Action> {
+        if job.shell || self.mode == Mode::Subprocess {
+            return Ok(Action::Command(job.prepare_command(
+                &self.binary_path,
+                &self.default_output,
+                &self.environment,
+            )));
+        }
+        let ctx = ctx.ok_or(Error::NoContext)?.clone();
+        if job.worker {
+            if !ctx
+                .queue_provider
+                .as_ref()
+                .is_some_and(|queue| queue.is_registered(&job.run))
+            {
+                return Err(Error::WorkerNotFound(job.run.clone()));
+            }
+            return Ok(Action::Worker {
+                ctx,
+                class: job.run.clone(),
+                args: job.args.clone().unwrap_or(JsonValue::Null),
+            });
+        }
+        let (name, args) = job.task_args()?;
+        Ok(Action::Task {
+            ctx,
+            tasks: self.tasks.clone(),
+            name,
+            args,
+        })
+    }
+
+    /// When `job` ticks.
+    fn ticks(&self, job: &Job) -> Result<Ticks> {
+        Ok(Ticks {
+            cron: parse_cron(&job.cron)?,
+            timezone: job
+                .timezone
+                .as_deref()
+                .map_or(Ok(self.timezone), parse_timezone)?,
+        })
+    }
 
+    async fn run_jobs(self, ctx: Option<&AppContext>) -> Result<()> {
+        let mut sched = JobScheduler::new().await?;
+        let lock = match (self.lock, ctx) {
+            (Lock::None, _) => None,
+            (lock, Some(ctx)) => lock::connect(lock, ctx).await?,
+            (_, None) => return Err(Error::NoContext),
+        };
+        let history = history::connect(&self.history, ctx).await?;
+        let mut jobs = Vec::new();
         for (job_name, job) in &self.jobs {
-            let job_description =
-                job.prepare_command(&self.binary_path, &self.default_output, &self.environment);
+            jobs.push((
+                job_name.clone(),
+                job,
+                self.action(job, ctx)?,
+                self.ticks(job)?,
+            ));
+        }
 
-            let cron_syntax = if get_re_is_cron_syntax().is_match(&job.cron) {
-                job.cron.clone()
-            } else {
-                english_to_cron::str_cron_syntax(&job.cron).map_err(|err| {
-                    Error::InvalidCronSyntax {
-                        cron: job.cron.clone(),
-                        error: err.to_string(),
-                    }
-                })?
+        let stop = CancellationToken::new();
+        let running = Arc::new(std::sync::Mutex::new(JoinSet::new()));
+        for (job_name, job, action, ticks) in jobs {
+            let runner = Runner {
+                job_name,
+                uuid: Uuid::new_v4(),
+                action,
+                timeout: job.timeout.map(Duration::from_secs),
+                overlap: (!job.allow_overlap).then_some(job.on_overlap),
+                lock: lock.clone(),
+                history: history.clone(),
+                running: Arc::default(),
+                queued: Arc::default(),
+                stop: stop.clone(),
             };
-
+            // Starting up is not a tick: every process runs its
+            // `run_on_start` jobs.
             if job.run_on_start {
-                let job_description = job_description.clone();
-                let job_name = job_name.clone();
-                sched
-                    .add(tokio_cron_scheduler::Job::new_one_shot_async(
-                        Duration::from_secs(0),
-                        move |uuid, _l| {
-                            let job_description = job_description.clone();
-                            let job_name = job_name.clone();
-                            Box::pin(async move {
-                                // `job_description.run()` blocks the thread for the
-                                // whole child-process lifetime; keep it off the
-                                // async runtime's worker threads.
-                                let _ = tokio::task::spawn_blocking(move || {
-                                    execute_job(job_name.as_str(), uuid, &job_description);
-                                })
-                                .await;
-                            })
-                        },
-                    )?)
-                    .await?;
+                runs(&running).spawn(runner.clone().fire(None));
             }
-
-            let job_name = job_name.clone();
-            sched
-                .add(tokio_cron_scheduler::Job::new_async(
-                    cron_syntax.as_str(),
-                    move |uuid, mut _l| {
-                        let job_description = job_description.clone();
-                        let job_name = job_name.clone();
-                        Box::pin(async move {
-                            // `job_description.run()` blocks the thread for the whole
-                            // child-process lifetime; keep it off the async runtime's
-                            // worker threads.
-                            let _ = tokio::task::spawn_blocking(move || {
-                                execute_job(job_name.as_str(), uuid, &job_description);
-                            })
-                            .await;
-                        })
-                    },
-                )?)
-                .await?;
+            schedule(sched.clone(), runner, ticks, Utc::now(), running.clone()).await?;
         }
 
         sched.start().await?;
 
         tokio::signal::ctrl_c().await?;
+        stop.cancel();
         sched.shutdown().await?;
+        let mut running = std::mem::take(&mut *runs(&running));
+        tokio::select! {
+            () = async { while running.join_next().await.is_some() {} } => {}
+            () = tokio::time::sleep(SHUTDOWN_GRACE) => {
+                tracing::warn!("scheduler runs still going after the shutdown grace period, stopping them");
+            }
+            _ = tokio::signal::ctrl_c() => {
+                tracing::warn!("stopping the scheduler runs still going");
+            }
+        }
+        running.shutdown().await;
+
+        Ok(())
+    }
+}
+
+/// Runs a job on its ticks.
+#[derive(Clone)]
+struct Runner {
+    job_name: String,
+    uuid: Uuid,
+    action: Action,
+    timeout: Option<Duration>,
+    /// What becomes of a tick overlapping a run, unless runs may overlap.
+    overlap: Option<Overlap>,
+    lock: Option<Arc<dyn TickLock>>,
+    history: Option<Arc<dyn RunStore>>,
+    /// Held for the length of a run, when runs may not overlap.
+    running: Arc<Mutex<()>>,
+    /// Whether a tick waits for the current run to finish.
+    queued: Arc<AtomicBool>,
+    /// Cancelled when the scheduler shuts down, which ends a tick's wait for
+    /// its turn.
+    stop: CancellationToken,
+}
+
+/// How long the scheduler waits on shutdown for the runs still going, unless
+/// interrupted a second time.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+
+/// How long a lease on a run lasts unless renewed, which is how long a run
+/// left behind by a process that died blocks the job's next runs.
+const LEASE_TTL: Duration = Duration::from_secs(60);
+
+/// How often a run renews its lease.
+const LEASE_RENEWAL: Duration = Duration::from_secs(20);
+
+/// How often a queued tick checks whether a replica's run has finished.
+const LEASE_POLL: Duration = Duration::from_secs(1);
+
+/// The turn of a run of a job whose runs may not overlap: its hold on the
+/// process's runs of the job, and with a lock, its lease in it.
+struct Turn {
+    _local: OwnedMutexGuard<()>,
+    lease: Option<(Arc<dyn TickLock>, String)>,
+}
+
+impl Runner {
+    /// Runs the job for `tick`, or for starting up when `None`, unless
+    /// another process claimed the tick or the tick overlaps a run.
+    async fn fire(self, tick: Option<DateTime<Utc>>) {
+        if let (Some(lock), Some(tick)) = (&self.lock, tick)
+            && !claimed(lock.as_ref(), &self.job_name, tick).await
+        {
+            return;
+        }
+        let turn = match self.overlap {
+            None => None,
+            Some(overlap) => {
+                let Some(turn) = self.take_turn(overlap).await else {
+                    tracing::info!(
+                        job_name = self.job_name,
+                        tick = ?tick,
+                        outcome = "skipped",
+                        "skipped scheduler tick: the previous run is still going"
+                    );
+                    self.record(Run::new(&self.job_name, Utc::now(), Outcome::Skipped))
+                        .await;
+                    return;
+                };
+                Some(turn)
+            }
+        };
+        let perform = self.action.clone().perform(
+            self.job_name.clone(),
+            self.uuid,
+            self.timeout,
+            self.history.is_some(),
+        );
+        let run = match turn.as_ref().and_then(|turn| turn.lease.as_ref()) {
+            Some((lock, owner)) => {
+                tokio::pin!(perform);
+                loop {
+                    tokio::select! {
+                        run = &mut perform => break run,
+                        () = tokio::time::sleep(LEASE_RENEWAL) => {
+                            self.renew(lock.as_ref(), owner).await;
+                        }
+                    }
+                }
+            }
+            None => perform.await,
+        };
+        if let Some((lock, owner)) = turn.and_then(|turn| turn.lease)
+            && let Err(err) = lock.release(&self.job_name, &owner).await
+        {
+            tracing::error!(job_name = self.job_name, error = %err, "failed to release scheduler run");
+        }
+        self.record(run).await;
+    }
+
+    /// Waits for the job's previous run to finish, in this process and with
+    /// a lock in every other, with `Overlap::Queue`; returns `None` when the
+    /// tick is skipped instead, or the scheduler stops while it waits.
+    async fn take_turn(&self, overlap: Overlap) -> Option<Turn> {
+        let queue = overlap == Overlap::Queue;
+        let (local, queued) = if let Ok(local) = self.running.clone().try_lock_owned() {
+            (local, false)
+        } else if queue && !self.queued.swap(true, Ordering::SeqCst) {
+            tokio::select! {
+                local = self.running.clone().lock_owned() => (local, true),
+                () = self.stop.cancelled() => {
+                    self.queued.store(false, Ordering::SeqCst);
+                    return None;
+                }
+            }
+        } else {
+            return None;
+        };
+        let lease = match &self.lock {
+            None => None,
+            Some(lock) => {
+                let owner = Uuid::new_v4().to_string();
+                let leased = loop {
+                    match lock.lease(&self.job_name, &owner, LEASE_TTL).await {
+                        Ok(true) => break true,
+                        Ok(false) if queue => tokio::select! {
+                            () = tokio::time::sleep(LEASE_POLL) => {}
+                            () = self.stop.cancelled() => break false,
+                        },
+                        Ok(false) => break false,
+                        Err(err) => {
+                            tracing::error!(job_name = self.job_name, error = %err, "failed to lease scheduler run");
+                            break false;
+                        }
+                    }
+                };
+                if !leased {
+                    if queued {
+                        self.queued.store(false, Ordering::SeqCst);
+                    }
+                    return None;
+                }
+                Some((lock.clone(), owner))
+            }
+        };
+        if queued {
+            self.queued.store(false, Ordering::SeqCst);
+        }
+        Some(Turn {
+            _local: local,
+            lease,
+        })
+    }
 
+    /// Renews the lease on the run going, which a replica may take over
+    /// once it lapses.
+    async fn renew(&self, lock: &dyn TickLock, owner: &str) {
+        match lock.lease(&self.job_name, owner, LEASE_TTL).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!(
+                    job_name = self.job_name,
+                    "scheduler run lease lapsed to another process"
+                );
+            }
+            Err(err) => {
+                tracing::error!(job_name = self.job_name, error = %err, "failed to renew scheduler run lease");
+            }
+        }
+    }
+
+    async fn record(&self, run: Run) {
+        if let Some(history) = &self.history
+            && let Err(err) = history.record(&run).await
+        {
+            tracing::error!(job_name = self.job_name, error = %err, "failed to record scheduler run");
+        }
+    }
+}
+
+/// The runs of the scheduler's jobs, which it waits for on shutdown.
+type Runs = Arc<std::sync::Mutex<JoinSet<()>>>;
+
+fn runs(running: &Runs) -> std::sync::MutexGuard<'_, JoinSet<()>> {
+    running
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Schedules a job's first tick after `after` on `sched`, whose run
+/// schedules the tick after it, until the scheduler stops.
+///
+/// Each tick is a one-shot job at its instant in the job's timezone: a cron
+/// job of `tokio-cron-scheduler` keeps the UTC offset its timezone had when
+/// it was added, and so would tick an hour off across a DST change.
+fn schedule(
+    sched: JobScheduler,
+    runner: Runner,
+    ticks: Ticks,
+    after: DateTime<Utc>,
+    running: Runs,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> {
+    Box::pin(async move {
+        let tick = match ticks.next_after(after) {
+            Ok(tick) => tick,
+            Err(err) => {
+                tracing::error!(job_name = runner.job_name, error = %err, "stopping scheduler job");
+                return Ok(());
+            }
+        };
+        let instant = Instant::now() + (tick - Utc::now()).to_std().unwrap_or_default();
+        sched
+            .add(tokio_cron_scheduler::Job::new_one_shot_at_instant_async(
+                instant,
+                move |_uuid, sched| {
+                    let runner = runner.clone();
+                    let ticks = ticks.clone();
+                    let running = running.clone();
+                    Box::pin(async move {
+                        // The scheduler keeps the instant to the second
+                        // before it, so the job may start early.
+                        let wait = (tick - Utc::now()).to_std().unwrap_or_default();
+                        tokio::select! {
+                            () = runner.stop.cancelled() => return,
+                            () = tokio::time::sleep(wait) => {}
+                        }
+                        {
+                            let mut running = runs(&running);
+                            if runner.stop.is_cancelled() {
+                                return;
+                            }
+                            while running.try_join_next().is_some() {}
+                            running.spawn(runner.clone().fire(Some(tick)));
+                        }
+                        let job_name = runner.job_name.clone();
+                        if let Err(err) =
+                            schedule(sched, runner, ticks, tick.max(Utc::now()), running).await
+                        {
+                            tracing::error!(job_name, error = %err, "stopping scheduler job");
+                        }
+                    })
+                },
+            )?)
+            .await?;
         Ok(())
+    })
+}
+
+/// Claims `tick` of a job in a lock shared with the other processes running
+/// the scheduler, returning whether this process runs it.
+async fn claimed(lock: &dyn TickLock, job_name: &str, tick: DateTime<Utc>) -> bool {
+    match lock.claim(job_name, tick).await {
+        Ok(true) => true,
+        Ok(false) => {
+            tracing::debug!(job_name, %tick, "scheduler tick claimed by another process");
+            false
+        }
+        Err(err) => {
+            tracing::error!(job_name, %tick, error = %err, "failed to claim scheduler tick");
+            false
+        }
     }
 }
 
-fn execute_job(job_name: &str, uuid: Uuid, job_description: &JobDescription) {
+fn execute_job(
+    job_name: &str,
+    uuid: Uuid,
+    job_description: &JobDescription,
+    timeout: Option<Duration>,
+    capture: bool,
+) -> Run {
     let task_span = tracing::span!(
         tracing::Level::DEBUG,
         "run_job",
         job_name,
         job_id = ?uuid,
     );
+    let started_at = Utc::now();
     let start = Instant::now();
     let _guard = task_span.enter();
-    match job_description.run() {
-        Ok(output) => {
-            tracing::debug!(
+    match job_description.execute(timeout, capture) {
+        Ok(Executed {
+            status: Some(status),
+            stdout,
+            stderr,
+        }) => {
+            let outcome = if status.success() {
+                tracing::debug!(
+                    duration = ?start.elapsed(),
+                    outcome = "succeeded",
+                    status_code = status.code(),
+                    "execute scheduler job finished"
+                );
+                Outcome::Succeeded
+            } else {
+                tracing::error!(
+                    duration = ?start.elapsed(),
+                    outcome = "failed",
+                    status_code = status.code(),
+                    "scheduler job exited with a failure"
+                );
+                Outcome::Failed
+            };
+            let mut run = Run::new(job_name, started_at, outcome);
+            run.exit_code = status.code();
+            run.stdout = stdout;
+            run.stderr = stderr;
+            run
+        }
+        Ok(Executed {
+            status: None,
+            stdout,
+            stderr,
+        }) => {
+            tracing::warn!(
                 duration = ?start.elapsed(),
-                status_code = output.status.code(),
-                "execute scheduler job finished"
+                outcome = "timed_out",
+                timeout = ?timeout,
+                "scheduler job timed out and its process was killed"
             );
+            let mut run = Run::new(job_name, started_at, Outcome::TimedOut);
+            run.stdout = stdout;
+            run.stderr = stderr;
+            run
         }
         Err(err) => {
             tracing::error!(
                 duration = ?start.elapsed(),
+                outcome = "failed",
                 error = %err,
                 "failed to execute scheduler job in sub process"
             );
+            let mut run = Run::new(job_name, started_at, Outcome::Failed);
+            run.error = Some(err.to_string());
+            run
         }
     }
 }
@@ -512,8 +1440,14 @@ jobs:
         let job = Job {
             run: run.to_string(),
             shell,
+            worker: false,
+            args: None,
             run_on_start: false,
+            allow_overlap: true,
+            on_overlap: Overlap::Skip,
+            timeout: None,
             cron: "*/5 * * * * *".to_string(),
+            timezone: None,
             tags: None,
             output: None,
         };
@@ -572,8 +1506,14 @@ jobs:
                         tree_fs.root.join("scheduler.txt").display()
                     ),
                     shell: true,
+                    worker: false,
+                    args: None,
                     run_on_start: false,
+                    allow_overlap: true,
+                    on_overlap: Overlap::Skip,
+                    timeout: None,
                     cron: "run every 1 second".to_string(),
+                    timezone: None,
                     tags: None,
                     output: None,
                 },
@@ -586,8 +1526,14 @@ jobs:
                         tree_fs.root.join("scheduler2.txt").display()
                     ),
                     shell: true,
+                    worker: false,
+                    args: None,
                     run_on_start: false,
+                    allow_overlap: true,
+                    on_overlap: Overlap::Skip,
+                    timeout: None,
                     cron: "* * * * * ? *".to_string(),
+                    timezone: None,
                     tags: None,
                     output: None,
                 },
@@ -600,8 +1546,14 @@ jobs:
                         tree_fs.root.join("scheduler3.txt").display()
                     ),
                     shell: true,
+                    worker: false,
+                    args: None,
                     run_on_start: true,
+                    allow_overlap: true,
+                    on_overlap: Overlap::Skip,
+                    timeout: None,
                     cron: "0 0 * * * * *".to_string(),
+                    timezone: None,
                     tags: None,
                     output: None,
                 },
@@ -637,4 +1589,606 @@ jobs:
             1
         );
     }
+
+    #[test]
+    pub fn worker_jobs_need_in_process_mode() {
+        let config: Config = serde_yaml::from_str(
+            r"
+jobs:
+  report:
+    run: ReportWorker
+    worker: true
+    schedule: every day at 3am
+",
+        )
+        .unwrap();
+        assert!(matches!(
+            Scheduler::new::<AppHook>(&config, &Environment::Test),
+            Err(Error::WorkerNeedsInProcess(job)) if job == "report"
+        ));
+
+        let config = Config {
+            mode: Mode::InProcess,
+            ..config
+        };
+        assert!(Scheduler::new::<AppHook>(&config, &Environment::Test).is_ok());
+    }
+
+    #[derive(Clone, Default)]
+    struct Record(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl crate::task::Task for Record {
+        fn task(&self) -> crate::task::TaskInfo {
+            crate::task::TaskInfo {
+                name: "record".to_string(),
+                detail: "records its `n` arg".to_string(),
+            }
+        }
+
+        async fn run(&self, _app_context: &AppContext, vars: &Vars) -> crate::Result<()> {
+            self.0.lock().unwrap().push(vars.cli_arg("n")?.to_string());
+            Ok(())
+        }
+    }
+
+    #[derive(serde::Deserialize, Serialize)]
+    struct ReportArgs {
+        days: u32,
+    }
+
+    struct ReportWorker;
+
+    #[async_trait::async_trait]
+    impl crate::bgworker::BackgroundWorker<ReportArgs> for ReportWorker {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+
+        async fn perform(&self, _args: ReportArgs) -> crate::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    pub async fn in_process_mode_runs_tasks_and_enqueues_workers() {
+        let (mut scheduler, _config_tree) = setup_scheduler_config();
+        let record = Record::default();
+        let mut tasks = Tasks::default();
+        tasks.register(record.clone());
+        scheduler.mode = Mode::InProcess;
+        scheduler.tasks = Arc::new(tasks);
+        let job = |run: &str, worker: bool, args: Option<JsonValue>| Job {
+            run: run.to_string(),
+            shell: false,
+            worker,
+            args,
+            run_on_start: true,
+            allow_overlap: true,
+            on_overlap: Overlap::Skip,
+            timeout: None,
+            cron: "0 0 * * * * *".to_string(),
+            timezone: None,
+            tags: None,
+            output: None,
+        };
+        scheduler.jobs = HashMap::from([
+            ("task".to_string(), job("record n:7", false, None)),
+            (
+                "worker".to_string(),
+                job("ReportWorker", true, Some(serde_json::json!({"days": 1}))),
+            ),
+        ]);
+
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        let queue = crate::bgworker::inmem::create_provider();
+        ctx.queue_provider = Some(Arc::new(queue));
+        assert!(matches!(
+            scheduler.clone().run_with_context(&ctx).await,
+            Err(Error::WorkerNotFound(worker)) if worker == "ReportWorker"
+        ));
+        assert!(matches!(
+            scheduler.clone().run().await,
+            Err(Error::NoContext)
+        ));
+
+        let queue = ctx.queue_provider.clone().unwrap();
+        queue.register(ReportWorker).await.unwrap();
+        let handle = tokio::spawn(async move {
+            scheduler.run_with_context(&ctx).await.unwrap();
+        });
+        time::sleep(Duration::from_secs(1)).await;
+        handle.abort();
+
+        assert_eq!(*record.0.lock().unwrap(), vec!["7".to_string()]);
+        let jobs = queue.get_jobs(None, None).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].name, "ReportWorker");
+        assert_eq!(jobs[0].data, serde_json::json!({"days": 1}));
+    }
+
+    #[cfg(feature = "worker")]
+    #[tokio::test]
+    pub async fn a_locked_tick_runs_in_one_process() {
+        // One connection, as every connection to `:memory:` is a database.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let lock: Arc<dyn TickLock> = Arc::new(lock::SqliteTickLock::new(pool).await.unwrap());
+        let tick = Utc::now().trunc_subsecs(0);
+
+        // Two scheduler processes, claiming the same job's tick.
+        let (first, second) = tokio::join!(
+            claimed(lock.as_ref(), "report", tick),
+            claimed(lock.as_ref(), "report", tick)
+        );
+        assert!(first ^ second);
+        assert!(claimed(lock.as_ref(), "cleanup", tick).await);
+        assert!(claimed(lock.as_ref(), "report", tick + chrono::Duration::hours(1)).await);
+    }
+
+    #[tokio::test]
+    pub async fn lock_needs_the_app_context() {
+        let (mut scheduler, _config_tree) = setup_scheduler_config();
+        scheduler.lock = Lock::Queue;
+        assert!(matches!(scheduler.run().await, Err(Error::NoContext)));
+    }
+
+    #[tokio::test]
+    pub async fn only_a_queue_history_needs_the_app_context() {
+        let (mut scheduler, config_tree) = setup_scheduler_config();
+        scheduler.history = History::File {
+            path: config_tree.root.join("history"),
+            keep: 10,
+        };
+        let last_runs = scheduler.last_runs(None).await.unwrap();
+        assert_eq!(last_runs.len(), scheduler.jobs.len());
+        assert!(last_runs.iter().all(|(_, run)| run.is_none()));
+
+        scheduler.history = History::Queue { keep: 10 };
+        assert!(matches!(
+            scheduler.last_runs(None).await,
+            Err(Error::NoContext)
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn runs_of_jobs_keep_to_the_selected_jobs() {
+        let (scheduler, config_tree) = setup_scheduler_config();
+        let path = config_tree.root.join("history");
+        let store = history::FileRunStore::new(&path, 10);
+        let mut names: Vec<String> = scheduler.jobs.keys().cloned().collect();
+        names.sort();
+        for name in names.iter().chain(std::iter::once(&"removed".to_string())) {
+            store
+                .record(&Run::new(name, Utc::now(), Outcome::Succeeded))
+                .await
+                .unwrap();
+        }
+        let scheduler = Scheduler {
+            history: History::File { path, keep: 10 },
+            ..scheduler
+        };
+
+        let all = scheduler.runs(None, None, 100).await.unwrap();
+        assert_eq!(all.len(), names.len() + 1);
+        let selected = scheduler
+            .by_spec(&Spec {
+                name: Some(names[0].clone()),
+                tag: None,
+            })
+            .runs_of_jobs(None, None, 100)
+            .await
+            .unwrap();
+        assert_eq!(
+            selected.iter().map(|run| &run.job).collect::<Vec<_>>(),
+            [&names[0]]
+        );
+    }
+
+    #[test]
+    fn ticks_follow_the_timezone_across_dst() {
+        let ticks = |schedule: &str, timezone: &str| Ticks {
+            cron: parse_cron(schedule).unwrap(),
+            timezone: parse_timezone(timezone).unwrap(),
+        };
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        // Berlin switches to CEST on 2026-03-29 and back on 2026-10-25.
+        let nine = ticks("0 0 9 * * *", "Europe/Berlin");
+        assert_eq!(
+            nine.next_after(at("2026-03-27T08:00:00Z")).unwrap(),
+            at("2026-03-28T08:00:00Z")
+        );
+        assert_eq!(
+            nine.next_after(at("2026-03-28T08:00:00Z")).unwrap(),
+            at("2026-03-29T07:00:00Z")
+        );
+
+        // 02:30 is skipped in spring and repeated in autumn; it runs once.
+        let half_past_two = ticks("0 30 2 * * *", "Europe/Berlin");
+        assert_eq!(
+            half_past_two
+                .next_after(at("2026-03-28T12:00:00Z"))
+                .unwrap(),
+            at("2026-03-29T01:00:00Z")
+        );
+        assert_eq!(
+            half_past_two
+                .next_after(at("2026-10-24T12:00:00Z"))
+                .unwrap(),
+            at("2026-10-25T00:30:00Z")
+        );
+        assert_eq!(
+            half_past_two
+                .next_after(at("2026-10-25T00:30:00Z"))
+                .unwrap(),
+            at("2026-10-26T01:30:00Z")
+        );
+
+        // New York falls back on 2026-11-01: 01:00-01:59 runs at -04:00,
+        // then again at -05:00. A job every minute ticks through both.
+        let every_minute = ticks("0 * * * * *", "America/New_York");
+        let mut tick = at("2026-11-01T04:58:00Z");
+        let mut minutes = Vec::new();
+        while tick < at("2026-11-01T07:02:00Z") {
+            tick = every_minute.next_after(tick).unwrap();
+            minutes.push(tick);
+        }
+        assert_eq!(minutes.len(), 124);
+        assert!(minutes
+            .windows(2)
+            .all(|pair| pair[1] - pair[0] == chrono::Duration::minutes(1)));
+        assert_eq!(
+            every_minute.next_after(at("2026-11-01T06:30:00Z")).unwrap(),
+            at("2026-11-01T06:31:00Z")
+        );
+        // A job at a fixed time in the repeated hour runs once.
+        let half_past_one = ticks("0 30 1 * * *", "America/New_York");
+        assert_eq!(
+            half_past_one
+                .next_after(at("2026-11-01T05:30:00Z"))
+                .unwrap(),
+            at("2026-11-02T06:30:00Z")
+        );
+        assert_eq!(
+            half_past_one
+                .next_after(at("2026-11-01T06:00:00Z"))
+                .unwrap(),
+            at("2026-11-02T06:30:00Z")
+        );
+
+        let english = ticks("every day at 9am", "America/New_York");
+        assert_eq!(
+            english.next_after(at("2026-03-07T12:00:00Z")).unwrap(),
+            at("2026-03-07T14:00:00Z")
+        );
+        assert_eq!(
+            english.next_after(at("2026-03-07T14:00:00Z")).unwrap(),
+            at("2026-03-08T13:00:00Z")
+        );
+    }
+
+    #[test]
+    fn jobs_take_the_scheduler_timezone_by_default() {
+        let (mut scheduler, _config_tree) = setup_scheduler_config();
+        scheduler.timezone = parse_timezone("Europe/Berlin").unwrap();
+        let mut job = scheduler.jobs.values().next().unwrap().clone();
+        job.cron = "0 0 9 * * *".to_string();
+        let after = "2026-06-01T00:00:00Z".parse().unwrap();
+
+        let tick = scheduler.ticks(&job).unwrap().next_after(after).unwrap();
+        assert_eq!(
+            tick,
+            "2026-06-01T07:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        job.timezone = Some("Asia/Tokyo".to_string());
+        let tick = scheduler.ticks(&job).unwrap().next_after(after).unwrap();
+        assert_eq!(
+            tick,
+            "2026-06-02T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+
+        let mut config = crate::tests_cfg::config::test_config().scheduler.unwrap();
+        config.timezone = Some("Mars/Olympus_Mons".to_string());
+        assert!(matches!(
+            Scheduler::new::<AppHook>(&config, &Environment::Test),
+            Err(Error::InvalidTimezone(timezone)) if timezone == "Mars/Olympus_Mons"
+        ));
+    }
+
+    /// Records `n` when it starts and when it finishes, `ms` milliseconds
+    /// later.
+    #[derive(Clone, Default)]
+    struct Nap(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl crate::task::Task for Nap {
+        fn task(&self) -> crate::task::TaskInfo {
+            crate::task::TaskInfo {
+                name: "nap".to_string(),
+                detail: "sleeps `ms` milliseconds".to_string(),
+            }
+        }
+
+        async fn run(&self, _app_context: &AppContext, vars: &Vars) -> crate::Result<()> {
+            let n = vars.cli_arg("n")?;
+            self.0.lock().unwrap().push(format!("start {n}"));
+            let ms = vars.cli_arg("ms")?.parse().unwrap();
+            time::sleep(Duration::from_millis(ms)).await;
+            self.0.lock().unwrap().push(format!("end {n}"));
+            Ok(())
+        }
+    }
+
+    async fn nap_runner(nap: &Nap, overlap: Option<Overlap>, timeout: Option<Duration>) -> Runner {
+        let mut tasks = Tasks::default();
+        tasks.register(nap.clone());
+        Runner {
+            job_name: "nap".to_string(),
+            uuid: Uuid::new_v4(),
+            action: Action::Task {
+                ctx: tests_cfg::app::get_app_context().await,
+                tasks: Arc::new(tasks),
+                name: "nap".to_string(),
+                args: Vec::new(),
+            },
+            timeout,
+            overlap,
+            lock: None,
+            history: None,
+            running: Arc::default(),
+            queued: Arc::default(),
+            stop: CancellationToken::new(),
+        }
+    }
+
+    fn with_args(runner: &Runner, n: u32, ms: u64) -> Runner {
+        let mut runner = runner.clone();
+        if let Action::Task { args, .. } = &mut runner.action {
+            *args = vec![
+                ("n".to_string(), n.to_string()),
+                ("ms".to_string(), ms.to_string()),
+            ];
+        }
+        runner
+    }
+
+    #[tokio::test]
+    pub async fn overlapping_ticks_are_skipped_or_queued() {
+        // Three ticks while the first run takes 200ms.
+        let fire_three = |runner: Runner| async move {
+            let first = tokio::spawn(with_args(&runner, 1, 200).fire(None));
+            time::sleep(Duration::from_millis(50)).await;
+            let second = tokio::spawn(with_args(&runner, 2, 0).fire(None));
+            let third = tokio::spawn(with_args(&runner, 3, 0).fire(None));
+            for run in [first, second, third] {
+                run.await.unwrap();
+            }
+        };
+
+        let nap = Nap::default();
+        fire_three(nap_runner(&nap, None, None).await).await;
+        let mut runs = nap.0.lock().unwrap().clone();
+        runs.sort();
+        assert_eq!(
+            runs,
+            ["end 1", "end 2", "end 3", "start 1", "start 2", "start 3"]
+        );
+
+        let nap = Nap::default();
+        fire_three(nap_runner(&nap, Some(Overlap::Skip), None).await).await;
+        assert_eq!(*nap.0.lock().unwrap(), ["start 1", "end 1"]);
+
+        let nap = Nap::default();
+        fire_three(nap_runner(&nap, Some(Overlap::Queue), None).await).await;
+        assert_eq!(
+            *nap.0.lock().unwrap(),
+            ["start 1", "end 1", "start 2", "end 2"]
+        );
+    }
+
+    #[cfg(feature = "worker")]
+    #[tokio::test]
+    pub async fn overlapping_runs_are_kept_apart_across_processes() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let lock: Arc<dyn TickLock> = Arc::new(lock::SqliteTickLock::new(pool).await.unwrap());
+        // Two processes sharing the lock, the second firing the next tick
+        // while the first runs.
+        let overlap = |overlap: Overlap, tick: DateTime<Utc>| {
+            let lock = lock.clone();
+            async move {
+                let nap = Nap::default();
+                let mut runners = Vec::new();
+                for _ in 0..2 {
+                    let mut runner = nap_runner(&nap, Some(overlap), None).await;
+                    runner.lock = Some(lock.clone());
+                    runners.push(runner);
+                }
+                let first = tokio::spawn(with_args(&runners[0], 1, 200).fire(Some(tick)));
+                time::sleep(Duration::from_millis(50)).await;
+                with_args(&runners[1], 2, 0)
+                    .fire(Some(tick + chrono::Duration::minutes(1)))
+                    .await;
+                first.await.unwrap();
+                nap
+            }
+        };
+
+        let tick = Utc::now();
+        let nap = overlap(Overlap::Skip, tick).await;
+        assert_eq!(*nap.0.lock().unwrap(), ["start 1", "end 1"]);
+
+        let nap = overlap(Overlap::Queue, tick + chrono::Duration::hours(1)).await;
+        assert_eq!(
+            *nap.0.lock().unwrap(),
+            ["start 1", "end 1", "start 2", "end 2"]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn each_tick_schedules_the_next_one() {
+        let nap = Nap::default();
+        let runner = with_args(&nap_runner(&nap, None, None).await, 1, 0);
+        let stop = runner.stop.clone();
+        let ticks = Ticks {
+            cron: parse_cron("* * * * * *").unwrap(),
+            timezone: Tz::Europe__Berlin,
+        };
+        let mut sched = JobScheduler::new().await.unwrap();
+        schedule(sched.clone(), runner, ticks, Utc::now(), Runs::default())
+            .await
+            .unwrap();
+        sched.start().await.unwrap();
+        time::sleep(Duration::from_millis(3500)).await;
+        stop.cancel();
+        sched.shutdown().await.unwrap();
+
+        let starts = nap.0.lock().unwrap().len() / 2;
+        assert!((2..=4).contains(&starts), "{starts} runs in 3.5s");
+        time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(nap.0.lock().unwrap().len() / 2, starts);
+    }
+
+    #[tokio::test]
+    pub async fn a_queued_tick_stops_waiting_on_shutdown() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let lock: Arc<dyn TickLock> = Arc::new(lock::SqliteTickLock::new(pool).await.unwrap());
+        let nap = Nap::default();
+        let mut runner = nap_runner(&nap, Some(Overlap::Queue), None).await;
+        runner.lock = Some(lock.clone());
+        // Another process's run, which does not end on its own.
+        assert!(lock
+            .lease(&runner.job_name, "replica", Duration::from_secs(60))
+            .await
+            .unwrap());
+
+        let queued = tokio::spawn(with_args(&runner, 1, 0).fire(Some(Utc::now())));
+        time::sleep(Duration::from_millis(50)).await;
+        runner.stop.cancel();
+        time::timeout(Duration::from_secs(5), queued)
+            .await
+            .expect("the tick stops waiting")
+            .unwrap();
+        assert!(nap.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn timeout_cancels_an_in_process_run() {
+        let nap = Nap::default();
+        let runner = nap_runner(&nap, Some(Overlap::Skip), Some(Duration::from_millis(50))).await;
+        with_args(&runner, 1, 1_000).fire(None).await;
+        with_args(&runner, 2, 0).fire(None).await;
+        assert_eq!(*nap.0.lock().unwrap(), ["start 1", "start 2", "end 2"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_a_command() {
+        let job = |command: &str| JobDescription {
+            command: command.to_string(),
+            output: Output::Silent,
+            environment: Environment::Test,
+        };
+        let start = std::time::Instant::now();
+        assert!(job("sleep 5")
+            .execute(Some(Duration::from_millis(100)), false)
+            .unwrap()
+            .status
+            .is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(job("true")
+            .execute(Some(Duration::from_secs(5)), false)
+            .unwrap()
+            .status
+            .is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn timeout_kills_what_a_command_started() {
+        let dir = TreeBuilder::default().drop(true).create().unwrap();
+        let pid_file = dir.root.join("pid");
+        let job = JobDescription {
+            command: format!("sleep 30 & echo $! > {}; wait", pid_file.display()),
+            output: Output::Silent,
+            environment: Environment::Test,
+        };
+        assert!(job
+            .execute(Some(Duration::from_millis(200)), false)
+            .unwrap()
+            .status
+            .is_none());
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let stat = PathBuf::from("/proc").join(pid.trim()).join("stat");
+        // Gone, or a zombie waiting for init to reap it.
+        let dead = || {
+            std::fs::read_to_string(&stat).map_or(true, |stat| {
+                stat.rsplit_once(") ")
+                    .is_some_and(|(_, rest)| rest.starts_with('Z'))
+            })
+        };
+        let start = std::time::Instant::now();
+        while !dead() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(dead());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn runs_are_recorded_with_their_output() {
+        let dir = TreeBuilder::default().drop(true).create().unwrap();
+        let store = Arc::new(history::FileRunStore::new(&dir.root, 10));
+        let runner = Runner {
+            job_name: "report".to_string(),
+            uuid: Uuid::new_v4(),
+            action: Action::Command(JobDescription {
+                command: "echo sent; echo late >&2; exit 3".to_string(),
+                output: Output::Silent,
+                environment: Environment::Test,
+            }),
+            timeout: None,
+            overlap: None,
+            lock: None,
+            history: Some(store.clone()),
+            running: Arc::default(),
+            queued: Arc::default(),
+            stop: CancellationToken::new(),
+        };
+        runner.fire(Some(Utc::now())).await;
+
+        let runs = store.runs(Some("report"), 10).await.unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].outcome, Outcome::Failed);
+        assert_eq!(runs[0].exit_code, Some(3));
+        assert_eq!(runs[0].stdout, "sent\n");
+        assert_eq!(runs[0].stderr, "late\n");
+        assert!(runs[0].started_at <= runs[0].finished_at);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_timed_out_command_keeps_its_output() {
+        let job = JobDescription {
+            command: "echo started; sleep 30".to_string(),
+            output: Output::Silent,
+            environment: Environment::Test,
+        };
+        let executed = job.execute(Some(Duration::from_millis(500)), true).unwrap();
+        assert!(executed.status.is_none());
+        assert_eq!(executed.stdout, "started\n");
+    }
 }
@@ -0,0 +1,558 @@
+//! The scheduler's run history.
+//!
+//! With `history` set, the scheduler records every run of a job, and every
+//! tick it skipped, in a store that `cargo loco scheduler history` and
+//! `cargo loco scheduler last-run` read back: JSON lines files, one per job,
+//! or a table in the queue's Postgres or `SQLite` database. Only the latest
+//! `keep` runs of each job are kept.
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::{Error, Result};
+use crate::app::AppContext;
+#[cfg(feature = "worker")]
+use crate::config::QueueConfig;
+
+/// How much of a command's stdout and stderr a run keeps: the last 4 KiB of
+/// each.
+pub const MAX_OUTPUT: usize = 4096;
+
+/// Where the scheduler records its runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum History {
+    /// Nowhere.
+    #[default]
+    None,
+    /// In JSON lines files under `path`, one per job.
+    File {
+        #[serde(default = "default_path")]
+        path: PathBuf,
+        /// The runs kept per job.
+        #[serde(default = "default_keep")]
+        keep: usize,
+    },
+    /// In the `loco_scheduler_runs` table of the queue's Postgres or `SQLite`
+    /// database.
+    Queue {
+        /// The runs kept per job.
+        #[serde(default = "default_keep")]
+        keep: usize,
+    },
+}
+
+fn default_path() -> PathBuf {
+    PathBuf::from("tmp/scheduler")
+}
+
+const fn default_keep() -> usize {
+    100
+}
+
+/// What came of a tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The job ran, and its command exited with 0 or its task returned `Ok`.
+    Succeeded,
+    /// The job ran, and failed.
+    Failed,
+    /// The job ran past its `timeout`, and was stopped.
+    TimedOut,
+    /// The job did not run, as its previous run was still going.
+    Skipped,
+}
+
+impl Outcome {
+    /// The outcome's name, as in the config and logs.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::TimedOut => "timed_out",
+            Self::Skipped => "skipped",
+        }
+    }
+
+    #[cfg(feature = "worker")]
+    fn parse(outcome: &str) -> Result<Self> {
+        match outcome {
+            "succeeded" => Ok(Self::Succeeded),
+            "failed" => Ok(Self::Failed),
+            "timed_out" => Ok(Self::TimedOut),
+            "skipped" => Ok(Self::Skipped),
+            _ => Err(Error::History(format!("unknown run outcome `{outcome}`"))),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A run of a scheduler job.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Run {
+    pub job: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub outcome: Outcome,
+    /// The exit code of a command, unless it was killed.
+    pub exit_code: Option<i32>,
+    /// The end of a command's stdout, at most [`MAX_OUTPUT`] bytes.
+    pub stdout: String,
+    /// The end of a command's stderr, at most [`MAX_OUTPUT`] bytes.
+    pub stderr: String,
+    /// Why the run failed, when not from its exit code.
+    pub error: Option<String>,
+}
+
+impl Run {
+    /// A run of `job` started at `started_at`, finishing now with `outcome`.
+    #[must_use]
+    pub fn new(job: &str, started_at: DateTime<Utc>, outcome: Outcome) -> Self {
+        Self {
+            job: job.to_string(),
+            started_at,
+            finished_at: Utc::now(),
+            outcome,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: None,
+        }
+    }
+}
+
+impl fmt::Display for Run {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let duration = (self.finished_at - self.started_at)
+            .to_std()
+            .unwrap_or_default();
+        write!(
+            f,
+            "{:<20} {} {:>8.1}s {:<10}",
+            self.job,
+            self.started_at
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            duration.as_secs_f64(),
+            self.outcome,
+        )?;
+        if let Some(code) = self.exit_code {
+            write!(f, " exit {code}")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, " {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The last [`MAX_OUTPUT`] bytes of `output`, cut at a character boundary.
+#[must_use]
+pub fn tail(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    let mut start = output.len().saturating_sub(MAX_OUTPUT);
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    output[start..].to_string()
+}
+
+/// A store of scheduler runs.
+#[async_trait]
+pub trait RunStore: Send + Sync {
+    /// Records `run`, dropping the runs of its job past the kept ones.
+    async fn record(&self, run: &Run) -> Result<()>;
+
+    /// The latest `limit` runs of `job`, or of every job, newest first.
+    async fn runs(&self, job: Option<&str>, limit: usize) -> Result<Vec<Run>>;
+}
+
+fn history_error(err: impl fmt::Display) -> Error {
+    Error::History(err.to_string())
+}
+
+/// The 64-bit FNV-1a hash of `bytes`, which unlike the standard library's
+/// hashers stays the same across builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Runs in JSON lines files, one per job.
+pub struct FileRunStore {
+    path: PathBuf,
+    keep: usize,
+    /// Serializes the rewrites of a file.
+    write: Mutex<()>,
+}
+
+impl FileRunStore {
+    /// A store of the latest `keep` runs per job, in files under `path`.
+    #[must_use]
+    pub fn new(path: &Path, keep: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            keep,
+            write: Mutex::new(()),
+        }
+    }
+
+    /// The file of `job`'s runs: its name made safe for a file name, and a
+    /// hash of the exact name, so that names differing in a replaced
+    /// character, or only in case, get files of their own.
+    fn file(&self, job: &str) -> PathBuf {
+        let name: String = job
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.path
+            .join(format!("{name}-{:016x}.jsonl", fnv1a(job.as_bytes())))
+    }
+
+    async fn read(file: &Path) -> Result<Vec<Run>> {
+        let content = match tokio::fs::read_to_string(file).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(history_error))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl RunStore for FileRunStore {
+    async fn record(&self, run: &Run) -> Result<()> {
+        let _write = self.write.lock().await;
+        let file = self.file(&run.job);
+        let mut runs = Self::read(&file).await?;
+        runs.push(run.clone());
+        let dropped = runs.len().saturating_sub(self.keep);
+        let mut content = String::new();
+        for run in &runs[dropped..] {
+            content.push_str(&serde_json::to_string(run).map_err(history_error)?);
+            content.push('\n');
+        }
+        tokio::fs::create_dir_all(&self.path).await?;
+        tokio::fs::write(&file, content).await?;
+        Ok(())
+    }
+
+    async fn runs(&self, job: Option<&str>, limit: usize) -> Result<Vec<Run>> {
+        let mut runs = Vec::new();
+        if let Some(job) = job {
+            runs = Self::read(&self.file(job)).await?;
+        } else {
+            let mut dir = match tokio::fs::read_dir(&self.path).await {
+                Ok(dir) => dir,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(runs),
+                Err(err) => return Err(err.into()),
+            };
+            while let Some(entry) = dir.next_entry().await? {
+                if entry.path().extension().is_some_and(|ext| ext == "jsonl") {
+                    runs.extend(Self::read(&entry.path()).await?);
+                }
+            }
+        }
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+        runs.truncate(limit);
+        Ok(runs)
+    }
+}
+
+/// The table of scheduler runs, with times in milliseconds since the Unix
+/// epoch.
+#[cfg(feature = "worker")]
+const RUNS_TABLE: &str = "loco_scheduler_runs";
+
+#[cfg(feature = "worker")]
+fn run_from_row<R: sqlx::Row>(row: &R) -> Result<Run>
+where
+    for<'r> &'r str: sqlx::ColumnIndex<R>,
+    for<'r> String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> Option<String>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> Option<i32>: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    let time = |column: &str| -> Result<DateTime<Utc>> {
+        let millis: i64 = row.try_get(column).map_err(history_error)?;
+        DateTime::from_timestamp_millis(millis)
+            .ok_or_else(|| Error::History(format!("invalid run time {millis}")))
+    };
+    Ok(Run {
+        job: row.try_get("job").map_err(history_error)?,
+        started_at: time("started_at")?,
+        finished_at: time("finished_at")?,
+        outcome: Outcome::parse(&row.try_get::<String, _>("outcome").map_err(history_error)?)?,
+        exit_code: row.try_get("exit_code").map_err(history_error)?,
+        stdout: row.try_get("stdout").map_err(history_error)?,
+        stderr: row.try_get("stderr").map_err(history_error)?,
+        error: row.try_get("error").map_err(history_error)?,
+    })
+}
+
+/// Generates the store of runs in a queue's SQL database, whose statements
+/// Postgres and `SQLite` share.
+#[cfg(feature = "worker")]
+macro_rules! sql_run_store {
+    ($(#[$doc:meta])* $name:ident, $pool:ty, $id:literal) => {
+        $(#[$doc])*
+        pub struct $name {
+            pool: $pool,
+            keep: usize,
+        }
+
+        impl $name {
+            /// Creates the runs table in `pool` if needed, for a store of
+            /// the latest `keep` runs per job.
+            ///
+            /// # Errors
+            /// When the table cannot be created.
+            pub async fn new(pool: $pool, keep: usize) -> Result<Self> {
+                sqlx::query(sqlx::AssertSqlSafe(format!(
+                    "CREATE TABLE IF NOT EXISTS {RUNS_TABLE} (id {}, job TEXT NOT NULL, \
+                     started_at BIGINT NOT NULL, finished_at BIGINT NOT NULL, outcome TEXT NOT \
+                     NULL, exit_code INTEGER, stdout TEXT NOT NULL, stderr TEXT NOT NULL, error \
+                     TEXT)",
+                    $id
+                )))
+                .execute(&pool)
+                .await
+                .map_err(history_error)?;
+                sqlx::query(sqlx::AssertSqlSafe(format!(
+                    "CREATE INDEX IF NOT EXISTS {RUNS_TABLE}_job_started_at ON {RUNS_TABLE} \
+                     (job, started_at)"
+                )))
+                .execute(&pool)
+                .await
+                .map_err(history_error)?;
+                Ok(Self { pool, keep })
+            }
+        }
+
+        #[async_trait]
+        impl RunStore for $name {
+            async fn record(&self, run: &Run) -> Result<()> {
+                sqlx::query(sqlx::AssertSqlSafe(format!(
+                    "INSERT INTO {RUNS_TABLE} (job, started_at, finished_at, outcome, exit_code, \
+                     stdout, stderr, error) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+                )))
+                .bind(&run.job)
+                .bind(run.started_at.timestamp_millis())
+                .bind(run.finished_at.timestamp_millis())
+                .bind(run.outcome.as_str())
+                .bind(run.exit_code)
+                .bind(&run.stdout)
+                .bind(&run.stderr)
+                .bind(&run.error)
+                .execute(&self.pool)
+                .await
+                .map_err(history_error)?;
+                sqlx::query(sqlx::AssertSqlSafe(format!(
+                    "DELETE FROM {RUNS_TABLE} WHERE job = $1 AND id NOT IN (SELECT id FROM \
+                     {RUNS_TABLE} WHERE job = $1 ORDER BY started_at DESC, id DESC LIMIT $2)"
+                )))
+                .bind(&run.job)
+                .bind(i64::try_from(self.keep).unwrap_or(i64::MAX))
+                .execute(&self.pool)
+                .await
+                .map_err(history_error)?;
+                Ok(())
+            }
+
+            async fn runs(&self, job: Option<&str>, limit: usize) -> Result<Vec<Run>> {
+                sqlx::query(sqlx::AssertSqlSafe(format!(
+                    "SELECT job, started_at, finished_at, outcome, exit_code, stdout, stderr, \
+                     error FROM {RUNS_TABLE} WHERE CAST($1 AS TEXT) IS NULL OR job = $1 ORDER BY started_at \
+                     DESC, id DESC LIMIT $2"
+                )))
+                .bind(job)
+                .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+                .fetch_all(&self.pool)
+                .await
+                .map_err(history_error)?
+                .iter()
+                .map(run_from_row)
+                .collect()
+            }
+        }
+    };
+}
+
+#[cfg(feature = "worker")]
+sql_run_store!(
+    /// Runs in the queue's Postgres database.
+    PgRunStore,
+    sqlx::PgPool,
+    "BIGSERIAL PRIMARY KEY"
+);
+
+#[cfg(feature = "worker")]
+sql_run_store!(
+    /// Runs in the queue's `SQLite` database.
+    SqliteRunStore,
+    sqlx::SqlitePool,
+    "INTEGER PRIMARY KEY AUTOINCREMENT"
+);
+
+/// Opens the store `history` points at; the queue's is found from the config
+/// of `ctx`.
+///
+/// # Errors
+/// When `history` is the queue's, and there is no `ctx`, or the queue is not
+/// Postgres or `SQLite` or cannot be reached.
+pub async fn connect(
+    history: &History,
+    ctx: Option<&AppContext>,
+) -> Result<Option<Arc<dyn RunStore>>> {
+    match history {
+        History::None => Ok(None),
+        History::File { path, keep } => Ok(Some(Arc::new(FileRunStore::new(path, *keep)))),
+        History::Queue { keep } => {
+            let ctx = ctx.ok_or(Error::NoContext)?;
+            match &ctx.config.queue {
+                #[cfg(feature = "worker")]
+                Some(QueueConfig::Postgres(qcfg)) => Ok(Some(Arc::new(
+                    PgRunStore::new(
+                        super::lock::postgres_pool(qcfg, ctx)
+                            .await
+                            .map_err(history_error)?,
+                        *keep,
+                    )
+                    .await?,
+                ))),
+                #[cfg(feature = "worker")]
+                Some(QueueConfig::Sqlite(qcfg)) => Ok(Some(Arc::new(
+                    SqliteRunStore::new(
+                        super::lock::sqlite_pool(qcfg, ctx)
+                            .await
+                            .map_err(history_error)?,
+                        *keep,
+                    )
+                    .await?,
+                ))),
+                _ => {
+                    let _ = keep;
+                    Err(Error::History(
+                        "`history: { kind: Queue }` needs a Postgres or SQLite queue".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(job: &str, started_at: &str, outcome: Outcome) -> Run {
+        let started_at = started_at.parse().unwrap();
+        Run {
+            job: job.to_string(),
+            started_at,
+            finished_at: started_at + chrono::Duration::seconds(3),
+            outcome,
+            exit_code: (outcome == Outcome::Failed).then_some(2),
+            stdout: String::new(),
+            stderr: if outcome == Outcome::Failed {
+                "disk full".to_string()
+            } else {
+                String::new()
+            },
+            error: None,
+        }
+    }
+
+    async fn records_and_lists_runs(store: &dyn RunStore) {
+        let cleanup = [
+            run("cleanup", "2026-03-01T03:00:00Z", Outcome::Succeeded),
+            run("cleanup", "2026-03-02T03:00:00Z", Outcome::Failed),
+            run("cleanup", "2026-03-03T03:00:00Z", Outcome::TimedOut),
+        ];
+        let report = run("report", "2026-03-02T09:00:00Z", Outcome::Skipped);
+        for run in cleanup.iter().chain([&report]) {
+            store.record(run).await.unwrap();
+        }
+
+        // Two runs are kept per job.
+        assert_eq!(
+            store.runs(Some("cleanup"), 10).await.unwrap(),
+            [cleanup[2].clone(), cleanup[1].clone()]
+        );
+        assert_eq!(
+            store.runs(None, 10).await.unwrap(),
+            [cleanup[2].clone(), report.clone(), cleanup[1].clone()]
+        );
+        assert_eq!(store.runs(Some("report"), 1).await.unwrap(), [report]);
+        assert!(store.runs(Some("backup"), 1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn file_store_keeps_the_latest_runs() {
+        let dir = tree_fs::TreeBuilder::default().drop(true).create().unwrap();
+        records_and_lists_runs(&FileRunStore::new(&dir.root.join("history"), 2)).await;
+    }
+
+    #[tokio::test]
+    async fn file_store_keeps_similar_job_names_apart() {
+        let dir = tree_fs::TreeBuilder::default().drop(true).create().unwrap();
+        let store = FileRunStore::new(&dir.root, 10);
+        let names = ["db.backup", "db/backup", "db_backup", "DB_backup"];
+        for (day, name) in names.iter().enumerate() {
+            let started_at = format!("2026-03-0{}T03:00:00Z", day + 1);
+            store
+                .record(&run(name, &started_at, Outcome::Succeeded))
+                .await
+                .unwrap();
+        }
+        for name in names {
+            let runs = store.runs(Some(name), 10).await.unwrap();
+            assert_eq!(runs.len(), 1);
+            assert_eq!(runs[0].job, name);
+        }
+    }
+
+    #[cfg(feature = "worker")]
+    #[tokio::test]
+    async fn sqlite_store_keeps_the_latest_runs() {
+        // One connection, as every connection to `:memory:` is a database.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        records_and_lists_runs(&SqliteRunStore::new(pool, 2).await.unwrap()).await;
+    }
+
+    #[test]
+    fn output_keeps_its_tail() {
+        assert_eq!(tail(b"done\n"), "done\n");
+        let long = format!("{}é{}", "a".repeat(10), "b".repeat(MAX_OUTPUT - 1));
+        let kept = tail(long.as_bytes());
+        assert_eq!(kept, "b".repeat(MAX_OUTPUT - 1));
+    }
+}
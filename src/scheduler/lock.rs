@@ -0,0 +1,454 @@
+//! Tick claims, which let several processes run the same scheduler.
+//!
+//! With `lock` set, a scheduler claims every tick of a job before running it,
+//! in a store all replicas share: the queue's Postgres, `SQLite` or Redis,
+//! or the cache's Redis. The store keeps the latest tick claimed per job, and
+//! a claim only succeeds for a later tick, so each tick runs on one replica
+//! however many run the scheduler.
+//!
+//! A job with `allow_overlap: false` also holds a lease on its run in the
+//! store while it runs, so that no replica starts a run of it meanwhile. The
+//! lease is renewed while the run goes and released when it ends; a replica
+//! that dies mid-run leaves it to expire.
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Result};
+use crate::app::AppContext;
+#[cfg(feature = "cache_redis")]
+use crate::config::CacheConfig;
+#[cfg(any(feature = "worker", feature = "worker_redis"))]
+use crate::config::QueueConfig;
+
+/// Where the scheduler claims ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lock {
+    /// Nowhere: every scheduler process runs every tick.
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    /// In the queue's store, from the `queue` config: Postgres, `SQLite` or
+    /// Redis.
+    #[serde(rename = "queue")]
+    Queue,
+    /// In the cache's Redis, from the `cache` config.
+    #[serde(rename = "cache")]
+    Cache,
+}
+
+/// A store of tick claims shared by scheduler replicas.
+#[async_trait]
+pub trait TickLock: Send + Sync {
+    /// Claims `tick` of job `job`, returning whether this process won it:
+    /// `false` if a replica already claimed it, or a later tick.
+    async fn claim(&self, job: &str, tick: DateTime<Utc>) -> Result<bool>;
+
+    /// Leases the run of job `job` to `owner` for `ttl`, returning whether
+    /// it got it: `false` while another owner's lease has not expired.
+    /// Leasing again renews the lease.
+    async fn lease(&self, job: &str, owner: &str, ttl: Duration) -> Result<bool>;
+
+    /// Ends the lease of `owner` on the run of job `job`, if it still holds
+    /// it.
+    async fn release(&self, job: &str, owner: &str) -> Result<()>;
+}
+
+#[cfg(any(feature = "worker", feature = "worker_redis", feature = "cache_redis"))]
+pub(super) fn lock_error(err: impl std::fmt::Display) -> Error {
+    Error::Lock(err.to_string())
+}
+
+/// The table keeping the latest tick claimed per job, in milliseconds since
+/// the Unix epoch.
+#[cfg(feature = "worker")]
+const TICKS_TABLE: &str = "loco_scheduler_ticks";
+
+/// The table keeping the lease on each job's run: its owner, and when it
+/// expires in milliseconds since the Unix epoch.
+#[cfg(feature = "worker")]
+const LEASES_TABLE: &str = "loco_scheduler_leases";
+
+/// When a lease for `ttl` taken now expires, and now, in milliseconds since
+/// the Unix epoch.
+#[cfg(feature = "worker")]
+fn lease_span(ttl: Duration) -> (i64, i64) {
+    let now = Utc::now();
+    let expires_at = chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| now.checked_add_signed(ttl))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    (expires_at.timestamp_millis(), now.timestamp_millis())
+}
+
+/// Claims in the queue's Postgres database.
+#[cfg(feature = "worker")]
+pub struct PgTickLock(sqlx::PgPool);
+
+#[cfg(feature = "worker")]
+impl PgTickLock {
+    /// Creates the claims table in `pool` if needed.
+    ///
+    /// # Errors
+    /// When the table cannot be created.
+    pub async fn new(pool: sqlx::PgPool) -> Result<Self> {
+        sqlx::query(sqlx::AssertSqlSafe(format!(
+            "CREATE TABLE IF NOT EXISTS {TICKS_TABLE} (job VARCHAR NOT NULL PRIMARY KEY, tick \
+             BIGINT NOT NULL)"
+        )))
+        .execute(&pool)
+        .await
+        .map_err(lock_error)?;
+        sqlx::query(sqlx::AssertSqlSafe(format!(
+            "CREATE TABLE IF NOT EXISTS {LEASES_TABLE} (job VARCHAR NOT NULL PRIMARY KEY, owner \
+             VARCHAR NOT NULL, expires_at BIGINT NOT NULL)"
+        )))
+        .execute(&pool)
+        .await
+        .map_err(lock_error)?;
+        Ok(Self(pool))
+    }
+}
+
+#[cfg(feature = "worker")]
+#[async_trait]
+impl TickLock for PgTickLock {
+    async fn claim(&self, job: &str, tick: DateTime<Utc>) -> Result<bool> {
+        let claimed = sqlx::query(sqlx::AssertSqlSafe(format!(
+            "INSERT INTO {TICKS_TABLE} (job, tick) VALUES ($1, $2) ON CONFLICT (job) DO UPDATE \
+             SET tick = EXCLUDED.tick WHERE {TICKS_TABLE}.tick < EXCLUDED.tick"
+        )))
+        .bind(job)
+        .bind(tick.timestamp_millis())
+        .execute(&self.0)
+        .await
+        .map_err(lock_error)?
+        .rows_affected();
+        Ok(claimed == 1)
+    }
+
+    async fn lease(&self, job: &str, owner: &str, ttl: Duration) -> Result<bool> {
+        let (expires_at, now) = lease_span(ttl);
+        let leased = sqlx::query(sqlx::AssertSqlSafe(format!(
+            "INSERT INTO {LEASES_TABLE} (job, owner, expires_at) VALUES ($1, $2, $3) ON CONFLICT \
+             (job) DO UPDATE SET owner = EXCLUDED.owner, expires_at = EXCLUDED.expires_at WHERE \
+             {LEASES_TABLE}.owner = EXCLUDED.owner OR {LEASES_TABLE}.expires_at < $4"
+        )))
+        .bind(job)
+        .bind(owner)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&self.0)
+        .await
+        .map_err(lock_error)?
+        .rows_affected();
+        Ok(leased == 1)
+    }
+
+    async fn release(&self, job: &str, owner: &str) -> Result<()> {
+        sqlx::query(sqlx::AssertSqlSafe(format!(
+            "DELETE FROM {LEASES_TABLE} WHERE job = $1 AND owner = $2"
+        )))
+        .bind(job)
+        .bind(owner)
+        .execute(&self.0)
+        .await
+        .map_err(lock_error)?;
+        Ok(())
+    }
+}
+
+/// Claims in the queue's `SQLite` database.
+#[cfg(feature = "worker")]
+pub struct SqliteTickLock(sqlx::SqlitePool);
+
+#[cfg(feature = "worker")]
+impl SqliteTickLock {
+    /// Creates the claims table in `pool` if needed.
+    ///
+    /// # Errors
+    /// When the table cannot be created.
+    pub async fn new(pool: sqlx::SqlitePool) -> Result<Self> {
+        sqlx::query(sqlx::AssertSqlSafe(format!(
+            "CREATE TABLE IF NOT EXISTS {TICKS_TABLE} (job TEXT NOT NULL PRIMARY KEY, tick \
+             INTEGER NOT NULL)"
+        )))
+        .execute(&pool)
+        .await
+        .map_err(lock_error)?;
+        sqlx::query(sqlx::AssertSqlSafe(format!(
+            "CREATE TABLE IF NOT EXISTS {LEASES_TABLE} (job TEXT NOT NULL PRIMARY KEY, owner \
+             TEXT NOT NULL, expires_at INTEGER NOT NULL)"
+        )))
+        .execute(&pool)
+        .await
+        .map_err(lock_error)?;
+        Ok(Self(pool))
+    }
+}
+
+#[cfg(feature = "worker")]
+#[async_trait]
+impl TickLock for SqliteTickLock {
+    async fn claim(&self, job: &str, tick: DateTime<Utc>) -> Result<bool> {
+        let claimed = sqlx::query(sqlx::AssertSqlSafe(format!(
+            "INSERT INTO {TICKS_TABLE} (job, tick) VALUES ($1, $2) ON CONFLICT (job) DO UPDATE \
+             SET tick = excluded.tick WHERE {TICKS_TABLE}.tick < excluded.tick"
+        )))
+        .bind(job)
+        .bind(tick.timestamp_millis())
+        .execute(&self.0)
+        .await
+        .map_err(lock_error)?
+        .rows_affected();
+        Ok(claimed == 1)
+    }
+
+    async fn lease(&self, job: &str, owner: &str, ttl: Duration) -> Result<bool> {
+        let (expires_at, now) = lease_span(ttl);
+        let leased = sqlx::query(sqlx::AssertSqlSafe(format!(
+            "INSERT INTO {LEASES_TABLE} (job, owner, expires_at) VALUES ($1, $2, $3) ON CONFLICT \
+             (job) DO UPDATE SET owner = excluded.owner, expires_at = excluded.expires_at WHERE \
+             {LEASES_TABLE}.owner = excluded.owner OR {LEASES_TABLE}.expires_at < $4"
+        )))
+        .bind(job)
+        .bind(owner)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&self.0)
+        .await
+        .map_err(lock_error)?
+        .rows_affected();
+        Ok(leased == 1)
+    }
+
+    async fn release(&self, job: &str, owner: &str) -> Result<()> {
+        sqlx::query(sqlx::AssertSqlSafe(format!(
+            "DELETE FROM {LEASES_TABLE} WHERE job = $1 AND owner = $2"
+        )))
+        .bind(job)
+        .bind(owner)
+        .execute(&self.0)
+        .await
+        .map_err(lock_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "cache_redis", not(feature = "worker_redis")))]
+use bb8_redis::redis as redis_client;
+#[cfg(feature = "worker_redis")]
+use redis as redis_client;
+
+/// Claims in Redis, as one key per job holding its latest tick, and one
+/// expiring key per job holding the owner of its run's lease.
+#[cfg(any(feature = "worker_redis", feature = "cache_redis"))]
+pub struct RedisTickLock(redis_client::Client);
+
+#[cfg(any(feature = "worker_redis", feature = "cache_redis"))]
+impl RedisTickLock {
+    /// A lock on the Redis at `uri`.
+    ///
+    /// # Errors
+    /// When `uri` is not a valid Redis URI.
+    pub fn new(uri: &str) -> Result<Self> {
+        redis_client::Client::open(uri)
+            .map(Self)
+            .map_err(lock_error)
+    }
+}
+
+#[cfg(any(feature = "worker_redis", feature = "cache_redis"))]
+#[async_trait]
+impl TickLock for RedisTickLock {
+    async fn claim(&self, job: &str, tick: DateTime<Utc>) -> Result<bool> {
+        let script = redis_client::Script::new(
+            r"
+            local last = redis.call('GET', KEYS[1])
+            if last and tonumber(last) >= tonumber(ARGV[1]) then
+                return 0
+            end
+            redis.call('SET', KEYS[1], ARGV[1])
+            return 1
+            ",
+        );
+        let mut conn = self
+            .0
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(lock_error)?;
+        let claimed: i32 = script
+            .key(format!("loco:scheduler:tick:{job}"))
+            .arg(tick.timestamp_millis())
+            .invoke_async(&mut conn)
+            .await
+            .map_err(lock_error)?;
+        Ok(claimed == 1)
+    }
+
+    async fn lease(&self, job: &str, owner: &str, ttl: Duration) -> Result<bool> {
+        let script = redis_client::Script::new(
+            r"
+            local owner = redis.call('GET', KEYS[1])
+            if owner and owner ~= ARGV[1] then
+                return 0
+            end
+            redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+            return 1
+            ",
+        );
+        let mut conn = self
+            .0
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(lock_error)?;
+        let leased: i32 = script
+            .key(format!("loco:scheduler:run:{job}"))
+            .arg(owner)
+            .arg(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1))
+            .invoke_async(&mut conn)
+            .await
+            .map_err(lock_error)?;
+        Ok(leased == 1)
+    }
+
+    async fn release(&self, job: &str, owner: &str) -> Result<()> {
+        let script = redis_client::Script::new(
+            r"
+            if redis.call('GET', KEYS[1]) == ARGV[1] then
+                redis.call('DEL', KEYS[1])
+            end
+            return 1
+            ",
+        );
+        let mut conn = self
+            .0
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(lock_error)?;
+        let _: i32 = script
+            .key(format!("loco:scheduler:run:{job}"))
+            .arg(owner)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(lock_error)?;
+        Ok(())
+    }
+}
+
+/// A pool on the database of a Postgres queue, which is the app's with
+/// `use_app_db`.
+#[cfg(feature = "worker")]
+pub(super) async fn postgres_pool(
+    qcfg: &crate::config::PostgresQueueConfig,
+    ctx: &AppContext,
+) -> crate::Result<sqlx::PgPool> {
+    #[cfg(feature = "with-db")]
+    if qcfg.use_app_db {
+        return Ok(ctx.db.get_postgres_connection_pool().clone());
+    }
+    #[cfg(not(feature = "with-db"))]
+    let _ = ctx;
+    crate::bgworker::pg::connect(qcfg).await
+}
+
+/// A pool on the database of a `SQLite` queue, which is the app's with
+/// `use_app_db`.
+#[cfg(feature = "worker")]
+pub(super) async fn sqlite_pool(
+    qcfg: &crate::config::SqliteQueueConfig,
+    ctx: &AppContext,
+) -> crate::Result<sqlx::SqlitePool> {
+    #[cfg(feature = "with-db")]
+    if qcfg.use_app_db {
+        return Ok(ctx.db.get_sqlite_connection_pool().clone());
+    }
+    #[cfg(not(feature = "with-db"))]
+    let _ = ctx;
+    crate::bgworker::sqlt::connect(qcfg).await
+}
+
+/// Opens the store `lock` points at, from the config of `ctx`.
+///
+/// # Errors
+/// When the queue or cache config has no store to claim ticks in (no queue,
+/// or the in-memory cache), or the store cannot be reached.
+pub async fn connect(lock: Lock, ctx: &AppContext) -> Result<Option<Arc<dyn TickLock>>> {
+    match lock {
+        Lock::None => Ok(None),
+        Lock::Queue => match &ctx.config.queue {
+            #[cfg(feature = "worker")]
+            Some(QueueConfig::Postgres(qcfg)) => Ok(Some(Arc::new(
+                PgTickLock::new(postgres_pool(qcfg, ctx).await.map_err(lock_error)?).await?,
+            ))),
+            #[cfg(feature = "worker")]
+            Some(QueueConfig::Sqlite(qcfg)) => Ok(Some(Arc::new(
+                SqliteTickLock::new(sqlite_pool(qcfg, ctx).await.map_err(lock_error)?).await?,
+            ))),
+            #[cfg(feature = "worker_redis")]
+            Some(QueueConfig::Redis(qcfg)) => Ok(Some(Arc::new(RedisTickLock::new(&qcfg.uri)?))),
+            _ => Err(Error::Lock(
+                "`lock: queue` needs a Postgres, SQLite or Redis queue".to_string(),
+            )),
+        },
+        Lock::Cache => match &ctx.config.cache {
+            #[cfg(feature = "cache_redis")]
+            CacheConfig::Redis(cache) => Ok(Some(Arc::new(RedisTickLock::new(&cache.uri)?))),
+            _ => Err(Error::Lock("`lock: cache` needs a Redis cache".to_string())),
+        },
+    }
+}
+
+#[cfg(all(test, feature = "worker"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_tick_is_claimed_once() {
+        // One connection, as every connection to `:memory:` is a database.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let lock = SqliteTickLock::new(pool).await.unwrap();
+        let tick: DateTime<Utc> = "2026-03-01T10:00:00Z".parse().unwrap();
+        let next = tick + chrono::Duration::minutes(1);
+
+        assert!(lock.claim("report", tick).await.unwrap());
+        assert!(!lock.claim("report", tick).await.unwrap());
+        assert!(lock.claim("cleanup", tick).await.unwrap());
+        assert!(lock.claim("report", next).await.unwrap());
+        // A replica lagging behind cannot claim a tick past the latest one.
+        assert!(!lock.claim("report", tick).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_run_is_leased_to_one_owner_at_a_time() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let lock = SqliteTickLock::new(pool).await.unwrap();
+        let ttl = Duration::from_secs(60);
+
+        assert!(lock.lease("report", "a", ttl).await.unwrap());
+        assert!(!lock.lease("report", "b", ttl).await.unwrap());
+        assert!(lock.lease("cleanup", "b", ttl).await.unwrap());
+        // The owner renews its lease.
+        assert!(lock.lease("report", "a", ttl).await.unwrap());
+
+        lock.release("report", "b").await.unwrap();
+        assert!(!lock.lease("report", "b", ttl).await.unwrap());
+        lock.release("report", "a").await.unwrap();
+        assert!(lock.lease("report", "b", ttl).await.unwrap());
+
+        // A lease its owner left behind expires.
+        assert!(lock.lease("expiring", "a", Duration::ZERO).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(lock.lease("expiring", "b", ttl).await.unwrap());
+    }
+}
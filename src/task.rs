@@ -86,6 +86,12 @@ pub struct Tasks {
     registry: BTreeMap<String, Box<dyn Task>>,
 }
 
+impl std::fmt::Debug for Tasks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.registry.keys()).finish()
+    }
+}
+
 impl Tasks {
     /// List all registered tasks with their information.
     #[must_use]
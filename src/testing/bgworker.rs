@@ -0,0 +1,180 @@
+//! Helpers for testing code that enqueues background jobs.
+//!
+//! They work on the queue of an [`AppContext`] booted with the in-memory
+//! queue provider, set up in the test config with:
+//!
+//! ```yaml
+//! workers:
+//!   mode: BackgroundQueue
+//! queue:
+//!   kind: InMem
+//! ```
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    app::AppContext,
+    bgworker::{BackgroundWorker, Job, JobStatus},
+};
+
+fn queue(ctx: &AppContext) -> &crate::bgworker::Queue {
+    ctx.queue_provider
+        .as_deref()
+        .expect("no queue provider: set `workers.mode: BackgroundQueue` and `queue.kind: InMem`")
+}
+
+/// Returns the jobs waiting in the queue, in the order they were enqueued.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use loco_rs::testing::prelude::*;
+///
+/// #[tokio::test]
+/// async fn test_signup_enqueues_welcome_email() {
+///     let boot = boot_test::<App>().await.unwrap();
+///     // ... sign up a user
+///     let jobs = enqueued_jobs(&boot.app_context).await;
+///     assert_eq!(jobs.len(), 1);
+/// }
+/// ```
+///
+/// # Panics
+///
+/// This function will panic if the context has no queue, or if listing its
+/// jobs fails.
+pub async fn enqueued_jobs(ctx: &AppContext) -> Vec<Job> {
+    queue(ctx)
+        .get_jobs(Some(&vec![JobStatus::Queued]), None)
+        .await
+        .expect("list enqueued jobs")
+}
+
+/// Asserts that a job for worker `W` is waiting in the queue, with args
+/// that `predicate` accepts.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use loco_rs::testing::prelude::*;
+///
+/// assert_enqueued::<DownloadWorker, _>(&boot.app_context, |args| args.user_guid == user.pid).await;
+/// ```
+///
+/// # Panics
+///
+/// This function will panic if no such job is waiting, or if the args of a
+/// job for `W` cannot be deserialized.
+pub async fn assert_enqueued<W, A>(ctx: &AppContext, predicate: impl Fn(&A) -> bool)
+where
+    W: BackgroundWorker<A>,
+    A: Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    let class = W::class_name();
+    let args = enqueued_jobs(ctx)
+        .await
+        .into_iter()
+        .filter(|job| job.name == class)
+        .map(|job| job.data)
+        .collect::<Vec<_>>();
+    let found = args.iter().any(|data| {
+        let args = serde_json::from_value(data.clone())
+            .unwrap_or_else(|err| panic!("args of a {class} job do not deserialize: {err}"));
+        predicate(&args)
+    });
+    assert!(
+        found,
+        "no matching {class} job enqueued, the enqueued ones have args: {args:?}"
+    );
+}
+
+/// Performs the due jobs in the queue through their registered workers, and
+/// the jobs these enqueue in turn, until none is left. Returns how many jobs
+/// were performed. See [`crate::bgworker::Queue::drain`].
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use loco_rs::testing::prelude::*;
+///
+/// assert_eq!(drain_jobs(&boot.app_context).await, 1);
+/// ```
+///
+/// # Panics
+///
+/// This function will panic if the context has no in-memory queue.
+pub async fn drain_jobs(ctx: &AppContext) -> usize {
+    queue(ctx).drain().await.expect("drain the queue")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    };
+
+    use async_trait::async_trait;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{bgworker, config, tests_cfg, Result};
+
+    static TOTAL: AtomicI64 = AtomicI64::new(0);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct AddArgs {
+        amount: i64,
+    }
+
+    struct AddWorker;
+
+    #[async_trait]
+    impl BackgroundWorker<AddArgs> for AddWorker {
+        fn build(_ctx: &AppContext) -> Self {
+            Self
+        }
+
+        async fn perform(&self, args: AddArgs) -> Result<()> {
+            TOTAL.fetch_add(args.amount, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_stay_enqueued_until_drained() {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        ctx.config.workers.mode = config::WorkerMode::BackgroundQueue;
+        let queue = Arc::new(bgworker::inmem::create_provider());
+        queue.register(AddWorker).await.unwrap();
+        ctx.queue_provider = Some(queue);
+
+        AddWorker::perform_later(&ctx, AddArgs { amount: 2 })
+            .await
+            .unwrap();
+        AddWorker::perform_later(&ctx, AddArgs { amount: 3 })
+            .await
+            .unwrap();
+
+        assert_eq!(enqueued_jobs(&ctx).await.len(), 2);
+        assert_enqueued::<AddWorker, _>(&ctx, |args| args.amount == 3).await;
+        assert_eq!(TOTAL.load(Ordering::SeqCst), 0);
+
+        assert_eq!(drain_jobs(&ctx).await, 2);
+        assert_eq!(TOTAL.load(Ordering::SeqCst), 5);
+        assert!(enqueued_jobs(&ctx).await.is_empty());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "no matching AddWorker job enqueued")]
+    async fn assert_enqueued_panics_without_a_matching_job() {
+        let mut ctx = tests_cfg::app::get_app_context().await;
+        ctx.config.workers.mode = config::WorkerMode::BackgroundQueue;
+        ctx.queue_provider = Some(Arc::new(bgworker::inmem::create_provider()));
+
+        AddWorker::perform_later(&ctx, AddArgs { amount: 1 })
+            .await
+            .unwrap();
+
+        assert_enqueued::<AddWorker, _>(&ctx, |args| args.amount == 2).await;
+    }
+}
@@ -1,3 +1,4 @@
+pub mod bgworker;
 #[cfg(feature = "with-db")]
 pub mod db;
 pub mod prelude;
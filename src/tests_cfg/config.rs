@@ -42,14 +42,24 @@ pub fn test_config() -> Config {
                 scheduler::Job {
                     run: "echo loco".to_string(),
                     shell: true,
+                    worker: false,
+                    args: None,
                     run_on_start: false,
+                    allow_overlap: true,
+                    on_overlap: scheduler::Overlap::Skip,
+                    timeout: None,
                     cron: "*/5 * * * * *".to_string(),
+                    timezone: None,
                     tags: Some(vec!["base".to_string()]),
                     output: None,
                 },
             )]),
 
             output: scheduler::Output::STDOUT,
+            mode: scheduler::Mode::Subprocess,
+            lock: scheduler::Lock::None,
+            history: scheduler::History::None,
+            timezone: None,
         }),
         // Always use in-memory cache for tests if available
         #[cfg(feature = "cache_inmem")]